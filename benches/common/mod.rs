@@ -0,0 +1,48 @@
+//! The input and the timing shared by all benchmarks
+//!
+//! The number of rows can be set with the `CSV_CONVERTER_BENCH_ROWS` environment variable
+use std::{
+	env,
+	time::{Duration, Instant},
+};
+
+/// The headings of the rows returned by [products]
+pub const HEADINGS: [&str; 6] = ["SKU", "Name", "Description", "Price", "Tags", "Images"];
+
+/// The number of rows to run a benchmark with, defaults to 200,000
+pub fn rows() -> usize {
+	env::var("CSV_CONVERTER_BENCH_ROWS").ok().and_then(|rows| rows.parse().ok()).unwrap_or(200_000)
+}
+
+/// Product rows that are the same on every run so runs are comparable
+///
+/// The cells have whitespace to trim, quotes, commas and line breaks so both the parser and the filters have work to
+/// do.
+pub fn products(rows: usize) -> Vec<Vec<String>> {
+	(0..rows)
+		.map(|i| {
+			vec![
+				format!("SKU {i:06}"),
+				format!("  Product number {i}  "),
+				String::from("A \"quoted\" description, with a comma\nand a line break"),
+				format!("{}", (i * 37) % 250),
+				format!("tag{}|tag{}|sale", i % 13, i % 7),
+				format!("{i}-front.jpg|{i}-back.jpg|{i}-side, left.jpg"),
+			]
+		})
+		.collect()
+}
+
+/// Run the function and measure how long it takes
+pub fn time<T>(run: impl FnOnce() -> T) -> (T, Duration) {
+	let time = Instant::now();
+	let result = run();
+	(result, time.elapsed())
+}
+
+/// Print how long each run took and how many rows per second that is
+pub fn report(rows: usize, runs: &[(&str, Duration)]) {
+	for (name, elapsed) in runs {
+		println!("{name:>12}: {elapsed:>12.2?} {:>12.0} rows/s", rows as f64 / elapsed.as_secs_f64());
+	}
+}
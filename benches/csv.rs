@@ -0,0 +1,41 @@
+//! Compares the throughput of reading records into a reused [Record] with iterating over the [CsvParser]
+//!
+//! Run with `cargo bench --bench csv`
+use std::{hint::black_box, io::Cursor};
+
+use csv_converter::csv::{export, CsvParser, Record};
+
+mod common;
+
+fn main() {
+	let rows = common::rows();
+
+	let mut input = String::new();
+	export(&[common::HEADINGS.map(String::from).to_vec()], &mut input);
+	let mut body = String::new();
+	export(&common::products(rows), &mut body);
+	input.push_str(&body);
+
+	let (iterated, iterator_time) = common::time(|| {
+		let mut iterated = 0;
+		for row in CsvParser::new(Cursor::new(input.as_bytes())) {
+			iterated += black_box(row).len();
+		}
+		iterated
+	});
+
+	let (read, read_record_time) = common::time(|| {
+		let mut read = 0;
+		let mut csv_file = CsvParser::new(Cursor::new(input.as_bytes()));
+		let mut record = Record::default();
+		while csv_file.read_record(&mut record).unwrap() {
+			read += black_box(&record).len();
+		}
+		read
+	});
+
+	assert_eq!(iterated, read, "The iterator and read_record disagree");
+
+	println!("{rows} rows, {} bytes of input", input.len());
+	common::report(rows, &[("iterator", iterator_time), ("read_record", read_record_time)]);
+}
@@ -0,0 +1,56 @@
+//! Compares the throughput of the interpreter with the compiled [Plan]
+//!
+//! Run with `cargo bench --bench plan`
+use std::{hint::black_box, io::Cursor};
+
+use csv_converter::{
+	config::OutputConfig,
+	csv::{export_with_dialect, CsvParser, Dialect},
+	plan::{Plan, Scratch},
+	process::{self, Context},
+};
+
+mod common;
+
+const CONFIG: &str = "Handle,Title,Vendor,Price,Tags,Image Src,Image Position\n\
+<col 'SKU' LOWER_CASE REPLACE|' '|'-'>,<col 'Name' TRIM>,ACME,:IF <col 'Price'> > 100 (<col 'Price' APPEND|'.00'>) ELSE (<col 'Price'>),<col 'Tags' REPLACE|'|'|', '>,,\n\
+:EACH <col 'Images'> BY '|'\n\
+<col 'SKU' LOWER_CASE REPLACE|' '|'-'>,,,,,<each TRIM PREPEND|'https://cdn.example.com/'>,<index>\n";
+
+fn main() {
+	let rows = common::rows();
+
+	let mut output_config = OutputConfig::new(CsvParser::new(Cursor::new(CONFIG))).unwrap();
+	output_config.resolve_headers(&common::HEADINGS.map(String::from)).unwrap();
+	let dialect = Dialect::default();
+	let input = common::products(rows);
+
+	let (interpreted, interpreter_time) = common::time(|| {
+		let mut interpreted = String::new();
+		let mut output = String::new();
+		let mut context = Context::new();
+		for row in &input {
+			export_with_dialect(&process::run(row, &output_config, &mut context).unwrap(), &mut output, &dialect);
+			interpreted.push_str(&output);
+		}
+		interpreted
+	});
+
+	let (compiled, plan_time) = common::time(|| {
+		let plan = Plan::new(&output_config).unwrap();
+		let mut scratch = Scratch::default();
+		let mut compiled = String::new();
+		let mut previous = None;
+		for row in &input {
+			plan.write(row, previous, &mut compiled, &dialect, &mut scratch).unwrap();
+			previous = Some(row.as_slice());
+		}
+		compiled
+	});
+
+	assert_eq!(black_box(&interpreted), black_box(&compiled), "The plan and the interpreter disagree");
+
+	println!("{rows} rows, {} bytes of output", compiled.len());
+	common::report(rows, &[("interpreter", interpreter_time), ("plan", plan_time)]);
+	println!("     speedup: {:.2}x", interpreter_time.as_secs_f64() / plan_time.as_secs_f64());
+}
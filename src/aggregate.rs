@@ -0,0 +1,416 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use crate::{
+	cli::{Error, ErrorStages},
+	config::{format_number, Aggregate, GroupBy, Item, OutputConfig, Row},
+	process::get_value,
+};
+
+/// Applies a config with a `:GROUP_BY` directive to the input rows
+///
+/// Push every input row that passed the filters together with the one that passed before it with [Aggregator::push]
+/// then call [Aggregator::finish] for the groups that are still open. Each group emits one output row per config line.
+///
+/// When the directive says the input is `SORTED` a group is emitted as soon as the key changes so only one group is
+/// held in memory, a key that comes back later starts a new group.
+/// Otherwise all groups are held in memory and emitted at the end in the order their first row came in.
+///
+/// ```rust
+/// use csv_converter::{aggregate::Aggregator, config::OutputConfig, csv::CsvParser};
+/// use std::io::Cursor;
+///
+/// let output_config =
+///     OutputConfig::new(CsvParser::new(Cursor::new("Handle,Stock\n:GROUP_BY <cell1>\n<cell1>,:SUM <cell2>\n"))).unwrap();
+/// let mut aggregator = Aggregator::new(&output_config).unwrap();
+/// let mut previous: Option<Vec<String>> = None;
+/// for row in [["a", "1"], ["b", "5"], ["a", "2"]] {
+///     let row = vec![row[0].to_string(), row[1].to_string()];
+///     assert!(aggregator.push(&row, previous.as_deref()).unwrap().is_empty());
+///     previous = Some(row);
+/// }
+/// assert_eq!(
+///     aggregator.finish(),
+///     vec![vec![String::from("a"), String::from("3")], vec![String::from("b"), String::from("5")]]
+/// );
+/// ```
+#[derive(Debug)]
+pub struct Aggregator<'a> {
+	output_config: &'a OutputConfig,
+	group_by: &'a GroupBy,
+	/// The groups that haven't been emitted yet in the order their first row came in
+	groups: Vec<Group>,
+	/// The position of each group within `groups` by its key, unused for sorted input
+	positions: HashMap<Vec<String>, usize>,
+}
+
+/// The key of a group and the state of every item of every config line
+#[derive(Debug)]
+struct Group {
+	key: Vec<String>,
+	lines: Vec<Vec<State>>,
+}
+
+/// What an item remembers of the rows of its group so far
+#[derive(Debug)]
+enum State {
+	/// The value of the first row for `:FIRST` and every item that isn't an aggregate
+	First(Option<String>),
+	/// The value of the latest row for `:LAST`, empty values included
+	Last(String),
+	/// The number of rows for `:COUNT` or of the rows with a value when it has an operand
+	Count(usize),
+	/// The total of the numbers for `:SUM`, empty values are skipped
+	Sum(f64),
+	/// The smallest number for `:MIN`, None until a row had a value
+	Min(Option<f64>),
+	/// The largest number for `:MAX`, None until a row had a value
+	Max(Option<f64>),
+	/// The values joined with the separator for `:JOIN`, None until a row had a value
+	Join(Option<String>),
+}
+
+impl<'a> Aggregator<'a> {
+	/// An aggregator for a config, errors if the config has no `:GROUP_BY` directive
+	pub fn new(output_config: &'a OutputConfig) -> Result<Self, Error> {
+		match &output_config.group {
+			Some(group_by) => Ok(Self {
+				output_config,
+				group_by,
+				groups: Vec::new(),
+				positions: HashMap::new(),
+			}),
+			None => {
+				Err(Error::new(ErrorStages::Process, String::from("The config needs a :GROUP_BY directive to aggregate rows")))
+			},
+		}
+	}
+
+	/// Add an input row that follows the given previous row to its group, returns the output of the groups that are
+	/// complete because of it
+	pub fn push(&mut self, input_line: &[String], previous: Option<&[String]>) -> Result<Vec<Vec<String>>, Error> {
+		let row = Row::after(input_line, previous);
+		let key = self
+			.group_by
+			.keys
+			.iter()
+			.map(|item| get_value(item, &row).map(Cow::into_owned))
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(|error| error.at(self.group_by.row, 1))?;
+
+		let mut output = Vec::new();
+		let position = if self.group_by.sorted {
+			if self.groups.last().is_some_and(|group| group.key != key) {
+				output = self.emit();
+			}
+			if self.groups.is_empty() {
+				self.groups.push(Group::new(key, self.output_config));
+			}
+			0
+		} else {
+			match self.positions.get(&key) {
+				Some(position) => *position,
+				None => {
+					self.positions.insert(key.clone(), self.groups.len());
+					self.groups.push(Group::new(key, self.output_config));
+					self.groups.len() - 1
+				},
+			}
+		};
+
+		self.groups[position].add(&row, self.output_config)?;
+		Ok(output)
+	}
+
+	/// Emit all groups that are still open, call this after the last input row
+	pub fn finish(mut self) -> Vec<Vec<String>> {
+		self.emit()
+	}
+
+	/// The output of all open groups, one row per config line of each group
+	fn emit(&mut self) -> Vec<Vec<String>> {
+		self.positions.clear();
+		self
+			.groups
+			.drain(..)
+			.flat_map(|group| group.lines.into_iter().map(|line| line.into_iter().map(State::finish).collect()))
+			.collect()
+	}
+}
+
+impl Group {
+	fn new(key: Vec<String>, output_config: &OutputConfig) -> Self {
+		Self {
+			key,
+			lines: output_config.lines.iter().map(|line| line.items.iter().map(State::new).collect()).collect(),
+		}
+	}
+
+	fn add(&mut self, row: &Row, output_config: &OutputConfig) -> Result<(), Error> {
+		for (line, states) in output_config.lines.iter().zip(self.lines.iter_mut()) {
+			for (column_index, (item, state)) in line.items.iter().zip(states.iter_mut()).enumerate() {
+				state.add(item, row).map_err(|error| error.at(line.row, column_index + 1))?;
+			}
+		}
+		Ok(())
+	}
+}
+
+impl State {
+	fn new(item: &Item) -> Self {
+		match item {
+			Item::Aggregate(Aggregate::Last, _) => State::Last(String::new()),
+			Item::Aggregate(Aggregate::Count, _) => State::Count(0),
+			Item::Aggregate(Aggregate::Sum, _) => State::Sum(0.0),
+			Item::Aggregate(Aggregate::Min, _) => State::Min(None),
+			Item::Aggregate(Aggregate::Max, _) => State::Max(None),
+			Item::Aggregate(Aggregate::Join(_), _) => State::Join(None),
+			_ => State::First(None),
+		}
+	}
+
+	fn add(&mut self, item: &Item, row: &Row) -> Result<(), Error> {
+		// the first value never changes so there is no need to evaluate the item again
+		if let State::First(Some(_)) = self {
+			return Ok(());
+		}
+
+		let operand = match item {
+			Item::Aggregate(_, operand) => operand.as_deref(),
+			_ => Some(item),
+		};
+		let value = match operand {
+			Some(operand) => get_value(operand, row)?,
+			None => Cow::Borrowed(""),
+		};
+
+		match self {
+			State::First(first) => *first = Some(value.into_owned()),
+			State::Last(last) => *last = value.into_owned(),
+			State::Count(count) => {
+				if operand.is_none() || !value.is_empty() {
+					*count += 1;
+				}
+			},
+			State::Join(joined) => {
+				let Item::Aggregate(Aggregate::Join(separator), _) = item else {
+					return Ok(());
+				};
+				if !value.is_empty() {
+					match joined {
+						Some(joined) => {
+							joined.push_str(separator);
+							joined.push_str(&value);
+						},
+						None => *joined = Some(value.into_owned()),
+					}
+				}
+			},
+			State::Sum(_) | State::Min(_) | State::Max(_) => {
+				if value.trim().is_empty() {
+					return Ok(());
+				}
+				let number = match value.trim().parse::<f64>() {
+					Ok(number) if number.is_finite() => number,
+					_ => {
+						return Err(Error::new(
+							ErrorStages::Process,
+							format!("The value of {item} is not a number, was \"{value}\""),
+						))
+					},
+				};
+				match self {
+					State::Sum(sum) => *sum += number,
+					State::Min(min) => *min = Some(min.map_or(number, |min| min.min(number))),
+					State::Max(max) => *max = Some(max.map_or(number, |max| max.max(number))),
+					_ => {},
+				}
+			},
+		}
+		Ok(())
+	}
+
+	fn finish(self) -> String {
+		match self {
+			State::First(value) | State::Join(value) => value.unwrap_or_default(),
+			State::Last(value) => value,
+			State::Count(count) => count.to_string(),
+			State::Sum(sum) => format_number(sum),
+			State::Min(number) | State::Max(number) => number.map(format_number).unwrap_or_default(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::csv::{rows, CsvParser};
+	use std::io::Cursor;
+
+	/// Aggregate the rows that pass the `:FILTER` directives like the binary does
+	fn aggregate(config: &str, input: &[&[&str]]) -> Result<Vec<Vec<String>>, Error> {
+		let output_config = OutputConfig::new(CsvParser::new(Cursor::new(config))).unwrap();
+		let mut aggregator = Aggregator::new(&output_config)?;
+		let mut output = Vec::new();
+		let mut previous: Option<Vec<String>> = None;
+		for row in rows(input) {
+			if output_config.keep(&row, previous.as_deref())? {
+				output.extend(aggregator.push(&row, previous.as_deref())?);
+				previous = Some(row);
+			}
+		}
+		output.extend(aggregator.finish());
+		Ok(output)
+	}
+
+	#[test]
+	fn aggregate_test() {
+		let input: &[&[&str]] = &[
+			&["shirt", "3", "19.99", "red"],
+			&["mug", "", "7.5", ""],
+			&["shirt", "2", "17.5", "blue"],
+			&["shirt", "-1", "21", ""],
+			&["mug", "10", "8", "white"],
+		];
+
+		assert_eq!(
+			aggregate(
+				"Handle,Stock,Min,Max,Rows,Tags,Count,First,Last\n\
+				:GROUP_BY <cell1>\n\
+				<cell1 UPPER_CASE>,:SUM <cell2>,:MIN <cell3>,:MAX <cell3>,:COUNT,\":JOIN|', ' <cell4>\",:COUNT <cell4>,:FIRST <cell3>,:LAST <cell3>\n",
+				input,
+			),
+			Ok(rows(&[
+				&["SHIRT", "4", "17.5", "21", "3", "red, blue", "2", "19.99", "21"],
+				&["MUG", "10", "7.5", "8", "2", "white", "1", "7.5", "8"],
+			]))
+		);
+
+		// sorted input emits every group when its key changes so a key that comes back starts a new group
+		assert_eq!(
+			aggregate("Handle,Stock\n:GROUP_BY <cell1> SORTED\n<cell1>,:SUM <cell2>\n", input),
+			Ok(rows(&[&["shirt", "3"], &["mug", "0"], &["shirt", "1"], &["mug", "10"]]))
+		);
+
+		assert_eq!(
+			aggregate("Handle,Line\n:GROUP_BY <cell1> <cell2 LENGTH>\n<cell1>,:COUNT\n<cell1>,:JOIN|'|' <cell3>\n", input),
+			Ok(rows(&[
+				&["shirt", "2"],
+				&["shirt", "19.99|17.5"],
+				&["mug", "1"],
+				&["mug", "7.5"],
+				&["shirt", "1"],
+				&["shirt", "21"],
+				&["mug", "1"],
+				&["mug", "8"],
+			]))
+		);
+
+		assert_eq!(aggregate("Handle,Stock\n:GROUP_BY <cell1>\n<cell1>,:SUM <cell2>\n", &[]), Ok(Vec::new()));
+	}
+
+	#[test]
+	fn aggregate_filter_test() {
+		let input: &[&[&str]] = &[
+			&["mug", "0", "white"],
+			&["shirt", "3", "red"],
+			&["hat", "1", "blue"],
+			&["mug", "2", "black"],
+			&["shirt", "4", "green"],
+			&["hat", "0", "grey"],
+		];
+
+		// the first mug is rejected so its group starts with the second mug after the shirts, a group without any kept
+		// row isn't emitted
+		assert_eq!(
+			aggregate(
+				"Handle,Stock,Tags\n:GROUP_BY <cell1>\n:FILTER <cell2> > 0 AND <cell1> != 'hat'\n<cell1>,:SUM <cell2>,\":JOIN|', ' <cell3>\"\n",
+				input
+			),
+			Ok(rows(&[&["shirt", "7", "red, green"], &["mug", "2", "black"]]))
+		);
+
+		// rejected rows don't split the groups of sorted input
+		assert_eq!(
+			aggregate(
+				"Handle,Stock\n:GROUP_BY <cell1> SORTED\n:FILTER <cell1> != 'mug'\n<cell1>,:COUNT\n",
+				&[&["shirt", "1"], &["mug", "1"], &["shirt", "1"], &["hat", "1"]]
+			),
+			Ok(rows(&[&["shirt", "2"], &["hat", "1"]]))
+		);
+	}
+
+	#[test]
+	fn aggregate_join_min_max_test() {
+		let config = "Handle,Tags,Min,Max\n:GROUP_BY <cell1>\n<cell1>,\":JOIN|' / ' <cell2>\",:MIN <cell3>,:MAX <cell3>\n";
+
+		assert_eq!(
+			aggregate(
+				config,
+				&[
+					&["shirt", "red", " -1.5 ", "a"],
+					&["mug", "", "", ""],
+					&["shirt", "", "", ""],
+					&["shirt", "blue", "20", ""],
+				]
+			),
+			Ok(rows(&[&["shirt", "red / blue", "-1.5", "20"], &["mug", "", "", ""]]))
+		);
+
+		// text is compared as a number so it fails instead of being sorted alphabetically
+		assert_eq!(
+			aggregate(config, &[&["shirt", "red", "10"], &["shirt", "blue", "large"]]),
+			Err(
+				Error::new(ErrorStages::Process, String::from("The value of :MIN <cell3> is not a number, was \"large\""))
+					.at(3, 3)
+			)
+		);
+		assert_eq!(
+			aggregate(config, &[&["shirt", "red", "NaN"]]),
+			Err(
+				Error::new(ErrorStages::Process, String::from("The value of :MIN <cell3> is not a number, was \"NaN\""))
+					.at(3, 3)
+			)
+		);
+	}
+
+	#[test]
+	fn aggregate_previous_test() {
+		let input: &[&[&str]] = &[&["shirt", "3"], &["shirt", "2"], &["mug", "10"], &["mug", "4"]];
+
+		assert_eq!(
+			aggregate("Handle,Before,Starts\n:GROUP_BY <cell1> SORTED\n<cell1>,:FIRST <prev2>,:JOIN|'|' <prev1>\n", input),
+			Ok(rows(&[&["shirt", "", "shirt"], &["mug", "2", "shirt|mug"]]))
+		);
+
+		assert_eq!(
+			aggregate(
+				"Handle,Groups\n:GROUP_BY <cell1>\n<cell1>,:COUNT :IF <cell1> IS_FIRST_IN_GROUP ('x') ELSE ('')\n",
+				input
+			),
+			Ok(rows(&[&["shirt", "1"], &["mug", "1"]]))
+		);
+	}
+
+	#[test]
+	fn aggregate_error_test() {
+		assert_eq!(
+			aggregate("Handle,Stock\n:GROUP_BY <cell1>\n<cell1>,:SUM <cell2>\n", &[&["shirt", "3"], &["shirt", "many"]]),
+			Err(
+				Error::new(ErrorStages::Process, String::from("The value of :SUM <cell2> is not a number, was \"many\""))
+					.at(3, 2)
+			)
+		);
+
+		assert_eq!(
+			aggregate("Handle,Stock\n:GROUP_BY <cell3>\n<cell1>,:SUM <cell2>\n", &[&["shirt", "3"]]),
+			Err(Error::new(ErrorStages::Process, String::from("Cell not found \"<cell3>\"")).at(2, 1))
+		);
+
+		assert_eq!(
+			Aggregator::new(&OutputConfig::new(CsvParser::new(Cursor::new("Handle\n<cell1>\n"))).unwrap())
+				.map(|_| ())
+				.map_err(|error| error.stage),
+			Err(ErrorStages::Process)
+		);
+	}
+}
@@ -3,11 +3,27 @@
 //! - A function to display help
 //! - A function to display color in the terminal
 //! - A function to handle exiting with the right error code
+//! - The [Error] type returned by the library
+use crate::{csv::Dialect, dedupe::Keep};
 use CliColor::*;
 
+/// The commands of the CLI, given as the first argument
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Command {
+	/// Convert the input file with the config, this is the default when no command is given
+	#[default]
+	Convert,
+	/// Report every problem of the config, and of the input headings if an input is given, without converting anything
+	Check,
+	/// Write a starter config for the input file, optionally mapped onto the headings of a template
+	Init,
+}
+
 /// The [Settings] struct is the code representation of your CI arguments
 #[derive(Debug, Default, PartialEq)]
 pub struct Settings {
+	/// The command to run
+	pub command: Command,
 	/// The path for the input file
 	pub input: String,
 	/// The path for the output file
@@ -18,12 +34,48 @@ pub struct Settings {
 	pub version: bool,
 	/// A boolean flag to show the help
 	pub help: bool,
+	/// The delimiter of the input file, detected from the file if not set
+	pub input_delimiter: Option<char>,
+	/// The quote character of the input file
+	pub input_quote: Option<char>,
+	/// The escape character of the input file
+	pub input_escape: Option<char>,
+	/// The delimiter of the output file
+	pub output_delimiter: Option<char>,
+	/// The quote character of the output file
+	pub output_quote: Option<char>,
+	/// The escape character of the output file
+	pub output_escape: Option<char>,
+	/// The line terminator of the output file
+	pub output_line_terminator: Option<String>,
+	/// A condition every input row has to meet to be processed, in addition to the `:FILTER` of the config
+	pub filter: Option<String>,
+	/// The number of worker threads, defaults to the available parallelism
+	pub threads: Option<usize>,
+	/// The row of the input file, counting the heading as row 1, to trace through the config instead of converting
+	pub explain: Option<usize>,
+	/// The path of a CSV file whose headings the config written by the init command maps the input onto
+	pub template: Option<String>,
+	/// Whether the init command may overwrite an existing config
+	pub force: bool,
+	/// An item evaluated for every output line, lines with a key that was already seen are dropped
+	pub dedupe_by: Option<String>,
+	/// Which of the output lines with the same key is kept
+	pub dedupe_keep: Keep,
+	/// The memory in megabytes the keys may take before they are spilled to disk
+	pub dedupe_memory: Option<usize>,
+	/// The output columns to sort the output by like `<col 'Handle'> <cell2> NUMERIC DESC`
+	pub sort_by: Option<String>,
+	/// The memory in megabytes the output lines may take before sorted runs are written to disk
+	pub sort_memory: Option<usize>,
 }
 
 impl Settings {
 	/// This function parses the CLI arguments into our [Settings] struct.
 	/// Pass in the args from env: `Settings::new(env::args().skip(1).collect());`
 	///
+	/// Returns an [Error] with the [ErrorStages::Cli] stage when an argument is missing
+	///
 	/// ```rust
 	/// use csv_converter::cli::Settings;
 	///
@@ -36,44 +88,108 @@ impl Settings {
 	///         String::from("-c"),
 	///         String::from("config_file.csv"),
 	///     ]),
-	///     Settings {
+	///     Ok(Settings {
 	///         input: String::from("input_file.csv"),
 	///         output: String::from("output_file.csv"),
 	///         output_config: String::from("config_file.csv"),
 	///         version: false,
 	///         help: false,
-	///     }
+	///         ..Default::default()
+	///     })
 	/// );
 	/// ```
-	pub fn new(args: Vec<String>) -> Self {
+	pub fn new(args: Vec<String>) -> Result<Self, Error> {
 		let mut settings: Settings = Default::default();
 
-		let mut args_iter = args.into_iter();
+		let mut args_iter = args.into_iter().peekable();
+		match args_iter.next_if(|arg| arg == "check" || arg == "init").as_deref() {
+			Some("check") => settings.command = Command::Check,
+			Some("init") => settings.command = Command::Init,
+			_ => {},
+		}
 		while let Some(arg) = args_iter.next() {
 			match arg.as_str() {
-				"-i" | "--input" => {
-					let item = args_iter.next();
-					if item.is_none() {
-						exit_with_error(Some(format!("Expected an argument after \"{arg}\"")), Some(ErrorStages::Cli), 1);
-					} else {
-						settings.input = item.unwrap();
-					}
+				"-i" | "--input" => match args_iter.next() {
+					Some(item) => settings.input = item,
+					None => return Err(Error::new(ErrorStages::Cli, format!("Expected an argument after \"{arg}\""))),
+				},
+				"-o" | "--output" => match args_iter.next() {
+					Some(item) => settings.output = item,
+					None => return Err(Error::new(ErrorStages::Cli, format!("Expected an argument after \"{arg}\""))),
+				},
+				"-c" | "--config" => match args_iter.next() {
+					Some(item) => settings.output_config = item,
+					None => return Err(Error::new(ErrorStages::Cli, format!("Expected an argument after \"{arg}\""))),
+				},
+				"--input-delimiter" => settings.input_delimiter = Some(parse_char(&arg, args_iter.next())?),
+				"--input-quote" => settings.input_quote = Some(parse_char(&arg, args_iter.next())?),
+				"--input-escape" => settings.input_escape = Some(parse_char(&arg, args_iter.next())?),
+				"--output-delimiter" => settings.output_delimiter = Some(parse_char(&arg, args_iter.next())?),
+				"--output-quote" => settings.output_quote = Some(parse_char(&arg, args_iter.next())?),
+				"--output-escape" => settings.output_escape = Some(parse_char(&arg, args_iter.next())?),
+				"--output-line-terminator" => match args_iter.next().as_deref() {
+					Some("lf" | "LF") => settings.output_line_terminator = Some(String::from("\n")),
+					Some("crlf" | "CRLF") => settings.output_line_terminator = Some(String::from("\r\n")),
+					Some(item) => {
+						return Err(Error::new(
+							ErrorStages::Cli,
+							format!("The line terminator must be \"lf\" or \"crlf\", was \"{item}\""),
+						))
+					},
+					None => return Err(Error::new(ErrorStages::Cli, format!("Expected an argument after \"{arg}\""))),
+				},
+				"--template" => match args_iter.next() {
+					Some(item) => settings.template = Some(item),
+					None => return Err(Error::new(ErrorStages::Cli, format!("Expected an argument after \"{arg}\""))),
+				},
+				"--force" => settings.force = true,
+				"-w" | "--where" => match args_iter.next() {
+					Some(item) => settings.filter = Some(item),
+					None => return Err(Error::new(ErrorStages::Cli, format!("Expected an argument after \"{arg}\""))),
+				},
+				"-t" | "--threads" => match args_iter.next().map(|item| item.parse::<usize>()) {
+					Some(Ok(threads)) if threads > 0 => settings.threads = Some(threads),
+					Some(_) => return Err(Error::new(ErrorStages::Cli, format!("Expected a positive number after \"{arg}\""))),
+					None => return Err(Error::new(ErrorStages::Cli, format!("Expected an argument after \"{arg}\""))),
+				},
+				"--dedupe-by" => match args_iter.next() {
+					Some(item) => settings.dedupe_by = Some(item),
+					None => return Err(Error::new(ErrorStages::Cli, format!("Expected an argument after \"{arg}\""))),
 				},
-				"-o" | "--output" => {
-					let item = args_iter.next();
-					if item.is_none() {
-						exit_with_error(Some(format!("Expected an argument after \"{arg}\"")), Some(ErrorStages::Cli), 1);
-					} else {
-						settings.output = item.unwrap();
-					}
+				"--dedupe-keep" => match args_iter.next().as_deref() {
+					Some("first") => settings.dedupe_keep = Keep::First,
+					Some("last") => settings.dedupe_keep = Keep::Last,
+					Some(item) => {
+						return Err(Error::new(
+							ErrorStages::Cli,
+							format!("The lines to keep must be \"first\" or \"last\", was \"{item}\""),
+						))
+					},
+					None => return Err(Error::new(ErrorStages::Cli, format!("Expected an argument after \"{arg}\""))),
 				},
-				"-c" | "--config" => {
-					let item = args_iter.next();
-					if item.is_none() {
-						exit_with_error(Some(format!("Expected an argument after \"{arg}\"")), Some(ErrorStages::Cli), 1);
-					} else {
-						settings.output_config = item.unwrap();
-					}
+				"--dedupe-memory" => match args_iter.next().map(|item| item.parse::<usize>()) {
+					Some(Ok(megabytes)) => settings.dedupe_memory = Some(megabytes),
+					Some(_) => return Err(Error::new(ErrorStages::Cli, format!("Expected a number after \"{arg}\""))),
+					None => return Err(Error::new(ErrorStages::Cli, format!("Expected an argument after \"{arg}\""))),
+				},
+				"--sort-by" => match args_iter.next() {
+					Some(item) => settings.sort_by = Some(item),
+					None => return Err(Error::new(ErrorStages::Cli, format!("Expected an argument after \"{arg}\""))),
+				},
+				"--sort-memory" => match args_iter.next().map(|item| item.parse::<usize>()) {
+					Some(Ok(megabytes)) => settings.sort_memory = Some(megabytes),
+					Some(_) => return Err(Error::new(ErrorStages::Cli, format!("Expected a number after \"{arg}\""))),
+					None => return Err(Error::new(ErrorStages::Cli, format!("Expected an argument after \"{arg}\""))),
+				},
+				"--explain" => match args_iter.next().map(|item| item.parse::<usize>()) {
+					Some(Ok(row)) if row > 1 => settings.explain = Some(row),
+					Some(_) => {
+						return Err(Error::new(
+							ErrorStages::Cli,
+							format!("Expected a row number of 2 or higher after \"{arg}\", row 1 is the heading"),
+						))
+					},
+					None => return Err(Error::new(ErrorStages::Cli, format!("Expected an argument after \"{arg}\""))),
 				},
 				"-v" | "-V" | "--version" => {
 					settings.version = true;
@@ -85,19 +201,47 @@ impl Settings {
 			}
 		}
 
-		if settings.input.is_empty() && !settings.version && !settings.help {
-			exit_with_error(Some(format!("Missing parameter  \"input\"\n{}", usage())), Some(ErrorStages::Cli), 1);
+		let is_check = settings.command == Command::Check;
+		if settings.input.is_empty() && !is_check && !settings.version && !settings.help {
+			return Err(Error::new(ErrorStages::Cli, format!("Missing parameter  \"input\"\n{}", usage())));
+		}
+
+		if settings.output.is_empty() && !is_check && settings.explain.is_none() && !settings.version && !settings.help {
+			return Err(Error::new(ErrorStages::Cli, format!("Missing parameter  \"output\"\n{}", usage())));
 		}
 
-		if settings.output.is_empty() && !settings.version && !settings.help {
-			exit_with_error(Some(format!("Missing parameter  \"output\"\n{}", usage())), Some(ErrorStages::Cli), 1);
+		if settings.output_config.is_empty() && settings.command != Command::Init && !settings.version && !settings.help {
+			return Err(Error::new(ErrorStages::Cli, format!("Missing parameter  \"config\"\n{}", usage())));
 		}
 
-		if settings.output_config.is_empty() && !settings.version && !settings.help {
-			exit_with_error(Some(format!("Missing parameter  \"config\"\n{}", usage())), Some(ErrorStages::Cli), 1);
+		Ok(settings)
+	}
+
+	/// The [Dialect] of the input file.
+	/// The delimiter is detected from the sample, usually the start of the file, unless it was given via the CLI.
+	/// The detection skips over the quotes and escapes given via the CLI.
+	pub fn input_dialect(&self, sample: &str) -> Dialect {
+		let quote = self.input_quote.unwrap_or(Dialect::default().quote);
+		match self.input_delimiter {
+			Some(delimiter) => Dialect {
+				delimiter,
+				quote,
+				escape: self.input_escape,
+				..Dialect::default()
+			},
+			None => Dialect::detect_with(sample, quote, self.input_escape),
 		}
+	}
 
-		settings
+	/// The [Dialect] of the output file
+	pub fn output_dialect(&self) -> Dialect {
+		let default = Dialect::default();
+		Dialect {
+			delimiter: self.output_delimiter.unwrap_or(default.delimiter),
+			quote: self.output_quote.unwrap_or(default.quote),
+			escape: self.output_escape,
+			line_terminator: self.output_line_terminator.clone().unwrap_or(default.line_terminator),
+		}
 	}
 }
 
@@ -116,13 +260,14 @@ mod tests {
 				String::from("-c"),
 				String::from("config_file.csv"),
 			]),
-			Settings {
+			Ok(Settings {
 				input: String::from("input_file.csv"),
 				output: String::from("output_file.csv"),
 				output_config: String::from("config_file.csv"),
 				version: false,
 				help: false,
-			}
+				..Default::default()
+			})
 		);
 	}
 
@@ -137,75 +282,320 @@ mod tests {
 				String::from("--config"),
 				String::from("config_file.csv"),
 			]),
-			Settings {
+			Ok(Settings {
 				input: String::from("input_file.csv"),
 				output: String::from("output_file.csv"),
 				output_config: String::from("config_file.csv"),
 				version: false,
 				help: false,
-			}
+				..Default::default()
+			})
 		);
 	}
 
 	#[test]
-	#[should_panic]
 	fn missing_input_shortcut_test() {
-		Settings::new(vec![
+		assert!(Settings::new(vec![
 			String::from("-o"),
 			String::from("output_file.csv"),
 			String::from("-c"),
 			String::from("config_file.csv"),
-		]);
+		])
+		.is_err());
 	}
 
 	#[test]
-	#[should_panic]
 	fn missing_input_longform_test() {
-		Settings::new(vec![
+		assert!(Settings::new(vec![
 			String::from("--output"),
 			String::from("output_file.csv"),
 			String::from("--config"),
 			String::from("config_file.csv"),
-		]);
+		])
+		.is_err());
 	}
 
 	#[test]
-	#[should_panic]
 	fn missing_output_shortcut_test() {
-		Settings::new(vec![
+		assert!(Settings::new(vec![
 			String::from("-i"),
 			String::from("input_file.csv"),
 			String::from("-c"),
 			String::from("config_file.csv"),
-		]);
+		])
+		.is_err());
 	}
 
 	#[test]
-	#[should_panic]
 	fn missing_output_longform_test() {
-		Settings::new(vec![
+		assert!(Settings::new(vec![
 			String::from("--input"),
 			String::from("input_file.csv"),
 			String::from("--config"),
 			String::from("config_file.csv"),
-		]);
+		])
+		.is_err());
 	}
 
 	#[test]
-	#[should_panic]
 	fn missing_config_shortcut_test() {
-		Settings::new(vec![
+		assert!(Settings::new(vec![
 			String::from("-i"),
 			String::from("input_file.csv"),
 			String::from("-o"),
 			String::from("output_file.csv"),
-		]);
+		])
+		.is_err());
 	}
 
 	#[test]
-	#[should_panic]
 	fn missing_all_test() {
-		Settings::new(Vec::new());
+		assert!(Settings::new(Vec::new()).is_err());
+	}
+
+	#[test]
+	fn parsing_args_dialect_test() {
+		let settings = Settings::new(vec![
+			String::from("-i"),
+			String::from("input_file.csv"),
+			String::from("-o"),
+			String::from("output_file.csv"),
+			String::from("-c"),
+			String::from("config_file.csv"),
+			String::from("--input-delimiter"),
+			String::from("tab"),
+			String::from("--input-escape"),
+			String::from("\\"),
+			String::from("--output-delimiter"),
+			String::from(";"),
+			String::from("--output-line-terminator"),
+			String::from("crlf"),
+		])
+		.unwrap();
+
+		assert_eq!(
+			settings.input_dialect("a,b,c\n"),
+			Dialect {
+				delimiter: '\t',
+				quote: '"',
+				escape: Some('\\'),
+				line_terminator: String::from("\n"),
+			}
+		);
+		assert_eq!(
+			settings.output_dialect(),
+			Dialect {
+				delimiter: ';',
+				quote: '"',
+				escape: None,
+				line_terminator: String::from("\r\n"),
+			}
+		);
+
+		assert!(Settings::new(vec![String::from("--input-delimiter"), String::from(";;")]).is_err());
+		assert!(Settings::new(vec![String::from("--output-line-terminator"), String::from("cr")]).is_err());
+	}
+
+	#[test]
+	fn parsing_args_where_test() {
+		assert_eq!(
+			Settings::new(vec![
+				String::from("-i"),
+				String::from("input_file.csv"),
+				String::from("-o"),
+				String::from("output_file.csv"),
+				String::from("-c"),
+				String::from("config_file.csv"),
+				String::from("--where"),
+				String::from("<cell1> IS_NOT_EMPTY"),
+			])
+			.map(|settings| settings.filter),
+			Ok(Some(String::from("<cell1> IS_NOT_EMPTY")))
+		);
+
+		assert!(Settings::new(vec![String::from("-w")]).is_err());
+	}
+
+	#[test]
+	fn parsing_args_dedupe_test() {
+		let settings = Settings::new(vec![
+			String::from("-i"),
+			String::from("-"),
+			String::from("-o"),
+			String::from("-"),
+			String::from("-c"),
+			String::from("config_file.csv"),
+			String::from("--dedupe-by"),
+			String::from("<col 'Handle'>"),
+			String::from("--dedupe-keep"),
+			String::from("last"),
+			String::from("--dedupe-memory"),
+			String::from("64"),
+		])
+		.unwrap();
+		assert_eq!(settings.dedupe_by, Some(String::from("<col 'Handle'>")));
+		assert_eq!(settings.dedupe_keep, Keep::Last);
+		assert_eq!(settings.dedupe_memory, Some(64));
+
+		assert!(Settings::new(vec![String::from("--dedupe-by")]).is_err());
+		assert!(Settings::new(vec![String::from("--dedupe-keep"), String::from("middle")]).is_err());
+		assert!(Settings::new(vec![String::from("--dedupe-memory"), String::from("lots")]).is_err());
+	}
+
+	#[test]
+	fn parsing_args_sort_test() {
+		let settings = Settings::new(vec![
+			String::from("-i"),
+			String::from("-"),
+			String::from("-o"),
+			String::from("-"),
+			String::from("-c"),
+			String::from("config_file.csv"),
+			String::from("--sort-by"),
+			String::from("<cell1> <cell3> NUMERIC DESC"),
+			String::from("--sort-memory"),
+			String::from("0"),
+		])
+		.unwrap();
+		assert_eq!(settings.sort_by, Some(String::from("<cell1> <cell3> NUMERIC DESC")));
+		assert_eq!(settings.sort_memory, Some(0));
+
+		assert!(Settings::new(vec![String::from("--sort-by")]).is_err());
+		assert!(Settings::new(vec![String::from("--sort-memory"), String::from("-1")]).is_err());
+	}
+
+	#[test]
+	fn parsing_args_threads_test() {
+		assert_eq!(
+			Settings::new(vec![
+				String::from("-i"),
+				String::from("-"),
+				String::from("-o"),
+				String::from("-"),
+				String::from("-c"),
+				String::from("config_file.csv"),
+				String::from("--threads"),
+				String::from("8"),
+			])
+			.map(|settings| settings.threads),
+			Ok(Some(8))
+		);
+
+		assert!(Settings::new(vec![String::from("-t"), String::from("0")]).is_err());
+		assert!(Settings::new(vec![String::from("-t"), String::from("many")]).is_err());
+		assert!(Settings::new(vec![String::from("-t")]).is_err());
+	}
+
+	#[test]
+	fn parsing_args_check_test() {
+		assert_eq!(
+			Settings::new(vec![
+				String::from("check"),
+				String::from("-c"),
+				String::from("config_file.csv")
+			]),
+			Ok(Settings {
+				command: Command::Check,
+				output_config: String::from("config_file.csv"),
+				..Default::default()
+			})
+		);
+		assert_eq!(
+			Settings::new(vec![
+				String::from("check"),
+				String::from("-c"),
+				String::from("config_file.csv"),
+				String::from("-i"),
+				String::from("sample.csv"),
+			])
+			.map(|settings| (settings.command, settings.input)),
+			Ok((Command::Check, String::from("sample.csv")))
+		);
+
+		assert!(Settings::new(vec![String::from("check")]).is_err());
+		// the command has to come first
+		assert!(Settings::new(vec![
+			String::from("-c"),
+			String::from("config_file.csv"),
+			String::from("check")
+		])
+		.is_err());
+	}
+
+	#[test]
+	fn parsing_args_explain_test() {
+		assert_eq!(
+			Settings::new(vec![
+				String::from("-i"),
+				String::from("input_file.csv"),
+				String::from("-c"),
+				String::from("config_file.csv"),
+				String::from("--explain"),
+				String::from("3"),
+			])
+			.map(|settings| settings.explain),
+			Ok(Some(3))
+		);
+
+		assert!(Settings::new(vec![String::from("--explain"), String::from("1")]).is_err());
+		assert!(Settings::new(vec![String::from("--explain"), String::from("last")]).is_err());
+		assert!(Settings::new(vec![String::from("--explain")]).is_err());
+	}
+
+	#[test]
+	fn parsing_args_init_test() {
+		assert_eq!(
+			Settings::new(vec![
+				String::from("init"),
+				String::from("-i"),
+				String::from("input_file.csv"),
+				String::from("-o"),
+				String::from("config_file.csv"),
+				String::from("--template"),
+				String::from("template.csv"),
+			]),
+			Ok(Settings {
+				command: Command::Init,
+				input: String::from("input_file.csv"),
+				output: String::from("config_file.csv"),
+				template: Some(String::from("template.csv")),
+				..Default::default()
+			})
+		);
+		assert_eq!(
+			Settings::new(vec![
+				String::from("init"),
+				String::from("-i"),
+				String::from("input_file.csv"),
+				String::from("-o"),
+				String::from("config_file.csv"),
+				String::from("--force"),
+			])
+			.map(|settings| settings.force),
+			Ok(true)
+		);
+
+		assert!(Settings::new(vec![String::from("init"), String::from("-i"), String::from("input_file.csv")]).is_err());
+		assert!(Settings::new(vec![String::from("init"), String::from("--template")]).is_err());
+	}
+}
+
+/// Parse a single character argument, allowing `tab` and `\t` for tabs since they are hard to type in a terminal
+fn parse_char(arg: &str, value: Option<String>) -> Result<char, Error> {
+	let value = match value {
+		Some(value) => value,
+		None => return Err(Error::new(ErrorStages::Cli, format!("Expected an argument after \"{arg}\""))),
+	};
+
+	match value.as_str() {
+		"tab" | "\\t" => Ok('\t'),
+		_ => {
+			let mut chars = value.chars();
+			match (chars.next(), chars.next()) {
+				(Some(c), None) => Ok(c),
+				_ => Err(Error::new(ErrorStages::Cli, format!("Expected a single character after \"{arg}\", was \"{value}\""))),
+			}
+		},
 	}
 }
 
@@ -224,24 +614,73 @@ pub fn help() -> String {
 fn usage() -> String {
 	format!(
 		r#"
-Usage: {} [OPTIONS]
+Usage: {name} [OPTIONS]
+       {name} check -c <file> [-i <file>] [OPTIONS]
+       {name} init -i <file> -o <file> [--template <file>] [--force] [OPTIONS]
+
+Commands:
+  check
+        Report every error and warning of the config and exit with 1 if there are any.
+        With an input file the config is also checked against the headings of that file.
+  init
+        Write a starter config to the output file that maps every column of the input file.
+        With --template the config uses the headings of the template and matches input columns to them by name.
+        An existing output file is only overwritten with --force.
 
 Options:
   -i <file>, --input <file>
-        Specify the input file to process.
+        Specify the input file to process, use "-" to read from stdin.
   -o <file>, --output <file>
-        Specify the output file to write results to.
+        Specify the output file to write results to, use "-" to write to stdout.
   -c <file>, --config <file>
         Specify the config file to determine what the output format is.
+  --input-delimiter <char>
+        The delimiter of the input file, detected from the file if omitted. Use "tab" for tabs.
+  --input-quote <char>
+        The quote character of the input file, defaults to ".
+  --input-escape <char>
+        The escape character of the input file, quotes are expected to be doubled if omitted.
+  --output-delimiter <char>
+        The delimiter of the output file, defaults to ",". Use "tab" for tabs.
+  --output-quote <char>
+        The quote character of the output file, defaults to ".
+  --output-escape <char>
+        The escape character of the output file, quotes are doubled if omitted.
+  --output-line-terminator <lf|crlf>
+        The line terminator of the output file, defaults to "lf".
+  -w <condition>, --where <condition>
+        Only process input rows that meet the condition, e.g. "<cell1> IS_NOT_EMPTY AND <cell2> > 0".
+  -t <number>, --threads <number>
+        The number of threads converting rows, defaults to the number of CPU cores.
+  --dedupe-by <item>
+        Drop output lines whose key was already emitted, e.g. "<cell1>" or "<col 'Handle' LOWER_CASE>".
+        The item is evaluated against the output line so <cell1> is the first output column.
+  --dedupe-keep <first|last>
+        Which of the lines with the same key is kept, defaults to "first".
+  --dedupe-memory <megabytes>
+        The memory the keys may take before they are spilled to temporary files, defaults to 256.
+  --sort-by <columns>
+        Sort the output by its columns, each optionally followed by NUMERIC and ASC or DESC.
+        e.g. "<col 'Handle'> <col 'Image Position'> NUMERIC DESC". Lines with equal columns keep their order.
+  --sort-memory <megabytes>
+        The memory the output may take before sorted runs are written to temporary files, defaults to 256.
+  --template <file>
+        A CSV file whose heading row the init command maps the input columns onto.
+  --force
+        Let the init command overwrite an existing config.
+  --explain <row>
+        Trace how the config is applied to a single input row instead of converting, the heading is row 1.
+        Shows the value of every item, the result of every filter and condition and why lines are skipped.
   -v, -V, --version
         Display the program's version information.
   -h, --help
         Display this help message."#,
-		env!("CARGO_PKG_NAME")
+		name = env!("CARGO_PKG_NAME")
 	)
 }
 
 /// The stages of errors possible
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ErrorStages {
 	/// Error in the CLI module
 	Cli,
@@ -253,31 +692,115 @@ pub enum ErrorStages {
 	ConfigConditionEvaluating,
 	/// Error in the config parser while parsing filters
 	ConfigFilterParsing,
+	/// Error in the config parser while applying filters
+	ConfigFilterEvaluating,
+	/// Error in the config parser while parsing calculations
+	ConfigCalcParsing,
+	/// Error in the config parser while evaluating calculations
+	ConfigCalcEvaluating,
 	/// Error in the process module
 	Process,
 	/// Errors while ding I/O
 	Io,
 }
 
-/// Handle exist gracefully and display a nice message
-pub fn exit_with_error(error: Option<String>, stage: Option<ErrorStages>, code: i32) -> ! {
-	if error.is_some() && stage.is_some() {
-		let prefix = match stage.unwrap() {
-			ErrorStages::Cli => format!("{Yellow}CLI{Reset}:"),
-			ErrorStages::ConfigParsing => format!("{Yellow}Config{Reset}::{Yellow}Parsing{Reset}:"),
+/// The error returned by the library whenever parsing or processing fails
+///
+/// ```rust
+/// use csv_converter::cli::{Error, ErrorStages};
+///
+/// let error = Error::new(ErrorStages::ConfigParsing, String::from("Invalid cell number"))
+///     .at(2, 3)
+///     .with_text("<cellx>");
+/// assert_eq!(error.to_string(), String::from("Invalid cell number (config row 2, column 3: \"<cellx>\")"));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+	/// The stage in which the error occurred
+	pub stage: ErrorStages,
+	/// A human readable description of what went wrong
+	pub message: String,
+	/// The row within the config file this error belongs to, starting at 1 for the heading
+	pub row: Option<usize>,
+	/// The column within the config file this error belongs to, starting at 1
+	pub column: Option<usize>,
+	/// The original text of the offending config cell
+	pub text: Option<String>,
+}
+
+impl Error {
+	/// Create a new error without a location
+	pub fn new(stage: ErrorStages, message: String) -> Self {
+		Self {
+			stage,
+			message,
+			row: None,
+			column: None,
+			text: None,
+		}
+	}
+
+	/// Attach the config row and column to this error
+	pub fn at(mut self, row: usize, column: usize) -> Self {
+		self.row = Some(row);
+		self.column = Some(column);
+		self
+	}
+
+	/// Attach the original text of the config cell to this error
+	pub fn with_text(mut self, text: impl Into<String>) -> Self {
+		self.text = Some(text.into());
+		self
+	}
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.message)?;
+		match (self.row, self.column, &self.text) {
+			(Some(row), Some(column), Some(text)) => write!(f, " (config row {row}, column {column}: \"{text}\")"),
+			(Some(row), Some(column), None) => write!(f, " (config row {row}, column {column})"),
+			(_, _, Some(text)) => write!(f, " (\"{text}\")"),
+			_ => Ok(()),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+impl std::fmt::Display for ErrorStages {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ErrorStages::Cli => write!(f, "{Yellow}CLI{Reset}:"),
+			ErrorStages::ConfigParsing => write!(f, "{Yellow}Config{Reset}::{Yellow}Parsing{Reset}:"),
 			ErrorStages::ConfigConditionParsing => {
-				format!("{Yellow}Config{Reset}::{Yellow}Condition{Reset}::{Yellow}Parsing{Reset}:")
+				write!(f, "{Yellow}Config{Reset}::{Yellow}Condition{Reset}::{Yellow}Parsing{Reset}:")
 			},
 			ErrorStages::ConfigConditionEvaluating => {
-				format!("{Yellow}Config{Reset}::{Yellow}Condition{Reset}::{Yellow}Evaluating{Reset}:")
+				write!(f, "{Yellow}Config{Reset}::{Yellow}Condition{Reset}::{Yellow}Evaluating{Reset}:")
 			},
 			ErrorStages::ConfigFilterParsing => {
-				format!("{Yellow}Config{Reset}::{Yellow}Filter{Reset}::{Yellow}Parsing{Reset}:")
+				write!(f, "{Yellow}Config{Reset}::{Yellow}Filter{Reset}::{Yellow}Parsing{Reset}:")
+			},
+			ErrorStages::ConfigFilterEvaluating => {
+				write!(f, "{Yellow}Config{Reset}::{Yellow}Filter{Reset}::{Yellow}Evaluating{Reset}:")
+			},
+			ErrorStages::ConfigCalcParsing => {
+				write!(f, "{Yellow}Config{Reset}::{Yellow}Calc{Reset}::{Yellow}Parsing{Reset}:")
 			},
-			ErrorStages::Process => format!("{Yellow}Processing{Reset}:"),
-			ErrorStages::Io => format!("{Yellow}I/O{Reset}:"),
-		};
-		eprintln!(" {Red}ERROR{Reset} {Yellow}{prefix}{Reset} {}", error.unwrap());
+			ErrorStages::ConfigCalcEvaluating => {
+				write!(f, "{Yellow}Config{Reset}::{Yellow}Calc{Reset}::{Yellow}Evaluating{Reset}:")
+			},
+			ErrorStages::Process => write!(f, "{Yellow}Processing{Reset}:"),
+			ErrorStages::Io => write!(f, "{Yellow}I/O{Reset}:"),
+		}
+	}
+}
+
+/// Handle exist gracefully and display a nice message
+pub fn exit_with_error(error: Option<String>, stage: Option<ErrorStages>, code: i32) -> ! {
+	if let (Some(error), Some(stage)) = (error, stage) {
+		eprintln!(" {Red}ERROR{Reset} {stage} {error}");
 	}
 
 	if cfg!(test) {
@@ -1,14 +1,27 @@
 //! This module handles the parsing of the config CSV file
-use std::{borrow::Cow, io::BufRead};
+use std::{
+	borrow::Cow,
+	collections::HashMap,
+	fs::File,
+	io::{BufRead, BufReader},
+	path::Path,
+	sync::Arc,
+};
 
 use crate::{
-	cli::{exit_with_error, ErrorStages},
-	csv::{self, CsvParser},
+	cli::{Error, ErrorStages},
+	csv::{export, CsvParser, Dialect, Record},
+	date::{check_format, format_offset, parse_offset, DateTime},
+	process::{self, Recorder},
+	regex::Regex,
 };
 
 /// Conditions within the config file
 ///
-/// Syntax: `:IF <cell1> [condition] ('then-item') [ELSE ('else-item')]`
+/// Syntax: `:IF <cell1> [condition] ('then-item') [ELSE IF <cell2> [condition] ('then-item')] [ELSE ('else-item')]`
+///
+/// Conditions can be combined with `AND`, `OR` and `NOT` and grouped with parentheses:
+/// `:IF (<cell1> > 0 AND <cell2> IS_NOT_EMPTY) OR NOT <cell3> IS_NUMERIC ('then-item')`
 #[derive(Debug, PartialEq, Clone)]
 pub enum Condition {
 	/// The CSV cell would contain: `:IF <cell1> IS_EMPTY (<cell2>)` to be parsed as this condition
@@ -17,12 +30,18 @@ pub enum Condition {
 	IsNotEmpty(Box<Item>),
 	/// The CSV cell would contain: `:IF <cell1> IS_NUMERIC (<cell2>)` to be parsed as this condition
 	IsNumeric(Box<Item>),
+	/// The CSV cell would contain: `:IF <cell6> IS_FIRST_IN_GROUP (<cell2>)` to be parsed as this condition
+	IsFirstInGroup(Box<Item>),
+	/// The CSV cell would contain: `:IF <cell6> IS_SAME_AS_PREVIOUS (<cell2>)` to be parsed as this condition
+	IsSameAsPrevious(Box<Item>),
 	/// The CSV cell would contain: `:IF <cell1> STARTS_WITH|'start' (<cell2>)` to be parsed as this condition
 	StartesWith(String, Box<Item>),
 	/// The CSV cell would contain: `:IF <cell1> ENDS_WITH|'end' (<cell2>)` to be parsed as this condition
 	EndsWith(String, Box<Item>),
 	/// The CSV cell would contain: `:IF <cell1> CONTAINS|'middle' (<cell2>)` to be parsed as this condition
 	Contains(String, Box<Item>),
+	/// The CSV cell would contain: `:IF <cell1> MATCHES|'^SKU-\d{4}$' (<cell2>)` to be parsed as this condition
+	Matches(Regex, Box<Item>),
 	/// The CSV cell would contain: `:IF <cell1> == 'needle' (<cell2>)` to be parsed as this condition
 	Equals(Box<Item>, Box<Item>),
 	/// The CSV cell would contain: `:IF <cell1> != 'needle' (<cell2>)` to be parsed as this condition
@@ -33,430 +52,1045 @@ pub enum Condition {
 	LessThan(Box<Item>, Box<Item>),
 	/// The CSV cell would contain: `:IF <cell1> % 2 = 0 (<cell2>)` to be parsed as this condition
 	Modulo(f64, f64, Box<Item>),
+	/// The CSV cell would contain: `:IF <cell1> BEFORE '2026-10-17' (<cell2>)` to be parsed as this condition
+	Before(Box<Item>, Box<Item>),
+	/// The CSV cell would contain: `:IF <cell1> AFTER '2026-10-17T08:00:00Z' (<cell2>)` to be parsed as this condition
+	After(Box<Item>, Box<Item>),
+	/// The CSV cell would contain: `:IF <cell1> IS_EMPTY AND <cell2> IS_EMPTY (<cell3>)` to be parsed as this condition
+	And(Box<Condition>, Box<Condition>),
+	/// The CSV cell would contain: `:IF <cell1> IS_EMPTY OR <cell2> IS_EMPTY (<cell3>)` to be parsed as this condition
+	Or(Box<Condition>, Box<Condition>),
+	/// The CSV cell would contain: `:IF NOT <cell1> IS_EMPTY (<cell2>)` to be parsed as this condition
+	Not(Box<Condition>),
 }
 
+const CONDITION_USAGE: &str = "\n\
+The syntax of an IF condition is: :IF <cell[x]> [condition] ([then-item]) [ELSE IF ...] [ELSE ([else-item])]\n\
+Examples:\n\
+:IF <cell1> == 'blue' ('green')\n\
+:IF <cell1> == <cell42> (<cell2>)\n\
+:IF <cell1 UPPER_CASE> == 'blue' ('green')\n\
+:IF <col 'Color'> == 'blue' ('green')\n\
+:IF <cell1> == 'blue' ('green') ELSE ('red')\n\
+:IF <cell1> > 0 AND NOT (<cell2> IS_EMPTY OR <cell3> IS_EMPTY) ('in stock')\n\
+:IF <cell1 DATE|'%d/%m/%Y'|'iso'> BEFORE '2026-10-17' ('old')\n\
+:IF <cell1> MATCHES|'^SKU-\\d+$' ('valid')\n\
+:IF <cell6> IS_FIRST_IN_GROUP ('NEW') ELSE ('MERGE')\n\
+:IF <cell1> == 'blue' ('green') ELSE IF <cell1> == 'red' ('orange') ELSE (:IF <cell2> IS_EMPTY ('none') ELSE (<cell2>))";
+
 impl Condition {
 	/// The parser for conditions.
 	///
 	/// Note: that the condition_str argument already has its `:IF ` prefix stripped before being passed into this function
-	pub fn parse(condition_str: &str) -> Item {
-		let usage = "\n\
-		The syntax of an IF condition is: :IF <cell[x]> [condition] ([then-item]) [ELSE ([else-item])]\n\
-		Examples:\n\
-		:IF <cell1> == 'blue' ('green')\n\
-		:IF <cell1> == <cell42> (<cell2>)\n\
-		:IF <cell1 UPPER_CASE> == 'blue' ('green')\n\
-		:IF <cell1> == 'blue' ('green') ELSE ('red')";
-
-		if !condition_str.starts_with("<cell") {
-			exit_with_error(
-				Some(format!("Condition must start with <cell> item, was \"{condition_str}\"\n{usage}")),
-				Some(ErrorStages::ConfigConditionParsing),
-				1,
-			);
-		}
+	pub fn parse(condition_str: &str) -> Result<Item, Error> {
+		let mut parser = ConditionParser::new(condition_str);
+		let item = parser.parse_if()?;
+		parser.expect_end()?;
+		Ok(item)
+	}
 
-		if !condition_str.ends_with(")") {
-			exit_with_error(
-				Some(format!("Condition must end with a then-item or an else-item, was \"{condition_str}\"\n{usage}")),
-				Some(ErrorStages::ConfigConditionParsing),
-				1,
-			);
-		}
+	/// The parser for a condition without any then or else items like `<cell1> IS_EMPTY AND <cell2> > 5`
+	pub fn parse_expression(expression_str: &str) -> Result<Self, Error> {
+		let mut parser = ConditionParser::new(expression_str);
+		let condition = parser.parse_or()?;
+		parser.expect_end()?;
+		Ok(condition)
+	}
 
-		let mut condition = None;
-		let mut condition_item = None;
-		let mut then_item = None;
-		let mut else_item = None;
-		let mut in_quotes = false;
-		let mut in_condition = false;
-		let mut escaped = false;
-		let mut buffer = String::new();
+	/// The value of an item the condition looks at, errors of the interpreter belong to the condition
+	fn get_val_from_item<'a, R: Recorder>(
+		item: &'a Item,
+		row: &Row<'a>,
+		recorder: &mut R,
+	) -> Result<Cow<'a, str>, Error> {
+		process::get_value_with(item, row, recorder).map_err(|error| match error.stage {
+			ErrorStages::Process => Error {
+				stage: ErrorStages::ConfigConditionEvaluating,
+				..error
+			},
+			_ => error,
+		})
+	}
 
-		for c in condition_str.chars() {
-			match c {
-				'\'' => {
-					if !escaped {
-						in_quotes = !in_quotes;
-					} else {
-						buffer.push(c);
-						escaped = false;
-					}
-				},
-				'\\' => {
-					escaped = !escaped;
-				},
-				'>' => {
-					buffer.push(c);
-					if !in_quotes {
-						if condition_item.is_none() {
-							condition_item = Some(Item::parse(buffer.trim().to_string()));
-							in_condition = true;
-							buffer.clear();
-						} else if then_item.is_none() {
-							if !in_condition {
-								then_item = Some(Item::parse(buffer.trim().to_string()));
-								buffer.clear();
-							}
-						} else {
-							if !in_condition {
-								else_item = Some(Box::new(Item::parse(buffer.trim().to_string())));
-							} else {
-								buffer.push(c);
-							}
-							buffer.clear();
-						}
-					} else {
-						escaped = false;
-					}
-				},
-				'(' => {
-					if !in_quotes {
-						in_condition = false;
-						if condition.is_none() {
-							condition = Some(buffer.trim().to_string());
-						}
-						buffer.clear();
-					} else {
-						buffer.push(c);
-					}
-				},
-				')' => {
-					if !in_quotes {
-						if then_item.is_none() {
-							then_item = Some(Item::parse(buffer.trim().to_string()));
-						} else if else_item.is_none() && !buffer.is_empty() {
-							else_item = Some(Box::new(Item::parse(buffer.trim().to_string())));
-						}
-						buffer.clear();
-					} else {
-						buffer.push(c);
-					}
-				},
-				_ => {
-					escaped = false;
-					buffer.push(c);
-				},
-			}
-		}
+	/// Whether the item has the same value for the row before, the item is evaluated for both rows
+	fn is_same_as_previous<R: Recorder>(item: &Item, row: &Row, recorder: &mut R) -> Result<bool, Error> {
+		let Some(previous) = row.previous else {
+			return Ok(false);
+		};
+		let previous_row = Row {
+			cells: previous,
+			previous: None,
+			..*row
+		};
+		Ok(Self::get_val_from_item(item, row, recorder)? == Self::get_val_from_item(item, &previous_row, recorder)?)
+	}
 
-		if condition.is_none() {
-			exit_with_error(
-				Some(format!("Condition not found, was \"{condition_str}\"\n{usage}")),
-				Some(ErrorStages::ConfigConditionParsing),
-				1,
-			);
+	fn get_date_from_item<R: Recorder>(
+		item: &Item,
+		row: &Row,
+		name: &str,
+		side: &str,
+		recorder: &mut R,
+	) -> Result<DateTime, Error> {
+		let value = Self::get_val_from_item(item, row, recorder)?;
+		match DateTime::parse(&value, "iso") {
+			Some(date) => Ok(date),
+			None => Err(Error::new(
+				ErrorStages::ConfigConditionEvaluating,
+				format!(
+					"The {name} condition {side} date cannot be parsed, was \"{value}\", use the DATE filter to read dates \
+					that aren't written like \"2026-10-17\" or \"2026-10-17T08:00:00+02:00\""
+				),
+			)),
 		}
+	}
 
-		if condition_item.is_none() {
-			exit_with_error(
-				Some(format!("Condition item not found, was \"{condition_str}\"\n{usage}")),
-				Some(ErrorStages::ConfigConditionParsing),
-				1,
-			);
+	fn get_num_from_item<R: Recorder>(
+		item: &Item,
+		row: &Row,
+		name: &str,
+		side: &str,
+		recorder: &mut R,
+	) -> Result<f64, Error> {
+		let value = Self::get_val_from_item(item, row, recorder)?;
+		match value.parse::<f64>() {
+			Ok(num) => Ok(num),
+			Err(_) => Err(Error::new(
+				ErrorStages::ConfigConditionEvaluating,
+				format!("The {name} condition {side} number cannot be parsed, was \"{value}\""),
+			)),
 		}
+	}
 
-		if then_item.is_none() {
-			exit_with_error(
-				Some(format!("Then item not found, was \"{condition_str}\"\n{usage}")),
-				Some(ErrorStages::ConfigConditionParsing),
-				1,
-			);
+	/// All items this condition compares against
+	pub fn items(&self) -> Vec<&Item> {
+		match self {
+			Self::IsEmpty(item)
+			| Self::IsNotEmpty(item)
+			| Self::IsNumeric(item)
+			| Self::IsFirstInGroup(item)
+			| Self::IsSameAsPrevious(item)
+			| Self::StartesWith(_, item)
+			| Self::EndsWith(_, item)
+			| Self::Contains(_, item)
+			| Self::Matches(_, item)
+			| Self::Modulo(_, _, item) => vec![item],
+			Self::Equals(item_a, item_b)
+			| Self::NotEquals(item_a, item_b)
+			| Self::GreaterThan(item_a, item_b)
+			| Self::LessThan(item_a, item_b)
+			| Self::Before(item_a, item_b)
+			| Self::After(item_a, item_b) => vec![item_a, item_b],
+			Self::And(condition_a, condition_b) | Self::Or(condition_a, condition_b) => {
+				let mut items = condition_a.items();
+				items.extend(condition_b.items());
+				items
+			},
+			Self::Not(condition) => condition.items(),
 		}
+	}
 
-		let condition = match condition.unwrap().as_str() {
-			c if c.starts_with("IS_EMPTY") => Condition::IsEmpty(Box::new(condition_item.unwrap())),
-			c if c.starts_with("IS_NOT_EMPTY") => Condition::IsNotEmpty(Box::new(condition_item.unwrap())),
-			c if c.starts_with("IS_NUMERIC") => Condition::IsNumeric(Box::new(condition_item.unwrap())),
-			c if c.starts_with("STARTS_WITH") => {
-				let needle = c.splitn(2, '|').collect::<Vec<&str>>();
-				Condition::StartesWith(needle[1].to_string(), Box::new(condition_item.unwrap()))
-			},
-			c if c.starts_with("ENDS_WITH") => {
-				let needle = c.splitn(2, '|').collect::<Vec<&str>>();
-				Condition::EndsWith(needle[1].to_string(), Box::new(condition_item.unwrap()))
+	/// All items this condition compares against, for changing them in place
+	pub fn items_mut(&mut self) -> Vec<&mut Item> {
+		match self {
+			Self::IsEmpty(item)
+			| Self::IsNotEmpty(item)
+			| Self::IsNumeric(item)
+			| Self::IsFirstInGroup(item)
+			| Self::IsSameAsPrevious(item)
+			| Self::StartesWith(_, item)
+			| Self::EndsWith(_, item)
+			| Self::Contains(_, item)
+			| Self::Matches(_, item)
+			| Self::Modulo(_, _, item) => vec![item],
+			Self::Equals(item_a, item_b)
+			| Self::NotEquals(item_a, item_b)
+			| Self::GreaterThan(item_a, item_b)
+			| Self::LessThan(item_a, item_b)
+			| Self::Before(item_a, item_b)
+			| Self::After(item_a, item_b) => vec![item_a, item_b],
+			Self::And(condition_a, condition_b) | Self::Or(condition_a, condition_b) => {
+				let mut items = condition_a.items_mut();
+				items.extend(condition_b.items_mut());
+				items
 			},
-			c if c.starts_with("CONTAINS") => {
-				let needle = c.splitn(2, '|').collect::<Vec<&str>>();
-				Condition::Contains(needle[1].to_string(), Box::new(condition_item.unwrap()))
+			Self::Not(condition) => condition.items_mut(),
+		}
+	}
+
+	/// Replace every `<col 'heading'>` item of the condition with the [Item::Cell] of the matching input heading
+	pub fn resolve_headers(&mut self, headings: &[String]) -> Result<(), Error> {
+		for item in self.items_mut() {
+			item.resolve_headers(headings)?;
+		}
+		Ok(())
+	}
+
+	/// The function to check if the condition is met for a row.
+	pub fn is_true(&self, row: &Row) -> Result<bool, Error> {
+		self.is_true_with(row, &mut ())
+	}
+
+	/// Check the condition for a row and tell the recorder about its operands and its outcome
+	pub(crate) fn is_true_with<R: Recorder>(&self, row: &Row, recorder: &mut R) -> Result<bool, Error> {
+		recorder.enter();
+		let outcome = match self {
+			Self::IsEmpty(cell) => Ok(Self::get_val_from_item(cell, row, recorder)?.is_empty()),
+			Self::IsNotEmpty(cell) => Ok(!Self::get_val_from_item(cell, row, recorder)?.is_empty()),
+			Self::IsNumeric(cell) => Ok(Self::get_val_from_item(cell, row, recorder)?.parse::<f64>().is_ok()),
+			Self::IsFirstInGroup(cell) => Ok(!Self::is_same_as_previous(cell, row, recorder)?),
+			Self::IsSameAsPrevious(cell) => Self::is_same_as_previous(cell, row, recorder),
+			Self::StartesWith(needle, cell) => Ok(Self::get_val_from_item(cell, row, recorder)?.starts_with(needle)),
+			Self::EndsWith(needle, cell) => Ok(Self::get_val_from_item(cell, row, recorder)?.ends_with(needle)),
+			Self::Contains(needle, cell) => Ok(Self::get_val_from_item(cell, row, recorder)?.contains(needle)),
+			Self::Matches(regex, cell) => Ok(regex.is_match(&Self::get_val_from_item(cell, row, recorder)?)),
+			// the item is written before the value it's compared to
+			Self::Equals(cell_a, cell_b) => {
+				Ok(Self::get_val_from_item(cell_b, row, recorder)? == Self::get_val_from_item(cell_a, row, recorder)?)
 			},
-			c if c.starts_with("==") => {
-				let start = if &c.replace("  ", " ")[2..3] == " " { 3 } else { 2 };
-				Condition::Equals(Box::new(Item::parse(c[start..c.len()].to_string())), Box::new(condition_item.unwrap()))
+			Self::NotEquals(cell_a, cell_b) => {
+				Ok(Self::get_val_from_item(cell_b, row, recorder)? != Self::get_val_from_item(cell_a, row, recorder)?)
 			},
-			c if c.starts_with("!=") => {
-				let start = if &c.replace("  ", " ")[2..3] == " " { 3 } else { 2 };
-				Condition::NotEquals(Box::new(Item::parse(c[start..c.len()].to_string())), Box::new(condition_item.unwrap()))
+			Self::GreaterThan(cell_b, cell_a) => {
+				let num_a = Self::get_num_from_item(cell_a, row, "GREATER_THAN", "left", recorder)?;
+				let num_b = Self::get_num_from_item(cell_b, row, "GREATER_THAN", "right", recorder)?;
+				Ok(num_a > num_b)
 			},
-			c if c.starts_with(">") => {
-				let c = &c.trim()[1..];
-				Condition::GreaterThan(Box::new(Item::parse(c.trim().to_string())), Box::new(condition_item.unwrap()))
+			Self::LessThan(cell_b, cell_a) => {
+				let num_a = Self::get_num_from_item(cell_a, row, "LESS_THAN", "left", recorder)?;
+				let num_b = Self::get_num_from_item(cell_b, row, "LESS_THAN", "right", recorder)?;
+				Ok(num_a < num_b)
 			},
-			c if c.starts_with("<") => {
-				let c = &c.trim()[1..];
-				Condition::LessThan(Box::new(Item::parse(c.trim().to_string())), Box::new(condition_item.unwrap()))
+			Self::Modulo(divisor, remainder, cell) => {
+				let int = Self::get_num_from_item(cell, row, "modulo", "cell", recorder)?;
+				Ok(int % divisor == *remainder)
 			},
-			c if c.starts_with("%") => {
-				let modulo = c.replace("%", "").replace("  ", " ");
-				let ints = modulo.splitn(2, "=").collect::<Vec<&str>>();
-				if ints.len() != 2 {
-					exit_with_error(
-						Some(format!("The modulo filter is missing divisor or remainder, was \"{modulo}\" but should be \":IF <cell1> % 2 = 0\"")),
-						Some(ErrorStages::ConfigConditionParsing),
-						1,
-					);
-				}
-				let divisor = match ints[0].trim().parse::<f64>() {
-					Ok(value) => value,
-					Err(_) => {
-						exit_with_error(
-							Some(format!("The divisor of the modulo filter cannot be parsed, was \"{}\"", ints[0].trim())),
-							Some(ErrorStages::ConfigConditionParsing),
-							1,
-						);
-					},
-				};
-				let remainder = match ints[1].trim().parse::<f64>() {
-					Ok(value) => value,
-					Err(_) => {
-						exit_with_error(
-							Some(format!("The remainder of the modulo filter cannot be parsed, was \"{}\"", ints[1].trim())),
-							Some(ErrorStages::ConfigConditionParsing),
-							1,
-						);
-					},
+			Self::Before(cell_b, cell_a) | Self::After(cell_b, cell_a) => {
+				let name = if matches!(self, Self::Before(_, _)) {
+					"BEFORE"
+				} else {
+					"AFTER"
 				};
-				Condition::Modulo(divisor, remainder, Box::new(condition_item.unwrap()))
+				let date_a = Self::get_date_from_item(cell_a, row, name, "left", recorder)?;
+				let date_b = Self::get_date_from_item(cell_b, row, name, "right", recorder)?;
+				let (moment_a, moment_b) = ((date_a.timestamp(), date_a.nanosecond), (date_b.timestamp(), date_b.nanosecond));
+				Ok(if name == "BEFORE" {
+					moment_a < moment_b
+				} else {
+					moment_a > moment_b
+				})
 			},
-			c => {
-				exit_with_error(
-					Some(format!("If condition not recognized, was \"{c}\"\n{usage}")),
-					Some(ErrorStages::ConfigConditionParsing),
-					1,
-				);
+			Self::And(condition_a, condition_b) => match condition_a.is_true_with(row, recorder)? {
+				true => condition_b.is_true_with(row, recorder),
+				false => {
+					recorder.note(format_args!("{condition_b} is not evaluated"));
+					Ok(false)
+				},
 			},
-		};
+			Self::Or(condition_a, condition_b) => match condition_a.is_true_with(row, recorder)? {
+				true => {
+					recorder.note(format_args!("{condition_b} is not evaluated"));
+					Ok(true)
+				},
+				false => condition_b.is_true_with(row, recorder),
+			},
+			Self::Not(condition) => Ok(!condition.is_true_with(row, recorder)?),
+		}?;
+		recorder.leave();
+		recorder.note(format_args!("{self} -> {outcome}"));
+		Ok(outcome)
+	}
 
-		Item::If(condition, Box::new(then_item.unwrap()), else_item)
+	/// The function to apply the condition to a row.
+	pub fn run<'a>(
+		&self,
+		then_item: &'a Item,
+		else_item: Option<&'a Item>,
+		row: &Row<'a>,
+	) -> Result<Cow<'a, str>, Error> {
+		if self.is_true(row)? {
+			Self::get_val_from_item(then_item, row, &mut ())
+		} else if let Some(else_item) = else_item {
+			Self::get_val_from_item(else_item, row, &mut ())
+		} else {
+			Ok(Cow::Owned(String::from("")))
+		}
 	}
+}
 
-	fn get_val_from_item<'a>(item: &Item, input_line: &[String]) -> Cow<'a, str> {
-		match item.clone() {
-			Item::Value(v) => Cow::Owned(v),
-			Item::Cell(i, filters) => match input_line.get(i) {
-				Some(v) => {
-					let mut value: Cow<str> = Cow::Borrowed(v.as_str());
-					if let Some(filters) = filters {
-						for filter in filters {
-							value = filter.run(value);
-						}
-					}
-					Cow::Owned(value.to_string())
-				},
-				None => {
-					exit_with_error(
-						Some(format!("Cell not found \"<cell{i}>\"")),
-						Some(ErrorStages::ConfigConditionEvaluating),
-						1,
-					);
-				},
-			},
-			Item::If(_, _, _) => {
-				exit_with_error(
-					Some(String::from("Condition cannot contain a nested IF clause")),
-					Some(ErrorStages::ConfigConditionEvaluating),
-					1,
-				);
-			},
+/// Arithmetic on numeric items within the config file
+///
+/// Syntax: `:CALC <cell1> * 1.1 + <cell2>`
+///
+/// `*`, `/` and `%` bind stronger than `+` and `-`, parentheses group and `ROUND(x, decimals)`, `FLOOR(x)`,
+/// `CEIL(x)` and `ABS(x)` round or change the sign of their argument.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expression {
+	/// A number written into the config like `1.1`
+	Number(f64),
+	/// An item whose value has to be a number like `<cell1>`
+	Item(Box<Item>),
+	/// `<cell1> + <cell2>`
+	Add(Box<Expression>, Box<Expression>),
+	/// `<cell1> - <cell2>`
+	Subtract(Box<Expression>, Box<Expression>),
+	/// `<cell1> * <cell2>`
+	Multiply(Box<Expression>, Box<Expression>),
+	/// `<cell1> / <cell2>`
+	Divide(Box<Expression>, Box<Expression>),
+	/// `<cell1> % <cell2>`
+	Remainder(Box<Expression>, Box<Expression>),
+	/// `-<cell1>`
+	Negate(Box<Expression>),
+	/// `ROUND(<cell1>, 2)` rounds half away from zero, without decimals it rounds to a whole number
+	Round(Box<Expression>, i32),
+	/// `FLOOR(<cell1>)`
+	Floor(Box<Expression>),
+	/// `CEIL(<cell1>)`
+	Ceil(Box<Expression>),
+	/// `ABS(<cell1>)`
+	Abs(Box<Expression>),
+}
+
+const CALC_USAGE: &str = "\n\
+The syntax of a calculation is: :CALC [number or item] [+ - * / %] [number or item] ...\n\
+Examples:\n\
+:CALC <cell1> * 1.1\n\
+:CALC (<cell1> + <cell2>) / 2\n\
+:CALC ROUND(<col 'Price'> * 1.1, 2)\n\
+:CALC CEIL(<cell1> / 1000) - ABS(<cell2>)";
+
+impl Expression {
+	/// The parser for calculations.
+	///
+	/// Note: that the expression_str argument already has its `:CALC ` prefix stripped before being passed into this
+	/// function
+	pub fn parse(expression_str: &str) -> Result<Self, Error> {
+		let mut parser = ConditionParser::new(expression_str);
+		let expression = parser.parse_sum()?;
+		parser.skip_whitespace();
+		if parser.rest().is_empty() {
+			Ok(expression)
+		} else {
+			Err(parser.calc_error(&format!("Unexpected \"{}\" at the end of the calculation", parser.rest())))
 		}
 	}
 
-	/// The function to apply the condition to a row.
-	pub fn run<'a>(&self, then_item: &Item, else_item: &Option<Item>, input_line: &[String]) -> Cow<'a, str> {
+	/// All items this calculation reads
+	pub fn items(&self) -> Vec<&Item> {
 		match self {
-			Self::IsEmpty(cell) => {
-				let value = Self::get_val_from_item(cell, input_line);
-
-				if value.is_empty() {
-					Self::get_val_from_item(then_item, input_line)
-				} else if else_item.is_none() {
-					Cow::Owned(String::from(""))
-				} else {
-					Self::get_val_from_item(else_item.as_ref().unwrap(), input_line)
-				}
+			Self::Number(_) => Vec::new(),
+			Self::Item(item) => vec![item],
+			Self::Add(expression_a, expression_b)
+			| Self::Subtract(expression_a, expression_b)
+			| Self::Multiply(expression_a, expression_b)
+			| Self::Divide(expression_a, expression_b)
+			| Self::Remainder(expression_a, expression_b) => {
+				let mut items = expression_a.items();
+				items.extend(expression_b.items());
+				items
 			},
-			Self::IsNotEmpty(cell) => {
-				let value = Self::get_val_from_item(cell, input_line);
+			Self::Negate(expression)
+			| Self::Round(expression, _)
+			| Self::Floor(expression)
+			| Self::Ceil(expression)
+			| Self::Abs(expression) => expression.items(),
+		}
+	}
 
-				if !value.is_empty() {
-					Self::get_val_from_item(then_item, input_line)
-				} else if else_item.is_none() {
-					Cow::Owned(String::from(""))
-				} else {
-					Self::get_val_from_item(else_item.as_ref().unwrap(), input_line)
-				}
+	/// All items this calculation reads, for changing them in place
+	pub fn items_mut(&mut self) -> Vec<&mut Item> {
+		match self {
+			Self::Number(_) => Vec::new(),
+			Self::Item(item) => vec![item],
+			Self::Add(expression_a, expression_b)
+			| Self::Subtract(expression_a, expression_b)
+			| Self::Multiply(expression_a, expression_b)
+			| Self::Divide(expression_a, expression_b)
+			| Self::Remainder(expression_a, expression_b) => {
+				let mut items = expression_a.items_mut();
+				items.extend(expression_b.items_mut());
+				items
 			},
-			Self::IsNumeric(cell) => {
-				let value = Self::get_val_from_item(cell, input_line);
+			Self::Negate(expression)
+			| Self::Round(expression, _)
+			| Self::Floor(expression)
+			| Self::Ceil(expression)
+			| Self::Abs(expression) => expression.items_mut(),
+		}
+	}
 
-				match value.parse::<f64>() {
-					Ok(_) => Self::get_val_from_item(then_item, input_line),
-					Err(_) => {
-						if else_item.is_none() {
-							Cow::Owned(String::from(""))
-						} else {
-							Self::get_val_from_item(else_item.as_ref().unwrap(), input_line)
-						}
+	/// Calculate the result for a row, fails if an item isn't a number or there is a division by zero
+	pub fn evaluate(&self, row: &Row) -> Result<f64, Error> {
+		self.evaluate_with(row, &mut ())
+	}
+
+	/// Calculate the result for a row and tell the recorder how the values of its items come together
+	pub(crate) fn evaluate_with<R: Recorder>(&self, row: &Row, recorder: &mut R) -> Result<f64, Error> {
+		let number = match self {
+			Self::Number(number) => *number,
+			Self::Item(item) => {
+				let value = process::get_value_with(item, row, recorder).map_err(|error| Error {
+					stage: ErrorStages::ConfigCalcEvaluating,
+					..error
+				})?;
+				match value.trim().parse::<f64>() {
+					Ok(number) if number.is_finite() => number,
+					_ => {
+						return Err(Error::new(
+							ErrorStages::ConfigCalcEvaluating,
+							format!("The value of {item} is not a number, was \"{value}\""),
+						))
 					},
 				}
 			},
-			Self::StartesWith(needle, cell) => {
-				let value = Self::get_val_from_item(cell, input_line);
-
-				if value.starts_with(needle) {
-					Self::get_val_from_item(then_item, input_line)
-				} else if else_item.is_none() {
-					Cow::Owned(String::from(""))
-				} else {
-					Self::get_val_from_item(else_item.as_ref().unwrap(), input_line)
-				}
+			Self::Add(expression_a, expression_b) => {
+				expression_a.evaluate_with(row, recorder)? + expression_b.evaluate_with(row, recorder)?
 			},
-			Self::EndsWith(needle, cell) => {
-				let value = Self::get_val_from_item(cell, input_line);
-
-				if value.ends_with(needle) {
-					Self::get_val_from_item(then_item, input_line)
-				} else if else_item.is_none() {
-					Cow::Owned(String::from(""))
-				} else {
-					Self::get_val_from_item(else_item.as_ref().unwrap(), input_line)
-				}
+			Self::Subtract(expression_a, expression_b) => {
+				expression_a.evaluate_with(row, recorder)? - expression_b.evaluate_with(row, recorder)?
 			},
-			Self::Contains(needle, cell) => {
-				let value = Self::get_val_from_item(cell, input_line);
-
-				if value.contains(needle) {
-					Self::get_val_from_item(then_item, input_line)
-				} else if else_item.is_none() {
-					Cow::Owned(String::from(""))
-				} else {
-					Self::get_val_from_item(else_item.as_ref().unwrap(), input_line)
-				}
+			Self::Multiply(expression_a, expression_b) => {
+				expression_a.evaluate_with(row, recorder)? * expression_b.evaluate_with(row, recorder)?
 			},
-			Self::Equals(cell_a, cell_b) => {
-				let value_a = Self::get_val_from_item(cell_a, input_line);
-				let value_b = Self::get_val_from_item(cell_b, input_line);
-
-				if value_a == value_b {
-					Self::get_val_from_item(then_item, input_line)
-				} else if else_item.is_none() {
-					Cow::Owned(String::from(""))
-				} else {
-					Self::get_val_from_item(else_item.as_ref().unwrap(), input_line)
+			Self::Divide(expression_a, expression_b) | Self::Remainder(expression_a, expression_b) => {
+				let dividend = expression_a.evaluate_with(row, recorder)?;
+				let divisor = expression_b.evaluate_with(row, recorder)?;
+				if divisor == 0.0 {
+					return Err(Error::new(
+						ErrorStages::ConfigCalcEvaluating,
+						format!("Division by zero in \"{self}\", {expression_b} is 0"),
+					));
+				}
+				match self {
+					Self::Divide(_, _) => dividend / divisor,
+					_ => dividend % divisor,
 				}
 			},
-			Self::NotEquals(cell_a, cell_b) => {
-				let value_a = Self::get_val_from_item(cell_a, input_line);
-				let value_b = Self::get_val_from_item(cell_b, input_line);
+			Self::Negate(expression) => -expression.evaluate_with(row, recorder)?,
+			Self::Round(expression, decimals) => round(expression.evaluate_with(row, recorder)?, *decimals),
+			Self::Floor(expression) => expression.evaluate_with(row, recorder)?.floor(),
+			Self::Ceil(expression) => expression.evaluate_with(row, recorder)?.ceil(),
+			Self::Abs(expression) => expression.evaluate_with(row, recorder)?.abs(),
+		};
 
-				if value_a != value_b {
-					Self::get_val_from_item(then_item, input_line)
-				} else if else_item.is_none() {
-					Cow::Owned(String::from(""))
-				} else {
-					Self::get_val_from_item(else_item.as_ref().unwrap(), input_line)
-				}
+		if number.is_finite() {
+			Ok(number)
+		} else {
+			Err(Error::new(ErrorStages::ConfigCalcEvaluating, format!("The result of \"{self}\" is too large")))
+		}
+	}
+
+	/// Calculate the result for a row and write it without trailing zeros
+	pub fn run(&self, row: &Row) -> Result<String, Error> {
+		self.evaluate(row).map(format_number)
+	}
+
+	/// Calculate the result for a row like [Expression::run] and tell the recorder how it comes together
+	pub(crate) fn run_with<R: Recorder>(&self, row: &Row, recorder: &mut R) -> Result<String, Error> {
+		self.evaluate_with(row, recorder).map(format_number)
+	}
+
+	/// How strongly this expression binds, used to only write the parentheses that are needed
+	fn precedence(&self) -> u8 {
+		match self {
+			Self::Add(_, _) | Self::Subtract(_, _) => 1,
+			Self::Multiply(_, _) | Self::Divide(_, _) | Self::Remainder(_, _) => 2,
+			Self::Negate(_) => 3,
+			_ => 4,
+		}
+	}
+}
+
+/// Round half away from zero on the decimal digits as they are written so `ROUND(1.005, 2)` is `1.01` and not `1`
+fn round(number: f64, decimals: i32) -> f64 {
+	// shifting the decimal point in the text avoids the error of multiplying with a power of ten
+	let shift = |number: f64, by: i32| format!("{number}e{by}").parse::<f64>().unwrap_or(number);
+	shift(shift(number, decimals).round(), -decimals)
+}
+
+/// Write a result without floating point noise like `21.989000000000004` and without trailing zeros
+pub(crate) fn format_number(number: f64) -> String {
+	// the noise is in the last of the 17 significant digits of a double so 15 of them are exact
+	let rounded = format!("{number:.14e}").parse::<f64>().unwrap_or(number);
+	match rounded {
+		0.0 => String::from("0"),
+		_ => rounded.to_string(),
+	}
+}
+
+/// A small recursive descent parser for the `:IF` and `:CALC` grammars
+///
+/// ```text
+/// if        := or '(' branch ')' [ 'ELSE' ( 'IF' if | '(' branch ')' ) ]
+/// or        := and { 'OR' and }
+/// and       := not { 'AND' not }
+/// not       := 'NOT' not | '(' or ')' | predicate
+/// predicate := item operator
+///
+/// sum       := product { ( '+' | '-' ) product }
+/// product   := unary { ( '*' | '/' | '%' ) unary }
+/// unary     := '-' unary | atom
+/// atom      := number | item | '(' sum ')' | function '(' sum [ ',' integer ] ')'
+/// ```
+struct ConditionParser<'a> {
+	input: &'a str,
+	position: usize,
+}
+
+impl<'a> ConditionParser<'a> {
+	fn new(input: &'a str) -> Self {
+		Self { input, position: 0 }
+	}
+
+	fn error(&self, message: &str) -> Error {
+		Error::new(ErrorStages::ConfigConditionParsing, format!("{message}, was \"{}\"\n{CONDITION_USAGE}", self.input))
+	}
+
+	fn calc_error(&self, message: &str) -> Error {
+		Error::new(ErrorStages::ConfigCalcParsing, format!("{message}, was \"{}\"\n{CALC_USAGE}", self.input))
+	}
+
+	fn rest(&self) -> &'a str {
+		&self.input[self.position..]
+	}
+
+	fn skip_whitespace(&mut self) {
+		self.position = self.input.len() - self.rest().trim_start().len();
+	}
+
+	fn expect_end(&mut self) -> Result<(), Error> {
+		self.skip_whitespace();
+		if self.rest().is_empty() {
+			Ok(())
+		} else {
+			Err(self.error(&format!("Unexpected \"{}\" at the end of the condition", self.rest())))
+		}
+	}
+
+	/// Consume the given keyword if it's the next word
+	fn eat_keyword(&mut self, keyword: &str) -> bool {
+		self.skip_whitespace();
+		match self.rest().strip_prefix(keyword) {
+			Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) || rest.starts_with('(') => {
+				self.position += keyword.len();
+				true
 			},
-			Self::GreaterThan(cell_b, cell_a) => {
-				let value_a = Self::get_val_from_item(cell_a, input_line);
-				let value_b = Self::get_val_from_item(cell_b, input_line);
+			_ => false,
+		}
+	}
 
-				let num_a = match value_a.parse::<f64>() {
-					Ok(value) => value,
-					Err(_) => {
-						exit_with_error(
-							Some(format!("The GREATER_THAN condition left number cannot be parsed, was \"{value_a}\"")),
-							Some(ErrorStages::ConfigConditionEvaluating),
-							1,
-						);
-					},
-				};
+	/// Consume the given symbol if it's next
+	fn eat_symbol(&mut self, symbol: &str) -> bool {
+		self.skip_whitespace();
+		if self.rest().starts_with(symbol) {
+			self.position += symbol.len();
+			true
+		} else {
+			false
+		}
+	}
+
+	/// Read until one of the stop characters is found outside of quotes and returns what was read
+	fn read_until(&mut self, stop: impl Fn(char, usize) -> bool) -> &'a str {
+		let start = self.position;
+		let mut in_quotes = false;
+		let mut escaped = false;
+		let mut depth = 0;
+
+		for (i, c) in self.rest().char_indices() {
+			if escaped {
+				escaped = false;
+				continue;
+			}
+			match c {
+				'\\' => escaped = true,
+				'\'' => in_quotes = !in_quotes,
+				_ if !in_quotes && stop(c, depth) => {
+					self.position = start + i;
+					return &self.input[start..self.position];
+				},
+				'(' if !in_quotes => depth += 1,
+				')' if !in_quotes && depth > 0 => depth -= 1,
+				_ => {},
+			}
+		}
+
+		self.position = self.input.len();
+		&self.input[start..]
+	}
+
+	/// Read a `<cell1>`, `<prev1>`, `<col 'heading'>`, `<each>` or `<index>` item including its filters
+	fn read_item(&mut self) -> Result<Item, Error> {
+		self.skip_whitespace();
+		if !["<cell", "<prev", "<col ", "<each", "<index"].iter().any(|prefix| self.rest().starts_with(prefix)) {
+			return Err(self.error("Condition must start with a <cell>, <prev>, <col>, <each> or <index> item"));
+		}
+
+		let item_str = self.read_until(|c, _| c == '>');
+		if !self.eat_symbol(">") {
+			return Err(self.error(&format!("Item \"{item_str}\" is missing its closing \">\"")));
+		}
+		Item::parse(format!("{item_str}>"))
+	}
+
+	/// Read the value a condition compares against which can be an item, a quoted string or a single word
+	fn read_operand(&mut self) -> Result<Item, Error> {
+		self.skip_whitespace();
+		if self.rest().starts_with('<') {
+			self.read_item()
+		} else {
+			let word = self.read_until(|c, depth| depth == 0 && (c.is_whitespace() || c == '(' || c == ')'));
+			if word.is_empty() {
+				Err(self.error("Condition is missing the value to compare against"))
+			} else {
+				Ok(Item::Value(unquote(word)))
+			}
+		}
+	}
+
+	/// Read a number for the modulo condition
+	fn read_number(&mut self, name: &str) -> Result<f64, Error> {
+		self.skip_whitespace();
+		let number = self.read_until(|c, depth| depth == 0 && (c.is_whitespace() || c == '=' || c == '(' || c == ')'));
+		match number.trim().parse::<f64>() {
+			Ok(value) => Ok(value),
+			Err(_) => Err(Error::new(
+				ErrorStages::ConfigConditionParsing,
+				format!("The {name} of the modulo filter cannot be parsed, was \"{}\"", number.trim()),
+			)),
+		}
+	}
+
+	fn parse_if(&mut self) -> Result<Item, Error> {
+		let condition = self.parse_or()?;
+		let then_item = self.parse_branch()?;
+		let else_item = if self.eat_keyword("ELSE") {
+			if self.eat_keyword("IF") || self.eat_keyword(":IF") {
+				Some(Box::new(self.parse_if()?))
+			} else {
+				Some(Box::new(self.parse_branch()?))
+			}
+		} else {
+			None
+		};
+
+		Ok(Item::If(condition, Box::new(then_item), else_item))
+	}
+
+	/// A then or else item wrapped in parentheses which can be a value, an item or a nested `:IF`
+	fn parse_branch(&mut self) -> Result<Item, Error> {
+		if !self.eat_symbol("(") {
+			return Err(self.error("Condition must be followed by a then-item or an else-item"));
+		}
+		let branch = self.read_until(|c, depth| depth == 0 && c == ')');
+		if !self.eat_symbol(")") {
+			return Err(self.error(&format!("Item \"{branch}\" is missing its closing \")\"")));
+		}
+
+		let branch = branch.trim();
+		if let Some(condition) = branch.strip_prefix(":IF ") {
+			Condition::parse(condition)
+		} else if let Some(expression) = branch.strip_prefix(":CALC ") {
+			Ok(Item::Calc(Expression::parse(expression)?))
+		} else if branch.starts_with('<') {
+			Item::parse(branch.to_string())
+		} else {
+			Ok(Item::Value(unquote(branch)))
+		}
+	}
+
+	fn parse_or(&mut self) -> Result<Condition, Error> {
+		let mut condition = self.parse_and()?;
+		while self.eat_keyword("OR") {
+			condition = Condition::Or(Box::new(condition), Box::new(self.parse_and()?));
+		}
+		Ok(condition)
+	}
+
+	fn parse_and(&mut self) -> Result<Condition, Error> {
+		let mut condition = self.parse_not()?;
+		while self.eat_keyword("AND") {
+			condition = Condition::And(Box::new(condition), Box::new(self.parse_not()?));
+		}
+		Ok(condition)
+	}
+
+	fn parse_not(&mut self) -> Result<Condition, Error> {
+		if self.eat_keyword("NOT") {
+			Ok(Condition::Not(Box::new(self.parse_not()?)))
+		} else if self.eat_symbol("(") {
+			let condition = self.parse_or()?;
+			if !self.eat_symbol(")") {
+				return Err(self.error("Condition group is missing its closing \")\""));
+			}
+			Ok(condition)
+		} else {
+			self.parse_predicate()
+		}
+	}
+
+	fn parse_predicate(&mut self) -> Result<Condition, Error> {
+		let item = Box::new(self.read_item()?);
+
+		if self.eat_symbol("==") {
+			Ok(Condition::Equals(Box::new(self.read_operand()?), item))
+		} else if self.eat_symbol("!=") {
+			Ok(Condition::NotEquals(Box::new(self.read_operand()?), item))
+		} else if self.eat_symbol(">") {
+			Ok(Condition::GreaterThan(Box::new(self.read_operand()?), item))
+		} else if self.eat_symbol("<") {
+			Ok(Condition::LessThan(Box::new(self.read_operand()?), item))
+		} else if self.eat_symbol("%") {
+			let divisor = self.read_number("divisor")?;
+			if !self.eat_symbol("=") {
+				return Err(
+					self.error("The modulo filter is missing its remainder and should look like \":IF <cell1> % 2 = 0\""),
+				);
+			}
+			let remainder = self.read_number("remainder")?;
+			Ok(Condition::Modulo(divisor, remainder, item))
+		} else if self.eat_keyword("BEFORE") {
+			Ok(Condition::Before(Box::new(self.read_operand()?), item))
+		} else if self.eat_keyword("AFTER") {
+			Ok(Condition::After(Box::new(self.read_operand()?), item))
+		} else {
+			let word = self.read_until(|c, depth| depth == 0 && (c.is_whitespace() || c == '(' || c == ')'));
+			let (name, argument) = match word.split_once('|') {
+				Some((name, argument)) => (name, Some(unquote(argument))),
+				None => (word, None),
+			};
+
+			match (name, argument) {
+				("IS_EMPTY", None) => Ok(Condition::IsEmpty(item)),
+				("IS_NOT_EMPTY", None) => Ok(Condition::IsNotEmpty(item)),
+				("IS_NUMERIC", None) => Ok(Condition::IsNumeric(item)),
+				("IS_FIRST_IN_GROUP", None) => Ok(Condition::IsFirstInGroup(item)),
+				("IS_SAME_AS_PREVIOUS", None) => Ok(Condition::IsSameAsPrevious(item)),
+				("STARTS_WITH", Some(needle)) => Ok(Condition::StartesWith(needle, item)),
+				("ENDS_WITH", Some(needle)) => Ok(Condition::EndsWith(needle, item)),
+				("CONTAINS", Some(needle)) => Ok(Condition::Contains(needle, item)),
+				("MATCHES", Some(pattern)) => match Regex::new(&pattern) {
+					Ok(regex) => Ok(Condition::Matches(regex, item)),
+					Err(message) => Err(self.error(&message)),
+				},
+				_ => Err(self.error(&format!("If condition not recognized \"{word}\""))),
+			}
+		}
+	}
+
+	fn parse_sum(&mut self) -> Result<Expression, Error> {
+		let mut expression = self.parse_product()?;
+		loop {
+			if self.eat_symbol("+") {
+				expression = Expression::Add(Box::new(expression), Box::new(self.parse_product()?));
+			} else if self.eat_symbol("-") {
+				expression = Expression::Subtract(Box::new(expression), Box::new(self.parse_product()?));
+			} else {
+				return Ok(expression);
+			}
+		}
+	}
 
-				let num_b = match value_b.parse::<f64>() {
-					Ok(value) => value,
+	fn parse_product(&mut self) -> Result<Expression, Error> {
+		let mut expression = self.parse_unary()?;
+		loop {
+			if self.eat_symbol("*") {
+				expression = Expression::Multiply(Box::new(expression), Box::new(self.parse_unary()?));
+			} else if self.eat_symbol("/") {
+				expression = Expression::Divide(Box::new(expression), Box::new(self.parse_unary()?));
+			} else if self.eat_symbol("%") {
+				expression = Expression::Remainder(Box::new(expression), Box::new(self.parse_unary()?));
+			} else {
+				return Ok(expression);
+			}
+		}
+	}
+
+	fn parse_unary(&mut self) -> Result<Expression, Error> {
+		if self.eat_symbol("-") {
+			Ok(Expression::Negate(Box::new(self.parse_unary()?)))
+		} else {
+			self.parse_atom()
+		}
+	}
+
+	fn parse_atom(&mut self) -> Result<Expression, Error> {
+		if self.eat_symbol("(") {
+			let expression = self.parse_sum()?;
+			if !self.eat_symbol(")") {
+				return Err(self.calc_error("Calculation group is missing its closing \")\""));
+			}
+			return Ok(expression);
+		}
+
+		if ["<cell", "<prev", "<col ", "<each", "<index"].iter().any(|prefix| self.rest().starts_with(prefix)) {
+			let item_str = self.read_until(|c, _| c == '>');
+			if !self.eat_symbol(">") {
+				return Err(self.calc_error(&format!("Item \"{item_str}\" is missing its closing \">\"")));
+			}
+			return Ok(Expression::Item(Box::new(Item::parse(format!("{item_str}>"))?)));
+		}
+
+		for name in ["ROUND", "FLOOR", "CEIL", "ABS"] {
+			if !self.eat_keyword(name) {
+				continue;
+			}
+			if !self.eat_symbol("(") {
+				return Err(self.calc_error(&format!("Function {name} must be followed by \"(\"")));
+			}
+			let argument = Box::new(self.parse_sum()?);
+			let decimals = if name == "ROUND" && self.eat_symbol(",") {
+				self.skip_whitespace();
+				let decimals = self.read_until(|c, _| c.is_whitespace() || c == ')');
+				match decimals.parse::<i32>() {
+					Ok(decimals) => decimals,
 					Err(_) => {
-						exit_with_error(
-							Some(format!("The GREATER_THAN condition right number cannot be parsed, was \"{value_b}\"")),
-							Some(ErrorStages::ConfigConditionEvaluating),
-							1,
-						);
+						return Err(self.calc_error(&format!("The decimals of ROUND must be a whole number, was \"{decimals}\"")))
 					},
-				};
-
-				if num_a > num_b {
-					Self::get_val_from_item(then_item, input_line)
-				} else if else_item.is_none() {
-					Cow::Owned(String::from(""))
-				} else {
-					Self::get_val_from_item(else_item.as_ref().unwrap(), input_line)
 				}
+			} else {
+				0
+			};
+			if !self.eat_symbol(")") {
+				return Err(self.calc_error(&format!("Function {name} is missing its closing \")\"")));
+			}
+			return Ok(match name {
+				"ROUND" => Expression::Round(argument, decimals),
+				"FLOOR" => Expression::Floor(argument),
+				"CEIL" => Expression::Ceil(argument),
+				_ => Expression::Abs(argument),
+			});
+		}
+
+		let number = self.read_until(|c, _| !(c.is_ascii_digit() || c == '.'));
+		if number.is_empty() {
+			return Err(self.calc_error(&format!(
+				"Expected a number, an item or a function but found \"{}\"",
+				self.rest().chars().next().map(String::from).unwrap_or_default()
+			)));
+		}
+		match number.parse::<f64>() {
+			Ok(number) => Ok(Expression::Number(number)),
+			Err(_) => Err(self.calc_error(&format!("Invalid number \"{number}\""))),
+		}
+	}
+}
+
+/// Remove the single quotes around a value and resolve escaped quotes and backslashes.
+/// Any other backslash is kept so patterns like `'\d+'` reach the regex engine unchanged.
+fn unquote(input: &str) -> String {
+	let mut output = String::with_capacity(input.len());
+	let mut chars = input.chars().peekable();
+
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => match chars.next_if(|next| *next == '\'' || *next == '\\') {
+				Some(next) => output.push(next),
+				None => output.push(c),
 			},
-			Self::LessThan(cell_b, cell_a) => {
-				let value_a = Self::get_val_from_item(cell_a, input_line);
-				let value_b = Self::get_val_from_item(cell_b, input_line);
+			'\'' => {},
+			_ => output.push(c),
+		}
+	}
 
-				let num_a = match value_a.parse::<f64>() {
-					Ok(value) => value,
-					Err(_) => {
-						exit_with_error(
-							Some(format!("The LESS_THAN condition left number cannot be parsed, was \"{value_a}\"")),
-							Some(ErrorStages::ConfigConditionEvaluating),
-							1,
-						);
-					},
-				};
+	output
+}
 
-				let num_b = match value_b.parse::<f64>() {
-					Ok(value) => value,
-					Err(_) => {
-						exit_with_error(
-							Some(format!("The LESS_THAN condition right number cannot be parsed, was \"{value_b}\"")),
-							Some(ErrorStages::ConfigConditionEvaluating),
-							1,
-						);
-					},
-				};
+/// Split a string at a separator that isn't within single quotes, the parts keep their quotes
+fn split_unquoted(input: &str, separator: char) -> Vec<String> {
+	let mut parts = Vec::new();
+	let mut buffer = String::new();
+	let mut in_quotes = false;
+	let mut escaped = false;
+
+	for c in input.chars() {
+		match c {
+			_ if escaped => escaped = false,
+			'\\' => escaped = true,
+			'\'' => in_quotes = !in_quotes,
+			_ if c == separator && !in_quotes => {
+				parts.push(std::mem::take(&mut buffer));
+				continue;
+			},
+			_ => {},
+		}
+		buffer.push(c);
+	}
+	parts.push(buffer);
 
-				if num_a < num_b {
-					Self::get_val_from_item(then_item, input_line)
-				} else if else_item.is_none() {
-					Cow::Owned(String::from(""))
-				} else {
-					Self::get_val_from_item(else_item.as_ref().unwrap(), input_line)
+	parts
+}
+
+/// The name and the unquoted arguments of a filter like `REPLACE|'a|b'|'c'`
+fn split_arguments(filter: &str) -> Vec<String> {
+	split_unquoted(filter, '|').iter().map(|bit| unquote(bit)).collect()
+}
+
+/// The opposite of [unquote] so a value can be written back into a config
+fn quote(input: &str) -> String {
+	format!("'{}'", input.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Write filters the way they are written within an item, each prefixed with a space
+fn fmt_filters(filters: &Option<Vec<Filter>>, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	for filter in filters.iter().flatten() {
+		write!(f, " {filter}")?;
+	}
+	Ok(())
+}
+
+/// Conditions are written in the config syntax, compound conditions are wrapped in parentheses
+impl std::fmt::Display for Condition {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::IsEmpty(item) => write!(f, "{item} IS_EMPTY"),
+			Self::IsNotEmpty(item) => write!(f, "{item} IS_NOT_EMPTY"),
+			Self::IsNumeric(item) => write!(f, "{item} IS_NUMERIC"),
+			Self::IsFirstInGroup(item) => write!(f, "{item} IS_FIRST_IN_GROUP"),
+			Self::IsSameAsPrevious(item) => write!(f, "{item} IS_SAME_AS_PREVIOUS"),
+			Self::StartesWith(needle, item) => write!(f, "{item} STARTS_WITH|{}", quote(needle)),
+			Self::EndsWith(needle, item) => write!(f, "{item} ENDS_WITH|{}", quote(needle)),
+			Self::Contains(needle, item) => write!(f, "{item} CONTAINS|{}", quote(needle)),
+			Self::Matches(regex, item) => write!(f, "{item} MATCHES|{}", quote(regex.as_str())),
+			Self::Equals(operand, item) => write!(f, "{item} == {operand}"),
+			Self::NotEquals(operand, item) => write!(f, "{item} != {operand}"),
+			Self::GreaterThan(operand, item) => write!(f, "{item} > {operand}"),
+			Self::LessThan(operand, item) => write!(f, "{item} < {operand}"),
+			Self::Modulo(divisor, remainder, item) => write!(f, "{item} % {divisor} = {remainder}"),
+			Self::Before(operand, item) => write!(f, "{item} BEFORE {operand}"),
+			Self::After(operand, item) => write!(f, "{item} AFTER {operand}"),
+			Self::And(condition_a, condition_b) => write!(f, "({condition_a} AND {condition_b})"),
+			Self::Or(condition_a, condition_b) => write!(f, "({condition_a} OR {condition_b})"),
+			Self::Not(condition) => write!(f, "NOT {condition}"),
+		}
+	}
+}
+
+/// Calculations are written in the config syntax with only the parentheses needed to keep the order of operations
+impl std::fmt::Display for Expression {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let (operator, expression_a, expression_b) = match self {
+			Self::Number(number) => return write!(f, "{number}"),
+			Self::Item(item) => return write!(f, "{item}"),
+			Self::Negate(expression) if expression.precedence() < self.precedence() => return write!(f, "-({expression})"),
+			Self::Negate(expression) => return write!(f, "-{expression}"),
+			Self::Round(expression, 0) => return write!(f, "ROUND({expression})"),
+			Self::Round(expression, decimals) => return write!(f, "ROUND({expression}, {decimals})"),
+			Self::Floor(expression) => return write!(f, "FLOOR({expression})"),
+			Self::Ceil(expression) => return write!(f, "CEIL({expression})"),
+			Self::Abs(expression) => return write!(f, "ABS({expression})"),
+			Self::Add(expression_a, expression_b) => ("+", expression_a, expression_b),
+			Self::Subtract(expression_a, expression_b) => ("-", expression_a, expression_b),
+			Self::Multiply(expression_a, expression_b) => ("*", expression_a, expression_b),
+			Self::Divide(expression_a, expression_b) => ("/", expression_a, expression_b),
+			Self::Remainder(expression_a, expression_b) => ("%", expression_a, expression_b),
+		};
+
+		if expression_a.precedence() < self.precedence() {
+			write!(f, "({expression_a})")?;
+		} else {
+			write!(f, "{expression_a}")?;
+		}
+		write!(f, " {operator} ")?;
+		// operators are evaluated left to right so the right side needs parentheses for the same precedence as well
+		if expression_b.precedence() <= self.precedence() {
+			write!(f, "({expression_b})")
+		} else {
+			write!(f, "{expression_b}")
+		}
+	}
+}
+
+/// Filters are written in the config syntax like `REPLACE|' '|'-'`
+impl std::fmt::Display for Filter {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::UpperCase => write!(f, "UPPER_CASE"),
+			Self::LowerCase => write!(f, "LOWER_CASE"),
+			Self::Length => write!(f, "LENGTH"),
+			Self::Trim => write!(f, "TRIM"),
+			Self::TrimStart => write!(f, "TRIM_START"),
+			Self::TrimEnd => write!(f, "TRIM_END"),
+			Self::Replace(search, replacement) => write!(f, "REPLACE|{}|{}", quote(search), quote(replacement)),
+			Self::Append(suffix) => write!(f, "APPEND|{}", quote(suffix)),
+			Self::Prepend(prefix) => write!(f, "PREPEND|{}", quote(prefix)),
+			Self::Split(needle, index) => write!(f, "SPLIT|{}|{index}", quote(needle)),
+			Self::SubString(start, None) => write!(f, "SUB_STRING|{start}"),
+			Self::SubString(start, Some(length)) => write!(f, "SUB_STRING|{start}|{length}"),
+			Self::Lookup(lookup) => {
+				write!(f, "LOOKUP|{}|{}|{}", quote(&lookup.path), lookup.key_column + 1, lookup.value_column + 1)?;
+				match &lookup.default {
+					Some(default) => write!(f, "|{}", quote(default)),
+					None => Ok(()),
 				}
 			},
-			Self::Modulo(divisor, remainder, cell) => {
-				let value_str = Self::get_val_from_item(cell, input_line);
-
-				let int = match value_str.parse::<f64>() {
-					Ok(value) => value,
-					Err(_) => {
-						exit_with_error(
-							Some(format!("The modulo condition cell number cannot be parsed, was \"{value_str}\"")),
-							Some(ErrorStages::ConfigConditionEvaluating),
-							1,
-						);
-					},
-				};
+			Self::Handleize => write!(f, "HANDLEIZE"),
+			Self::Slugify(separator) => write!(f, "SLUGIFY|{}", quote(separator)),
+			Self::Round(decimals) => write!(f, "ROUND|{decimals}"),
+			Self::Fixed(decimals) => write!(f, "FIXED|{decimals}"),
+			Self::Ceil => write!(f, "CEIL"),
+			Self::Floor => write!(f, "FLOOR"),
+			Self::PadNumber(width) => write!(f, "PAD_NUMBER|{width}"),
+			Self::ParseNumber(locale) => write!(f, "PARSE_NUMBER|{}", quote(locale)),
+			Self::Date(input_format, output_format, offset) => {
+				write!(f, "DATE|{}|{}", quote(input_format), quote(output_format))?;
+				match offset {
+					Some(offset) => write!(f, "|{}", quote(&format_offset(*offset, ":"))),
+					None => Ok(()),
+				}
+			},
+			Self::RegexReplace(regex, replacement) => {
+				write!(f, "REGEX_REPLACE|{}|{}", quote(regex.as_str()), quote(replacement))
+			},
+			Self::RegexExtract(regex, group) => write!(f, "REGEX_EXTRACT|{}|{group}", quote(regex.as_str())),
+			Self::Unknown(name) => write!(f, "{name}"),
+		}
+	}
+}
 
-				if int % divisor == *remainder {
-					Self::get_val_from_item(then_item, input_line)
-				} else if else_item.is_none() {
-					Cow::Owned(String::from(""))
-				} else {
-					Self::get_val_from_item(else_item.as_ref().unwrap(), input_line)
+/// Items are written in the config syntax, values are quoted the way they are within conditions
+impl std::fmt::Display for Item {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Value(value) => write!(f, "{}", quote(value)),
+			Self::If(condition, then_item, else_item) => {
+				write!(f, ":IF {condition} ({then_item})")?;
+				match else_item {
+					Some(else_item) => write!(f, " ELSE ({else_item})"),
+					None => Ok(()),
 				}
 			},
+			Self::Cell(i, filters) => {
+				write!(f, "<cell{}", i + 1)?;
+				fmt_filters(filters, f)?;
+				write!(f, ">")
+			},
+			Self::Previous(i, filters) => {
+				write!(f, "<prev{}", i + 1)?;
+				fmt_filters(filters, f)?;
+				write!(f, ">")
+			},
+			Self::Column(name, filters) => {
+				write!(f, "<col {}", quote(name))?;
+				fmt_filters(filters, f)?;
+				write!(f, ">")
+			},
+			Self::Each(filters) => {
+				write!(f, "<each")?;
+				fmt_filters(filters, f)?;
+				write!(f, ">")
+			},
+			Self::Index(filters) => {
+				write!(f, "<index")?;
+				fmt_filters(filters, f)?;
+				write!(f, ">")
+			},
+			Self::Calc(expression) => write!(f, ":CALC {expression}"),
+			Self::Aggregate(aggregate, item) => match item {
+				Some(item) => write!(f, ":{aggregate} {item}"),
+				None => write!(f, ":{aggregate}"),
+			},
 		}
 	}
 }
@@ -488,48 +1122,127 @@ pub enum Filter {
 	Split(String, usize),
 	/// The CSV cell would contain a cell like this: `<cell1 SUB_STRING|3>` or `<cell1 SUB_STRING|3|2>`
 	SubString(usize, Option<usize>),
+	/// The CSV cell would contain a cell like this: `<cell1 LOOKUP|'colors.csv'|1|2>` or
+	/// `<cell1 LOOKUP|'colors.csv'|1|2|'default'>`
+	Lookup(Lookup),
+	/// The CSV cell would contain a cell like this: `<cell1 HANDLEIZE>` to get a Shopify handle like `mens-t-shirt`
+	Handleize,
+	/// The CSV cell would contain a cell like this: `<cell1 SLUGIFY>` or `<cell1 SLUGIFY|'_'>`
+	Slugify(String),
+	/// The CSV cell would contain a cell like this: `<cell1 ROUND>` or `<cell1 ROUND|2>`
+	Round(i32),
+	/// The CSV cell would contain a cell like this: `<cell1 FIXED|2>`
+	Fixed(usize),
+	/// The CSV cell would contain a cell like this: `<cell1 CEIL>`
+	Ceil,
+	/// The CSV cell would contain a cell like this: `<cell1 FLOOR>`
+	Floor,
+	/// The CSV cell would contain a cell like this: `<cell1 PAD_NUMBER|5>`
+	PadNumber(usize),
+	/// The CSV cell would contain a cell like this: `<cell1 PARSE_NUMBER>` or `<cell1 PARSE_NUMBER|'de'>`
+	ParseNumber(String),
+	/// The CSV cell would contain a cell like this: `<cell1 DATE|'%d/%m/%Y'|'%Y-%m-%d'>` or
+	/// `<cell1 DATE|'iso'|'%Y-%m-%d %H:%M'|'+02:00'>` with the input format, the output format and the UTC offset in
+	/// seconds to move the date to
+	Date(String, String, Option<i32>),
+	/// The CSV cell would contain a cell like this: `<cell1 REGEX_REPLACE|'\s+'|' '>` where `$1` within the
+	/// replacement is the text of the first group
+	RegexReplace(Regex, String),
+	/// The CSV cell would contain a cell like this: `<cell1 REGEX_EXTRACT|'(\d+) ?cm'|1>` or
+	/// `<cell1 REGEX_EXTRACT|'\d+'>` for the whole match, the cell is empty when nothing matches
+	RegexExtract(Regex, usize),
+	/// A filter we don't recognize which is ignored and reported as a warning by [OutputConfig::check]
+	Unknown(String),
 }
 
-impl Filter {
-	/// The parser for filters.
-	///
-	/// Note: that the filter_str argument already has its `<cellx ` prefix stripped before being passed into this function
-	pub fn parse(filter_str: &str) -> Vec<Self> {
-		let mut in_quotes = false;
-		let mut escaped = false;
-		let mut filters_str = Vec::new();
-		let mut buffer = String::new();
-		let mut filters = Vec::new();
+/// A lookup table from an auxiliary CSV file used by the `LOOKUP` filter
+#[derive(Debug, PartialEq, Clone)]
+pub struct Lookup {
+	/// The path of the CSV file
+	pub path: String,
+	/// The column that is matched against the cell value
+	pub key_column: usize,
+	/// The column that replaces the cell value
+	pub value_column: usize,
+	/// The value used when the key isn't in the table, the cell value is kept if this is not set
+	pub default: Option<String>,
+	/// The table itself which is shared between all filters that use the same file and columns
+	pub values: Arc<HashMap<String, String>>,
+}
 
-		for c in filter_str.trim().chars() {
-			match c {
-				'\'' => {
-					if !escaped {
-						in_quotes = !in_quotes;
-					} else {
-						buffer.push(c);
-						escaped = false;
-					}
-				},
-				'\\' => {
-					escaped = !escaped;
+impl Lookup {
+	/// Read the table from its file, a relative path is resolved against the given directory of the config file.
+	/// The first value of a key wins and the first row is treated like any other row.
+	pub fn load(&self, dir: &Path) -> Result<HashMap<String, String>, Error> {
+		let path = dir.join(&self.path);
+		let file = match File::open(&path) {
+			Ok(file) => file,
+			Err(error) => {
+				return Err(Error::new(
+					ErrorStages::Io,
+					format!("Could not open lookup file \"{}\": \"{error}\"", path.display()),
+				))
+			},
+		};
+		let mut reader = BufReader::new(file);
+		let dialect = match reader.fill_buf() {
+			Ok(sample) => Dialect::detect(&String::from_utf8_lossy(sample)),
+			Err(error) => {
+				return Err(Error::new(
+					ErrorStages::Io,
+					format!("Could not read lookup file \"{}\": \"{error}\"", path.display()),
+				))
+			},
+		};
+
+		let mut values = HashMap::new();
+		let mut csv_file = CsvParser::with_dialect(reader, dialect);
+		let mut record = Record::default();
+		for row_index in 0.. {
+			match csv_file.read_record(&mut record) {
+				Ok(true) => {},
+				Ok(false) => break,
+				Err(error) => {
+					return Err(Error::new(
+						ErrorStages::Io,
+						format!("Could not read lookup file \"{}\": \"{}\"", path.display(), error.message),
+					))
 				},
-				' ' => {
-					if !in_quotes {
-						filters_str.push(buffer.clone());
-						buffer.clear();
-						escaped = false;
-					} else {
-						buffer.push(c);
+			}
+			if record.len() == 1 && record.get(0) == Some("") {
+				continue;
+			}
+			match (record.get(self.key_column), record.get(self.value_column)) {
+				(Some(key), Some(value)) => {
+					if !values.contains_key(key) {
+						values.insert(key.to_string(), value.to_string());
 					}
 				},
 				_ => {
-					escaped = false;
-					buffer.push(c);
+					return Err(Error::new(
+						ErrorStages::ConfigParsing,
+						format!(
+							"Row {} of lookup file \"{}\" has no column {}",
+							row_index + 1,
+							path.display(),
+							self.key_column.max(self.value_column) + 1
+						),
+					))
 				},
 			}
 		}
-		filters_str.push(buffer.clone());
+
+		Ok(values)
+	}
+}
+
+impl Filter {
+	/// The parser for filters.
+	///
+	/// Note: that the filter_str argument already has its `<cellx ` prefix stripped before being passed into this function
+	pub fn parse(filter_str: &str) -> Result<Vec<Self>, Error> {
+		let filters_str = split_unquoted(filter_str.trim(), ' ');
+		let mut filters = Vec::new();
 
 		for filter in filters_str {
 			match filter.as_str() {
@@ -540,92 +1253,85 @@ impl Filter {
 				"TRIM_START" => filters.push(Filter::TrimStart),
 				"TRIM_END" => filters.push(Filter::TrimEnd),
 				f if f.starts_with("REPLACE") => {
-					let bits = f.split("|").collect::<Vec<&str>>();
+					let bits = split_arguments(f);
 					if bits.len() != 3 {
-						exit_with_error(
-							Some(format!(
+						return Err(Error::new(
+							ErrorStages::ConfigFilterParsing,
+							format!(
 								"Invalid REPLACE filter \"{filter}\"\n\
 								Usage: REPLACE|[string]|[string]\n\
 								Example:\n\
 								cell1 = \"My csv is great\"\n\
 								<cell1 REPLACE|'great'|'awesome'>\n\
 								cell1 = \"My csv is awesome\""
-							)),
-							Some(ErrorStages::ConfigFilterParsing),
-							1,
-						);
+							),
+						));
 					}
 					filters.push(Filter::Replace(bits[1].to_string(), bits[2].to_string()));
 				},
 				f if f.starts_with("APPEND") => {
-					let bits = f.split("|").collect::<Vec<&str>>();
+					let bits = split_arguments(f);
 					if bits.len() != 2 {
-						exit_with_error(
-							Some(format!(
+						return Err(Error::new(
+							ErrorStages::ConfigFilterParsing,
+							format!(
 								"Invalid APPEND filter \"{filter}\"\n\
 								Usage: REPLACE|[string]\n\
 								Example:\n\
 								cell1 = \"dark\"\n\
 								<cell1 APPEND|'-brown'>\n\
 								cell1 = \"dark-brown\""
-							)),
-							Some(ErrorStages::ConfigFilterParsing),
-							1,
-						);
+							),
+						));
 					}
 					filters.push(Filter::Append(bits[1].to_string()));
 				},
 				f if f.starts_with("PREPEND") => {
-					let bits = f.split("|").collect::<Vec<&str>>();
+					let bits = split_arguments(f);
 					if bits.len() != 2 {
-						exit_with_error(
-							Some(format!(
+						return Err(Error::new(
+							ErrorStages::ConfigFilterParsing,
+							format!(
 								"Invalid PREPEND filter \"{filter}\"\n\
 								Usage: PREPEND|[string]\n\
 								Example:\n\
 								cell1 = \"Bond\"\n\
 								<cell1 PREPEND|'James '>\n\
 								cell1 = \"James Bond\""
-							)),
-							Some(ErrorStages::ConfigFilterParsing),
-							1,
-						);
+							),
+						));
 					}
 					filters.push(Filter::Prepend(bits[1].to_string()));
 				},
 				f if f.starts_with("SPLIT") => {
-					let bits = f.split("|").collect::<Vec<&str>>();
+					let bits = split_arguments(f);
 					if bits.len() != 3 {
-						exit_with_error(
-							Some(format!(
+						return Err(Error::new(
+							ErrorStages::ConfigFilterParsing,
+							format!(
 								"Invalid SPLIT filter \"{filter}\"\n\
 								Usage: SPLIT|[string]|[number]\n\
 								Example:\n\
 								cell1 = \"one,two,three,four\"\n\
 								<cell1 SPLIT|','|3>\n\
-								cell1 = \"two\""
-							)),
-							Some(ErrorStages::ConfigFilterParsing),
-							1,
-						);
+								cell1 = \"two\""
+							),
+						));
 					}
 					let index = match bits[2].parse::<usize>() {
 						Ok(n) => n,
 						Err(_) => {
-							exit_with_error(
-								Some(format!("Invalid SPLIT index \"{}\"", bits[2])),
-								Some(ErrorStages::ConfigFilterParsing),
-								1,
-							);
+							return Err(Error::new(ErrorStages::ConfigFilterParsing, format!("Invalid SPLIT index \"{}\"", bits[2])));
 						},
 					};
 					filters.push(Filter::Split(bits[1].to_string(), index));
 				},
 				f if f.starts_with("SUB_STRING") => {
-					let bits = f.split("|").collect::<Vec<&str>>();
+					let bits = split_arguments(f);
 					if bits.len() != 2 && bits.len() != 3 {
-						exit_with_error(
-							Some(format!(
+						return Err(Error::new(
+							ErrorStages::ConfigFilterParsing,
+							format!(
 								"Invalid SUB_STRING filter \"{filter}\"\n\
 								Usage: SUB_STRING|[number]|[number optional]\n\
 								Example:\n\
@@ -636,30 +1342,26 @@ impl Filter {
 								cell1 = \"The Working Party\"\n\
 								<cell1 SPLIT|4|7>\n\
 								cell1 = \"Working\""
-							)),
-							Some(ErrorStages::ConfigFilterParsing),
-							1,
-						);
+							),
+						));
 					}
 					let start = match bits[1].parse::<usize>() {
 						Ok(n) => n,
 						Err(_) => {
-							exit_with_error(
-								Some(format!("Invalid SUB_STRING start \"{}\"", bits[1])),
-								Some(ErrorStages::ConfigFilterParsing),
-								1,
-							);
+							return Err(Error::new(
+								ErrorStages::ConfigFilterParsing,
+								format!("Invalid SUB_STRING start \"{}\"", bits[1]),
+							));
 						},
 					};
 					let end = if bits.len() == 3 {
 						match bits[2].parse::<usize>() {
 							Ok(n) => Some(n),
 							Err(_) => {
-								exit_with_error(
-									Some(format!("Invalid SUB_STRING start \"{}\"", bits[1])),
-									Some(ErrorStages::ConfigFilterParsing),
-									1,
-								);
+								return Err(Error::new(
+									ErrorStages::ConfigFilterParsing,
+									format!("Invalid SUB_STRING start \"{}\"", bits[1]),
+								));
 							},
 						}
 					} else {
@@ -667,18 +1369,255 @@ impl Filter {
 					};
 					filters.push(Filter::SubString(start, end));
 				},
-				_ => {
-					eprintln!(r#"OutputConfig error: Filter not recognized "{filter}" and will be ignored."#);
+				f if f.starts_with("LOOKUP") => {
+					let bits = split_arguments(f);
+					let columns = if bits.len() == 4 || bits.len() == 5 {
+						bits[2].parse::<usize>().ok().zip(bits[3].parse::<usize>().ok())
+					} else {
+						None
+					};
+					match columns {
+						Some((key_column, value_column)) if key_column > 0 && value_column > 0 => {
+							filters.push(Filter::Lookup(Lookup {
+								path: bits[1].to_string(),
+								key_column: key_column - 1,
+								value_column: value_column - 1,
+								default: bits.get(4).map(|default| default.to_string()),
+								values: Arc::new(HashMap::new()),
+							}));
+						},
+						_ => {
+							return Err(Error::new(
+								ErrorStages::ConfigFilterParsing,
+								format!(
+									"Invalid LOOKUP filter \"{filter}\"\n\
+									Usage: LOOKUP|[file]|[key column]|[value column]|[default optional]\n\
+									Example:\n\
+									colors.csv = \"BLK,Black\"\n\
+									cell1 = \"BLK\"\n\
+									<cell1 LOOKUP|'colors.csv'|1|2|'Unknown'>\n\
+									cell1 = \"Black\""
+								),
+							));
+						},
+					}
+				},
+				"HANDLEIZE" => filters.push(Filter::Handleize),
+				f if f.starts_with("SLUGIFY") => {
+					let bits = split_arguments(f);
+					match bits.len() {
+						1 => filters.push(Filter::Slugify(String::from("-"))),
+						2 => filters.push(Filter::Slugify(bits[1].to_string())),
+						_ => {
+							return Err(Error::new(
+								ErrorStages::ConfigFilterParsing,
+								format!(
+									"Invalid SLUGIFY filter \"{filter}\"\n\
+									Usage: SLUGIFY|[separator optional]\n\
+									Example:\n\
+									cell1 = \"Crème Brûlée (Large)\"\n\
+									<cell1 SLUGIFY|'_'>\n\
+									cell1 = \"creme_brulee_large\""
+								),
+							));
+						},
+					}
+				},
+				"CEIL" => filters.push(Filter::Ceil),
+				"FLOOR" => filters.push(Filter::Floor),
+				f if f.starts_with("ROUND") => {
+					let bits = split_arguments(f);
+					let decimals = match bits.len() {
+						1 => Some(0),
+						2 => bits[1].parse::<i32>().ok(),
+						_ => None,
+					};
+					match decimals {
+						Some(decimals) => filters.push(Filter::Round(decimals)),
+						None => {
+							return Err(Error::new(
+								ErrorStages::ConfigFilterParsing,
+								format!(
+									"Invalid ROUND filter \"{filter}\"\n\
+									Usage: ROUND|[number optional]\n\
+									Example:\n\
+									cell1 = \"12.499999\"\n\
+									<cell1 ROUND|2>\n\
+									cell1 = \"12.5\""
+								),
+							));
+						},
+					}
+				},
+				f if f.starts_with("FIXED") => {
+					let bits = split_arguments(f);
+					match bits.get(1).and_then(|decimals| decimals.parse::<usize>().ok()) {
+						Some(decimals) if bits.len() == 2 => filters.push(Filter::Fixed(decimals)),
+						_ => {
+							return Err(Error::new(
+								ErrorStages::ConfigFilterParsing,
+								format!(
+									"Invalid FIXED filter \"{filter}\"\n\
+									Usage: FIXED|[number]\n\
+									Example:\n\
+									cell1 = \"12.5\"\n\
+									<cell1 FIXED|2>\n\
+									cell1 = \"12.50\""
+								),
+							));
+						},
+					}
+				},
+				f if f.starts_with("PAD_NUMBER") => {
+					let bits = split_arguments(f);
+					match bits.get(1).and_then(|width| width.parse::<usize>().ok()) {
+						Some(width) if bits.len() == 2 => filters.push(Filter::PadNumber(width)),
+						_ => {
+							return Err(Error::new(
+								ErrorStages::ConfigFilterParsing,
+								format!(
+									"Invalid PAD_NUMBER filter \"{filter}\"\n\
+									Usage: PAD_NUMBER|[number]\n\
+									Example:\n\
+									cell1 = \"42\"\n\
+									<cell1 PAD_NUMBER|5>\n\
+									cell1 = \"00042\""
+								),
+							));
+						},
+					}
+				},
+				f if f.starts_with("PARSE_NUMBER") => {
+					let bits = split_arguments(f);
+					let locale = match bits.len() {
+						1 => Some("en"),
+						2 => Some(bits[1].as_str()),
+						_ => None,
+					};
+					match locale {
+						Some(locale) if number_separators(locale).is_some() => {
+							filters.push(Filter::ParseNumber(locale.to_string()))
+						},
+						_ => {
+							return Err(Error::new(
+								ErrorStages::ConfigFilterParsing,
+								format!(
+									"Invalid PARSE_NUMBER filter \"{filter}\"\n\
+									Usage: PARSE_NUMBER|['en', 'de', 'fr' or 'ch' optional]\n\
+									Example:\n\
+									cell1 = \"1.234,50 €\"\n\
+									<cell1 PARSE_NUMBER|'de'>\n\
+									cell1 = \"1234.5\""
+								),
+							));
+						},
+					}
+				},
+				f if f.starts_with("DATE") => {
+					let bits = split_arguments(f);
+					let usage = "Usage: DATE|[input format]|[output format]|[UTC offset optional]\n\
+						Example:\n\
+						cell1 = \"17/10/2026 08:00\"\n\
+						<cell1 DATE|'%d/%m/%Y %H:%M'|'%Y-%m-%d'>\n\
+						cell1 = \"2026-10-17\"";
+					if bits.len() != 3 && bits.len() != 4 {
+						return Err(Error::new(
+							ErrorStages::ConfigFilterParsing,
+							format!("Invalid DATE filter \"{filter}\"\n{usage}"),
+						));
+					}
+					for format in &bits[1..3] {
+						if let Err(specifier) = check_format(format) {
+							return Err(Error::new(
+								ErrorStages::ConfigFilterParsing,
+								format!("Unknown specifier \"{specifier}\" in the DATE filter \"{filter}\"\n{usage}"),
+							));
+						}
+					}
+					if bits[2] == "excel" {
+						return Err(Error::new(
+							ErrorStages::ConfigFilterParsing,
+							format!("Excel serial dates can only be read by the DATE filter \"{filter}\"\n{usage}"),
+						));
+					}
+					let offset = match bits.get(3) {
+						Some(offset) => match parse_offset(offset) {
+							Some(offset) => Some(offset),
+							None => {
+								return Err(Error::new(
+									ErrorStages::ConfigFilterParsing,
+									format!(
+										"Invalid UTC offset \"{offset}\" in the DATE filter \"{filter}\", \
+										use an offset like '+02:00', '-05:00' or 'Z'\n{usage}"
+									),
+								));
+							},
+						},
+						None => None,
+					};
+					filters.push(Filter::Date(bits[1].to_string(), bits[2].to_string(), offset));
+				},
+				f if f.starts_with("REGEX_REPLACE") => {
+					let bits = split_arguments(f);
+					if bits.len() != 3 {
+						return Err(Error::new(
+							ErrorStages::ConfigFilterParsing,
+							format!(
+								"Invalid REGEX_REPLACE filter \"{filter}\"\n\
+								Usage: REGEX_REPLACE|[pattern]|[string]\n\
+								Example:\n\
+								cell1 = \"Shirt  (XL)\"\n\
+								<cell1 REGEX_REPLACE|'\\s*\\((\\w+)\\)'|' - $1'>\n\
+								cell1 = \"Shirt - XL\""
+							),
+						));
+					}
+					let regex = Regex::new(&bits[1]).map_err(|message| Error::new(ErrorStages::ConfigFilterParsing, message))?;
+					filters.push(Filter::RegexReplace(regex, bits[2].to_string()));
+				},
+				f if f.starts_with("REGEX_EXTRACT") => {
+					let bits = split_arguments(f);
+					let usage = "Usage: REGEX_EXTRACT|[pattern]|[group number optional]\n\
+						Example:\n\
+						cell1 = \"Width 42 cm\"\n\
+						<cell1 REGEX_EXTRACT|'(\\d+) ?cm'|1>\n\
+						cell1 = \"42\"";
+					let group = match bits.len() {
+						2 => Some(0),
+						3 => bits[2].parse::<usize>().ok(),
+						_ => None,
+					};
+					let Some(group) = group else {
+						return Err(Error::new(
+							ErrorStages::ConfigFilterParsing,
+							format!("Invalid REGEX_EXTRACT filter \"{filter}\"\n{usage}"),
+						));
+					};
+					let regex = Regex::new(&bits[1]).map_err(|message| Error::new(ErrorStages::ConfigFilterParsing, message))?;
+					if group > regex.groups() {
+						return Err(Error::new(
+							ErrorStages::ConfigFilterParsing,
+							format!(
+								"The REGEX_EXTRACT filter \"{filter}\" asks for group {group} but its pattern only has {}\n{usage}",
+								regex.groups()
+							),
+						));
+					}
+					filters.push(Filter::RegexExtract(regex, group));
 				},
+				"" => {},
+				_ => filters.push(Filter::Unknown(filter)),
 			}
 		}
 
-		filters
+		Ok(filters)
 	}
 
 	/// The function to apply the filter to a cell.
-	pub fn run<'a>(&self, input: Cow<'a, str>) -> Cow<'a, str> {
-		match self {
+	///
+	/// Number filters leave empty cells empty and fail for anything else that isn't a number
+	pub fn run<'a>(&self, input: Cow<'a, str>) -> Result<Cow<'a, str>, Error> {
+		Ok(match self {
 			Self::UpperCase => Cow::Owned(input.to_uppercase()),
 			Self::LowerCase => Cow::Owned(input.to_lowercase()),
 			Self::Length => Cow::Owned(input.len().to_string()),
@@ -697,15 +1636,27 @@ impl Filter {
 				Cow::Owned(s)
 			},
 			Self::Split(needle, index) => Cow::Owned(input.split(needle).nth(*index).unwrap_or_default().to_string()),
+			Self::Lookup(lookup) => match lookup.values.get(input.as_ref()) {
+				Some(value) => Cow::Owned(value.clone()),
+				None => match &lookup.default {
+					Some(default) => Cow::Owned(default.clone()),
+					None => input,
+				},
+			},
+			Self::Unknown(_) => input,
+			Self::Handleize => Cow::Owned(slugify(&input, "-")),
+			Self::Slugify(separator) => Cow::Owned(slugify(&input, separator)),
+			Self::RegexReplace(regex, replacement) => Cow::Owned(regex.replace_all(&input, replacement)),
+			Self::RegexExtract(regex, group) => Cow::Owned(regex.extract(&input, *group).unwrap_or_default().to_string()),
 			Self::SubString(start, length) => {
 				let start_byte = match input.char_indices().nth(*start) {
 					Some((byte_idx, _)) => byte_idx,
-					None => return Cow::Owned(String::from("")),
+					None => return Ok(Cow::Owned(String::from(""))),
 				};
 				let end_byte = match *length {
 					Some(len) => {
 						if len == 0 {
-							return Cow::Owned(String::from(""));
+							return Ok(Cow::Owned(String::from("")));
 						} else {
 							match input.char_indices().nth(start + len) {
 								Some((byte_idx, _)) => byte_idx,
@@ -717,6 +1668,249 @@ impl Filter {
 				};
 				Cow::Owned(input[start_byte..end_byte].to_string())
 			},
+			Self::Round(_)
+			| Self::Fixed(_)
+			| Self::Ceil
+			| Self::Floor
+			| Self::PadNumber(_)
+			| Self::ParseNumber(_)
+			| Self::Date(_, _, _)
+				if input.trim().is_empty() =>
+			{
+				input
+			},
+			Self::Round(decimals) => Cow::Owned(format_number(round(self.number(&input)?, *decimals))),
+			Self::Fixed(decimals) => {
+				let number = round(self.number(&input)?, *decimals as i32);
+				// no "-0.00" for small negative numbers
+				Cow::Owned(format!("{:.*}", decimals, if number == 0.0 { 0.0 } else { number }))
+			},
+			Self::Ceil => Cow::Owned(format_number(self.number(&input)?.ceil())),
+			Self::Floor => Cow::Owned(format_number(self.number(&input)?.floor())),
+			Self::PadNumber(width) => {
+				let number = self.number(&input)?;
+				let number = match input.trim() {
+					plain if plain.chars().all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+')) => plain.to_string(),
+					_ => format_number(number),
+				};
+				let (sign, digits) = match number.strip_prefix('-') {
+					Some(digits) => ("-", digits),
+					None => ("", number.strip_prefix('+').unwrap_or(&number)),
+				};
+				let whole = digits.find('.').unwrap_or(digits.len());
+				Cow::Owned(format!("{sign}{}{digits}", "0".repeat(width.saturating_sub(whole))))
+			},
+			Self::ParseNumber(locale) => match parse_number(&input, locale) {
+				Some(number) => Cow::Owned(format_number(number)),
+				None => return Err(self.not_a_number(&input)),
+			},
+			Self::Date(input_format, output_format, offset) => match DateTime::parse(&input, input_format)
+				.and_then(|date| offset.map_or(Some(date), |offset| date.to_offset(offset)))
+			{
+				Some(date) => Cow::Owned(date.format(output_format)),
+				None => {
+					return Err(Error::new(
+						ErrorStages::ConfigFilterEvaluating,
+						format!("The {self} filter needs a date written like {}, was \"{input}\"", quote(input_format)),
+					))
+				},
+			},
+		})
+	}
+
+	/// The number a number filter works on
+	fn number(&self, input: &str) -> Result<f64, Error> {
+		match input.trim().parse::<f64>() {
+			Ok(number) if number.is_finite() => Ok(number),
+			_ => Err(self.not_a_number(input)),
+		}
+	}
+
+	fn not_a_number(&self, input: &str) -> Error {
+		let hint = match self {
+			Self::ParseNumber(_) => "",
+			_ => ", use PARSE_NUMBER first for numbers like \"1.234,50 €\"",
+		};
+		Error::new(ErrorStages::ConfigFilterEvaluating, format!("The {self} filter needs a number, was \"{input}\"{hint}"))
+	}
+}
+
+/// The thousands separators and the decimal separator of the locales `PARSE_NUMBER` supports
+///
+/// Whitespace is always allowed between the digits
+fn number_separators(locale: &str) -> Option<(&'static [char], char)> {
+	match locale {
+		"en" => Some((&[','], '.')),
+		"de" => Some((&['.'], ',')),
+		"fr" => Some((&[], ',')),
+		"ch" => Some((&['\'', '’'], '.')),
+		_ => None,
+	}
+}
+
+/// Read a number written for people like `$1,234.50`, `1.234,50 €` or `-12 kg` in the given locale
+fn parse_number(input: &str, locale: &str) -> Option<f64> {
+	let (thousands, decimal) = number_separators(locale)?;
+	let first = input.find(|c: char| c.is_ascii_digit())?;
+	let last = input.rfind(|c: char| c.is_ascii_digit())? + 1;
+	// a leading decimal separator like in ".5" belongs to the number
+	let first = match input[..first].strip_suffix(decimal) {
+		Some(prefix) => prefix.len(),
+		None => first,
+	};
+
+	let mut number = String::with_capacity(last - first + 1);
+	if is_minus_sign(&input[..first]) || input[last..].starts_with('-') {
+		number.push('-');
+	}
+	let mut decimals = false;
+	// the digits since the last thousands separator, a group that isn't three digits long means the number is written
+	// in another locale like "12.5" is for "de"
+	let mut group = None;
+	for c in input[first..last].chars() {
+		match c {
+			'0'..='9' => {
+				group = group.map(|digits| digits + 1);
+				number.push(c);
+			},
+			_ if c == decimal && !decimals && group.is_none_or(|digits| digits == 3) => {
+				decimals = true;
+				number.push('.');
+			},
+			// a thousands separator after the decimal one means the number is written in another locale
+			_ if (thousands.contains(&c) || c.is_whitespace()) && !decimals => {
+				if group.is_some_and(|digits| digits != 3) {
+					return None;
+				}
+				group = Some(0);
+			},
+			_ => return None,
+		}
+	}
+	if !decimals && group.is_some_and(|digits| digits != 3) {
+		return None;
+	}
+
+	number.parse::<f64>().ok()
+}
+
+/// Whether the text before a number ends in its minus sign like in "-12", "EUR -12" or "-$ 12"
+///
+/// Only a currency symbol and a space after it may come between the sign and the digits, a dash within a word like in
+/// "SKU-12" or on its own like in "Size - 5" isn't a sign.
+fn is_minus_sign(prefix: &str) -> bool {
+	let is_symbol = |c: char| !c.is_alphanumeric() && !c.is_whitespace() && c != '-';
+	let spaced = prefix.trim_end();
+	let unsigned = match spaced.trim_end_matches(is_symbol) {
+		symbols_removed if symbols_removed.len() < spaced.len() => symbols_removed,
+		_ => prefix,
+	};
+	unsigned.strip_suffix('-').is_some_and(|before| before.chars().next_back().is_none_or(char::is_whitespace))
+}
+
+/// Turn text into lower case words joined by the separator the way Shopify builds handles.
+///
+/// Latin letters with diacritics lose them, letters of other scripts like Cyrillic or Japanese are kept, quotes are
+/// dropped so "Men's" becomes `mens` and everything else that isn't a letter or digit separates words.
+fn slugify(input: &str, separator: &str) -> String {
+	let mut output = String::with_capacity(input.len());
+	let mut pending_separator = false;
+
+	for c in input.chars().flat_map(char::to_lowercase) {
+		let ascii = match c {
+			'a'..='z' | '0'..='9' => {
+				let mut buffer = [0; 4];
+				push_slug_word(&mut output, c.encode_utf8(&mut buffer), separator, &mut pending_separator);
+				continue;
+			},
+			// combining marks of decomposed letters like "e\u{301}"
+			'\u{300}'..='\u{36f}' | '\'' | '"' | '’' | '‘' | '`' | '´' => continue,
+			_ => transliterate(c),
+		};
+		match ascii {
+			Some(ascii) => push_slug_word(&mut output, ascii, separator, &mut pending_separator),
+			None if c.is_alphanumeric() => {
+				let mut buffer = [0; 4];
+				push_slug_word(&mut output, c.encode_utf8(&mut buffer), separator, &mut pending_separator);
+			},
+			None => pending_separator = true,
+		}
+	}
+
+	output
+}
+
+fn push_slug_word(output: &mut String, word: &str, separator: &str, pending_separator: &mut bool) {
+	if *pending_separator && !output.is_empty() {
+		output.push_str(separator);
+	}
+	*pending_separator = false;
+	output.push_str(word);
+}
+
+/// The ASCII letters for a lower case Latin letter with diacritics
+fn transliterate(c: char) -> Option<&'static str> {
+	Some(match c {
+		'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+		'æ' => "ae",
+		'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+		'ď' | 'đ' | 'ð' => "d",
+		'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+		'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+		'ĥ' | 'ħ' => "h",
+		'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+		'ĳ' => "ij",
+		'ĵ' => "j",
+		'ķ' => "k",
+		'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+		'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+		'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+		'œ' => "oe",
+		'ŕ' | 'ŗ' | 'ř' => "r",
+		'ś' | 'ŝ' | 'ş' | 'š' | 'ș' => "s",
+		'ß' => "ss",
+		'ţ' | 'ť' | 'ŧ' | 'ț' => "t",
+		'þ' => "th",
+		'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+		'ŵ' => "w",
+		'ý' | 'ÿ' | 'ŷ' => "y",
+		'ź' | 'ż' | 'ž' => "z",
+		_ => return None,
+	})
+}
+
+/// The names of the aggregates a `:GROUP_BY` config can use
+const AGGREGATES: [&str; 7] = ["SUM", "MIN", "MAX", "COUNT", "FIRST", "LAST", "JOIN"];
+
+/// How the values of all input rows of a group are combined into one by [Item::Aggregate]
+#[derive(Debug, PartialEq, Clone)]
+pub enum Aggregate {
+	/// The sum of all numeric values, empty values are ignored: `:SUM <cell3>`
+	Sum,
+	/// The smallest numeric value, empty values are ignored: `:MIN <cell3>`
+	Min,
+	/// The largest numeric value, empty values are ignored: `:MAX <cell3>`
+	Max,
+	/// The number of rows in the group `:COUNT` or the number of non-empty values `:COUNT <cell3>`
+	Count,
+	/// The value of the first row of the group: `:FIRST <cell3>`
+	First,
+	/// The value of the last row of the group: `:LAST <cell3>`
+	Last,
+	/// All non-empty values joined by a separator: `:JOIN|',' <cell3>`
+	Join(String),
+}
+
+impl std::fmt::Display for Aggregate {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Sum => write!(f, "SUM"),
+			Self::Min => write!(f, "MIN"),
+			Self::Max => write!(f, "MAX"),
+			Self::Count => write!(f, "COUNT"),
+			Self::First => write!(f, "FIRST"),
+			Self::Last => write!(f, "LAST"),
+			Self::Join(separator) => write!(f, "JOIN|{}", quote(separator)),
 		}
 	}
 }
@@ -730,81 +1924,956 @@ pub enum Item {
 	If(Condition, Box<Item>, Option<Box<Item>>),
 	/// A cell reference which would look like this in the CSV: `<cell1>`
 	Cell(usize, Option<Vec<Filter>>),
+	/// A cell of the input row before the current one which would look like this in the CSV: `<prev1>`
+	///
+	/// It is empty for the first input row
+	Previous(usize, Option<Vec<Filter>>),
+	/// A reference to an input column by its heading which would look like this in the CSV: `<col 'SKU'>`
+	///
+	/// Columns are turned into [Item::Cell] by [OutputConfig::resolve_headers] once the input headings are known
+	Column(String, Option<Vec<Filter>>),
+	/// The current element of an `:EACH` directive which would look like this in the CSV: `<each>`
+	Each(Option<Vec<Filter>>),
+	/// The position of the current element of an `:EACH` directive starting at 1 which would look like this in the
+	/// CSV: `<index>`
+	Index(Option<Vec<Filter>>),
+	/// A calculation on numeric items which would look like this in the CSV: `:CALC <cell1> * 1.1 + <cell2>`
+	Calc(Expression),
+	/// The values of an item combined over all input rows of a group which would look like this in the CSV:
+	/// `:SUM <cell3>`
+	///
+	/// Only `:COUNT` can go without an item. Aggregates need a `:GROUP_BY` directive and must be the whole cell
+	Aggregate(Aggregate, Option<Box<Item>>),
 }
 
 impl Item {
 	/// The parser for each item within a config CSV file row
-	pub fn parse(input: String) -> Self {
-		if input.starts_with("<cell") && input.ends_with('>') {
+	pub fn parse(input: String) -> Result<Self, Error> {
+		let previous = input.starts_with("<prev");
+		if (input.starts_with("<cell") || previous) && input.ends_with('>') {
 			let cell_str = &input[5..input.len() - 1];
 			let mut filter = None;
 			let num_str = match cell_str.find(' ') {
 				Some(index) => {
-					filter = Some(Filter::parse(&cell_str[index + 1..]));
+					filter = Some(Filter::parse(&cell_str[index + 1..])?);
 					&cell_str[..index]
 				},
 				None => cell_str,
 			};
 
-			match num_str.parse::<usize>() {
-				Ok(n) => {
-					if n > 0 {
-						Item::Cell(n - 1, filter)
-					} else {
-						exit_with_error(
-							Some(format!("Cell number must be positive for item \"{input}\"")),
-							Some(ErrorStages::ConfigParsing),
-							1,
-						);
-					}
-				},
-				Err(_) => {
-					exit_with_error(Some(format!("Invalid cell number \"{input}\"")), Some(ErrorStages::ConfigParsing), 1);
-				},
-			}
-		} else if let Some(condition) = input.strip_prefix(":IF ") {
-			Condition::parse(condition)
-		} else {
-			Item::Value(input.to_string())
+			match num_str.parse::<usize>() {
+				Ok(n) => {
+					if n > 0 && previous {
+						Ok(Item::Previous(n - 1, filter))
+					} else if n > 0 {
+						Ok(Item::Cell(n - 1, filter))
+					} else {
+						Err(Error::new(ErrorStages::ConfigParsing, format!("Cell number must be positive for item \"{input}\"")))
+					}
+				},
+				Err(_) => Err(Error::new(ErrorStages::ConfigParsing, format!("Invalid cell number \"{input}\""))),
+			}
+		} else if input.starts_with("<col ") && input.ends_with('>') {
+			let col_str = input[5..input.len() - 1].trim_start();
+			let usage = "Usage: <col '[heading]' [filters optional]>\n\
+			Example:\n\
+			<col 'SKU' UPPER_CASE>";
+
+			if !col_str.starts_with('\'') {
+				return Err(Error::new(
+					ErrorStages::ConfigParsing,
+					format!("Column name must be wrapped in single quotes for item \"{input}\"\n{usage}"),
+				));
+			}
+
+			let mut name = String::new();
+			let mut escaped = false;
+			let mut end = None;
+			for (i, c) in col_str.char_indices().skip(1) {
+				match c {
+					'\\' if !escaped => escaped = true,
+					'\'' if !escaped => {
+						end = Some(i);
+						break;
+					},
+					_ => {
+						escaped = false;
+						name.push(c);
+					},
+				}
+			}
+
+			match end {
+				Some(end) => {
+					let filter_str = col_str[end + 1..].trim();
+					let filter = if filter_str.is_empty() {
+						None
+					} else {
+						Some(Filter::parse(filter_str)?)
+					};
+					Ok(Item::Column(name, filter))
+				},
+				None => Err(Error::new(
+					ErrorStages::ConfigParsing,
+					format!("Column name is missing its closing quote for item \"{input}\"\n{usage}"),
+				)),
+			}
+		} else if input == "<each>" || (input.starts_with("<each ") && input.ends_with('>')) {
+			let filter_str = input[5..input.len() - 1].trim();
+			if filter_str.is_empty() {
+				Ok(Item::Each(None))
+			} else {
+				Ok(Item::Each(Some(Filter::parse(filter_str)?)))
+			}
+		} else if input == "<index>" || (input.starts_with("<index ") && input.ends_with('>')) {
+			let filter_str = input[6..input.len() - 1].trim();
+			if filter_str.is_empty() {
+				Ok(Item::Index(None))
+			} else {
+				Ok(Item::Index(Some(Filter::parse(filter_str)?)))
+			}
+		} else if let Some(condition) = input.strip_prefix(":IF ") {
+			Condition::parse(condition)
+		} else if let Some(expression) = input.strip_prefix(":CALC ") {
+			Ok(Item::Calc(Expression::parse(expression)?))
+		} else if input.strip_prefix(':').is_some_and(|aggregate_str| {
+			AGGREGATES.iter().any(|name| {
+				aggregate_str.strip_prefix(name).is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '|']))
+			})
+		}) {
+			Item::parse_aggregate(&input)
+		} else {
+			Ok(Item::Value(input.to_string()))
+		}
+	}
+
+	/// The parser for aggregates like `:SUM <cell3>` or `:JOIN|',' <cell5>`
+	fn parse_aggregate(input: &str) -> Result<Self, Error> {
+		let error = |message: &str| {
+			Error::new(
+				ErrorStages::ConfigParsing,
+				format!(
+					"{message}, was \"{input}\"\n\
+					Usage: :[SUM|MIN|MAX|COUNT|FIRST|LAST|JOIN|'[separator]'] <cell[x]>\n\
+					Examples:\n\
+					:SUM <cell3>\n\
+					:COUNT\n\
+					:JOIN|', ' <col 'Tags' TRIM>"
+				),
+			)
+		};
+
+		let name_str = split_unquoted(&input[1..], ' ').into_iter().next().unwrap_or_default();
+		let item_str = input[1 + name_str.len()..].trim();
+		let bits = split_arguments(&name_str);
+		let aggregate = match bits[0].as_str() {
+			"SUM" => Aggregate::Sum,
+			"MIN" => Aggregate::Min,
+			"MAX" => Aggregate::Max,
+			"COUNT" => Aggregate::Count,
+			"FIRST" => Aggregate::First,
+			"LAST" => Aggregate::Last,
+			_ => match bits.len() {
+				2 => Aggregate::Join(bits[1].clone()),
+				_ => return Err(error("JOIN takes exactly one separator")),
+			},
+		};
+		if bits.len() > 1 && !matches!(aggregate, Aggregate::Join(_)) {
+			return Err(error(&format!("{aggregate} takes no arguments")));
+		}
+
+		if item_str.is_empty() {
+			return match aggregate {
+				Aggregate::Count => Ok(Item::Aggregate(aggregate, None)),
+				_ => Err(error(&format!("{aggregate} needs an item to aggregate"))),
+			};
+		}
+		let item = Item::parse(item_str.to_string())?;
+		if matches!(item, Item::Aggregate(_, _)) {
+			return Err(error("Aggregates can't be nested"));
+		}
+		Ok(Item::Aggregate(aggregate, Some(Box::new(item))))
+	}
+
+	/// All filters of this item including the ones nested in conditions
+	pub fn filters_mut(&mut self) -> Vec<&mut Filter> {
+		match self {
+			Item::Cell(_, filters)
+			| Item::Previous(_, filters)
+			| Item::Column(_, filters)
+			| Item::Each(filters)
+			| Item::Index(filters) => filters.iter_mut().flatten().collect(),
+			Item::If(condition, then_item, else_item) => {
+				let mut filters: Vec<&mut Filter> =
+					condition.items_mut().into_iter().flat_map(|item| item.filters_mut()).collect();
+				filters.extend(then_item.filters_mut());
+				if let Some(else_item) = else_item {
+					filters.extend(else_item.filters_mut());
+				}
+				filters
+			},
+			Item::Calc(expression) => expression.items_mut().into_iter().flat_map(|item| item.filters_mut()).collect(),
+			Item::Aggregate(_, item) => item.iter_mut().flat_map(|item| item.filters_mut()).collect(),
+			Item::Value(_) => Vec::new(),
+		}
+	}
+
+	/// The indices of all cells this item reads, including the ones nested in conditions
+	pub fn cells(&self) -> Vec<usize> {
+		match self {
+			Item::Cell(i, _) | Item::Previous(i, _) => vec![*i],
+			Item::If(condition, then_item, else_item) => {
+				let mut cells: Vec<usize> = condition.items().into_iter().flat_map(|item| item.cells()).collect();
+				cells.extend(then_item.cells());
+				if let Some(else_item) = else_item {
+					cells.extend(else_item.cells());
+				}
+				cells
+			},
+			Item::Calc(expression) => expression.items().into_iter().flat_map(|item| item.cells()).collect(),
+			Item::Aggregate(_, item) => item.iter().flat_map(|item| item.cells()).collect(),
+			Item::Value(_) | Item::Column(_, _) | Item::Each(_) | Item::Index(_) => Vec::new(),
+		}
+	}
+
+	/// Whether this item is a condition that can evaluate to the deprecated `SKIP_THIS_LINE` value
+	pub fn uses_skip_this_line(&self) -> bool {
+		match self {
+			Item::If(_, then_item, else_item) => {
+				matches!(**then_item, Item::Value(ref value) if value == "SKIP_THIS_LINE")
+					|| then_item.uses_skip_this_line()
+					|| else_item.as_ref().is_some_and(|else_item| {
+						matches!(**else_item, Item::Value(ref value) if value == "SKIP_THIS_LINE")
+							|| else_item.uses_skip_this_line()
+					})
+			},
+			_ => false,
+		}
+	}
+
+	/// Replace every [Item::Column] within this item, including the ones nested in conditions, with the [Item::Cell]
+	/// of the matching input heading
+	pub fn resolve_headers(&mut self, headings: &[String]) -> Result<(), Error> {
+		match self {
+			Item::Column(name, filters) => match headings.iter().position(|heading| heading.trim() == name.trim()) {
+				Some(index) => {
+					*self = Item::Cell(index, filters.take());
+					Ok(())
+				},
+				None => Err(Error::new(
+					ErrorStages::ConfigParsing,
+					format!("Column \"{name}\" not found in the input headings \"{}\"", headings.join(",")),
+				)),
+			},
+			Item::If(condition, then_item, else_item) => {
+				for item in condition.items_mut() {
+					item.resolve_headers(headings)?;
+				}
+				then_item.resolve_headers(headings)?;
+				if let Some(else_item) = else_item {
+					else_item.resolve_headers(headings)?;
+				}
+				Ok(())
+			},
+			Item::Calc(expression) => {
+				for item in expression.items_mut() {
+					item.resolve_headers(headings)?;
+				}
+				Ok(())
+			},
+			Item::Aggregate(_, item) => match item {
+				Some(item) => item.resolve_headers(headings),
+				None => Ok(()),
+			},
+			Item::Value(_) | Item::Cell(_, _) | Item::Previous(_, _) | Item::Each(_) | Item::Index(_) => Ok(()),
+		}
+	}
+}
+
+/// The input row items are evaluated against
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Row<'a> {
+	/// The cells of the input row
+	pub cells: &'a [String],
+	/// The current element and its index starting at 1 when the line is repeated by an `:EACH` directive
+	pub each: Option<(&'a str, usize)>,
+	/// The cells of the input row before this one, None for the first input row
+	pub previous: Option<&'a [String]>,
+}
+
+impl<'a> Row<'a> {
+	/// A row without an `:EACH` element or a previous row
+	pub fn new(cells: &'a [String]) -> Self {
+		Self {
+			cells,
+			each: None,
+			previous: None,
+		}
+	}
+
+	/// A row without an `:EACH` element that follows the given row
+	pub fn after(cells: &'a [String], previous: Option<&'a [String]>) -> Self {
+		Self {
+			cells,
+			each: None,
+			previous,
+		}
+	}
+}
+
+/// Where an `:EACH` directive gets its elements from
+#[derive(Debug, PartialEq, Clone)]
+pub enum EachSource {
+	/// A single item split by a separator which would look like this in the CSV: `:EACH <cell5> BY '|'`
+	Split(Item, String),
+	/// A range of cells which would look like this in the CSV: `:EACH <cell3..cell5>`
+	///
+	/// The filters are applied to each cell of the range
+	Range(usize, usize, Option<Vec<Filter>>),
+}
+
+/// The `:EACH` directive repeats the config line below it for every non-empty element of a list.
+/// The element and its index are available to the items of that line as `<each>` and `<index>`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Each {
+	/// The row of the directive within the config file
+	pub row: usize,
+	/// Where the elements come from
+	pub source: EachSource,
+}
+
+impl Each {
+	/// The parser for the `:EACH` directive
+	///
+	/// Note: that the each_str argument already has its `:EACH ` prefix stripped before being passed into this function
+	pub fn parse(each_str: &str, row: usize) -> Result<Self, Error> {
+		let error = |message: String| {
+			Error::new(
+				ErrorStages::ConfigParsing,
+				format!(
+					"{message}, was \"{each_str}\"\n\
+					Usage: :EACH <cell[x]> BY '[separator]' or :EACH <cell[x]..cell[y]>\n\
+					Examples:\n\
+					:EACH <cell5> BY '|'\n\
+					:EACH <col 'Images' TRIM> BY ','\n\
+					:EACH <cell3..cell5>"
+				),
+			)
+		};
+
+		let mut parser = ConditionParser::new(each_str);
+		parser.skip_whitespace();
+		let item_str = parser.read_until(|c, _| c == '>');
+		if !parser.eat_symbol(">") {
+			return Err(error(format!("Item \"{item_str}\" is missing its closing \">\"")));
+		}
+
+		let source = match item_str.strip_prefix("<cell").and_then(|range_str| {
+			let (range, filter_str) = range_str.split_once(' ').unwrap_or((range_str, ""));
+			range.split_once("..").map(|(start, end)| (start, end, filter_str))
+		}) {
+			Some((start, end, filter_str)) => {
+				let start = start.parse::<usize>().unwrap_or(0);
+				let end = end.strip_prefix("cell").and_then(|end| end.parse::<usize>().ok()).unwrap_or(0);
+				if start == 0 || end < start {
+					return Err(error(String::from("The cell range must go from a positive cell number to a higher one")));
+				}
+				let filters = if filter_str.trim().is_empty() {
+					None
+				} else {
+					Some(Filter::parse(filter_str)?)
+				};
+				EachSource::Range(start - 1, end - 1, filters)
+			},
+			None => {
+				let item = Item::parse(format!("{item_str}>"))?;
+				if !parser.eat_keyword("BY") {
+					return Err(error(String::from("The item must be followed by BY and a separator")));
+				}
+				let separator = parser.read_operand().map(|separator| match separator {
+					Item::Value(separator) => separator,
+					_ => String::new(),
+				})?;
+				if separator.is_empty() {
+					return Err(error(String::from("The separator must be a non-empty string")));
+				}
+				EachSource::Split(item, separator)
+			},
+		};
+
+		parser.skip_whitespace();
+		if !parser.rest().is_empty() {
+			return Err(error(format!("Unexpected \"{}\" at the end of the directive", parser.rest())));
+		}
+
+		Ok(Self { row, source })
+	}
+
+	/// All filters of this directive
+	pub fn filters_mut(&mut self) -> Vec<&mut Filter> {
+		match &mut self.source {
+			EachSource::Split(item, _) => item.filters_mut(),
+			EachSource::Range(_, _, filters) => filters.iter_mut().flatten().collect(),
+		}
+	}
+}
+
+/// The `:WHERE` directive only emits the config line below it for rows that meet its condition
+///
+/// The CSV cell would contain: `:WHERE <cell1> > 0 AND <cell2> IS_NOT_EMPTY`
+#[derive(Debug, PartialEq, Clone)]
+pub struct Where {
+	/// The row of the directive within the config file
+	pub row: usize,
+	/// The condition a row has to meet
+	pub condition: Condition,
+}
+
+impl Where {
+	/// The parser for the `:WHERE` directive
+	///
+	/// Note: that the where_str argument already has its `:WHERE ` prefix stripped before being passed into this function
+	pub fn parse(where_str: &str, row: usize) -> Result<Self, Error> {
+		Ok(Self {
+			row,
+			condition: Condition::parse_expression(where_str)?,
+		})
+	}
+
+	/// All filters of this directive
+	pub fn filters_mut(&mut self) -> Vec<&mut Filter> {
+		self.condition.items_mut().into_iter().flat_map(|item| item.filters_mut()).collect()
+	}
+}
+
+/// The `:GROUP_BY` directive turns the config into a summary with one output per group of input rows that share the
+/// same key. The lines are applied once per group with [Item::Aggregate] combining the values of all of its rows and
+/// any other item taking the value of the first row.
+///
+/// The CSV cell would contain: `:GROUP_BY <cell1>` or `:GROUP_BY <cell1> <cell2 TRIM> SORTED`
+#[derive(Debug, PartialEq, Clone)]
+pub struct GroupBy {
+	/// The row of the directive within the config file
+	pub row: usize,
+	/// The items whose values together make up the key of a group
+	pub keys: Vec<Item>,
+	/// Whether the input is sorted by the key so each group can be emitted as soon as the key changes instead of
+	/// holding all groups in memory until the end of the input
+	pub sorted: bool,
+}
+
+impl GroupBy {
+	/// The parser for the `:GROUP_BY` directive
+	///
+	/// Note: that the group_str argument already has its `:GROUP_BY ` prefix stripped before being passed into this
+	/// function
+	pub fn parse(group_str: &str, row: usize) -> Result<Self, Error> {
+		let error = |message: String| {
+			Error::new(
+				ErrorStages::ConfigParsing,
+				format!(
+					"{message}, was \"{group_str}\"\n\
+					Usage: :GROUP_BY <cell[x]> [more items optional] [SORTED optional]\n\
+					Examples:\n\
+					:GROUP_BY <cell1>\n\
+					:GROUP_BY <col 'Handle'> <cell3 TRIM> SORTED"
+				),
+			)
+		};
+
+		let mut parser = ConditionParser::new(group_str);
+		let mut keys = Vec::new();
+		let mut sorted = false;
+		loop {
+			parser.skip_whitespace();
+			if parser.rest().is_empty() {
+				break;
+			}
+			if parser.eat_keyword("SORTED") {
+				sorted = true;
+				parser.skip_whitespace();
+				if !parser.rest().is_empty() {
+					return Err(error(format!("Unexpected \"{}\" after SORTED", parser.rest())));
+				}
+				break;
+			}
+			if !parser.rest().starts_with('<') {
+				return Err(error(format!("Expected an item but found \"{}\"", parser.rest())));
+			}
+			let item_str = parser.read_until(|c, _| c == '>');
+			if !parser.eat_symbol(">") {
+				return Err(error(format!("Item \"{item_str}\" is missing its closing \">\"")));
+			}
+			keys.push(Item::parse(format!("{item_str}>"))?);
+		}
+
+		if keys.is_empty() {
+			return Err(error(String::from("The directive needs at least one item to group by")));
+		}
+		Ok(Self { row, keys, sorted })
+	}
+
+	/// All filters of this directive
+	pub fn filters_mut(&mut self) -> Vec<&mut Filter> {
+		self.keys.iter_mut().flat_map(|item| item.filters_mut()).collect()
+	}
+}
+
+/// A line of the config file which is applied to every input row
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Line {
+	/// The row of this line within the config file
+	pub row: usize,
+	/// The items for each column of the output
+	pub items: Vec<Item>,
+	/// The `:EACH` directive that repeats this line
+	pub each: Option<Each>,
+	/// The `:WHERE` directive that decides if this line is emitted
+	pub guard: Option<Where>,
+}
+
+impl Line {
+	/// A line without any directives
+	pub fn new(row: usize, items: Vec<Item>) -> Self {
+		Self {
+			row,
+			items,
+			each: None,
+			guard: None,
 		}
 	}
 }
 
+/// The prefixes of config rows which apply to the line below them instead of being a line themselves
+const DIRECTIVES: [&str; 4] = [":EACH ", ":WHERE ", ":FILTER ", ":GROUP_BY "];
+
 /// This is where we hold our config for this application
 #[derive(Debug, PartialEq, Clone, Default)]
 pub struct OutputConfig {
 	/// The string for the heading and the columns
 	pub heading: String,
-	/// The lines with all their filters and conditions
-	pub lines: Vec<Vec<Item>>,
+	/// The lines with all their filters, conditions and directives
+	pub lines: Vec<Line>,
+	/// The `:FILTER` directive which drops input rows before any line is applied to them
+	pub filter: Option<Where>,
+	/// The `:GROUP_BY` directive which applies the lines once per group of input rows instead of once per row
+	pub group: Option<GroupBy>,
+}
+
+/// Everything wrong with a config file, collected by [OutputConfig::check]
+///
+/// Warnings use the same [Error] type so they carry the config row, column and cell text as well
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Diagnostics {
+	/// Problems that stop the config from being used
+	pub errors: Vec<Error>,
+	/// Problems that are ignored when converting, like unknown filters or deprecated syntax
+	pub warnings: Vec<Error>,
+}
+
+impl Diagnostics {
+	/// Whether nothing at all is wrong
+	pub fn is_empty(&self) -> bool {
+		self.errors.is_empty() && self.warnings.is_empty()
+	}
+
+	/// Add a warning for every unknown filter
+	fn unknown_filters(&mut self, filters: Vec<&mut Filter>, row: usize, column: usize, text: &str) {
+		for filter in filters {
+			if let Filter::Unknown(name) = filter {
+				self.warnings.push(
+					Error::new(ErrorStages::ConfigFilterParsing, format!("Filter not recognized \"{name}\" and will be ignored"))
+						.at(row, column)
+						.with_text(text),
+				);
+			}
+		}
+	}
 }
 
 impl OutputConfig {
 	/// This function takes a [CsvParser] and uses this to read the contents of the file
-	pub fn new<R: BufRead>(config_file: CsvParser<R>) -> Self {
+	///
+	/// Rows starting with a directive like `:EACH` or `:WHERE` apply to the line below them
+	/// while a `:FILTER` or `:GROUP_BY` directive applies to the whole config no matter where it is.
+	///
+	/// Errors carry the config row and column of the cell that could not be parsed.
+	/// Warnings are ignored, use [OutputConfig::check] to get them as well.
+	pub fn new<R: BufRead>(config_file: CsvParser<R>) -> Result<Self, Error> {
+		let (output_config, diagnostics) = Self::check(config_file);
+		match diagnostics.errors.into_iter().next() {
+			Some(error) => Err(error),
+			None => Ok(output_config),
+		}
+	}
+
+	/// Parse the entire config and collect every error and warning instead of stopping at the first one
+	///
+	/// Cells that could not be parsed are kept as their plain text so the config can still be inspected.
+	///
+	/// ```rust
+	/// use csv_converter::{config::OutputConfig, csv::CsvParser};
+	/// use std::io::Cursor;
+	///
+	/// let (_, diagnostics) = OutputConfig::check(CsvParser::new(Cursor::new("H1,H2\n<cellx>,<cell1 UPPERCASE>\n")));
+	/// assert_eq!(diagnostics.errors[0].column, Some(1));
+	/// assert_eq!(diagnostics.warnings[0].column, Some(2));
+	/// ```
+	pub fn check<R: BufRead>(config_file: CsvParser<R>) -> (Self, Diagnostics) {
+		Self::check_in(config_file, Path::new(""))
+	}
+
+	/// Like [OutputConfig::check] for a config file in the given directory, relative `LOOKUP` paths are resolved
+	/// against it instead of the current directory
+	pub fn check_in<R: BufRead>(mut config_file: CsvParser<R>, dir: &Path) -> (Self, Diagnostics) {
+		let mut diagnostics = Diagnostics::default();
 		let mut heading = String::new();
 		let mut is_heading = true;
 		let mut lines = Vec::new();
-
-		for row in config_file {
+		let mut each: Option<Each> = None;
+		let mut guard: Option<Where> = None;
+		let mut filter: Option<Where> = None;
+		let mut group: Option<GroupBy> = None;
+
+		let mut record = Record::default();
+		for row_index in 0.. {
+			let row = match config_file.read_record(&mut record) {
+				Ok(true) => record.to_vec(),
+				Ok(false) => break,
+				Err(error) => {
+					diagnostics.errors.push(error.at(row_index + 1, 1));
+					break;
+				},
+			};
 			if is_heading {
-				csv::export(&[row], &mut heading);
+				export(&[row], &mut heading);
 				heading.drain(..heading.len().saturating_sub(heading.trim_start().len()));
 				heading.truncate(heading.trim_end().len());
 				is_heading = false;
+			} else if row.first().is_some_and(|cell| DIRECTIVES.iter().any(|directive| cell.starts_with(directive))) {
+				if let Some((column_index, cell)) = row.iter().enumerate().skip(1).find(|(_, cell)| !cell.is_empty()) {
+					diagnostics.errors.push(
+						Error::new(
+							ErrorStages::ConfigParsing,
+							String::from("A directive row must not contain anything after its first cell"),
+						)
+						.at(row_index + 1, column_index + 1)
+						.with_text(cell),
+					);
+					continue;
+				}
+
+				let directive = &row[0];
+				let result = if let Some(each_str) = directive.strip_prefix(":EACH ") {
+					let is_duplicate = each.is_some();
+					Each::parse(each_str, row_index + 1).map(|mut parsed| {
+						diagnostics.unknown_filters(parsed.filters_mut(), row_index + 1, 1, directive);
+						each = Some(parsed);
+						is_duplicate
+					})
+				} else if let Some(where_str) = directive.strip_prefix(":WHERE ") {
+					let is_duplicate = guard.is_some();
+					Where::parse(where_str, row_index + 1).map(|mut parsed| {
+						diagnostics.unknown_filters(parsed.filters_mut(), row_index + 1, 1, directive);
+						guard = Some(parsed);
+						is_duplicate
+					})
+				} else if let Some(filter_str) = directive.strip_prefix(":FILTER ") {
+					let is_duplicate = filter.is_some();
+					Where::parse(filter_str, row_index + 1).map(|mut parsed| {
+						diagnostics.unknown_filters(parsed.filters_mut(), row_index + 1, 1, directive);
+						filter = Some(parsed);
+						is_duplicate
+					})
+				} else if let Some(group_str) = directive.strip_prefix(":GROUP_BY ") {
+					let is_duplicate = group.is_some();
+					GroupBy::parse(group_str, row_index + 1).map(|mut parsed| {
+						diagnostics.unknown_filters(parsed.filters_mut(), row_index + 1, 1, directive);
+						group = Some(parsed);
+						is_duplicate
+					})
+				} else {
+					Ok(false)
+				};
+				match result {
+					Ok(true) => diagnostics.errors.push(
+						Error::new(ErrorStages::ConfigParsing, String::from("A line can only have one directive of each kind"))
+							.at(row_index + 1, 1)
+							.with_text(directive),
+					),
+					Ok(false) => {},
+					Err(error) => diagnostics.errors.push(error.at(row_index + 1, 1).with_text(directive)),
+				}
 			} else {
 				let mut cells = Vec::new();
-				for cell in row {
-					cells.push(Item::parse(cell.to_string()));
+				for (column_index, cell) in row.into_iter().enumerate() {
+					match Item::parse(cell.to_string()) {
+						Ok(mut item) => {
+							if item.uses_skip_this_line() {
+								diagnostics.warnings.push(
+									Error::new(
+										ErrorStages::ConfigParsing,
+										String::from("SKIP_THIS_LINE is deprecated, use a :WHERE directive instead"),
+									)
+									.at(row_index + 1, column_index + 1)
+									.with_text(&cell),
+								);
+							}
+							diagnostics.unknown_filters(item.filters_mut(), row_index + 1, column_index + 1, &cell);
+							cells.push(item)
+						},
+						Err(error) => {
+							diagnostics.errors.push(error.at(row_index + 1, column_index + 1).with_text(&cell));
+							cells.push(Item::Value(cell));
+						},
+					}
+				}
+				let mut line = Line::new(row_index + 1, cells);
+				line.each = each.take();
+				line.guard = guard.take();
+				lines.push(line);
+			}
+		}
+
+		if let Some(row) = each.map(|each| each.row).or(guard.map(|guard| guard.row)) {
+			diagnostics.errors.push(
+				Error::new(ErrorStages::ConfigParsing, String::from("The directive has no line below it to apply to"))
+					.at(row, 1),
+			);
+		}
+
+		diagnostics.errors.extend(Self::check_aggregates(&lines, group.as_ref()));
+
+		let mut output_config = Self {
+			heading,
+			lines,
+			filter,
+			group,
+		};
+		output_config.load_lookups(dir, &mut diagnostics.errors);
+		(output_config, diagnostics)
+	}
+
+	/// Aggregates need a `:GROUP_BY` directive and a config with one is applied once per group so it can't repeat or
+	/// skip lines with `:EACH` or `:WHERE`
+	fn check_aggregates(lines: &[Line], group: Option<&GroupBy>) -> Vec<Error> {
+		let mut errors = Vec::new();
+
+		for line in lines {
+			if group.is_some() {
+				if let Some(row) = line.each.as_ref().map(|each| each.row).or(line.guard.as_ref().map(|guard| guard.row)) {
+					errors.push(
+						Error::new(
+							ErrorStages::ConfigParsing,
+							String::from("The :EACH and :WHERE directives can't be used in a config with a :GROUP_BY directive"),
+						)
+						.at(row, 1),
+					);
+				}
+			} else {
+				for (column_index, item) in line.items.iter().enumerate() {
+					if matches!(item, Item::Aggregate(_, _)) {
+						errors.push(
+							Error::new(
+								ErrorStages::ConfigParsing,
+								String::from("Aggregates can only be used in a config with a :GROUP_BY directive"),
+							)
+							.at(line.row, column_index + 1)
+							.with_text(item.to_string()),
+						);
+					}
+				}
+			}
+		}
+
+		errors
+	}
+
+	/// Load the tables of all `LOOKUP` filters so every file is only read once for each pair of columns
+	fn load_lookups(&mut self, dir: &Path, errors: &mut Vec<Error>) {
+		let mut tables: HashMap<(String, usize, usize), Arc<HashMap<String, String>>> = HashMap::new();
+		let mut load = |filters: Vec<&mut Filter>, row: usize, column: usize| {
+			for filter in filters {
+				if let Filter::Lookup(lookup) = filter {
+					let key = (lookup.path.clone(), lookup.key_column, lookup.value_column);
+					lookup.values = match tables.get(&key) {
+						Some(values) => Arc::clone(values),
+						None => match lookup.load(dir) {
+							Ok(values) => {
+								let values = Arc::new(values);
+								tables.insert(key, Arc::clone(&values));
+								values
+							},
+							Err(error) => {
+								errors.push(error.at(row, column));
+								continue;
+							},
+						},
+					};
+				}
+			}
+		};
+
+		if let Some(filter) = &mut self.filter {
+			let row = filter.row;
+			load(filter.filters_mut(), row, 1);
+		}
+		if let Some(group) = &mut self.group {
+			let row = group.row;
+			load(group.filters_mut(), row, 1);
+		}
+		for line in self.lines.iter_mut() {
+			if let Some(each) = &mut line.each {
+				let row = each.row;
+				load(each.filters_mut(), row, 1);
+			}
+			if let Some(guard) = &mut line.guard {
+				let row = guard.row;
+				load(guard.filters_mut(), row, 1);
+			}
+			for (column_index, item) in line.items.iter_mut().enumerate() {
+				load(item.filters_mut(), line.row, column_index + 1);
+			}
+		}
+	}
+
+	/// The cells of the [OutputConfig::heading], to write the heading in another dialect or to find output columns
+	///
+	/// ```rust
+	/// use csv_converter::{config::OutputConfig, csv::CsvParser};
+	/// use std::io::Cursor;
+	///
+	/// let output_config = OutputConfig::new(CsvParser::new(Cursor::new("Title,\"Price, EUR\"\n<cell1>,<cell2>\n"))).unwrap();
+	/// assert_eq!(output_config.heading, "Title,\"Price, EUR\"");
+	/// assert_eq!(output_config.headings(), vec![String::from("Title"), String::from("Price, EUR")]);
+	/// ```
+	pub fn headings(&self) -> Vec<String> {
+		let mut record = Record::default();
+		match CsvParser::new(self.heading.as_bytes()).read_record(&mut record) {
+			Ok(true) => record.to_vec(),
+			_ => Vec::new(),
+		}
+	}
+
+	/// Resolve all `<col 'heading'>` items against the headings of the input file.
+	/// Call this with the first row of your input before processing any other row.
+	///
+	/// ```rust
+	/// use csv_converter::{config::{Item, OutputConfig}, csv::CsvParser};
+	/// use std::io::Cursor;
+	///
+	/// let mut output_config = OutputConfig::new(CsvParser::new(Cursor::new("H1,H2\n<col 'SKU'>,<col 'Name'>\n"))).unwrap();
+	/// output_config.resolve_headers(&[String::from("Name"), String::from("SKU")]).unwrap();
+	/// assert_eq!(output_config.lines[0].items, vec![Item::Cell(1, None), Item::Cell(0, None)]);
+	/// ```
+	pub fn resolve_headers(&mut self, headings: &[String]) -> Result<(), Error> {
+		if let Some(filter) = &mut self.filter {
+			for item in filter.condition.items_mut() {
+				item.resolve_headers(headings).map_err(|error| error.at(filter.row, 1))?;
+			}
+		}
+		if let Some(group) = &mut self.group {
+			for item in group.keys.iter_mut() {
+				item.resolve_headers(headings).map_err(|error| error.at(group.row, 1))?;
+			}
+		}
+		for line in self.lines.iter_mut() {
+			if let Some(Each {
+				row,
+				source: EachSource::Split(item, _),
+			}) = &mut line.each
+			{
+				item.resolve_headers(headings).map_err(|error| error.at(*row, 1))?;
+			}
+			if let Some(guard) = &mut line.guard {
+				for item in guard.condition.items_mut() {
+					item.resolve_headers(headings).map_err(|error| error.at(guard.row, 1))?;
+				}
+			}
+			for (column_index, item) in line.items.iter_mut().enumerate() {
+				item.resolve_headers(headings).map_err(|error| error.at(line.row, column_index + 1))?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Check the config against the headings of an input file
+	///
+	/// Like [OutputConfig::resolve_headers] this resolves all `<col 'heading'>` items but collects every unknown column
+	/// and every `<cellN>` beyond the last input column instead of stopping at the first problem
+	pub fn check_headers(&mut self, headings: &[String]) -> Vec<Error> {
+		let mut errors = Vec::new();
+		let mut check = |item: &mut Item, row: usize, column: usize| {
+			if let Err(error) = item.resolve_headers(headings) {
+				errors.push(error.at(row, column));
+			}
+			let mut cells = item.cells();
+			cells.sort_unstable();
+			cells.dedup();
+			for cell in cells.into_iter().filter(|cell| *cell >= headings.len()) {
+				errors.push(missing_cell(cell, headings.len()).at(row, column));
+			}
+		};
+
+		if let Some(filter) = &mut self.filter {
+			for item in filter.condition.items_mut() {
+				check(item, filter.row, 1);
+			}
+		}
+		if let Some(group) = &mut self.group {
+			for item in group.keys.iter_mut() {
+				check(item, group.row, 1);
+			}
+		}
+		for line in self.lines.iter_mut() {
+			match &mut line.each {
+				Some(Each {
+					row,
+					source: EachSource::Split(item, _),
+				}) => check(item, *row, 1),
+				Some(Each {
+					row,
+					source: EachSource::Range(_, end, _),
+				}) => {
+					// the whole range is fine as long as its last cell exists
+					let mut item = Item::Cell(*end, None);
+					check(&mut item, *row, 1);
+				},
+				None => {},
+			}
+			if let Some(guard) = &mut line.guard {
+				for item in guard.condition.items_mut() {
+					check(item, guard.row, 1);
 				}
-				lines.push(cells);
+			}
+			for (column_index, item) in line.items.iter_mut().enumerate() {
+				check(item, line.row, column_index + 1);
 			}
 		}
 
-		Self { heading, lines }
+		errors
+	}
+
+	/// Whether an input row passes the `:FILTER` directive and should be processed at all
+	pub fn keep(&self, input_line: &[String], previous: Option<&[String]>) -> Result<bool, Error> {
+		match &self.filter {
+			Some(filter) => {
+				filter.condition.is_true(&Row::after(input_line, previous)).map_err(|error| error.at(filter.row, 1))
+			},
+			None => Ok(true),
+		}
 	}
 }
 
+fn missing_cell(cell: usize, columns: usize) -> Error {
+	Error::new(
+		ErrorStages::ConfigParsing,
+		format!("Cell \"<cell{}>\" does not exist, the input only has {columns} columns", cell + 1),
+	)
+	.with_text(format!("<cell{}>", cell + 1))
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -813,49 +2882,88 @@ mod tests {
 	#[test]
 	fn new_test() {
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("heading1,heading2,heading3\n<cell1>,<cell2>,<cell3>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("heading1,heading2,heading3\n<cell1>,<cell2>,<cell3>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("heading1,heading2,heading3"),
-				lines: vec![vec![Item::Cell(0, None), Item::Cell(1, None), Item::Cell(2, None),]],
+				lines: vec![Line::new(
+					2,
+					vec![Item::Cell(0, None), Item::Cell(1, None), Item::Cell(2, None),]
+				)],
+				..Default::default()
 			}
 		);
 
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("h1,h2,h3,h4\n<cell1>,,hardcoded,:IF <cell1> IS_EMPTY ('foo')\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("h1,h2,h3,h4\n<cell1>,,hardcoded,:IF <cell1> IS_EMPTY ('foo')\n")))
+				.unwrap(),
 			OutputConfig {
 				heading: String::from("h1,h2,h3,h4"),
-				lines: vec![vec![
-					Item::Cell(0, None),
-					Item::Value(String::from("")),
-					Item::Value(String::from("hardcoded")),
-					Item::If(Condition::IsEmpty(Box::new(Item::Cell(0, None))), Box::new(Item::Value(String::from("foo"))), None),
-				]],
+				lines: vec![Line::new(
+					2,
+					vec![
+						Item::Cell(0, None),
+						Item::Value(String::from("")),
+						Item::Value(String::from("hardcoded")),
+						Item::If(
+							Condition::IsEmpty(Box::new(Item::Cell(0, None))),
+							Box::new(Item::Value(String::from("foo"))),
+							None
+						),
+					]
+				)],
+				..Default::default()
 			}
 		);
 	}
 
+	#[test]
+	fn new_error_test() {
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new("H1,H2\n<cell1>,<cell2>\n<cell1>,<cell0>\n"))),
+			Err(
+				Error::new(ErrorStages::ConfigParsing, String::from("Cell number must be positive for item \"<cell0>\""))
+					.at(3, 2)
+					.with_text("<cell0>")
+			)
+		);
+
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n:IF <cell1> IS_BLUE ('x')\n"))).map_err(|error| (
+				error.stage,
+				error.row,
+				error.column
+			)),
+			Err((ErrorStages::ConfigConditionParsing, Some(2), Some(1)))
+		);
+	}
+
 	#[test]
 	fn filter_test() {
 		assert_eq!(
 			OutputConfig::new(CsvParser::new(Cursor::new(
 				"H1,H2,H3\n<cell1 LENGTH>,<cell2 UPPER_CASE LOWER_CASE REPLACE|' '|'-' SPLIT|'-'|3 APPEND|'end'>,<cell3>\n"
-			))),
+			)))
+			.unwrap(),
 			OutputConfig {
 				heading: String::from("H1,H2,H3"),
-				lines: vec![vec![
-					Item::Cell(0, Some(vec![Filter::Length])),
-					Item::Cell(
-						1,
-						Some(vec![
-							Filter::UpperCase,
-							Filter::LowerCase,
-							Filter::Replace(String::from(" "), String::from("-")),
-							Filter::Split(String::from("-"), 3),
-							Filter::Append(String::from("end")),
-						])
-					),
-					Item::Cell(2, None),
-				]],
+				lines: vec![Line::new(
+					2,
+					vec![
+						Item::Cell(0, Some(vec![Filter::Length])),
+						Item::Cell(
+							1,
+							Some(vec![
+								Filter::UpperCase,
+								Filter::LowerCase,
+								Filter::Replace(String::from(" "), String::from("-")),
+								Filter::Split(String::from("-"), 3),
+								Filter::Append(String::from("end")),
+							])
+						),
+						Item::Cell(2, None),
+					]
+				)],
+				..Default::default()
 			}
 		);
 	}
@@ -863,119 +2971,133 @@ mod tests {
 	#[test]
 	fn upper_case_test() {
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 UPPER_CASE>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 UPPER_CASE>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(0, Some(vec![Filter::UpperCase,])),]],
+				lines: vec![Line::new(2, vec![Item::Cell(0, Some(vec![Filter::UpperCase,])),])],
+				..Default::default()
 			}
 		);
 
-		assert_eq!(Filter::UpperCase.run(Cow::Borrowed("test")), Cow::Borrowed("TEST"));
-		assert_eq!(Filter::UpperCase.run(Cow::Borrowed("TEST")), Cow::Borrowed("TEST"));
-		assert_eq!(Filter::UpperCase.run(Cow::Borrowed("TeSt 😬")), Cow::Borrowed("TEST 😬"));
+		assert_eq!(Filter::UpperCase.run(Cow::Borrowed("test")).unwrap(), Cow::Borrowed("TEST"));
+		assert_eq!(Filter::UpperCase.run(Cow::Borrowed("TEST")).unwrap(), Cow::Borrowed("TEST"));
+		assert_eq!(Filter::UpperCase.run(Cow::Borrowed("TeSt 😬")).unwrap(), Cow::Borrowed("TEST 😬"));
 	}
 
 	#[test]
 	fn lower_case_test() {
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 LOWER_CASE>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 LOWER_CASE>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(0, Some(vec![Filter::LowerCase,])),]],
+				lines: vec![Line::new(2, vec![Item::Cell(0, Some(vec![Filter::LowerCase,])),])],
+				..Default::default()
 			}
 		);
 
-		assert_eq!(Filter::LowerCase.run(Cow::Borrowed("test")), Cow::Borrowed("test"));
-		assert_eq!(Filter::LowerCase.run(Cow::Borrowed("TEST")), Cow::Borrowed("test"));
-		assert_eq!(Filter::LowerCase.run(Cow::Borrowed("TeSt 😬")), Cow::Borrowed("test 😬"));
+		assert_eq!(Filter::LowerCase.run(Cow::Borrowed("test")).unwrap(), Cow::Borrowed("test"));
+		assert_eq!(Filter::LowerCase.run(Cow::Borrowed("TEST")).unwrap(), Cow::Borrowed("test"));
+		assert_eq!(Filter::LowerCase.run(Cow::Borrowed("TeSt 😬")).unwrap(), Cow::Borrowed("test 😬"));
 	}
 
 	#[test]
 	fn length_test() {
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 LENGTH>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 LENGTH>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(0, Some(vec![Filter::Length,])),]],
+				lines: vec![Line::new(2, vec![Item::Cell(0, Some(vec![Filter::Length,])),])],
+				..Default::default()
 			}
 		);
 
-		assert_eq!(Filter::Length.run(Cow::Borrowed("test")), Cow::Borrowed("4"));
-		assert_eq!(Filter::Length.run(Cow::Borrowed("123456789 ")), Cow::Borrowed("10"));
+		assert_eq!(Filter::Length.run(Cow::Borrowed("test")).unwrap(), Cow::Borrowed("4"));
+		assert_eq!(Filter::Length.run(Cow::Borrowed("123456789 ")).unwrap(), Cow::Borrowed("10"));
 	}
 
 	#[test]
 	fn trim_test() {
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 TRIM>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 TRIM>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(0, Some(vec![Filter::Trim,])),]],
+				lines: vec![Line::new(2, vec![Item::Cell(0, Some(vec![Filter::Trim,])),])],
+				..Default::default()
 			}
 		);
 
-		assert_eq!(Filter::Trim.run(Cow::Borrowed(" te st  ")), Cow::Borrowed("te st"));
-		assert_eq!(Filter::Trim.run(Cow::Borrowed(" \n te  st  \n  ")), Cow::Borrowed("te  st"));
+		assert_eq!(Filter::Trim.run(Cow::Borrowed(" te st  ")).unwrap(), Cow::Borrowed("te st"));
+		assert_eq!(Filter::Trim.run(Cow::Borrowed(" \n te  st  \n  ")).unwrap(), Cow::Borrowed("te  st"));
 	}
 
 	#[test]
 	fn trim_start_test() {
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 TRIM_START>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 TRIM_START>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(0, Some(vec![Filter::TrimStart,])),]],
+				lines: vec![Line::new(2, vec![Item::Cell(0, Some(vec![Filter::TrimStart,])),])],
+				..Default::default()
 			}
 		);
 
-		assert_eq!(Filter::TrimStart.run(Cow::Borrowed(" \n   te  st  ")), Cow::Borrowed("te  st  "));
-		assert_eq!(Filter::TrimStart.run(Cow::Borrowed("  te  st  \n  ")), Cow::Borrowed("te  st  \n  "));
+		assert_eq!(Filter::TrimStart.run(Cow::Borrowed(" \n   te  st  ")).unwrap(), Cow::Borrowed("te  st  "));
+		assert_eq!(Filter::TrimStart.run(Cow::Borrowed("  te  st  \n  ")).unwrap(), Cow::Borrowed("te  st  \n  "));
 	}
 
 	#[test]
 	fn trim_end_test() {
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 TRIM_END>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 TRIM_END>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(0, Some(vec![Filter::TrimEnd,])),]],
+				lines: vec![Line::new(2, vec![Item::Cell(0, Some(vec![Filter::TrimEnd,])),])],
+				..Default::default()
 			}
 		);
 
-		assert_eq!(Filter::TrimEnd.run(Cow::Borrowed(" \n   te  st  ")), Cow::Borrowed(" \n   te  st"));
-		assert_eq!(Filter::TrimEnd.run(Cow::Borrowed("  te  st  \n  ")), Cow::Borrowed("  te  st"));
+		assert_eq!(Filter::TrimEnd.run(Cow::Borrowed(" \n   te  st  ")).unwrap(), Cow::Borrowed(" \n   te  st"));
+		assert_eq!(Filter::TrimEnd.run(Cow::Borrowed("  te  st  \n  ")).unwrap(), Cow::Borrowed("  te  st"));
 	}
 
 	#[test]
 	fn replace_test() {
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 REPLACE|'-'|' '>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 REPLACE|'-'|' '>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(
-					0,
-					Some(vec![Filter::Replace(String::from("-"), String::from(" ")),])
-				),]],
+				lines: vec![Line::new(
+					2,
+					vec![Item::Cell(
+						0,
+						Some(vec![Filter::Replace(String::from("-"), String::from(" ")),])
+					),]
+				)],
+				..Default::default()
 			}
 		);
 
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 REPLACE|'...'|'##'>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 REPLACE|'...'|'##'>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(
-					0,
-					Some(vec![Filter::Replace(String::from("..."), String::from("##")),])
-				),]],
+				lines: vec![Line::new(
+					2,
+					vec![Item::Cell(
+						0,
+						Some(vec![Filter::Replace(String::from("..."), String::from("##")),])
+					),]
+				)],
+				..Default::default()
 			}
 		);
 
 		assert_eq!(
-			Filter::Replace(String::from("blue"), String::from("green")).run(Cow::Borrowed("The shirt is blue")),
+			Filter::Replace(String::from("blue"), String::from("green")).run(Cow::Borrowed("The shirt is blue")).unwrap(),
 			Cow::Borrowed("The shirt is green")
 		);
 		assert_eq!(
-			Filter::Replace(String::from(" "), String::from("")).run(Cow::Borrowed(" The shirt  is blue")),
+			Filter::Replace(String::from(" "), String::from("")).run(Cow::Borrowed(" The shirt  is blue")).unwrap(),
 			Cow::Borrowed("Theshirtisblue")
 		);
 	}
@@ -983,103 +3105,400 @@ mod tests {
 	#[test]
 	fn append_test() {
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 APPEND|'end'>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 APPEND|'end'>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(0, Some(vec![Filter::Append(String::from("end")),])),]],
+				lines: vec![Line::new(
+					2,
+					vec![Item::Cell(0, Some(vec![Filter::Append(String::from("end")),])),]
+				)],
+				..Default::default()
 			}
 		);
 
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 APPEND|'###'>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 APPEND|'###'>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(0, Some(vec![Filter::Append(String::from("###")),])),]],
+				lines: vec![Line::new(
+					2,
+					vec![Item::Cell(0, Some(vec![Filter::Append(String::from("###")),])),]
+				)],
+				..Default::default()
 			}
 		);
 
-		assert_eq!(Filter::Append(String::from("-end")).run(Cow::Borrowed("middle")), Cow::Borrowed("middle-end"));
-		assert_eq!(Filter::Append(String::from("- 😬")).run(Cow::Borrowed("middle")), Cow::Borrowed("middle- 😬"));
+		assert_eq!(Filter::Append(String::from("-end")).run(Cow::Borrowed("middle")).unwrap(), Cow::Borrowed("middle-end"));
+		assert_eq!(Filter::Append(String::from("- 😬")).run(Cow::Borrowed("middle")).unwrap(), Cow::Borrowed("middle- 😬"));
 	}
 
 	#[test]
 	fn prepend_test() {
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 PREPEND|'front'>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 PREPEND|'front'>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(0, Some(vec![Filter::Prepend(String::from("front")),])),]],
+				lines: vec![Line::new(
+					2,
+					vec![Item::Cell(0, Some(vec![Filter::Prepend(String::from("front")),])),]
+				)],
+				..Default::default()
 			}
 		);
 
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 PREPEND|'###'>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 PREPEND|'###'>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(0, Some(vec![Filter::Prepend(String::from("###")),])),]],
+				lines: vec![Line::new(
+					2,
+					vec![Item::Cell(0, Some(vec![Filter::Prepend(String::from("###")),])),]
+				)],
+				..Default::default()
 			}
 		);
 
-		assert_eq!(Filter::Prepend(String::from("start-")).run(Cow::Borrowed("middle")), Cow::Borrowed("start-middle"));
-		assert_eq!(Filter::Prepend(String::from("😬 -")).run(Cow::Borrowed("middle")), Cow::Borrowed("😬 -middle"));
+		assert_eq!(
+			Filter::Prepend(String::from("start-")).run(Cow::Borrowed("middle")).unwrap(),
+			Cow::Borrowed("start-middle")
+		);
+		assert_eq!(
+			Filter::Prepend(String::from("😬 -")).run(Cow::Borrowed("middle")).unwrap(),
+			Cow::Borrowed("😬 -middle")
+		);
 	}
 
 	#[test]
 	fn split_test() {
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 SPLIT|'-'|6>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 SPLIT|'-'|6>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(0, Some(vec![Filter::Split(String::from("-"), 6),])),]],
+				lines: vec![Line::new(
+					2,
+					vec![Item::Cell(0, Some(vec![Filter::Split(String::from("-"), 6),])),]
+				)],
+				..Default::default()
 			}
 		);
 
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 SPLIT|'###'|666>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 SPLIT|'###'|666>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(
-					0,
-					Some(vec![Filter::Split(String::from("###"), 666),])
-				),]],
+				lines: vec![Line::new(
+					2,
+					vec![Item::Cell(0, Some(vec![Filter::Split(String::from("###"), 666),])),]
+				)],
+				..Default::default()
 			}
 		);
 
-		assert_eq!(Filter::Split(String::from("-"), 3).run(Cow::Borrowed("0-1-2-3-4-5")), Cow::Borrowed("3"));
-		assert_eq!(Filter::Split(String::from("-"), 10).run(Cow::Borrowed("0-1-2-3-4-5")), Cow::Borrowed(""));
-		assert_eq!(Filter::Split(String::from("-"), 10).run(Cow::Borrowed("no dashes")), Cow::Borrowed(""));
+		assert_eq!(Filter::Split(String::from("-"), 3).run(Cow::Borrowed("0-1-2-3-4-5")).unwrap(), Cow::Borrowed("3"));
+		assert_eq!(Filter::Split(String::from("-"), 10).run(Cow::Borrowed("0-1-2-3-4-5")).unwrap(), Cow::Borrowed(""));
+		assert_eq!(Filter::Split(String::from("-"), 10).run(Cow::Borrowed("no dashes")).unwrap(), Cow::Borrowed(""));
 	}
 
 	#[test]
 	fn sub_string_test() {
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 SUB_STRING|5>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 SUB_STRING|5>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(0, Some(vec![Filter::SubString(5, None)])),]],
+				lines: vec![Line::new(
+					2,
+					vec![Item::Cell(0, Some(vec![Filter::SubString(5, None)])),]
+				)],
+				..Default::default()
 			}
 		);
 
 		assert_eq!(
-			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 SUB_STRING|999|666>\n"))),
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 SUB_STRING|999|666>\n"))).unwrap(),
 			OutputConfig {
 				heading: String::from("H1"),
-				lines: vec![vec![Item::Cell(0, Some(vec![Filter::SubString(999, Some(666))])),]],
+				lines: vec![Line::new(
+					2,
+					vec![Item::Cell(0, Some(vec![Filter::SubString(999, Some(666))])),]
+				)],
+				..Default::default()
 			}
 		);
 
-		assert_eq!(Filter::SubString(5, None).run(Cow::Borrowed("12345678910 end")), Cow::Borrowed("678910 end"));
-		assert_eq!(Filter::SubString(5, Some(3)).run(Cow::Borrowed("12345678910 end")), Cow::Borrowed("678"));
+		assert_eq!(Filter::SubString(5, None).run(Cow::Borrowed("12345678910 end")).unwrap(), Cow::Borrowed("678910 end"));
+		assert_eq!(Filter::SubString(5, Some(3)).run(Cow::Borrowed("12345678910 end")).unwrap(), Cow::Borrowed("678"));
+	}
+
+	#[test]
+	fn round_test() {
+		assert_eq!(
+			Filter::parse("ROUND|2 ROUND ROUND|-2"),
+			Ok(vec![Filter::Round(2), Filter::Round(0), Filter::Round(-2)])
+		);
+		assert!(Filter::parse("ROUND|two").is_err());
+		assert!(Filter::parse("ROUND|1|2").is_err());
+
+		assert_eq!(Filter::Round(2).run(Cow::Borrowed("12.499999")).unwrap(), Cow::Borrowed("12.5"));
+		assert_eq!(Filter::Round(2).run(Cow::Borrowed(" 1.005 ")).unwrap(), Cow::Borrowed("1.01"));
+		assert_eq!(Filter::Round(0).run(Cow::Borrowed("-2.5")).unwrap(), Cow::Borrowed("-3"));
+		assert_eq!(Filter::Round(-2).run(Cow::Borrowed("1250")).unwrap(), Cow::Borrowed("1300"));
+		assert_eq!(Filter::Round(2).run(Cow::Borrowed("")).unwrap(), Cow::Borrowed(""));
+	}
+
+	#[test]
+	fn fixed_test() {
+		assert_eq!(Filter::parse("FIXED|2"), Ok(vec![Filter::Fixed(2)]));
+		assert!(Filter::parse("FIXED").is_err());
+		assert!(Filter::parse("FIXED|-1").is_err());
+
+		assert_eq!(Filter::Fixed(2).run(Cow::Borrowed("12.5")).unwrap(), Cow::Borrowed("12.50"));
+		assert_eq!(Filter::Fixed(2).run(Cow::Borrowed("12.499999")).unwrap(), Cow::Borrowed("12.50"));
+		assert_eq!(Filter::Fixed(2).run(Cow::Borrowed("2.675")).unwrap(), Cow::Borrowed("2.68"));
+		assert_eq!(Filter::Fixed(0).run(Cow::Borrowed("7")).unwrap(), Cow::Borrowed("7"));
+		assert_eq!(Filter::Fixed(2).run(Cow::Borrowed("-0.001")).unwrap(), Cow::Borrowed("0.00"));
+	}
+
+	#[test]
+	fn ceil_floor_test() {
+		assert_eq!(Filter::parse("CEIL FLOOR"), Ok(vec![Filter::Ceil, Filter::Floor]));
+
+		assert_eq!(Filter::Ceil.run(Cow::Borrowed("12.01")).unwrap(), Cow::Borrowed("13"));
+		assert_eq!(Filter::Ceil.run(Cow::Borrowed("-12.5")).unwrap(), Cow::Borrowed("-12"));
+		assert_eq!(Filter::Floor.run(Cow::Borrowed("12.99")).unwrap(), Cow::Borrowed("12"));
+		assert_eq!(Filter::Floor.run(Cow::Borrowed("-0.5")).unwrap(), Cow::Borrowed("-1"));
+		assert_eq!(Filter::Floor.run(Cow::Borrowed("1e3")).unwrap(), Cow::Borrowed("1000"));
+	}
+
+	#[test]
+	fn pad_number_test() {
+		assert_eq!(Filter::parse("PAD_NUMBER|5"), Ok(vec![Filter::PadNumber(5)]));
+		assert!(Filter::parse("PAD_NUMBER").is_err());
+
+		assert_eq!(Filter::PadNumber(5).run(Cow::Borrowed("42")).unwrap(), Cow::Borrowed("00042"));
+		assert_eq!(Filter::PadNumber(5).run(Cow::Borrowed("-42")).unwrap(), Cow::Borrowed("-00042"));
+		assert_eq!(Filter::PadNumber(3).run(Cow::Borrowed("4.50")).unwrap(), Cow::Borrowed("004.50"));
+		assert_eq!(Filter::PadNumber(2).run(Cow::Borrowed("12345")).unwrap(), Cow::Borrowed("12345"));
+		assert_eq!(Filter::PadNumber(6).run(Cow::Borrowed("1e3")).unwrap(), Cow::Borrowed("001000"));
+	}
+
+	#[test]
+	fn parse_number_test() {
+		assert_eq!(
+			Filter::parse("PARSE_NUMBER PARSE_NUMBER|'de'"),
+			Ok(vec![
+				Filter::ParseNumber(String::from("en")),
+				Filter::ParseNumber(String::from("de"))
+			])
+		);
+		assert!(Filter::parse("PARSE_NUMBER|'xx'").is_err());
+
+		for (locale, input, output) in [
+			("en", "$12.5", "12.5"),
+			("en", "1,234.50 USD", "1234.5"),
+			("en", "-$1,000", "-1000"),
+			("en", ".5", "0.5"),
+			("en", "12 kg", "12"),
+			("de", "1.234,50", "1234.5"),
+			("de", "1.234,50 €", "1234.5"),
+			("de", "EUR -0,99", "-0.99"),
+			("de", "12-", "-12"),
+			("fr", "1 234 567,8 €", "1234567.8"),
+			("fr", "1\u{202f}234,5", "1234.5"),
+			("ch", "CHF 1'234.50", "1234.5"),
+			("en", "1,234,567", "1234567"),
+			("en", "12,345.678", "12345.678"),
+			("de", "12,5", "12.5"),
+			("en", "-$ 12", "-12"),
+			("en", "SKU-12", "12"),
+			("en", "Size - 5", "5"),
+			("en", "5 - large", "5"),
+		] {
+			assert_eq!(
+				Filter::ParseNumber(String::from(locale)).run(Cow::Borrowed(input)).unwrap(),
+				Cow::<str>::Owned(String::from(output)),
+				"{input} in {locale}"
+			);
+		}
+		assert_eq!(Filter::ParseNumber(String::from("en")).run(Cow::Borrowed(" ")).unwrap(), Cow::Borrowed(" "));
+		assert!(Filter::ParseNumber(String::from("en")).run(Cow::Borrowed("1.234,50")).is_err());
+		assert!(Filter::ParseNumber(String::from("de")).run(Cow::Borrowed("12 x 4")).is_err());
+
+		// a thousands separator must be followed by a group of three digits
+		for (locale, input) in [
+			("de", "12.499999"),
+			("de", "$12.5"),
+			("de", "1.234.5"),
+			("en", "1,23"),
+			("en", "1,2345.5"),
+			("en", "1,234,56.5"),
+			("fr", "12 34,5"),
+			("ch", "1'23"),
+			("en", "1.2.3"),
+		] {
+			assert_eq!(
+				Filter::ParseNumber(String::from(locale)).run(Cow::Borrowed(input)).map_err(|error| error.stage),
+				Err(ErrorStages::ConfigFilterEvaluating),
+				"{input} in {locale}"
+			);
+		}
+	}
+
+	#[test]
+	fn number_filter_error_test() {
+		for filter in [
+			Filter::Round(2),
+			Filter::Fixed(2),
+			Filter::Ceil,
+			Filter::Floor,
+			Filter::PadNumber(5),
+		] {
+			let error = filter.run(Cow::Borrowed("abc")).unwrap_err();
+			assert_eq!(error.stage, ErrorStages::ConfigFilterEvaluating);
+			assert_eq!(
+				error.message,
+				format!(
+					"The {filter} filter needs a number, was \"abc\", use PARSE_NUMBER first for numbers like \"1.234,50 €\""
+				)
+			);
+			assert!(filter.run(Cow::Borrowed("inf")).is_err());
+		}
+		assert_eq!(
+			Filter::ParseNumber(String::from("de")).run(Cow::Borrowed("n/a")).unwrap_err().message,
+			"The PARSE_NUMBER|'de' filter needs a number, was \"n/a\""
+		);
+
+		let output_config =
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 PARSE_NUMBER|'de' FIXED|2>\n"))).unwrap();
+		let row = [String::from("1.234,5 €")];
+		assert_eq!(
+			crate::process::run(&row, &output_config, &mut Default::default()),
+			Ok(vec![vec![String::from("1234.50")]])
+		);
+		assert_eq!(
+			crate::process::run(&[String::from("€")], &output_config, &mut Default::default()).map_err(|error| (
+				error.stage,
+				error.row,
+				error.column
+			)),
+			Err((ErrorStages::ConfigFilterEvaluating, Some(2), Some(1)))
+		);
+	}
+
+	#[test]
+	fn date_test() {
+		assert_eq!(
+			Filter::parse("DATE|'%d/%m/%Y %H:%M'|'%Y-%m-%d' DATE|'iso'|'iso'|'+02:00'"),
+			Ok(vec![
+				Filter::Date(String::from("%d/%m/%Y %H:%M"), String::from("%Y-%m-%d"), None),
+				Filter::Date(String::from("iso"), String::from("iso"), Some(7200)),
+			])
+		);
+		for filter in [
+			"DATE|'%d/%m/%Y'",
+			"DATE|'%d/%m/%Q'|'%Y'",
+			"DATE|'iso'|'excel'",
+			"DATE|'iso'|'iso'|'CET'",
+		] {
+			assert_eq!(Filter::parse(filter).unwrap_err().stage, ErrorStages::ConfigFilterParsing, "{filter}");
+		}
+
+		let date = |input_format: &str, output_format: &str, offset: Option<i32>, input: &str| {
+			Filter::Date(String::from(input_format), String::from(output_format), offset)
+				.run(Cow::Borrowed(input))
+				.map(|output| output.into_owned())
+		};
+		assert_eq!(date("%d/%m/%Y", "%Y-%m-%d", None, "17/10/2026"), Ok(String::from("2026-10-17")));
+		assert_eq!(date("iso", "%d.%m.%Y %H:%M", None, "2026-10-17T08:00:00Z"), Ok(String::from("17.10.2026 08:00")));
+		assert_eq!(date("iso", "iso", Some(7200), "2026-10-17T08:00:00Z"), Ok(String::from("2026-10-17T10:00:00+02:00")));
+		assert_eq!(date("%d/%m/%Y", "iso", Some(-18000), "17/10/2026"), Ok(String::from("2026-10-17T00:00:00-05:00")));
+		assert_eq!(date("excel", "%Y-%m-%d %H:%M", None, "46312.5"), Ok(String::from("2026-10-17 12:00")));
+		assert_eq!(date("excel", "%F", None, " "), Ok(String::from(" ")));
+
+		let error = date("%d/%m/%Y", "%F", None, "2026-10-17").unwrap_err();
+		assert_eq!(error.stage, ErrorStages::ConfigFilterEvaluating);
+		assert_eq!(
+			error.message,
+			"The DATE|'%d/%m/%Y'|'%F' filter needs a date written like '%d/%m/%Y', was \"2026-10-17\""
+		);
+		assert!(date("%d/%m/%Y", "%F", None, "31/02/2026").is_err());
+	}
+
+	#[test]
+	fn slugify_test() {
+		assert_eq!(
+			Filter::parse("HANDLEIZE SLUGIFY SLUGIFY|'_' SLUGIFY|''"),
+			Ok(vec![
+				Filter::Handleize,
+				Filter::Slugify(String::from("-")),
+				Filter::Slugify(String::from("_")),
+				Filter::Slugify(String::new()),
+			])
+		);
+		assert_eq!(Filter::parse("SLUGIFY|'-'|'_'").unwrap_err().stage, ErrorStages::ConfigFilterParsing);
+
+		let handleize = |input: &str| Filter::Handleize.run(Cow::Borrowed(input)).unwrap().into_owned();
+		assert_eq!(handleize("Men's T-Shirt"), "mens-t-shirt");
+		assert_eq!(handleize("  Crème Brûlée -- (Large)!  "), "creme-brulee-large");
+		assert_eq!(handleize("Größe: ÆRØ Straße"), "grosse-aero-strasse");
+		assert_eq!(handleize("Łódź, Zürich & İstanbul"), "lodz-zurich-istanbul");
+		assert_eq!(handleize("Cafe\u{301} 100% “Bio”"), "cafe-100-bio");
+		assert_eq!(handleize("日本 Tea"), "日本-tea");
+		assert_eq!(handleize("Футболка «Лето» XL"), "футболка-лето-xl");
+		assert_eq!(handleize("Tシャツ（白）"), "tシャツ-白");
+		assert_eq!(handleize("Ελληνικό Μέλι"), "ελληνικό-μέλι");
+		assert_eq!(handleize("---"), "");
+
+		let slugify = |separator: &str, input: &str| {
+			Filter::Slugify(String::from(separator)).run(Cow::Borrowed(input)).unwrap().into_owned()
+		};
+		assert_eq!(slugify("_", "Crème Brûlée (Large)"), "creme_brulee_large");
+		assert_eq!(slugify("", "Blue / Red"), "bluered");
+		assert_eq!(slugify(" - ", "a.b"), "a - b");
+	}
+
+	#[test]
+	fn regex_test() {
+		let regex = |pattern: &str| Regex::new(pattern).unwrap();
+		assert_eq!(
+			Filter::parse(r"REGEX_REPLACE|'\s*\((S|M|L)\)'|' - $1' REGEX_EXTRACT|'(\d+) ?cm'|1 REGEX_EXTRACT|'it\'s'"),
+			Ok(vec![
+				Filter::RegexReplace(regex(r"\s*\((S|M|L)\)"), String::from(" - $1")),
+				Filter::RegexExtract(regex(r"(\d+) ?cm"), 1),
+				Filter::RegexExtract(regex("it's"), 0),
+			])
+		);
+		assert_eq!(Filter::parse(r"REPLACE|'|'|'\\'"), Ok(vec![Filter::Replace(String::from("|"), String::from("\\"))]));
+		for filter in [
+			"REGEX_REPLACE|'a'",
+			"REGEX_EXTRACT|'(a'",
+			"REGEX_EXTRACT|'(a)'|2",
+			"REGEX_EXTRACT|'a'|x",
+		] {
+			assert_eq!(Filter::parse(filter).unwrap_err().stage, ErrorStages::ConfigFilterParsing, "{filter}");
+		}
+		assert_eq!(
+			Filter::parse("REGEX_REPLACE|'a**'|''").unwrap_err().message,
+			"Invalid regular expression \"a**\": nothing to repeat at position 3"
+		);
+
+		let run = |filter: &str, input: &str| {
+			Filter::parse(filter).unwrap()[0].run(Cow::Borrowed(input)).map(|output| output.into_owned())
+		};
+		assert_eq!(run(r"REGEX_REPLACE|'\s*\((S|M|L)\)'|' - $1'", "Shirt  (M)"), Ok(String::from("Shirt - M")));
+		assert_eq!(run(r"REGEX_REPLACE|'[^\w-]+'|'-'", "Blue & Red, 2x"), Ok(String::from("Blue-Red-2x")));
+		assert_eq!(run(r"REGEX_EXTRACT|'(\d+) ?cm'|1", "Width: 42 cm"), Ok(String::from("42")));
+		assert_eq!(run(r"REGEX_EXTRACT|'(\d+) ?cm'|1", "Width: 42 mm"), Ok(String::new()));
+		assert_eq!(run(r"REGEX_EXTRACT|'(?i)sku-\d+'", "Item sku-0042"), Ok(String::from("sku-0042")));
 	}
 
 	#[test]
 	fn item_parse_test() {
-		assert_eq!(Item::parse(String::from("TEST")), Item::Value(String::from("TEST")));
-		assert_eq!(Item::parse(String::from("<cell1>")), Item::Cell(0, None));
-		assert_eq!(Item::parse(String::from("<cell999>")), Item::Cell(998, None));
-		assert_eq!(Item::parse(String::from("<cell1 UPPER_CASE>")), Item::Cell(0, Some(vec![Filter::UpperCase])));
+		assert_eq!(Item::parse(String::from("TEST")).unwrap(), Item::Value(String::from("TEST")));
+		assert_eq!(Item::parse(String::from("<cell1>")).unwrap(), Item::Cell(0, None));
+		assert_eq!(Item::parse(String::from("<cell999>")).unwrap(), Item::Cell(998, None));
+		assert_eq!(Item::parse(String::from("<cell1 UPPER_CASE>")).unwrap(), Item::Cell(0, Some(vec![Filter::UpperCase])));
 		assert_eq!(
-			Item::parse(String::from("<cell1 REPLACE|'\"'|'\\'' LOWER_CASE>")),
+			Item::parse(String::from("<cell1 REPLACE|'\"'|'\\'' LOWER_CASE>")).unwrap(),
 			Item::Cell(
 				0,
 				Some(vec![
@@ -1090,23 +3509,81 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn column_test() {
+		assert_eq!(Item::parse(String::from("<col 'SKU'>")), Ok(Item::Column(String::from("SKU"), None)));
+		assert_eq!(
+			Item::parse(String::from("<col 'Product Name' UPPER_CASE SPLIT|' '|0>")),
+			Ok(Item::Column(
+				String::from("Product Name"),
+				Some(vec![Filter::UpperCase, Filter::Split(String::from(" "), 0)])
+			))
+		);
+		assert_eq!(Item::parse(String::from("<col 'It\\'s'>")), Ok(Item::Column(String::from("It's"), None)));
+		assert!(Item::parse(String::from("<col SKU>")).is_err());
+		assert!(Item::parse(String::from("<col 'SKU>")).is_err());
+
+		assert_eq!(
+			Condition::parse("<col 'Color'> == <col 'Old Color'> (<col 'Name' REPLACE|' '|'-'>) ELSE ('none')"),
+			Ok(Item::If(
+				Condition::Equals(
+					Box::new(Item::Column(String::from("Old Color"), None)),
+					Box::new(Item::Column(String::from("Color"), None))
+				),
+				Box::new(Item::Column(String::from("Name"), Some(vec![Filter::Replace(String::from(" "), String::from("-"))]))),
+				Some(Box::new(Item::Value(String::from("none"))))
+			))
+		);
+
+		let mut output_config = OutputConfig::new(CsvParser::new(Cursor::new(
+			"H1,H2\n<col 'Name'>,:IF <col 'Color'> IS_EMPTY ('none') ELSE (<col 'Color' UPPER_CASE>)\n",
+		)))
+		.unwrap();
+		output_config.resolve_headers(&[String::from("Color"), String::from("Name")]).unwrap();
+		assert_eq!(
+			output_config.lines,
+			vec![Line::new(
+				2,
+				vec![
+					Item::Cell(1, None),
+					Item::If(
+						Condition::IsEmpty(Box::new(Item::Cell(0, None))),
+						Box::new(Item::Value(String::from("none"))),
+						Some(Box::new(Item::Cell(0, Some(vec![Filter::UpperCase]))))
+					),
+				]
+			)]
+		);
+
+		let mut output_config =
+			OutputConfig::new(CsvParser::new(Cursor::new("H1,H2\n<col 'Name'>,<col 'Size'>\n"))).unwrap();
+		assert_eq!(
+			output_config.resolve_headers(&[String::from("Name"), String::from("Color")]).map_err(|error| (
+				error.stage,
+				error.row,
+				error.column
+			)),
+			Err((ErrorStages::ConfigParsing, Some(2), Some(2)))
+		);
+	}
+
 	#[test]
 	fn filter_parsing_test() {
-		assert_eq!(Filter::parse("UPPER_CASE"), vec![Filter::UpperCase]);
-		assert_eq!(Filter::parse("LOWER_CASE"), vec![Filter::LowerCase]);
-		assert_eq!(Filter::parse("LENGTH"), vec![Filter::Length]);
-		assert_eq!(Filter::parse("TRIM"), vec![Filter::Trim]);
-		assert_eq!(Filter::parse("TRIM_START"), vec![Filter::TrimStart]);
-		assert_eq!(Filter::parse("TRIM_END"), vec![Filter::TrimEnd]);
-		assert_eq!(Filter::parse("REPLACE|' '|''"), vec![Filter::Replace(String::from(" "), String::from(""))]);
-		assert_eq!(Filter::parse("APPEND|'x'"), vec![Filter::Append(String::from("x"))]);
-		assert_eq!(Filter::parse("PREPEND|'x'"), vec![Filter::Prepend(String::from("x"))]);
-		assert_eq!(Filter::parse("SPLIT|'x'|3"), vec![Filter::Split(String::from("x"), 3)]);
-		assert_eq!(Filter::parse("SUB_STRING|5"), vec![Filter::SubString(5, None)]);
-		assert_eq!(Filter::parse("SUB_STRING|5|10"), vec![Filter::SubString(5, Some(10))]);
-
-		assert_eq!(
-			Filter::parse("REPLACE|'\"'|'\\'' LOWER_CASE"),
+		assert_eq!(Filter::parse("UPPER_CASE").unwrap(), vec![Filter::UpperCase]);
+		assert_eq!(Filter::parse("LOWER_CASE").unwrap(), vec![Filter::LowerCase]);
+		assert_eq!(Filter::parse("LENGTH").unwrap(), vec![Filter::Length]);
+		assert_eq!(Filter::parse("TRIM").unwrap(), vec![Filter::Trim]);
+		assert_eq!(Filter::parse("TRIM_START").unwrap(), vec![Filter::TrimStart]);
+		assert_eq!(Filter::parse("TRIM_END").unwrap(), vec![Filter::TrimEnd]);
+		assert_eq!(Filter::parse("REPLACE|' '|''").unwrap(), vec![Filter::Replace(String::from(" "), String::from(""))]);
+		assert_eq!(Filter::parse("APPEND|'x'").unwrap(), vec![Filter::Append(String::from("x"))]);
+		assert_eq!(Filter::parse("PREPEND|'x'").unwrap(), vec![Filter::Prepend(String::from("x"))]);
+		assert_eq!(Filter::parse("SPLIT|'x'|3").unwrap(), vec![Filter::Split(String::from("x"), 3)]);
+		assert_eq!(Filter::parse("SUB_STRING|5").unwrap(), vec![Filter::SubString(5, None)]);
+		assert_eq!(Filter::parse("SUB_STRING|5|10").unwrap(), vec![Filter::SubString(5, Some(10))]);
+
+		assert_eq!(
+			Filter::parse("REPLACE|'\"'|'\\'' LOWER_CASE").unwrap(),
 			vec![
 				Filter::Replace(String::from("\""), String::from("'")),
 				Filter::LowerCase
@@ -1114,7 +3591,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Filter::parse("UPPER_CASE LOWER_CASE LENGTH TRIM TRIM_START TRIM_END REPLACE|'blue'|'green' APPEND|'x' PREPEND|'x' SPLIT|' '|666 SUB_STRING|5 SUB_STRING|5|10"),
+			Filter::parse("UPPER_CASE LOWER_CASE LENGTH TRIM TRIM_START TRIM_END REPLACE|'blue'|'green' APPEND|'x' PREPEND|'x' SPLIT|' '|666 SUB_STRING|5 SUB_STRING|5|10").unwrap(),
 			vec![
 				Filter::UpperCase,
 				Filter::LowerCase,
@@ -1135,7 +3612,7 @@ mod tests {
 	#[test]
 	fn conditional_isempty_test() {
 		assert_eq!(
-			Condition::parse("<cell1> IS_EMPTY ('yay') ELSE (<cell2>)"),
+			Condition::parse("<cell1> IS_EMPTY ('yay') ELSE (<cell2>)").unwrap(),
 			Item::If(
 				Condition::IsEmpty(Box::new(Item::Cell(0, None))),
 				Box::new(Item::Value(String::from("yay"))),
@@ -1144,39 +3621,35 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1> IS_EMPTY (<cell2>)"),
+			Condition::parse("<cell1> IS_EMPTY (<cell2>)").unwrap(),
 			Item::If(Condition::IsEmpty(Box::new(Item::Cell(0, None))), Box::new(Item::Cell(1, None)), None)
 		);
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_EMPTY (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_EMPTY (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_EMPTY (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_EMPTY (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from(""), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from(""), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_EMPTY (<cell2>) ELSE (<cell3>)") {
+		if let Item::If(condition, then_item, else_item) =
+			Condition::parse("<cell1> IS_EMPTY (<cell2>) ELSE (<cell3>)").unwrap()
+		{
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("C")
 			);
 		}
@@ -1185,7 +3658,7 @@ mod tests {
 	#[test]
 	fn conditional_isnotempty_test() {
 		assert_eq!(
-			Condition::parse("<cell1> IS_NOT_EMPTY ('yay') ELSE (<cell2>)"),
+			Condition::parse("<cell1> IS_NOT_EMPTY ('yay') ELSE (<cell2>)").unwrap(),
 			Item::If(
 				Condition::IsNotEmpty(Box::new(Item::Cell(0, None))),
 				Box::new(Item::Value(String::from("yay"))),
@@ -1194,40 +3667,35 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1> IS_NOT_EMPTY (<cell2>)"),
+			Condition::parse("<cell1> IS_NOT_EMPTY (<cell2>)").unwrap(),
 			Item::If(Condition::IsNotEmpty(Box::new(Item::Cell(0, None))), Box::new(Item::Cell(1, None)), None)
 		);
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_NOT_EMPTY (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_NOT_EMPTY (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_NOT_EMPTY (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_NOT_EMPTY (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from(""), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from(""), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_NOT_EMPTY (<cell2>) ELSE (<cell3>)")
+		if let Item::If(condition, then_item, else_item) =
+			Condition::parse("<cell1> IS_NOT_EMPTY (<cell2>) ELSE (<cell3>)").unwrap()
 		{
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("B")
 			);
 		}
@@ -1236,7 +3704,7 @@ mod tests {
 	#[test]
 	fn conditional_isnumeric_test() {
 		assert_eq!(
-			Condition::parse("<cell1> IS_NUMERIC ('yay') ELSE (<cell2>)"),
+			Condition::parse("<cell1> IS_NUMERIC ('yay') ELSE (<cell2>)").unwrap(),
 			Item::If(
 				Condition::IsNumeric(Box::new(Item::Cell(0, None))),
 				Box::new(Item::Value(String::from("yay"))),
@@ -1245,50 +3713,56 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1> IS_NUMERIC (<cell2>)"),
+			Condition::parse("<cell1> IS_NUMERIC (<cell2>)").unwrap(),
 			Item::If(Condition::IsNumeric(Box::new(Item::Cell(0, None))), Box::new(Item::Cell(1, None)), None)
 		);
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_NUMERIC (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_NUMERIC (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("666"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(
+						&then_item,
+						else_item.as_deref(),
+						&Row::new(&[String::from("666"), String::from("B"), String::from("C")])
+					)
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_NUMERIC (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_NUMERIC (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("666.42"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(
+						&then_item,
+						else_item.as_deref(),
+						&Row::new(&[String::from("666.42"), String::from("B"), String::from("C")])
+					)
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_NUMERIC (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_NUMERIC (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("-666.42"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(
+						&then_item,
+						else_item.as_deref(),
+						&Row::new(&[String::from("-666.42"), String::from("B"), String::from("C")])
+					)
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> IS_NUMERIC (<cell2>) ELSE (<cell3>)") {
+		if let Item::If(condition, then_item, else_item) =
+			Condition::parse("<cell1> IS_NUMERIC (<cell2>) ELSE (<cell3>)").unwrap()
+		{
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("C")
 			);
 		}
@@ -1297,7 +3771,7 @@ mod tests {
 	#[test]
 	fn conditional_starteswith_test() {
 		assert_eq!(
-			Condition::parse("<cell1> STARTS_WITH|'foo' ('ya\\'y') ELSE (<cell2>)"),
+			Condition::parse("<cell1> STARTS_WITH|'foo' ('ya\\'y') ELSE (<cell2>)").unwrap(),
 			Item::If(
 				Condition::StartesWith(String::from("foo"), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Value(String::from("ya'y"))),
@@ -1306,7 +3780,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1> STARTS_WITH|'foo' (<cell2>)"),
+			Condition::parse("<cell1> STARTS_WITH|'foo' (<cell2>)").unwrap(),
 			Item::If(
 				Condition::StartesWith(String::from("foo"), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1315,7 +3789,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1> STARTS_WITH|'fo\\'o' (<cell2>)"),
+			Condition::parse("<cell1> STARTS_WITH|'fo\\'o' (<cell2>)").unwrap(),
 			Item::If(
 				Condition::StartesWith(String::from("fo'o"), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1323,37 +3797,41 @@ mod tests {
 			)
 		);
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> STARTS_WITH|'foo' (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> STARTS_WITH|'foo' (<cell2>)").unwrap()
+		{
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("foobar"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(
+						&then_item,
+						else_item.as_deref(),
+						&Row::new(&[String::from("foobar"), String::from("B"), String::from("C")])
+					)
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> STARTS_WITH|'foo' (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> STARTS_WITH|'foo' (<cell2>)").unwrap()
+		{
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("furchtbar"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(
+						&then_item,
+						else_item.as_deref(),
+						&Row::new(&[String::from("furchtbar"), String::from("B"), String::from("C")])
+					)
+					.unwrap(),
 				String::from("")
 			);
 		}
 
 		if let Item::If(condition, then_item, else_item) =
-			Condition::parse("<cell1> STARTS_WITH|'foo' (<cell2>) ELSE (<cell3>)")
+			Condition::parse("<cell1> STARTS_WITH|'foo' (<cell2>) ELSE (<cell3>)").unwrap()
 		{
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("C")
 			);
 		}
@@ -1362,7 +3840,7 @@ mod tests {
 	#[test]
 	fn conditional_endswith_test() {
 		assert_eq!(
-			Condition::parse("<cell1> ENDS_WITH|'foo' ('ya\\'y') ELSE (<cell2>)"),
+			Condition::parse("<cell1> ENDS_WITH|'foo' ('ya\\'y') ELSE (<cell2>)").unwrap(),
 			Item::If(
 				Condition::EndsWith(String::from("foo"), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Value(String::from("ya'y"))),
@@ -1371,7 +3849,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1> ENDS_WITH|'foo' (<cell2>)"),
+			Condition::parse("<cell1> ENDS_WITH|'foo' (<cell2>)").unwrap(),
 			Item::If(
 				Condition::EndsWith(String::from("foo"), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1380,7 +3858,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1> ENDS_WITH|'fo\\'o' (<cell2>)"),
+			Condition::parse("<cell1> ENDS_WITH|'fo\\'o' (<cell2>)").unwrap(),
 			Item::If(
 				Condition::EndsWith(String::from("fo'o"), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1388,37 +3866,39 @@ mod tests {
 			)
 		);
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> ENDS_WITH|'foo' (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> ENDS_WITH|'foo' (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("kungfoo"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(
+						&then_item,
+						else_item.as_deref(),
+						&Row::new(&[String::from("kungfoo"), String::from("B"), String::from("C")])
+					)
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> ENDS_WITH|'foo' (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> ENDS_WITH|'foo' (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("Kung Fu"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(
+						&then_item,
+						else_item.as_deref(),
+						&Row::new(&[String::from("Kung Fu"), String::from("B"), String::from("C")])
+					)
+					.unwrap(),
 				String::from("")
 			);
 		}
 
 		if let Item::If(condition, then_item, else_item) =
-			Condition::parse("<cell1> ENDS_WITH|'foo' (<cell2>) ELSE (<cell3>)")
+			Condition::parse("<cell1> ENDS_WITH|'foo' (<cell2>) ELSE (<cell3>)").unwrap()
 		{
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("C")
 			);
 		}
@@ -1427,7 +3907,7 @@ mod tests {
 	#[test]
 	fn conditional_contains_test() {
 		assert_eq!(
-			Condition::parse("<cell1> CONTAINS|'foo' ('yay') ELSE (<cell2>)"),
+			Condition::parse("<cell1> CONTAINS|'foo' ('yay') ELSE (<cell2>)").unwrap(),
 			Item::If(
 				Condition::Contains(String::from("foo"), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Value(String::from("yay"))),
@@ -1436,7 +3916,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1> CONTAINS|'foo' (<cell2>)"),
+			Condition::parse("<cell1> CONTAINS|'foo' (<cell2>)").unwrap(),
 			Item::If(
 				Condition::Contains(String::from("foo"), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1444,37 +3924,39 @@ mod tests {
 			)
 		);
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> CONTAINS|'foo' (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> CONTAINS|'foo' (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("kungfoo"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(
+						&then_item,
+						else_item.as_deref(),
+						&Row::new(&[String::from("kungfoo"), String::from("B"), String::from("C")])
+					)
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> CONTAINS|'foo' (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> CONTAINS|'foo' (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("Kung Fu"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(
+						&then_item,
+						else_item.as_deref(),
+						&Row::new(&[String::from("Kung Fu"), String::from("B"), String::from("C")])
+					)
+					.unwrap(),
 				String::from("")
 			);
 		}
 
 		if let Item::If(condition, then_item, else_item) =
-			Condition::parse("<cell1> CONTAINS|'foo' (<cell2>) ELSE (<cell3>)")
+			Condition::parse("<cell1> CONTAINS|'foo' (<cell2>) ELSE (<cell3>)").unwrap()
 		{
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("C")
 			);
 		}
@@ -1483,7 +3965,7 @@ mod tests {
 	#[test]
 	fn conditional_equals_test() {
 		assert_eq!(
-			Condition::parse("<cell1> == 'foo' ('yay') ELSE (<cell2>)"),
+			Condition::parse("<cell1> == 'foo' ('yay') ELSE (<cell2>)").unwrap(),
 			Item::If(
 				Condition::Equals(Box::new(Item::Value(String::from("foo"))), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Value(String::from("yay"))),
@@ -1492,7 +3974,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1> == 'foo' (<cell2>)"),
+			Condition::parse("<cell1> == 'foo' (<cell2>)").unwrap(),
 			Item::If(
 				Condition::Equals(Box::new(Item::Value(String::from("foo"))), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1501,7 +3983,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1>=='foo'(<cell2>)"),
+			Condition::parse("<cell1>=='foo'(<cell2>)").unwrap(),
 			Item::If(
 				Condition::Equals(Box::new(Item::Value(String::from("foo"))), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1510,7 +3992,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1> == <cell666> (<cell2>)"),
+			Condition::parse("<cell1> == <cell666> (<cell2>)").unwrap(),
 			Item::If(
 				Condition::Equals(Box::new(Item::Cell(665, None)), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1518,57 +4000,49 @@ mod tests {
 			)
 		);
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> == A (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> == A (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> == X (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> == X (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> == <cell3> (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> == <cell3> (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("A")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("A")]))
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> == <cell3> (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> == <cell3> (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> == X (<cell2>) ELSE (<cell3>)") {
+		if let Item::If(condition, then_item, else_item) =
+			Condition::parse("<cell1> == X (<cell2>) ELSE (<cell3>)").unwrap()
+		{
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("C")
 			);
 		}
@@ -1577,7 +4051,7 @@ mod tests {
 	#[test]
 	fn conditional_notequals_test() {
 		assert_eq!(
-			Condition::parse("<cell1> != 'foo' ('yay') ELSE (<cell2>)"),
+			Condition::parse("<cell1> != 'foo' ('yay') ELSE (<cell2>)").unwrap(),
 			Item::If(
 				Condition::NotEquals(Box::new(Item::Value(String::from("foo"))), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Value(String::from("yay"))),
@@ -1586,7 +4060,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1>    != 'foo' (<cell2>)"),
+			Condition::parse("<cell1>    != 'foo' (<cell2>)").unwrap(),
 			Item::If(
 				Condition::NotEquals(Box::new(Item::Value(String::from("foo"))), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1595,7 +4069,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1>!='foo'(<cell2>)"),
+			Condition::parse("<cell1>!='foo'(<cell2>)").unwrap(),
 			Item::If(
 				Condition::NotEquals(Box::new(Item::Value(String::from("foo"))), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1604,7 +4078,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1> != <cell666> (<cell2>)"),
+			Condition::parse("<cell1> != <cell666> (<cell2>)").unwrap(),
 			Item::If(
 				Condition::NotEquals(Box::new(Item::Cell(665, None)), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1612,57 +4086,49 @@ mod tests {
 			)
 		);
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> != X (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> != X (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> != A (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> != A (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> != <cell3> (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> != <cell3> (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> != <cell3> (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> != <cell3> (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("A")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("A")]))
+					.unwrap(),
 				String::from("")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> != A (<cell2>) ELSE (<cell3>)") {
+		if let Item::If(condition, then_item, else_item) =
+			Condition::parse("<cell1> != A (<cell2>) ELSE (<cell3>)").unwrap()
+		{
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("A"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("A"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("C")
 			);
 		}
@@ -1671,7 +4137,7 @@ mod tests {
 	#[test]
 	fn conditional_greaterthan_test() {
 		assert_eq!(
-			Condition::parse("<cell1> > 5 ('yay') ELSE (<cell2>)"),
+			Condition::parse("<cell1> > 5 ('yay') ELSE (<cell2>)").unwrap(),
 			Item::If(
 				Condition::GreaterThan(Box::new(Item::Value(String::from("5"))), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Value(String::from("yay"))),
@@ -1680,7 +4146,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1> > 666 (<cell2>)"),
+			Condition::parse("<cell1> > 666 (<cell2>)").unwrap(),
 			Item::If(
 				Condition::GreaterThan(Box::new(Item::Value(String::from("666"))), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1689,7 +4155,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1>  >   -666      (<cell2>)"),
+			Condition::parse("<cell1>  >   -666      (<cell2>)").unwrap(),
 			Item::If(
 				Condition::GreaterThan(Box::new(Item::Value(String::from("-666"))), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1697,57 +4163,48 @@ mod tests {
 			)
 		);
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> > 5 (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> > 5 (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("6"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("6"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> > 5 (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> > 5 (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("5"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("5"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> > <cell3> (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> > <cell3> (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("6"), String::from("B"), String::from("5")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("6"), String::from("B"), String::from("5")]))
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> > <cell3> (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> > <cell3> (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("5"), String::from("B"), String::from("5")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("5"), String::from("B"), String::from("5")]))
+					.unwrap(),
 				String::from("")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> > 5 (<cell2>) ELSE (<cell3>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> > 5 (<cell2>) ELSE (<cell3>)").unwrap()
+		{
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("4"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("4"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("C")
 			);
 		}
@@ -1756,7 +4213,7 @@ mod tests {
 	#[test]
 	fn conditional_lessthan_test() {
 		assert_eq!(
-			Condition::parse("<cell1> < 5 ('yay') ELSE (<cell2>)"),
+			Condition::parse("<cell1> < 5 ('yay') ELSE (<cell2>)").unwrap(),
 			Item::If(
 				Condition::LessThan(Box::new(Item::Value(String::from("5"))), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Value(String::from("yay"))),
@@ -1765,7 +4222,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1> < 666 (<cell2>)"),
+			Condition::parse("<cell1> < 666 (<cell2>)").unwrap(),
 			Item::If(
 				Condition::LessThan(Box::new(Item::Value(String::from("666"))), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1774,7 +4231,7 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1>  <   -666      (<cell2>)"),
+			Condition::parse("<cell1>  <   -666      (<cell2>)").unwrap(),
 			Item::If(
 				Condition::LessThan(Box::new(Item::Value(String::from("-666"))), Box::new(Item::Cell(0, None))),
 				Box::new(Item::Cell(1, None)),
@@ -1782,57 +4239,48 @@ mod tests {
 			)
 		);
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> < 5 (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> < 5 (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("4"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("4"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> < 5 (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> < 5 (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("5"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("5"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> < <cell3> (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> < <cell3> (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("4"), String::from("B"), String::from("5")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("4"), String::from("B"), String::from("5")]))
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> < <cell3> (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> < <cell3> (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("5"), String::from("B"), String::from("5")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("5"), String::from("B"), String::from("5")]))
+					.unwrap(),
 				String::from("")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> < 5 (<cell2>) ELSE (<cell3>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> < 5 (<cell2>) ELSE (<cell3>)").unwrap()
+		{
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("6"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("6"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("C")
 			);
 		}
@@ -1841,7 +4289,7 @@ mod tests {
 	#[test]
 	fn conditional_modulo_test() {
 		assert_eq!(
-			Condition::parse("<cell1> % 3 = 0 ('yay') ELSE (<cell2>)"),
+			Condition::parse("<cell1> % 3 = 0 ('yay') ELSE (<cell2>)").unwrap(),
 			Item::If(
 				Condition::Modulo(3.0, 0.0, Box::new(Item::Cell(0, None))),
 				Box::new(Item::Value(String::from("yay"))),
@@ -1850,41 +4298,850 @@ mod tests {
 		);
 
 		assert_eq!(
-			Condition::parse("<cell1>  %  42   =  -20  (<cell2>)"),
+			Condition::parse("<cell1>  %  42   =  -20  (<cell2>)").unwrap(),
 			Item::If(Condition::Modulo(42.0, -20.0, Box::new(Item::Cell(0, None))), Box::new(Item::Cell(1, None)), None)
 		);
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> % 2 = 0 (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> % 2 = 0 (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("4"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("4"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("B")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> % 2 = 0 (<cell2>)") {
+		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> % 2 = 0 (<cell2>)").unwrap() {
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("5"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("5"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("")
 			);
 		}
 
-		if let Item::If(condition, then_item, else_item) = Condition::parse("<cell1> % 2 = 0 (<cell2>) ELSE (<cell3>)") {
+		if let Item::If(condition, then_item, else_item) =
+			Condition::parse("<cell1> % 2 = 0 (<cell2>) ELSE (<cell3>)").unwrap()
+		{
 			assert_eq!(
-				condition.run(
-					&*then_item,
-					&else_item.map(|b| *b),
-					&vec![String::from("5"), String::from("B"), String::from("C")]
-				),
+				condition
+					.run(&then_item, else_item.as_deref(), &Row::new(&[String::from("5"), String::from("B"), String::from("C")]))
+					.unwrap(),
 				String::from("C")
 			);
 		}
 	}
+
+	#[test]
+	fn conditional_compound_test() {
+		assert_eq!(
+			Condition::parse("<cell1> IS_EMPTY AND <cell2> > 0 OR NOT <cell3> IS_NUMERIC ('yay')").unwrap(),
+			Item::If(
+				Condition::Or(
+					Box::new(Condition::And(
+						Box::new(Condition::IsEmpty(Box::new(Item::Cell(0, None)))),
+						Box::new(Condition::GreaterThan(Box::new(Item::Value(String::from("0"))), Box::new(Item::Cell(1, None))))
+					)),
+					Box::new(Condition::Not(Box::new(Condition::IsNumeric(Box::new(Item::Cell(2, None))))))
+				),
+				Box::new(Item::Value(String::from("yay"))),
+				None
+			)
+		);
+
+		assert_eq!(
+			Condition::parse("<cell1> IS_EMPTY AND (<cell2> == 'a b' OR <cell3> STARTS_WITH|'(x)') (<cell4>)").unwrap(),
+			Item::If(
+				Condition::And(
+					Box::new(Condition::IsEmpty(Box::new(Item::Cell(0, None)))),
+					Box::new(Condition::Or(
+						Box::new(Condition::Equals(Box::new(Item::Value(String::from("a b"))), Box::new(Item::Cell(1, None)))),
+						Box::new(Condition::StartesWith(String::from("(x)"), Box::new(Item::Cell(2, None))))
+					))
+				),
+				Box::new(Item::Cell(3, None)),
+				None
+			)
+		);
+
+		assert_eq!(
+			Condition::parse_expression("NOT (<cell1> IS_EMPTY OR <cell2> IS_EMPTY)"),
+			Ok(Condition::Not(Box::new(Condition::Or(
+				Box::new(Condition::IsEmpty(Box::new(Item::Cell(0, None)))),
+				Box::new(Condition::IsEmpty(Box::new(Item::Cell(1, None))))
+			))))
+		);
+
+		let condition = Condition::parse_expression("<cell1> > 0 AND NOT (<cell2> IS_EMPTY OR <cell3> IS_EMPTY)").unwrap();
+		assert_eq!(condition.is_true(&Row::new(&[String::from("5"), String::from("B"), String::from("C")])), Ok(true));
+		assert_eq!(condition.is_true(&Row::new(&[String::from("5"), String::from(""), String::from("C")])), Ok(false));
+		assert_eq!(condition.is_true(&Row::new(&[String::from("0"), String::from("B"), String::from("C")])), Ok(false));
+		// short circuit means the right side is never evaluated so it can't fail
+		let condition = Condition::parse_expression("<cell1> IS_EMPTY OR <cell2> > 5").unwrap();
+		assert_eq!(condition.is_true(&Row::new(&[String::from(""), String::from("not a number")])), Ok(true));
+		assert!(condition.is_true(&Row::new(&[String::from("A"), String::from("not a number")])).is_err());
+
+		assert!(Condition::parse("<cell1> IS_EMPTY AND ('yay')").is_err());
+		assert!(Condition::parse("(<cell1> IS_EMPTY ('yay')").is_err());
+		assert!(Condition::parse("<cell1> IS_EMPTY ('yay') <cell2>").is_err());
+		assert!(Condition::parse_expression("<cell1> IS_EMPTY ('yay')").is_err());
+	}
+
+	#[test]
+	fn conditional_nested_test() {
+		assert_eq!(
+			Condition::parse("<cell1> == 'a' ('A') ELSE IF <cell1> == 'b' ('B') ELSE ('C')").unwrap(),
+			Item::If(
+				Condition::Equals(Box::new(Item::Value(String::from("a"))), Box::new(Item::Cell(0, None))),
+				Box::new(Item::Value(String::from("A"))),
+				Some(Box::new(Item::If(
+					Condition::Equals(Box::new(Item::Value(String::from("b"))), Box::new(Item::Cell(0, None))),
+					Box::new(Item::Value(String::from("B"))),
+					Some(Box::new(Item::Value(String::from("C"))))
+				)))
+			)
+		);
+
+		assert_eq!(
+			Condition::parse("<cell1> IS_EMPTY (:IF <cell2> IS_EMPTY ('both (empty)') ELSE (<cell2>)) ELSE (<cell1>)")
+				.unwrap(),
+			Item::If(
+				Condition::IsEmpty(Box::new(Item::Cell(0, None))),
+				Box::new(Item::If(
+					Condition::IsEmpty(Box::new(Item::Cell(1, None))),
+					Box::new(Item::Value(String::from("both (empty)"))),
+					Some(Box::new(Item::Cell(1, None)))
+				)),
+				Some(Box::new(Item::Cell(0, None)))
+			)
+		);
+
+		if let Item::If(condition, then_item, else_item) = Condition::parse(
+			"<cell1> == 'a' ('A') ELSE IF <cell1> == 'b' (:IF <cell2> IS_EMPTY ('B') ELSE (<cell2>)) ELSE ('C')",
+		)
+		.unwrap()
+		{
+			let else_item = else_item.as_deref();
+			assert_eq!(condition.run(&then_item, else_item, &Row::new(&[String::from("a"), String::from("")])).unwrap(), "A");
+			assert_eq!(condition.run(&then_item, else_item, &Row::new(&[String::from("b"), String::from("")])).unwrap(), "B");
+			assert_eq!(
+				condition.run(&then_item, else_item, &Row::new(&[String::from("b"), String::from("X")])).unwrap(),
+				"X"
+			);
+			assert_eq!(condition.run(&then_item, else_item, &Row::new(&[String::from("c"), String::from("")])).unwrap(), "C");
+		}
+	}
+
+	#[test]
+	fn each_test() {
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new(
+				"H1,H2,H3\n<cell1>,<cell2>,\n:EACH <cell5 TRIM> BY '|',,\n<cell1>,<each UPPER_CASE>,<index>\n:EACH <cell3..cell4>\n<cell1>,:IF <each> ENDS_WITH|'.png' ('png'),\n"
+			)))
+			.unwrap()
+			.lines,
+			vec![
+				Line::new(2, vec![Item::Cell(0, None), Item::Cell(1, None), Item::Value(String::from(""))]),
+				Line {
+					row: 4,
+					items: vec![Item::Cell(0, None), Item::Each(Some(vec![Filter::UpperCase])), Item::Index(None)],
+					each: Some(Each {
+						row: 3,
+						source: EachSource::Split(Item::Cell(4, Some(vec![Filter::Trim])), String::from("|"))
+					}),
+					guard: None,
+				},
+				Line {
+					row: 6,
+					items: vec![
+						Item::Cell(0, None),
+						Item::If(
+							Condition::EndsWith(String::from(".png"), Box::new(Item::Each(None))),
+							Box::new(Item::Value(String::from("png"))),
+							None
+						),
+						Item::Value(String::from("")),
+					],
+					each: Some(Each {
+						row: 5,
+						source: EachSource::Range(2, 3, None)
+					}),
+					guard: None,
+				},
+			]
+		);
+
+		assert_eq!(
+			Each::parse("<col 'Images'> BY ', '", 2),
+			Ok(Each {
+				row: 2,
+				source: EachSource::Split(Item::Column(String::from("Images"), None), String::from(", "))
+			})
+		);
+		assert_eq!(
+			Each::parse("<cell1..cell3 TRIM LOWER_CASE>", 2),
+			Ok(Each {
+				row: 2,
+				source: EachSource::Range(0, 2, Some(vec![Filter::Trim, Filter::LowerCase]))
+			})
+		);
+		assert!(Each::parse("<cell1>", 2).is_err());
+		assert!(Each::parse("<cell1> BY ''", 2).is_err());
+		assert!(Each::parse("<cell3..cell1>", 2).is_err());
+		assert!(Each::parse("<cell0..cell1>", 2).is_err());
+		assert!(Each::parse("<cell1..cell2> BY '|'", 2).is_err());
+
+		let mut output_config =
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n:EACH <col 'Images'> BY '|'\n<each>\n"))).unwrap();
+		output_config.resolve_headers(&[String::from("SKU"), String::from("Images")]).unwrap();
+		assert_eq!(
+			output_config.lines[0].each,
+			Some(Each {
+				row: 2,
+				source: EachSource::Split(Item::Cell(1, None), String::from("|"))
+			})
+		);
+
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new("H1,H2\n<cell1>,<cell2>\n:EACH <cell1> BY '|'\n")))
+				.map_err(|error| (error.stage, error.row, error.column)),
+			Err((ErrorStages::ConfigParsing, Some(3), Some(1)))
+		);
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new("H1,H2\n:EACH <cell1> BY '|',<cell2>\n<each>,<index>\n")))
+				.map_err(|error| (error.stage, error.row, error.column)),
+			Err((ErrorStages::ConfigParsing, Some(2), Some(2)))
+		);
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n:EACH <cell1> BY '|'\n:EACH <cell2> BY '|'\n<each>\n")))
+				.map_err(|error| (error.stage, error.row, error.column)),
+			Err((ErrorStages::ConfigParsing, Some(3), Some(1)))
+		);
+	}
+
+	#[test]
+	fn where_test() {
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new(
+				"H1,H2\n:WHERE <cell1> > 0 AND NOT <cell2> IS_EMPTY\n<cell1>,<cell2>\n:EACH <cell3> BY '|'\n:WHERE <each> ENDS_WITH|'.jpg'\n<each>,<index>\n"
+			)))
+			.unwrap()
+			.lines,
+			vec![
+				Line {
+					row: 3,
+					items: vec![Item::Cell(0, None), Item::Cell(1, None)],
+					each: None,
+					guard: Some(Where {
+						row: 2,
+						condition: Condition::And(
+							Box::new(Condition::GreaterThan(
+								Box::new(Item::Value(String::from("0"))),
+								Box::new(Item::Cell(0, None))
+							)),
+							Box::new(Condition::Not(Box::new(Condition::IsEmpty(Box::new(Item::Cell(1, None))))))
+						),
+					}),
+				},
+				Line {
+					row: 6,
+					items: vec![Item::Each(None), Item::Index(None)],
+					each: Some(Each {
+						row: 4,
+						source: EachSource::Split(Item::Cell(2, None), String::from("|"))
+					}),
+					guard: Some(Where {
+						row: 5,
+						condition: Condition::EndsWith(String::from(".jpg"), Box::new(Item::Each(None))),
+					}),
+				},
+			]
+		);
+
+		let mut output_config =
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n:WHERE <col 'Price'> > 0\n<col 'SKU'>\n"))).unwrap();
+		output_config.resolve_headers(&[String::from("SKU"), String::from("Price")]).unwrap();
+		assert_eq!(
+			output_config.lines[0].guard,
+			Some(Where {
+				row: 2,
+				condition: Condition::GreaterThan(Box::new(Item::Value(String::from("0"))), Box::new(Item::Cell(1, None))),
+			})
+		);
+
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n:WHERE <cell1> IS_EMPTY ('x')\n<cell1>\n"))).map_err(|error| (
+				error.stage,
+				error.row,
+				error.column
+			)),
+			Err((ErrorStages::ConfigConditionParsing, Some(2), Some(1)))
+		);
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n:WHERE <cell1> IS_EMPTY\n:WHERE <cell2> IS_EMPTY\n<cell1>\n")))
+				.map_err(|error| (error.stage, error.row, error.column)),
+			Err((ErrorStages::ConfigParsing, Some(3), Some(1)))
+		);
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1>\n:WHERE <cell1> IS_EMPTY\n"))).map_err(|error| (
+				error.stage,
+				error.row,
+				error.column
+			)),
+			Err((ErrorStages::ConfigParsing, Some(3), Some(1)))
+		);
+
+		assert!(Condition::parse("<cell1> IS_EMPTY ('SKIP_THIS_LINE')").unwrap().uses_skip_this_line());
+		assert!(Condition::parse("<cell1> IS_EMPTY ('x') ELSE IF <cell2> IS_EMPTY ('SKIP_THIS_LINE')")
+			.unwrap()
+			.uses_skip_this_line());
+		assert!(!Condition::parse("<cell1> IS_EMPTY ('x') ELSE (<cell2>)").unwrap().uses_skip_this_line());
+		assert!(!Item::Value(String::from("SKIP_THIS_LINE")).uses_skip_this_line());
+	}
+
+	#[test]
+	fn filter_directive_test() {
+		let mut output_config = OutputConfig::new(CsvParser::new(Cursor::new(
+			"H1,H2\n<cell1>,<cell2>\n:FILTER <col 'Price'> > 0 AND <cell1> IS_NOT_EMPTY,\n",
+		)))
+		.unwrap();
+		assert_eq!(output_config.lines, vec![Line::new(2, vec![Item::Cell(0, None), Item::Cell(1, None)])]);
+		assert_eq!(output_config.filter.as_ref().map(|filter| filter.row), Some(3));
+
+		output_config.resolve_headers(&[String::from("SKU"), String::from("Price")]).unwrap();
+		assert_eq!(output_config.keep(&[String::from("A"), String::from("5")], None), Ok(true));
+		assert_eq!(output_config.keep(&[String::from("A"), String::from("0")], None), Ok(false));
+		assert_eq!(output_config.keep(&[String::from(""), String::from("5")], None), Ok(false));
+		assert_eq!(
+			output_config.keep(&[String::from("A"), String::from("free")], None).map_err(|error| (error.row, error.column)),
+			Err((Some(3), Some(1)))
+		);
+
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1>\n"))).unwrap().keep(&[String::from("")], None),
+			Ok(true)
+		);
+
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new(
+				"H1\n:FILTER <cell1> IS_EMPTY\n<cell1>\n:FILTER <cell2> IS_EMPTY\n"
+			)))
+			.map_err(|error| (error.stage, error.row, error.column)),
+			Err((ErrorStages::ConfigParsing, Some(4), Some(1)))
+		);
+	}
+
+	#[test]
+	fn group_by_test() {
+		let mut output_config = OutputConfig::new(CsvParser::new(Cursor::new(
+			"H1,H2,H3\n\
+			<cell1>,:SUM <cell2>,\":JOIN|', ' <col 'Tags' TRIM>\"\n\
+			<cell1>,:COUNT,:LAST <cell3>\n\
+			:GROUP_BY <col 'Handle'> <cell2 TRIM> SORTED\n",
+		)))
+		.unwrap();
+		assert_eq!(
+			output_config.group,
+			Some(GroupBy {
+				row: 4,
+				keys: vec![
+					Item::Column(String::from("Handle"), None),
+					Item::Cell(1, Some(vec![Filter::Trim]))
+				],
+				sorted: true,
+			})
+		);
+		assert_eq!(
+			output_config.lines[0].items[2],
+			Item::Aggregate(
+				Aggregate::Join(String::from(", ")),
+				Some(Box::new(Item::Column(String::from("Tags"), Some(vec![Filter::Trim]))))
+			)
+		);
+		assert_eq!(output_config.lines[1].items[1], Item::Aggregate(Aggregate::Count, None));
+
+		output_config.resolve_headers(&[String::from("Handle"), String::from("Stock"), String::from("Tags")]).unwrap();
+		assert_eq!(output_config.group.as_ref().map(|group| group.keys[0].clone()), Some(Item::Cell(0, None)));
+		assert_eq!(
+			output_config.lines[0].items[2],
+			Item::Aggregate(Aggregate::Join(String::from(", ")), Some(Box::new(Item::Cell(2, Some(vec![Filter::Trim])))))
+		);
+
+		assert_eq!(GroupBy::parse("<cell1>", 2).map(|group| group.sorted), Ok(false));
+		for group_str in ["", "SORTED", "<cell1> SORTED <cell2>", "cell1", "<cell1"] {
+			assert_eq!(
+				GroupBy::parse(group_str, 2).map_err(|error| error.stage),
+				Err(ErrorStages::ConfigParsing),
+				"{group_str}"
+			);
+		}
+		for item_str in [
+			":SUM",
+			":MIN|2 <cell1>",
+			":JOIN <cell1>",
+			":COUNT <cell1> <cell2>",
+			":FIRST :LAST <cell1>",
+		] {
+			assert_eq!(
+				Item::parse(String::from(item_str)).map_err(|error| error.stage),
+				Err(ErrorStages::ConfigParsing),
+				"{item_str}"
+			);
+		}
+		// only the exact names are aggregates
+		assert_eq!(Item::parse(String::from(":SUMMARY")), Ok(Item::Value(String::from(":SUMMARY"))));
+
+		let (_, diagnostics) = OutputConfig::check(CsvParser::new(Cursor::new(
+			"H1,H2\n\
+			<cell1>,:SUM <cell2>\n\
+			:WHERE <cell1> IS_EMPTY\n\
+			<cell1>,\":IF <cell1> IS_EMPTY (:COUNT)\"\n",
+		)));
+		// within a condition :COUNT is just a value
+		assert_eq!(
+			diagnostics.errors.iter().map(|error| (error.row, error.column)).collect::<Vec<_>>(),
+			vec![(Some(2), Some(2))]
+		);
+
+		let (_, diagnostics) = OutputConfig::check(CsvParser::new(Cursor::new(
+			"H1,H2\n\
+			:GROUP_BY <cell1>\n\
+			:EACH <cell2> BY '|'\n\
+			<cell1>,<each>\n\
+			<cell1>,:CALC :MAX <cell2> * 2\n\
+			:GROUP_BY <cell2>\n",
+		)));
+		assert_eq!(
+			diagnostics.errors.iter().map(|error| (error.row, error.column)).collect::<Vec<_>>(),
+			vec![(Some(5), Some(2)), (Some(6), Some(1)), (Some(3), Some(1))]
+		);
+	}
+
+	#[test]
+	fn lookup_test() {
+		let path = std::env::temp_dir().join("csv_converter_lookup_test.csv");
+		std::fs::write(&path, "Code;Color;Hex\nBLK;Black;#000\nWHT;White;#fff\nBLK;Duplicate;#111\n").unwrap();
+		let path = path.to_string_lossy();
+
+		assert_eq!(
+			Filter::parse(&format!("LOOKUP|'{path}'|1|2|'Unknown' UPPER_CASE")).unwrap(),
+			vec![
+				Filter::Lookup(Lookup {
+					path: path.to_string(),
+					key_column: 0,
+					value_column: 1,
+					default: Some(String::from("Unknown")),
+					values: Arc::new(HashMap::new()),
+				}),
+				Filter::UpperCase,
+			]
+		);
+		assert!(Filter::parse("LOOKUP|'colors.csv'|1").is_err());
+		assert!(Filter::parse("LOOKUP|'colors.csv'|0|2").is_err());
+		assert!(Filter::parse("LOOKUP|'colors.csv'|one|2").is_err());
+
+		let output_config = OutputConfig::new(CsvParser::new(Cursor::new(format!(
+			"H1,H2,H3\n<cell1 LOOKUP|'{path}'|1|2>,<cell1 LOOKUP|'{path}'|1|2|''>,:IF <cell1 LOOKUP|'{path}'|1|3> == '#fff' ('light')\n"
+		))))
+		.unwrap();
+		let (Item::Cell(_, Some(filters_a)), Item::Cell(_, Some(filters_b))) =
+			(&output_config.lines[0].items[0], &output_config.lines[0].items[1])
+		else {
+			panic!("Expected cells with filters");
+		};
+		let (Filter::Lookup(lookup_a), Filter::Lookup(lookup_b)) = (&filters_a[0], &filters_b[0]) else {
+			panic!("Expected lookup filters");
+		};
+		// the same file and columns are only loaded once
+		assert!(Arc::ptr_eq(&lookup_a.values, &lookup_b.values));
+		assert_eq!(lookup_a.values.len(), 3);
+
+		assert_eq!(filters_a[0].run(Cow::Borrowed("BLK")).unwrap(), Cow::<str>::Owned(String::from("Black")));
+		assert_eq!(filters_a[0].run(Cow::Borrowed("RED")).unwrap(), Cow::Borrowed("RED"));
+		assert_eq!(filters_b[0].run(Cow::Borrowed("RED")).unwrap(), Cow::Borrowed(""));
+		assert_eq!(
+			crate::process::run(&[String::from("WHT")], &output_config, &mut Default::default()),
+			Ok(vec![vec![
+				String::from("White"),
+				String::from("White"),
+				String::from("light")
+			]])
+		);
+
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new("H1,H2\n<cell1>,<cell2 LOOKUP|'does/not/exist.csv'|1|2>\n")))
+				.map_err(|error| (error.stage, error.row, error.column)),
+			Err((ErrorStages::Io, Some(2), Some(2)))
+		);
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new(format!("H1\n<cell1 LOOKUP|'{path}'|1|4>\n")))).map_err(|error| (
+				error.stage,
+				error.row,
+				error.column
+			)),
+			Err((ErrorStages::ConfigParsing, Some(2), Some(1)))
+		);
+
+		// relative paths are resolved against the directory of the config file
+		let dir = std::env::temp_dir().join("csv_converter_lookup_dir_test");
+		std::fs::create_dir_all(&dir).unwrap();
+		std::fs::write(dir.join("colors.csv"), "BLK,Black\n").unwrap();
+		let (output_config, diagnostics) =
+			OutputConfig::check_in(CsvParser::new(Cursor::new("H1\n<cell1 LOOKUP|'colors.csv'|1|2>\n")), &dir);
+		assert_eq!(diagnostics.errors, Vec::new());
+		assert_eq!(
+			crate::process::run(&[String::from("BLK")], &output_config, &mut Default::default()),
+			Ok(vec![vec![String::from("Black")]])
+		);
+	}
+
+	#[test]
+	fn check_test() {
+		let (output_config, diagnostics) = OutputConfig::check(CsvParser::new(Cursor::new(
+			"H1,H2,H3\n\
+			<cellx>,<cell1 UPPERCASE TRIM>,<cell2>\n\
+			:WHERE <cell1> IS_BLUE\n\
+			:EACH <cell3> BY '|',oops\n\
+			<each>,:IF <cell2> IS_EMPTY ('SKIP_THIS_LINE'),\n\
+			:FILTER <cell1 NOPE> IS_EMPTY\n\
+			:EACH <cell3> BY '|'\n",
+		)));
+
+		assert_eq!(
+			diagnostics
+				.errors
+				.iter()
+				.map(|error| (error.stage, error.row, error.column, error.text.as_deref()))
+				.collect::<Vec<_>>(),
+			vec![
+				(ErrorStages::ConfigParsing, Some(2), Some(1), Some("<cellx>")),
+				(ErrorStages::ConfigConditionParsing, Some(3), Some(1), Some(":WHERE <cell1> IS_BLUE")),
+				(ErrorStages::ConfigParsing, Some(4), Some(2), Some("oops")),
+				(ErrorStages::ConfigParsing, Some(7), Some(1), None),
+			]
+		);
+		assert_eq!(
+			diagnostics
+				.warnings
+				.iter()
+				.map(|warning| (warning.row, warning.column, warning.text.as_deref()))
+				.collect::<Vec<_>>(),
+			vec![
+				(Some(2), Some(2), Some("<cell1 UPPERCASE TRIM>")),
+				(Some(5), Some(2), Some(":IF <cell2> IS_EMPTY ('SKIP_THIS_LINE')")),
+				(Some(6), Some(1), Some(":FILTER <cell1 NOPE> IS_EMPTY")),
+			]
+		);
+		// broken cells are kept as text so the rest of the config can still be checked
+		assert_eq!(output_config.lines[0].items[0], Item::Value(String::from("<cellx>")));
+		assert_eq!(
+			output_config.lines[0].items[1],
+			Item::Cell(0, Some(vec![Filter::Unknown(String::from("UPPERCASE")), Filter::Trim]))
+		);
+		assert!(output_config.filter.is_some());
+
+		// unknown filters are ignored when converting
+		assert_eq!(Filter::Unknown(String::from("NOPE")).run(Cow::Borrowed(" x ")).unwrap(), " x ");
+
+		// the first error is what new returns
+		assert_eq!(
+			OutputConfig::new(CsvParser::new(Cursor::new("H1,H2\n<cell1>,<celly>\n<cellz>,\n")))
+				.map_err(|error| (error.row, error.column)),
+			Err((Some(2), Some(2)))
+		);
+
+		let (_, diagnostics) = OutputConfig::check(CsvParser::new(Cursor::new("H1,H2\n<cell1>,<col 'SKU' TRIM>\n")));
+		assert!(diagnostics.is_empty());
+	}
+
+	#[test]
+	fn check_headers_test() {
+		let (mut output_config, diagnostics) = OutputConfig::check(CsvParser::new(Cursor::new(
+			"H1,H2,H3\n\
+			:FILTER <cell4> IS_NOT_EMPTY\n\
+			:EACH <cell2..cell5>\n\
+			<col 'SKU'>,<col 'Missing'>,:IF <cell9> == 'x' (<cell9>) ELSE (<col 'Name'>)\n",
+		)));
+		assert!(diagnostics.is_empty());
+
+		let errors = output_config.check_headers(&[String::from("SKU"), String::from("Name"), String::from("Price")]);
+		assert_eq!(
+			errors.iter().map(|error| (error.row, error.column, error.text.as_deref())).collect::<Vec<_>>(),
+			vec![
+				(Some(2), Some(1), Some("<cell4>")),
+				(Some(3), Some(1), Some("<cell5>")),
+				(Some(4), Some(2), None),
+				(Some(4), Some(3), Some("<cell9>")),
+			]
+		);
+		assert!(errors[2].message.starts_with("Column \"Missing\" not found"));
+		assert_eq!(output_config.lines[0].items[0], Item::Cell(0, None));
+	}
+
+	#[test]
+	fn conditional_before_after_test() {
+		let condition = Condition::parse_expression("<cell1> BEFORE '2026-10-17' OR <cell1> AFTER <cell2>").unwrap();
+		assert_eq!(
+			condition,
+			Condition::Or(
+				Box::new(Condition::Before(Box::new(Item::Value(String::from("2026-10-17"))), Box::new(Item::Cell(0, None)))),
+				Box::new(Condition::After(Box::new(Item::Cell(1, None)), Box::new(Item::Cell(0, None))))
+			)
+		);
+
+		let is_true = |cells: &[&str]| {
+			let cells = cells.iter().map(|cell| cell.to_string()).collect::<Vec<_>>();
+			condition.is_true(&Row::new(&cells))
+		};
+		assert_eq!(is_true(&["2026-10-16", "2030-01-01"]), Ok(true));
+		assert_eq!(is_true(&["2026-10-17", "2030-01-01"]), Ok(false));
+		assert_eq!(is_true(&["2026-10-17T08:00:00+02:00", "2026-10-17T07:00:00+00:00"]), Ok(false));
+		assert_eq!(is_true(&["2026-10-17T08:00:00+02:00", "2026-10-17T05:59:59.9Z"]), Ok(true));
+		assert_eq!(is_true(&["2026-10-17 00:00:00.5", "2026-10-17"]), Ok(true));
+
+		let error = is_true(&["17/10/2026", "2030-01-01"]).unwrap_err();
+		assert_eq!(error.stage, ErrorStages::ConfigConditionEvaluating);
+		assert!(error.message.starts_with("The BEFORE condition left date cannot be parsed, was \"17/10/2026\""));
+		assert!(is_true(&["2030-01-01", ""]).is_err());
+
+		assert_eq!(
+			Condition::parse_expression("<cell1 DATE|'%d/%m/%Y'|'iso'> AFTER '2026-01-01'")
+				.unwrap()
+				.is_true(&Row::new(&[String::from("17/10/2026")])),
+			Ok(true)
+		);
+	}
+
+	#[test]
+	fn display_test() {
+		for condition in [
+			"<cell1> IS_EMPTY ('x')",
+			"<cell1 TRIM UPPER_CASE> == 'it\\'s' (<cell2 REPLACE|' '|'-'>) ELSE ('back\\\\slash')",
+			"(<cell1> > '10' OR NOT <cell2> STARTS_WITH|'a b') AND <cell3> % 2 = 1 ('x') ELSE (:IF <each> != <index> ('y'))",
+			"NOT (<col 'SKU'> < '0' AND <cell1> CONTAINS|'|') (<cell1 SPLIT|','|2 SUB_STRING|1|3 APPEND|')' PREPEND|'('>)",
+			"<cell1 LOOKUP|'colors.csv'|1|2|'none' LENGTH> IS_NUMERIC (<cell1 LOWER_CASE TRIM_START TRIM_END SUB_STRING|4>)",
+			"<cell1 DATE|'%d/%m/%Y %H:%M'|'iso'|'-05:30'> BEFORE '2026-10-17 08:00' OR <cell2> AFTER <cell3> ('x')",
+			"<cell1 PARSE_NUMBER|'de' ROUND|2 FIXED|2 CEIL FLOOR PAD_NUMBER|5> IS_NUMERIC (:CALC <cell1> * 2)",
+			"<cell1 HANDLEIZE SLUGIFY|'_'> IS_EMPTY ('x')",
+			"<cell6> IS_FIRST_IN_GROUP OR <cell1> IS_SAME_AS_PREVIOUS ('NEW') ELSE (<prev6 TRIM>)",
+			r"<cell1> MATCHES|'^(it\'s|\d+ \\\\)$' (<cell1 REGEX_REPLACE|'\s+|\''|'$1 '><cell2 REGEX_EXTRACT|'(a|b)'|1>)",
+		] {
+			let item = Condition::parse(condition).unwrap();
+			let written = item.to_string();
+			assert_eq!(Condition::parse(written.strip_prefix(":IF ").unwrap()), Ok(item), "{written}");
+		}
+
+		assert_eq!(
+			Condition::parse("<cell1> == blue ('x') ELSE IF <cell2> IS_EMPTY ('y')").unwrap().to_string(),
+			":IF <cell1> == 'blue' ('x') ELSE (:IF <cell2> IS_EMPTY ('y'))"
+		);
+		assert_eq!(Item::Value(String::from("NEW")).to_string(), "'NEW'");
+		assert_eq!(Filter::Unknown(String::from("UPPERCASE")).to_string(), "UPPERCASE");
+
+		for aggregate in [
+			":SUM <cell1 TRIM>",
+			":COUNT",
+			":JOIN|'\\'s' <col 'Tags'>",
+			":FIRST :IF <cell1> IS_EMPTY ('x')",
+		] {
+			let item = Item::parse(String::from(aggregate)).unwrap();
+			assert_eq!(Item::parse(item.to_string()), Ok(item), "{aggregate}");
+		}
+	}
+
+	#[test]
+	fn calc_parse_test() {
+		assert_eq!(
+			Item::parse(String::from(":CALC <cell5> * 1.1 + <cell6>")).unwrap(),
+			Item::Calc(Expression::Add(
+				Box::new(Expression::Multiply(
+					Box::new(Expression::Item(Box::new(Item::Cell(4, None)))),
+					Box::new(Expression::Number(1.1))
+				)),
+				Box::new(Expression::Item(Box::new(Item::Cell(5, None))))
+			))
+		);
+		assert_eq!(
+			Expression::parse("-(1 - 2) - 3").unwrap(),
+			Expression::Subtract(
+				Box::new(Expression::Negate(Box::new(Expression::Subtract(
+					Box::new(Expression::Number(1.0)),
+					Box::new(Expression::Number(2.0))
+				)))),
+				Box::new(Expression::Number(3.0))
+			)
+		);
+		assert_eq!(
+			Item::parse(String::from(":IF <cell1> IS_NUMERIC (:CALC ROUND(<cell1 TRIM> / 3, 2))")).unwrap(),
+			Item::If(
+				Condition::IsNumeric(Box::new(Item::Cell(0, None))),
+				Box::new(Item::Calc(Expression::Round(
+					Box::new(Expression::Divide(
+						Box::new(Expression::Item(Box::new(Item::Cell(0, Some(vec![Filter::Trim]))))),
+						Box::new(Expression::Number(3.0))
+					)),
+					2
+				))),
+				None
+			)
+		);
+
+		for calculation in [
+			"<cell1> * 1.1 + <cell2>",
+			"(<cell1> + <cell2>) * 2",
+			"<cell1> - (<cell2> - <cell3>)",
+			"-(<each> + 1) % 3",
+			"ROUND(<col 'Price' REPLACE|','|'.'> * 1.19, 2) / CEIL(<index>)",
+			"FLOOR(ABS(-<cell1>)) - ROUND(0.5)",
+		] {
+			let item = Item::parse(format!(":CALC {calculation}")).unwrap();
+			assert_eq!(item.to_string(), format!(":CALC {calculation}"));
+		}
+
+		for (calculation, message) in [
+			("", "Expected a number, an item or a function but found \"\""),
+			("<cell1> +", "Expected a number, an item or a function but found \"\""),
+			("<cell1> * x", "Expected a number, an item or a function but found \"x\""),
+			("(<cell1> + 1", "Calculation group is missing its closing \")\""),
+			("<cell1> 2", "Unexpected \"2\" at the end of the calculation"),
+			("1.2.3", "Invalid number \"1.2.3\""),
+			("ROUND <cell1>", "Function ROUND must be followed by \"(\""),
+			("ROUND(<cell1>, two)", "The decimals of ROUND must be a whole number, was \"two\""),
+			("ABS(<cell1>, 2)", "Function ABS is missing its closing \")\""),
+		] {
+			let error = Expression::parse(calculation).unwrap_err();
+			assert_eq!(error.stage, ErrorStages::ConfigCalcParsing, "{calculation}");
+			assert!(error.message.starts_with(message), "{calculation}: {}", error.message);
+		}
+		assert_eq!(Expression::parse("<cell0> + 1").unwrap_err().stage, ErrorStages::ConfigParsing);
+	}
+
+	#[test]
+	fn calc_evaluate_test() {
+		let cells = [
+			String::from("19.99"),
+			String::from(" 2 "),
+			String::from("abc"),
+			String::from(""),
+			String::from("0"),
+			String::from("1.005"),
+		];
+		let row = Row::new(&cells);
+		let run = |calculation: &str| Expression::parse(calculation).unwrap().run(&row);
+
+		assert_eq!(run("<cell1> * 1.1"), Ok(String::from("21.989")));
+		assert_eq!(run("<cell1> + <cell2> * 3"), Ok(String::from("25.99")));
+		assert_eq!(run("(<cell1> + <cell2>) * 3"), Ok(String::from("65.97")));
+		assert_eq!(run("10 - 4 - 3"), Ok(String::from("3")));
+		assert_eq!(run("2 * -<cell2>"), Ok(String::from("-4")));
+		assert_eq!(run("7 % <cell2>"), Ok(String::from("1")));
+		assert_eq!(run("1 / 3"), Ok(String::from("0.333333333333333")));
+		assert_eq!(run("<cell1> / 1000000000000"), Ok(String::from("0.00000000001999")));
+		assert_eq!(run("0.1 + 0.2"), Ok(String::from("0.3")));
+		assert_eq!(run("123456789 * 1000000000000"), Ok(String::from("123456789000000000000")));
+		assert_eq!(run("ROUND(<cell1> * 1.1, 2)"), Ok(String::from("21.99")));
+		assert_eq!(run("ROUND(<cell6>, 2)"), Ok(String::from("1.01")));
+		assert_eq!(run("ROUND(-2.5)"), Ok(String::from("-3")));
+		assert_eq!(run("ROUND(1234, -2)"), Ok(String::from("1200")));
+		assert_eq!(run("FLOOR(<cell1>) + CEIL(0.1) + ABS(-1)"), Ok(String::from("21")));
+		assert_eq!(run("-<cell5>"), Ok(String::from("0")));
+		assert_eq!(run("<cell1 SPLIT|'.'|0> + 1"), Ok(String::from("20")));
+
+		let error = run("<cell1> + <cell3>").unwrap_err();
+		assert_eq!(error.stage, ErrorStages::ConfigCalcEvaluating);
+		assert_eq!(error.message, "The value of <cell3> is not a number, was \"abc\"");
+		assert_eq!(run("<cell4> * 2").unwrap_err().message, "The value of <cell4> is not a number, was \"\"");
+		assert_eq!(
+			run("<cell1> / <cell5>").unwrap_err().message,
+			"Division by zero in \"<cell1> / <cell5>\", <cell5> is 0"
+		);
+		assert_eq!(
+			run("1 % (<cell2> - 2)").unwrap_err().message,
+			"Division by zero in \"1 % (<cell2> - 2)\", <cell2> - 2 is 0"
+		);
+		let error = run("<cell9> + 1").unwrap_err();
+		assert_eq!(error.stage, ErrorStages::ConfigCalcEvaluating);
+		assert_eq!(error.message, "Cell not found \"<cell9>\"");
+		assert!(run("<each> + 1").is_err());
+	}
+
+	#[test]
+	fn conditional_group_test() {
+		let condition =
+			Condition::parse_expression("<cell1 TRIM> IS_FIRST_IN_GROUP OR <prev2> IS_SAME_AS_PREVIOUS").unwrap();
+		assert_eq!(
+			condition,
+			Condition::Or(
+				Box::new(Condition::IsFirstInGroup(Box::new(Item::Cell(0, Some(vec![Filter::Trim]))))),
+				Box::new(Condition::IsSameAsPrevious(Box::new(Item::Previous(1, None))))
+			)
+		);
+
+		let rows = [
+			vec![String::from("shirt"), String::from("S")],
+			vec![String::from("shirt "), String::from("M")],
+			vec![String::from("shirt"), String::from("M")],
+			vec![String::from("pants"), String::from("M")],
+		];
+		let is_first = |index: usize| {
+			let previous = index.checked_sub(1).map(|previous| rows[previous].as_slice());
+			Condition::IsFirstInGroup(Box::new(Item::parse(String::from("<cell1 TRIM>")).unwrap()))
+				.is_true(&Row::after(&rows[index], previous))
+		};
+		assert_eq!(is_first(0), Ok(true));
+		assert_eq!(is_first(1), Ok(false));
+		assert_eq!(is_first(2), Ok(false));
+		assert_eq!(is_first(3), Ok(true));
+
+		let is_same = |index: usize, item: &str| {
+			let previous = index.checked_sub(1).map(|previous| rows[previous].as_slice());
+			Condition::IsSameAsPrevious(Box::new(Item::parse(String::from(item)).unwrap()))
+				.is_true(&Row::after(&rows[index], previous))
+		};
+		assert_eq!(is_same(0, "<cell2>"), Ok(false));
+		assert_eq!(is_same(1, "<cell2>"), Ok(false));
+		assert_eq!(is_same(2, "<cell2>"), Ok(true));
+		assert_eq!(is_same(3, "<cell1>"), Ok(false));
+		assert_eq!(is_same(1, "<cell3>").map_err(|error| error.message), Err(String::from("Cell not found \"<cell3>\"")));
+
+		let value = |item: &str, previous: Option<&[String]>| {
+			process::get_value(&Item::parse(String::from(item)).unwrap(), &Row::after(&rows[1], previous))
+				.map(|value| value.into_owned())
+		};
+		assert_eq!(value("<prev2 LOWER_CASE>", Some(&rows[0])), Ok(String::from("s")));
+		assert_eq!(value("<prev2>", None), Ok(String::new()));
+		assert_eq!(
+			value("<prev3>", Some(&rows[0])).map_err(|error| error.message),
+			Err(String::from("Cell not found \"<prev3>\""))
+		);
+		assert!(Item::parse(String::from("<prev0>")).is_err());
+	}
+
+	#[test]
+	fn conditional_matches_test() {
+		let condition =
+			Condition::parse_expression(r"<cell1> MATCHES|'^SKU-\d{4}$' OR <cell2> MATCHES|'(?i)^(s|m|l)$'").unwrap();
+		assert_eq!(
+			condition,
+			Condition::Or(
+				Box::new(Condition::Matches(Regex::new(r"^SKU-\d{4}$").unwrap(), Box::new(Item::Cell(0, None)))),
+				Box::new(Condition::Matches(Regex::new("(?i)^(s|m|l)$").unwrap(), Box::new(Item::Cell(1, None))))
+			)
+		);
+
+		let is_true = |cells: &[&str]| {
+			let cells = cells.iter().map(|cell| cell.to_string()).collect::<Vec<_>>();
+			condition.is_true(&Row::new(&cells))
+		};
+		assert_eq!(is_true(&["SKU-0042", "XL"]), Ok(true));
+		assert_eq!(is_true(&["SKU-42", "M"]), Ok(true));
+		assert_eq!(is_true(&["SKU-00420", "XL"]), Ok(false));
+		assert_eq!(is_true(&["", ""]), Ok(false));
+
+		let error = Condition::parse_expression("<cell1> MATCHES|'[a-'").unwrap_err();
+		assert_eq!(error.stage, ErrorStages::ConfigConditionParsing);
+		assert!(error.message.starts_with(
+			"Invalid regular expression \"[a-\": the class at position 1 is missing its closing \"]\", was \"<cell1> MATCHES|'[a-'\""
+		));
+	}
 }
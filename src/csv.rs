@@ -1,5 +1,186 @@
 //! This module handles the parsing of any CSV file
-use std::io::BufRead;
+use std::{
+	io::{BufRead, Write},
+	mem,
+};
+
+use crate::{
+	cli::{Error, ErrorStages},
+	sort::{SortKey, Sorter},
+};
+
+/// The characters that make up the flavor of a CSV file
+///
+/// ```rust
+/// use csv_converter::csv::Dialect;
+///
+/// let dialect = Dialect::detect("name;price\nshirt;12,50\n");
+/// assert_eq!(dialect.delimiter, ';');
+/// assert_eq!(dialect.quote, '"');
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dialect {
+	/// The character between two cells
+	pub delimiter: char,
+	/// The character wrapping cells that contain special characters
+	pub quote: char,
+	/// The character escaping a quote within a quoted cell, if `None` quotes are escaped by doubling them
+	pub escape: Option<char>,
+	/// The string written at the end of each line when exporting
+	pub line_terminator: String,
+}
+
+impl Default for Dialect {
+	fn default() -> Self {
+		Self {
+			delimiter: ',',
+			quote: '"',
+			escape: None,
+			line_terminator: String::from("\n"),
+		}
+	}
+}
+
+impl Dialect {
+	/// The delimiters we look for when detecting the dialect of a file
+	pub const DELIMITERS: [char; 4] = [',', ';', '\t', '|'];
+
+	/// Guess the dialect from the start of a file.
+	///
+	/// We pick the delimiter that appears most consistently outside of quotes across the first lines of the sample and
+	/// fall back to the default dialect if nothing stands out.
+	pub fn detect(sample: &str) -> Self {
+		let default = Self::default();
+		Self::detect_with(sample, default.quote, default.escape)
+	}
+
+	/// Guess the dialect from the start of a file whose quote and escape characters are already known
+	pub fn detect_with(sample: &str, quote: char, escape: Option<char>) -> Self {
+		let mut dialect = Self {
+			quote,
+			escape,
+			..Self::default()
+		};
+
+		let mut counts: Vec<[usize; 4]> = Vec::new();
+		let mut line_counts = [0; 4];
+		let mut in_quotes = false;
+		let mut chars = sample.chars();
+		while let Some(c) = chars.next() {
+			if in_quotes && escape != Some(quote) && Some(c) == escape {
+				// the escaped character can't end the quotes
+				chars.next();
+			} else if c == quote {
+				in_quotes = !in_quotes;
+			} else if c == '\n' && !in_quotes {
+				counts.push(line_counts);
+				line_counts = [0; 4];
+				if counts.len() >= 10 {
+					break;
+				}
+			} else if !in_quotes {
+				if let Some(index) = Self::DELIMITERS.iter().position(|delimiter| *delimiter == c) {
+					line_counts[index] += 1;
+				}
+			}
+		}
+		// a sample that ends in the middle of a line is only used if it's all we have
+		if counts.is_empty() {
+			counts.push(line_counts);
+		}
+
+		let mut best_score = 0;
+		for (index, delimiter) in Self::DELIMITERS.iter().enumerate() {
+			let first = counts[0][index];
+			let consistent = counts.iter().all(|line| line[index] == first);
+			// delimiters with the same count in every line are a much stronger signal than a lot of them in one line
+			let score = if consistent {
+				first * 1000
+			} else {
+				counts.iter().map(|line| line[index]).min().unwrap_or(0)
+			};
+			if score > best_score {
+				best_score = score;
+				dialect.delimiter = *delimiter;
+			}
+		}
+
+		if sample.contains("\r\n") {
+			dialect.line_terminator = String::from("\r\n");
+		}
+
+		dialect
+	}
+}
+
+/// A single CSV record stored as one contiguous buffer and the offsets where each cell ends
+///
+/// Reading into the same record again reuses its allocations which makes [CsvParser::read_record] a lot faster than
+/// iterating over the parser for large files.
+///
+/// ```rust
+/// use csv_converter::csv::{CsvParser, Record};
+/// use std::io::Cursor;
+///
+/// let mut csv_file = CsvParser::new(Cursor::new("a,\"b,c\"\n1,2\n"));
+/// let mut record = Record::default();
+/// while csv_file.read_record(&mut record).unwrap() {
+///     assert_eq!(record.len(), 2);
+/// }
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Record {
+	buffer: String,
+	ends: Vec<usize>,
+}
+
+impl Record {
+	/// The number of cells in this record
+	pub fn len(&self) -> usize {
+		self.ends.len()
+	}
+
+	/// Whether this record has no cells which is only the case before anything was read into it
+	pub fn is_empty(&self) -> bool {
+		self.ends.is_empty()
+	}
+
+	/// The cell at the given index starting at 0
+	pub fn get(&self, index: usize) -> Option<&str> {
+		let end = *self.ends.get(index)?;
+		let start = if index == 0 { 0 } else { self.ends[index - 1] };
+		Some(&self.buffer[start..end])
+	}
+
+	/// Iterate over all cells of this record
+	pub fn iter(&self) -> impl Iterator<Item = &str> {
+		(0..self.len()).filter_map(|index| self.get(index))
+	}
+
+	/// Copy all cells into owned Strings
+	pub fn to_vec(&self) -> Vec<String> {
+		self.iter().map(String::from).collect()
+	}
+
+	/// Copy all cells into the given row, reusing the allocations of the cells it already has
+	pub fn copy_to(&self, row: &mut Vec<String>) {
+		row.truncate(self.len());
+		for (index, cell) in self.iter().enumerate() {
+			match row.get_mut(index) {
+				Some(existing) => {
+					existing.clear();
+					existing.push_str(cell);
+				},
+				None => row.push(cell.to_string()),
+			}
+		}
+	}
+
+	fn clear(&mut self) {
+		self.buffer.clear();
+		self.ends.clear();
+	}
+}
 
 /// A struct that we use to read through very large CSV files line by line to avoid loading the entire file into memory
 ///
@@ -15,9 +196,11 @@ use std::io::BufRead;
 /// ```
 pub struct CsvParser<R: BufRead> {
 	reader: R,
-	buffer: String,
-	temp_line: String,
-	in_quotes: bool,
+	delimiter: Vec<u8>,
+	quote: Vec<u8>,
+	escape: Option<Vec<u8>>,
+	temp_line: Vec<u8>,
+	record: Record,
 	/// We keep track of how much we have read so far for progress calculations
 	pub bytes_read: u128,
 }
@@ -25,49 +208,196 @@ pub struct CsvParser<R: BufRead> {
 impl<R: BufRead> CsvParser<R> {
 	/// Simple instantiation without logic
 	pub fn new(reader: R) -> Self {
+		Self::with_dialect(reader, Dialect::default())
+	}
+
+	/// Instantiation for files that don't use commas and double quotes
+	///
+	/// ```rust
+	/// use csv_converter::csv::{CsvParser, Dialect};
+	/// use std::io::Cursor;
+	///
+	/// let dialect = Dialect {
+	///     delimiter: '\t',
+	///     ..Dialect::default()
+	/// };
+	/// let mut csv_file = CsvParser::with_dialect(Cursor::new("a\tb,c\n"), dialect);
+	/// assert_eq!(csv_file.next(), Some(vec![String::from("a"), String::from("b,c")]));
+	/// ```
+	pub fn with_dialect(reader: R, dialect: Dialect) -> Self {
+		let encode = |c: char| c.to_string().into_bytes();
 		Self {
 			reader,
-			buffer: String::new(),
-			temp_line: String::new(),
-			in_quotes: false,
+			delimiter: encode(dialect.delimiter),
+			quote: encode(dialect.quote),
+			escape: dialect.escape.filter(|escape| *escape != dialect.quote).map(encode),
+			temp_line: Vec::new(),
+			record: Record::default(),
 			bytes_read: 0,
 		}
 	}
 
-	fn parse_csv_line(&self) -> Vec<String> {
-		let mut in_quotes = false;
-		let mut chars = self.buffer.chars().peekable();
-		let mut cell = String::new();
-		let mut record = Vec::new();
+	/// Read the next record into the given [Record], reusing its buffers.
+	/// Returns `false` once the end of the file is reached.
+	///
+	/// Invalid UTF-8 is replaced with `U+FFFD` instead of failing the entire file.
+	pub fn read_record(&mut self, record: &mut Record) -> Result<bool, Error> {
+		let mut state = State::Unquoted;
+		let mut buffer = mem::take(&mut record.buffer).into_bytes();
+		buffer.clear();
+		record.ends.clear();
+		let mut has_content = false;
 
-		while let Some(c) = chars.next() {
-			match c {
-				'"' => {
-					if in_quotes {
-						if chars.peek() == Some(&'"') {
-							cell.push('"');
-							chars.next();
-						} else {
-							in_quotes = false;
-						}
+		loop {
+			self.temp_line.clear();
+			match self.reader.read_until(b'\n', &mut self.temp_line) {
+				Ok(0) => break,
+				Ok(bytes) => {
+					self.bytes_read += bytes as u128;
+					has_content = true;
+					if self.parse_line(&mut state, &mut buffer, &mut record.ends) {
+						break;
+					}
+				},
+				Err(error) => {
+					record.clear();
+					return Err(Error::new(ErrorStages::Io, format!("Error reading line: {error}")));
+				},
+			}
+		}
+
+		if !has_content {
+			return Ok(false);
+		}
+		record.ends.push(buffer.len());
+
+		match String::from_utf8(buffer) {
+			Ok(buffer) => record.buffer = buffer,
+			Err(error) => {
+				// the cell boundaries move when invalid bytes are replaced so we rebuild the record cell by cell
+				let bytes = error.into_bytes();
+				let mut start = 0;
+				for end in record.ends.iter_mut() {
+					record.buffer.push_str(&String::from_utf8_lossy(&bytes[start..*end]));
+					start = *end;
+					*end = record.buffer.len();
+				}
+			},
+		}
+
+		Ok(true)
+	}
+
+	/// Feed the physical line we just read through the state machine.
+	/// Returns `true` once the line terminator of the record was found outside of quotes.
+	fn parse_line(&self, state: &mut State, buffer: &mut Vec<u8>, ends: &mut Vec<usize>) -> bool {
+		let line = &self.temp_line[..];
+		let delimiter = &self.delimiter[..];
+		let quote = &self.quote[..];
+		let escape = self.escape.as_deref();
+		let mut i = 0;
+
+		while i < line.len() {
+			match *state {
+				State::Quoted => {
+					// everything up to the next quote or escape character is content
+					let run = line[i..]
+						.iter()
+						.position(|byte| *byte == quote[0] || Some(*byte) == escape.map(|escape| escape[0]))
+						.unwrap_or(line.len() - i);
+					buffer.extend_from_slice(&line[i..i + run]);
+					i += run;
+					if i == line.len() {
+						break;
+					}
+
+					if escape.is_some_and(|escape| line[i..].starts_with(escape)) {
+						i += escape.map_or(0, <[u8]>::len);
+						*state = State::EscapeInQuoted;
+					} else if line[i..].starts_with(quote) {
+						i += quote.len();
+						*state = State::QuoteInQuoted;
 					} else {
-						in_quotes = true;
+						buffer.push(line[i]);
+						i += 1;
 					}
 				},
-				',' if !in_quotes => {
-					record.push(std::mem::take(&mut cell));
-					cell.clear();
+				State::EscapeInQuoted => {
+					// the remaining bytes of a multi-byte character can never be mistaken for a special character
+					buffer.push(line[i]);
+					i += 1;
+					*state = State::Quoted;
+				},
+				State::QuoteInQuoted if line[i..].starts_with(quote) => {
+					buffer.extend_from_slice(quote);
+					i += quote.len();
+					*state = State::Quoted;
+				},
+				State::Unquoted | State::QuoteInQuoted => {
+					*state = State::Unquoted;
+					let run = line[i..]
+						.iter()
+						.position(|byte| *byte == quote[0] || *byte == delimiter[0] || *byte == b'\n' || *byte == b'\r')
+						.unwrap_or(line.len() - i);
+					buffer.extend_from_slice(&line[i..i + run]);
+					i += run;
+					if i == line.len() {
+						break;
+					}
+
+					if line[i..].starts_with(quote) {
+						i += quote.len();
+						*state = State::Quoted;
+					} else if line[i..].starts_with(delimiter) {
+						i += delimiter.len();
+						ends.push(buffer.len());
+					} else if line[i] == b'\n' || line[i..].starts_with(b"\r\n") {
+						return true;
+					} else {
+						buffer.push(line[i]);
+						i += 1;
+					}
 				},
-				_ => cell.push(c),
 			}
 		}
-		record.push(cell);
-		record
+
+		false
 	}
 }
 
+/// The states of the parser while walking through a record
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+	/// Outside of quotes, where delimiters and line terminators end cells and records
+	Unquoted,
+	/// Inside quotes, where everything is content
+	Quoted,
+	/// We found a quote inside quotes which either closes the quotes or, if doubled, is a literal quote
+	QuoteInQuoted,
+	/// We found the escape character inside quotes so the next character is content
+	EscapeInQuoted,
+}
+
 /// Convert a two dimensional collection of Strings into a CSV compatible String
 pub fn export(records: &[Vec<String>], output: &mut String) {
+	export_with_dialect(records, output, &Dialect::default());
+}
+
+/// Convert a two dimensional collection of Strings into a CSV String of the given [Dialect]
+///
+/// ```rust
+/// use csv_converter::csv::{export_with_dialect, Dialect};
+///
+/// let dialect = Dialect {
+///     delimiter: ';',
+///     line_terminator: String::from("\r\n"),
+///     ..Dialect::default()
+/// };
+/// let mut output = String::new();
+/// export_with_dialect(&[vec![String::from("12,50"), String::from("a;b")]], &mut output, &dialect);
+/// assert_eq!(output, String::from("12,50;\"a;b\"\r\n"));
+/// ```
+pub fn export_with_dialect(records: &[Vec<String>], output: &mut String, dialect: &Dialect) {
 	output.clear();
 	output.reserve(records.iter().map(|line| line.len() * 32).sum());
 
@@ -75,89 +405,107 @@ pub fn export(records: &[Vec<String>], output: &mut String) {
 		let mut first_cell = true;
 		for cell in line {
 			if !first_cell {
-				output.push(',');
+				output.push(dialect.delimiter);
 			}
 			first_cell = false;
-			quote_csv_cell(cell, output);
+			quote_csv_cell(cell, output, dialect);
 		}
-		output.push('\n');
+		output.push_str(&dialect.line_terminator);
 	}
 }
 
-fn quote_csv_cell(cell: &str, output: &mut String) {
-	let needs_quotes = cell.contains(&[',', '\n', '"'][..]);
+/// Sort records by the given columns and write them as CSV of the given [Dialect]
+///
+/// The records are held in memory until they take more than the memory budget in bytes, after that they are sorted
+/// in runs on disk and merged so this works for more records than fit into memory. Records that compare equal keep
+/// their order.
+///
+/// ```rust
+/// use csv_converter::{
+///     csv::{export_sorted, Dialect},
+///     sort::SortKey,
+/// };
+///
+/// let records = vec![
+///     vec![String::from("shirt"), String::from("2")],
+///     vec![String::from("mug"), String::from("1")],
+///     vec![String::from("shirt"), String::from("1")],
+/// ];
+/// let keys = [SortKey { column: 0, numeric: false, descending: false }];
+/// let mut output = Vec::new();
+/// export_sorted(records, &keys, 1024 * 1024, &Dialect::default(), &mut output).unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap(), "mug,1\nshirt,2\nshirt,1\n");
+/// ```
+pub fn export_sorted(
+	records: impl IntoIterator<Item = Vec<String>>,
+	keys: &[SortKey],
+	memory_budget: usize,
+	dialect: &Dialect,
+	output: &mut impl Write,
+) -> Result<(), Error> {
+	let mut sorter = Sorter::new(keys.to_vec(), memory_budget);
+	for record in records {
+		sorter.push(record)?;
+	}
+
+	let mut buffer = String::new();
+	sorter.finish(|record| {
+		export_with_dialect(&[record], &mut buffer, dialect);
+		output
+			.write_all(buffer.as_bytes())
+			.map_err(|error| Error::new(ErrorStages::Io, format!("Failed to write the sorted records: {error}")))
+	})
+}
+
+/// Append a single cell to the output, quoting it if it contains any special characters of the [Dialect]
+pub fn quote_csv_cell(cell: &str, output: &mut String, dialect: &Dialect) {
+	let needs_quotes = cell
+		.chars()
+		.any(|c| c == dialect.delimiter || c == dialect.quote || c == '\n' || c == '\r' || Some(c) == dialect.escape);
 	if needs_quotes {
-		output.push('"');
+		output.push(dialect.quote);
 		for c in cell.chars() {
-			if c == '"' {
-				output.push('"');
+			if c == dialect.quote || Some(c) == dialect.escape {
+				output.push(dialect.escape.unwrap_or(dialect.quote));
 			}
 			output.push(c);
 		}
-		output.push('"');
+		output.push(dialect.quote);
 	} else {
 		output.push_str(cell);
 	}
 }
 
+/// Iterating ends at the first read error, use [CsvParser::read_record] wherever a read error must not go unnoticed
 impl<R: BufRead> Iterator for CsvParser<R> {
 	type Item = Vec<String>;
 
 	fn next(&mut self) -> Option<Self::Item> {
-		self.buffer.clear();
-		loop {
-			self.temp_line.clear();
-			match self.reader.read_line(&mut self.temp_line) {
-				Ok(0) => {
-					if self.buffer.is_empty() {
-						return None;
-					} else {
-						break;
-					}
-				},
-				Ok(bytes) => {
-					self.bytes_read += bytes as u128;
-					if !self.buffer.is_empty() {
-						self.buffer.push('\n');
-					}
-					self.buffer.push_str(self.temp_line.trim_end());
-
-					let mut i = 0;
-					let bytes = self.temp_line.as_bytes();
-					while i < bytes.len() {
-						if bytes[i] == b'"' {
-							let mut quote_count = 1;
-							while i + 1 < bytes.len() && bytes[i + 1] == b'"' {
-								quote_count += 1;
-								i += 1;
-							}
-							if quote_count % 2 != 0 {
-								self.in_quotes = !self.in_quotes;
-							}
-						}
-						i += 1;
-					}
+		let mut record = mem::take(&mut self.record);
+		let result = self.read_record(&mut record);
+		self.record = record;
 
-					if !self.in_quotes {
-						break;
-					}
-				},
-				Err(error) => {
-					eprintln!("Error reading line: {}", error);
-					return None;
-				},
-			}
+		match result {
+			Ok(true) => Some(self.record.to_vec()),
+			Ok(false) | Err(_) => None,
 		}
-
-		Some(self.parse_csv_line())
 	}
 }
 
+/// Owned rows from rows of cell texts, for the tests of the modules working with lines
+#[cfg(test)]
+pub(crate) fn rows(rows: &[&[&str]]) -> Vec<Vec<String>> {
+	rows.iter().map(|row| row.iter().map(|cell| cell.to_string()).collect()).collect()
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
 	use crate::cli::exit_with_error;
-	use std::{fs::File, io::BufReader};
+	use std::{
+		fs::File,
+		io::{BufReader, Cursor},
+	};
 
 	#[test]
 	fn csvparser_iterator_test() {
@@ -270,19 +618,186 @@ mod tests {
 		assert_eq!(output, String::from("Charlie Brown,25 Peanuts Lane,\"Good grief, he forgot his keys again.\",<p><strong>Stay hopeful!</strong></p>\n"));
 	}
 
+	#[test]
+	fn whitespace_and_line_terminator_test() {
+		let mut csv_file =
+			CsvParser::new(Cursor::new("a  ,b\t\r\n\"  pre\r\n  formatted  \r\n\",\"trailing \"  \n\nlast,line"));
+		assert_eq!(csv_file.next(), Some(vec![String::from("a  "), String::from("b\t")]));
+		assert_eq!(csv_file.next(), Some(vec![String::from("  pre\r\n  formatted  \r\n"), String::from("trailing   ")]));
+		assert_eq!(csv_file.next(), Some(vec![String::from("")]));
+		assert_eq!(csv_file.next(), Some(vec![String::from("last"), String::from("line")]));
+		assert_eq!(csv_file.next(), None);
+
+		let mut csv_file = CsvParser::new(Cursor::new("\"unterminated\nquote"));
+		assert_eq!(csv_file.next(), Some(vec![String::from("unterminated\nquote")]));
+		assert_eq!(csv_file.next(), None);
+	}
+
+	#[test]
+	fn round_trip_test() {
+		let inputs = [
+			"a,b,c\n1,2,3\n",
+			"\"a,b\",\"He said \"\"hi\"\"\",c\n",
+			"\"multi\r\nline  \",trailing  ,\t\n",
+			"\"line\nbreak\",\"\"\"\",\n,,\n",
+			"  leading,\"trailing\r\",\"mid\rdle\"\n",
+			"emoji 😬,ünïcödé,\"🎉,🎉\"\n",
+		];
+
+		for input in inputs {
+			let records = CsvParser::new(Cursor::new(input)).collect::<Vec<Vec<String>>>();
+			let mut output = String::new();
+			export(&records, &mut output);
+			assert_eq!(output, input);
+		}
+
+		let dialect = Dialect {
+			delimiter: '\t',
+			quote: '\'',
+			escape: Some('\\'),
+			line_terminator: String::from("\r\n"),
+		};
+		let input = "a\t'b\tc'\r\n'it\\'s'\t'back\\\\slash'\r\n";
+		let records = CsvParser::with_dialect(Cursor::new(input), dialect.clone()).collect::<Vec<Vec<String>>>();
+		assert_eq!(records[1], vec![String::from("it's"), String::from("back\\slash")]);
+		let mut output = String::new();
+		export_with_dialect(&records, &mut output, &dialect);
+		assert_eq!(output, input);
+	}
+
+	#[test]
+	fn dialect_detect_test() {
+		assert_eq!(Dialect::detect("a,b,c\n1,2,3\n"), Dialect::default());
+		assert_eq!(Dialect::detect("a;b;c\n\"1,5\";\"2,5\";3\n").delimiter, ';');
+		assert_eq!(Dialect::detect("a\tb\tc\n1, 2\t2\t3\n").delimiter, '\t');
+		assert_eq!(Dialect::detect("a|b\r\n1|2\r\n").delimiter, '|');
+		assert_eq!(Dialect::detect("a|b\r\n1|2\r\n").line_terminator, String::from("\r\n"));
+		assert_eq!(Dialect::detect("single column\nno delimiter\n"), Dialect::default());
+		assert_eq!(Dialect::detect("a;b;c").delimiter, ';');
+		// quoted cells full of the wrong delimiter only look like cells with the configured quote and escape
+		let sample = "'a,b';'c,d'\n'1,5';'2,5'\n";
+		assert_eq!(Dialect::detect(sample).delimiter, ',');
+		assert_eq!(Dialect::detect_with(sample, '\'', None).delimiter, ';');
+		assert_eq!(Dialect::detect_with(sample, '\'', None).quote, '\'');
+		let sample = "\"a\\\";b;c\\\";d\",1\n\"e\\\";f;g\\\";h\",2\n";
+		assert_eq!(Dialect::detect(sample).delimiter, ';');
+		assert_eq!(Dialect::detect_with(sample, '"', Some('\\')).delimiter, ',');
+	}
+
+	#[test]
+	fn dialect_parse_test() {
+		let dialect = Dialect {
+			delimiter: ';',
+			quote: '\'',
+			escape: Some('\\'),
+			..Dialect::default()
+		};
+		let mut csv_file = CsvParser::with_dialect(
+			Cursor::new("name;note\n'Bob; the builder';'It\\'s\nfixed, \"really\"'\n"),
+			dialect.clone(),
+		);
+		assert_eq!(csv_file.next(), Some(vec![String::from("name"), String::from("note")]));
+		let next = csv_file.next();
+		assert_eq!(
+			next,
+			Some(vec![
+				String::from("Bob; the builder"),
+				String::from("It's\nfixed, \"really\"")
+			])
+		);
+		assert_eq!(csv_file.next(), None);
+
+		let mut output = String::new();
+		export_with_dialect(&[next.unwrap()], &mut output, &dialect);
+		assert_eq!(output, String::from("'Bob; the builder';'It\\'s\nfixed, \"really\"'\n"));
+	}
+
+	#[test]
+	fn read_record_test() {
+		let mut csv_file = CsvParser::new(Cursor::new("a,\"b,\"\"c\"\"\",\n\"multi\nline\",x\nlast"));
+		let mut record = Record::default();
+		assert!(record.is_empty());
+
+		assert_eq!(csv_file.read_record(&mut record), Ok(true));
+		assert_eq!(record.len(), 3);
+		assert_eq!(record.get(0), Some("a"));
+		assert_eq!(record.get(1), Some("b,\"c\""));
+		assert_eq!(record.get(2), Some(""));
+		assert_eq!(record.get(3), None);
+
+		assert_eq!(csv_file.read_record(&mut record), Ok(true));
+		assert_eq!(record.iter().collect::<Vec<&str>>(), vec!["multi\nline", "x"]);
+		assert_eq!(csv_file.read_record(&mut record), Ok(true));
+		assert_eq!(record.to_vec(), vec![String::from("last")]);
+		assert_eq!(csv_file.read_record(&mut record), Ok(false));
+		assert_eq!(csv_file.bytes_read, 32);
+
+		// multi-byte special characters
+		let dialect = Dialect {
+			delimiter: '§',
+			quote: '«',
+			escape: Some('\\'),
+			..Dialect::default()
+		};
+		let mut csv_file = CsvParser::with_dialect(Cursor::new("ä§«b§\\«c«§d\r\n"), dialect);
+		assert_eq!(csv_file.read_record(&mut record), Ok(true));
+		assert_eq!(record.to_vec(), vec![String::from("ä"), String::from("b§«c"), String::from("d")]);
+
+		// invalid UTF-8 doesn't end the file
+		let mut csv_file = CsvParser::new(Cursor::new(b"ok,bad \xFF byte,\xC3\nnext\n".to_vec()));
+		assert_eq!(csv_file.read_record(&mut record), Ok(true));
+		assert_eq!(
+			record.to_vec(),
+			vec![
+				String::from("ok"),
+				String::from("bad \u{FFFD} byte"),
+				String::from("\u{FFFD}")
+			]
+		);
+		assert_eq!(csv_file.next(), Some(vec![String::from("next")]));
+
+		let mut row = vec![String::from("old"), String::from("cells"), String::from("to drop")];
+		let mut csv_file = CsvParser::new(Cursor::new("a,b\n"));
+		assert_eq!(csv_file.read_record(&mut record), Ok(true));
+		record.copy_to(&mut row);
+		assert_eq!(row, vec![String::from("a"), String::from("b")]);
+	}
+
+	/// A reader that fails after the first line
+	struct FailingReader(bool);
+
+	impl std::io::Read for FailingReader {
+		fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+			if self.0 {
+				return Err(std::io::Error::other("disk on fire"));
+			}
+			self.0 = true;
+			buffer[..4].copy_from_slice(b"a,b\n");
+			Ok(4)
+		}
+	}
+
+	#[test]
+	fn read_error_test() {
+		let mut csv_file = CsvParser::new(BufReader::new(FailingReader(false)));
+		let mut record = Record::default();
+		assert_eq!(csv_file.read_record(&mut record), Ok(true));
+		assert_eq!(csv_file.read_record(&mut record).map_err(|error| error.stage), Err(ErrorStages::Io));
+	}
+
 	#[test]
 	fn quote_csv_cell_test() {
 		let mut output = String::from("");
-		quote_csv_cell("test", &mut output);
+		quote_csv_cell("test", &mut output, &Dialect::default());
 		assert_eq!(output, String::from("test"));
 		output.clear();
-		quote_csv_cell("test,", &mut output);
+		quote_csv_cell("test,", &mut output, &Dialect::default());
 		assert_eq!(output, String::from("\"test,\""));
 		output.clear();
-		quote_csv_cell("test\"", &mut output);
+		quote_csv_cell("test\"", &mut output, &Dialect::default());
 		assert_eq!(output, String::from("\"test\"\"\""));
 		output.clear();
-		quote_csv_cell("test\ntest", &mut output);
+		quote_csv_cell("test\ntest", &mut output, &Dialect::default());
 		assert_eq!(output, String::from("\"test\ntest\""));
 	}
 }
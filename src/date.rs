@@ -0,0 +1,640 @@
+//! This module reads and writes dates for the `DATE` filter and the `BEFORE` and `AFTER` conditions
+//!
+//! Formats use the strftime specifiers like `%d/%m/%Y`, besides them a date can be read from an ISO 8601 text with
+//! the `iso` format or from an Excel serial number with the `excel` format.
+//! Dates keep the fixed UTC offset they were written with, if any, and can be moved to another one.
+
+/// The specifiers a format can use
+const SPECIFIERS: [char; 23] = [
+	'Y', 'y', 'm', 'd', 'e', 'H', 'I', 'p', 'M', 'S', 'f', 'b', 'B', 'h', 'a', 'A', 'j', 'z', 's', 'F', 'T', 'D', '%',
+];
+
+const MONTHS: [&str; 12] = [
+	"January",
+	"February",
+	"March",
+	"April",
+	"May",
+	"June",
+	"July",
+	"August",
+	"September",
+	"October",
+	"November",
+	"December",
+];
+
+const WEEKDAYS: [&str; 7] = [
+	"Sunday",
+	"Monday",
+	"Tuesday",
+	"Wednesday",
+	"Thursday",
+	"Friday",
+	"Saturday",
+];
+
+/// Day 0 of Excel serial dates, 1899-12-30, in days since 1970-01-01
+///
+/// Excel counts a 29th of February 1900 that never was, serials before March 1900 are a day off because of it.
+const EXCEL_EPOCH_DAYS: i64 = -25_569;
+
+/// A date and time of day with an optional fixed UTC offset
+///
+/// ```rust
+/// use csv_converter::date::DateTime;
+///
+/// let date = DateTime::parse("17/10/2026 8:05", "%d/%m/%Y %H:%M").unwrap();
+/// assert_eq!(date.format("%Y-%m-%dT%H:%M:%S"), String::from("2026-10-17T08:05:00"));
+/// assert_eq!(DateTime::parse("46312", "excel").unwrap().format("%F"), String::from("2026-10-17"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+	pub year: i32,
+	/// The month from 1 to 12
+	pub month: u32,
+	/// The day of the month starting at 1
+	pub day: u32,
+	pub hour: u32,
+	pub minute: u32,
+	pub second: u32,
+	pub nanosecond: u32,
+	/// The offset from UTC in seconds, `None` when the date didn't say
+	pub offset: Option<i32>,
+}
+
+impl Default for DateTime {
+	fn default() -> Self {
+		Self {
+			year: 1970,
+			month: 1,
+			day: 1,
+			hour: 0,
+			minute: 0,
+			second: 0,
+			nanosecond: 0,
+			offset: None,
+		}
+	}
+}
+
+impl DateTime {
+	/// Read a date in the given format, returns `None` if it doesn't match the format or isn't a valid date
+	pub fn parse(input: &str, format: &str) -> Option<Self> {
+		let input = input.trim();
+		match format {
+			"iso" => parse_iso(input),
+			"excel" => parse_excel(input),
+			_ => parse_format(input, format),
+		}
+	}
+
+	/// Write the date in the given strftime format, `%z` is left out for dates without an offset
+	///
+	/// The `iso` format writes the date like `2026-10-17T08:00:00+02:00`
+	pub fn format(&self, format: &str) -> String {
+		if format == "iso" {
+			return self.format("%Y-%m-%dT%H:%M:%S%:z");
+		}
+
+		let mut output = String::with_capacity(format.len() + 8);
+		let mut chars = format.chars();
+
+		while let Some(c) = chars.next() {
+			if c != '%' {
+				output.push(c);
+				continue;
+			}
+			match chars.next() {
+				Some('Y') => output.push_str(&format!("{:04}", self.year)),
+				Some('y') => output.push_str(&format!("{:02}", self.year.rem_euclid(100))),
+				Some('m') => output.push_str(&format!("{:02}", self.month)),
+				Some('d') => output.push_str(&format!("{:02}", self.day)),
+				Some('e') => output.push_str(&format!("{:2}", self.day)),
+				Some('H') => output.push_str(&format!("{:02}", self.hour)),
+				Some('I') => output.push_str(&format!("{:02}", (self.hour + 11) % 12 + 1)),
+				Some('p') => output.push_str(if self.hour < 12 { "AM" } else { "PM" }),
+				Some('M') => output.push_str(&format!("{:02}", self.minute)),
+				Some('S') => output.push_str(&format!("{:02}", self.second)),
+				Some('f') => output.push_str(&format!("{:06}", self.nanosecond / 1000)),
+				Some('b') | Some('h') => output.push_str(&MONTHS[self.month as usize - 1][..3]),
+				Some('B') => output.push_str(MONTHS[self.month as usize - 1]),
+				Some('a') => output.push_str(&WEEKDAYS[self.weekday()][..3]),
+				Some('A') => output.push_str(WEEKDAYS[self.weekday()]),
+				Some('j') => output.push_str(&format!("{:03}", self.day_of_year())),
+				Some('s') => output.push_str(&self.timestamp().to_string()),
+				Some('F') => output.push_str(&self.format("%Y-%m-%d")),
+				Some('T') => output.push_str(&self.format("%H:%M:%S")),
+				Some('D') => output.push_str(&self.format("%m/%d/%y")),
+				Some('z') => output.push_str(&self.offset.map(|offset| format_offset(offset, "")).unwrap_or_default()),
+				Some(':') if chars.clone().next() == Some('z') => {
+					chars.next();
+					output.push_str(&self.offset.map(|offset| format_offset(offset, ":")).unwrap_or_default());
+				},
+				Some('%') => output.push('%'),
+				Some(other) => {
+					output.push('%');
+					output.push(other);
+				},
+				None => output.push('%'),
+			}
+		}
+
+		output
+	}
+
+	/// The seconds since 1970-01-01 00:00:00 UTC, dates without an offset are taken as UTC
+	pub fn timestamp(&self) -> i64 {
+		days_from_civil(self.year, self.month, self.day) * 86_400
+			+ i64::from(self.hour * 3600 + self.minute * 60 + self.second)
+			- i64::from(self.offset.unwrap_or(0))
+	}
+
+	/// The same moment at another UTC offset, a date without an offset is taken to already be at the new one
+	///
+	/// None if the year at the new offset is out of range.
+	pub fn to_offset(self, offset: i32) -> Option<Self> {
+		match self.offset {
+			Some(_) => from_timestamp(self.timestamp(), self.nanosecond, Some(offset)),
+			None => Some(Self {
+				offset: Some(offset),
+				..self
+			}),
+		}
+	}
+
+	/// The day of the week from 0 for Sunday to 6 for Saturday
+	fn weekday(&self) -> usize {
+		(days_from_civil(self.year, self.month, self.day) + 4).rem_euclid(7) as usize
+	}
+
+	fn day_of_year(&self) -> i64 {
+		days_from_civil(self.year, self.month, self.day) - days_from_civil(self.year, 1, 1) + 1
+	}
+
+	fn is_valid(&self) -> bool {
+		(1..=12).contains(&self.month)
+			&& (1..=days_in_month(self.year, self.month)).contains(&self.day)
+			&& self.hour < 24
+			&& self.minute < 60
+			&& self.second < 60
+	}
+}
+
+/// Check a strftime format only uses specifiers we know, returns the first unknown one
+pub fn check_format(format: &str) -> Result<(), String> {
+	if format == "iso" || format == "excel" {
+		return Ok(());
+	}
+
+	let mut chars = format.chars();
+	while let Some(c) = chars.next() {
+		if c != '%' {
+			continue;
+		}
+		match chars.next() {
+			Some(':') if chars.next() == Some('z') => {},
+			Some(specifier) if SPECIFIERS.contains(&specifier) => {},
+			Some(':') => return Err(String::from("%:")),
+			Some(specifier) => return Err(format!("%{specifier}")),
+			None => return Err(String::from("%")),
+		}
+	}
+	Ok(())
+}
+
+/// Read a fixed UTC offset like `+02:00`, `-0500`, `+01` or `Z` into seconds
+pub fn parse_offset(input: &str) -> Option<i32> {
+	if input.trim() == "UTC" {
+		return Some(0);
+	}
+	let mut scanner = Scanner::new(input.trim());
+	scanner.offset().filter(|_| scanner.rest().is_empty())
+}
+
+/// Write an offset in seconds like `+02:00` with the given separator between hours and minutes
+pub fn format_offset(offset: i32, separator: &str) -> String {
+	let sign = if offset < 0 { '-' } else { '+' };
+	let minutes = offset.unsigned_abs() / 60;
+	format!("{sign}{:02}{separator}{:02}", minutes / 60, minutes % 60)
+}
+
+/// A date in the ISO 8601 style like `2026-10-17`, `2026-10-17T08:00:00Z` or `2026-10-17 08:00:00.5+02:00`
+fn parse_iso(input: &str) -> Option<DateTime> {
+	let mut scanner = Scanner::new(input);
+	let mut date = DateTime {
+		year: scanner.number(4)? as i32,
+		..Default::default()
+	};
+	scanner.expect('-')?;
+	date.month = scanner.number(2)?;
+	scanner.expect('-')?;
+	date.day = scanner.number(2)?;
+
+	if scanner.eat('T') || scanner.eat(' ') {
+		date.hour = scanner.number(2)?;
+		scanner.expect(':')?;
+		date.minute = scanner.number(2)?;
+		if scanner.eat(':') {
+			date.second = scanner.number(2)?;
+			if scanner.eat('.') || scanner.eat(',') {
+				date.nanosecond = scanner.fraction()?;
+			}
+		}
+		if !scanner.rest().is_empty() {
+			date.offset = Some(scanner.offset()?);
+		}
+	}
+
+	(scanner.rest().is_empty() && date.is_valid()).then_some(date)
+}
+
+/// An Excel serial date, the days since 1899-12-30 with the time of day as the fraction
+fn parse_excel(input: &str) -> Option<DateTime> {
+	let serial = input.parse::<f64>().ok().filter(|serial| (0.0..=2_958_466.0).contains(serial))?;
+	let seconds = (serial * 86_400.0).round() as i64;
+	from_timestamp(seconds + EXCEL_EPOCH_DAYS * 86_400, 0, None)
+}
+
+fn parse_format(input: &str, format: &str) -> Option<DateTime> {
+	let mut scanner = Scanner::new(input);
+	let mut date = DateTime::default();
+	let mut pm = None;
+	let mut day_of_year = None;
+	let mut timestamp = None;
+	let mut chars = format.chars();
+
+	while let Some(c) = chars.next() {
+		if c != '%' {
+			if c.is_whitespace() {
+				scanner.skip_whitespace();
+			} else {
+				scanner.expect(c)?;
+			}
+			continue;
+		}
+		match chars.next()? {
+			'Y' => date.year = scanner.number(4)? as i32,
+			'y' => {
+				// like POSIX 69 to 99 are in the 1900s and 00 to 68 in the 2000s
+				let year = scanner.number(2)? as i32;
+				date.year = if year < 69 { 2000 + year } else { 1900 + year };
+			},
+			'm' => date.month = scanner.number(2)?,
+			'd' | 'e' => {
+				scanner.skip_whitespace();
+				date.day = scanner.number(2)?;
+			},
+			'H' => date.hour = scanner.number(2)?,
+			'I' => date.hour = scanner.number(2)?.checked_sub(1).filter(|hour| *hour < 12)? + 1,
+			'p' => pm = Some(scanner.name(&["PM", "AM"], 2)? == 0),
+			'M' => date.minute = scanner.number(2)?,
+			'S' => date.second = scanner.number(2)?,
+			'f' => date.nanosecond = scanner.fraction()?,
+			'b' | 'h' | 'B' => date.month = scanner.name(&MONTHS, 3)? as u32 + 1,
+			'a' | 'A' => {
+				scanner.name(&WEEKDAYS, 3)?;
+			},
+			'j' => day_of_year = Some(i64::from(scanner.number(3)?)),
+			'z' => date.offset = Some(scanner.offset()?),
+			':' if chars.next() == Some('z') => date.offset = Some(scanner.offset()?),
+			's' => timestamp = Some(scanner.signed_number()?),
+			'F' => {
+				let day = parse_format(scanner.take(10), "%Y-%m-%d")?;
+				(date.year, date.month, date.day) = (day.year, day.month, day.day);
+			},
+			'T' => {
+				let time = parse_format(scanner.take(8), "%H:%M:%S")?;
+				(date.hour, date.minute, date.second) = (time.hour, time.minute, time.second);
+			},
+			'D' => {
+				let day = parse_format(scanner.take(8), "%m/%d/%y")?;
+				(date.year, date.month, date.day) = (day.year, day.month, day.day);
+			},
+			'%' => scanner.expect('%')?,
+			_ => return None,
+		}
+	}
+	if !scanner.rest().is_empty() {
+		return None;
+	}
+
+	if let Some(pm) = pm {
+		date.hour = date.hour % 12 + if pm { 12 } else { 0 };
+	}
+	if let Some(day_of_year) = day_of_year {
+		let (year, month, day) = civil_from_days(days_from_civil(date.year, 1, 1) + day_of_year - 1);
+		if year != i64::from(date.year) || day_of_year == 0 {
+			return None;
+		}
+		(date.month, date.day) = (month, day);
+	}
+	if let Some(timestamp) = timestamp {
+		date = from_timestamp(timestamp, date.nanosecond, Some(0))?;
+	}
+
+	date.is_valid().then_some(date)
+}
+
+/// The date of a Unix timestamp at the offset, None if its year doesn't fit
+fn from_timestamp(timestamp: i64, nanosecond: u32, offset: Option<i32>) -> Option<DateTime> {
+	let local = timestamp.checked_add(i64::from(offset.unwrap_or(0)))?;
+	let (year, month, day) = civil_from_days(local.div_euclid(86_400));
+	let seconds = local.rem_euclid(86_400) as u32;
+	Some(DateTime {
+		year: i32::try_from(year).ok()?,
+		month,
+		day,
+		hour: seconds / 3600,
+		minute: seconds / 60 % 60,
+		second: seconds % 60,
+		nanosecond,
+		offset,
+	})
+}
+
+fn is_leap_year(year: i32) -> bool {
+	(year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+	match month {
+		2 if is_leap_year(year) => 29,
+		2 => 28,
+		4 | 6 | 9 | 11 => 30,
+		_ => 31,
+	}
+}
+
+/// The days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i32, month: u32, day: u32) -> i64 {
+	// years start in March so the leap day is the last day of the year
+	let year = i64::from(year) - i64::from(month <= 2);
+	let era = year.div_euclid(400);
+	let year_of_era = year - era * 400;
+	let month = i64::from(month);
+	let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	era * 146_097 + day_of_era - 719_468
+}
+
+/// The opposite of [days_from_civil]
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+	let days = days + 719_468;
+	let era = days.div_euclid(146_097);
+	let day_of_era = days - era * 146_097;
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let month_from_march = (5 * day_of_year + 2) / 153;
+	let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+	let month = if month_from_march < 10 {
+		month_from_march + 3
+	} else {
+		month_from_march - 9
+	} as u32;
+	(year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// Reads the parts of a date one after another
+struct Scanner<'a> {
+	input: &'a str,
+	position: usize,
+}
+
+impl<'a> Scanner<'a> {
+	fn new(input: &'a str) -> Self {
+		Self { input, position: 0 }
+	}
+
+	fn rest(&self) -> &'a str {
+		&self.input[self.position..]
+	}
+
+	fn skip_whitespace(&mut self) {
+		self.position = self.input.len() - self.rest().trim_start().len();
+	}
+
+	fn eat(&mut self, c: char) -> bool {
+		if self.rest().starts_with(c) {
+			self.position += c.len_utf8();
+			true
+		} else {
+			false
+		}
+	}
+
+	fn expect(&mut self, c: char) -> Option<()> {
+		self.eat(c).then_some(())
+	}
+
+	/// Take the next characters up to the given length
+	fn take(&mut self, length: usize) -> &'a str {
+		let end = self.rest().char_indices().nth(length).map_or(self.input.len(), |(i, _)| self.position + i);
+		let taken = &self.input[self.position..end];
+		self.position = end;
+		taken
+	}
+
+	/// A number of at least one and at most the given digits
+	fn number(&mut self, max_digits: usize) -> Option<u32> {
+		let digits = self.rest().bytes().take(max_digits).take_while(u8::is_ascii_digit).count();
+		let number = self.rest()[..digits].parse().ok()?;
+		self.position += digits;
+		Some(number)
+	}
+
+	fn signed_number(&mut self) -> Option<i64> {
+		let negative = self.eat('-');
+		let digits = self.rest().bytes().take_while(u8::is_ascii_digit).count();
+		let number = self.rest()[..digits].parse::<i64>().ok()?;
+		self.position += digits;
+		Some(if negative { -number } else { number })
+	}
+
+	/// The digits after the decimal point as nanoseconds
+	fn fraction(&mut self) -> Option<u32> {
+		let digits = self.rest().bytes().take_while(u8::is_ascii_digit).count();
+		if digits == 0 {
+			return None;
+		}
+		let nanosecond = format!("{:0<9}", &self.rest()[..digits.min(9)]).parse().ok()?;
+		self.position += digits;
+		Some(nanosecond)
+	}
+
+	/// The index of the name that's next, either in full or with at least the given number of leading characters
+	fn name(&mut self, names: &[&str], min_length: usize) -> Option<usize> {
+		let rest = self.rest();
+		for (index, name) in names.iter().enumerate() {
+			for length in [name.len(), min_length] {
+				if rest.len() >= length && rest.is_char_boundary(length) && rest[..length].eq_ignore_ascii_case(&name[..length])
+				{
+					self.position += length;
+					return Some(index);
+				}
+			}
+		}
+		None
+	}
+
+	/// A UTC offset like `Z`, `+02:00`, `+0200` or `+02`
+	fn offset(&mut self) -> Option<i32> {
+		if self.eat('Z') || self.eat('z') {
+			return Some(0);
+		}
+		let sign = if self.eat('+') {
+			1
+		} else if self.eat('-') {
+			-1
+		} else {
+			return None;
+		};
+		let hours = self.number(2)?;
+		self.eat(':');
+		let minutes = if self.rest().starts_with(|c: char| c.is_ascii_digit()) {
+			self.number(2)?
+		} else {
+			0
+		};
+		if hours > 23 || minutes > 59 {
+			return None;
+		}
+		Some(sign * (hours * 3600 + minutes * 60) as i32)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn date(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32, offset: Option<i32>) -> DateTime {
+		DateTime {
+			year,
+			month,
+			day,
+			hour,
+			minute,
+			second,
+			nanosecond: 0,
+			offset,
+		}
+	}
+
+	#[test]
+	fn parse_format_test() {
+		assert_eq!(DateTime::parse("17/10/2026", "%d/%m/%Y"), Some(date(2026, 10, 17, 0, 0, 0, None)));
+		assert_eq!(DateTime::parse(" 7/1/2026 ", "%d/%m/%Y"), Some(date(2026, 1, 7, 0, 0, 0, None)));
+		assert_eq!(DateTime::parse("20261017", "%Y%m%d"), Some(date(2026, 10, 17, 0, 0, 0, None)));
+		assert_eq!(DateTime::parse("10/17/26 8:05 pm", "%m/%d/%y %I:%M %p"), Some(date(2026, 10, 17, 20, 5, 0, None)));
+		assert_eq!(DateTime::parse("12:30 AM", "%I:%M %p"), Some(date(1970, 1, 1, 0, 30, 0, None)));
+		assert_eq!(DateTime::parse("Sat, 17 Oct 2026", "%a, %d %b %Y"), Some(date(2026, 10, 17, 0, 0, 0, None)));
+		assert_eq!(DateTime::parse("17. october 2026", "%d. %B %Y"), Some(date(2026, 10, 17, 0, 0, 0, None)));
+		assert_eq!(DateTime::parse("2026-10-17 08:00:00 +0200", "%F %T %z"), Some(date(2026, 10, 17, 8, 0, 0, Some(7200))));
+		assert_eq!(DateTime::parse("2026-290", "%Y-%j"), Some(date(2026, 10, 17, 0, 0, 0, None)));
+		assert_eq!(DateTime::parse("1792224000", "%s"), Some(date(2026, 10, 17, 8, 0, 0, Some(0))));
+		// %F and %D only set the date so the time and offset read before stay
+		assert_eq!(
+			DateTime::parse("08:30 +0200 2026-10-17", "%H:%M %z %F"),
+			Some(date(2026, 10, 17, 8, 30, 0, Some(7200)))
+		);
+		assert_eq!(DateTime::parse("08:30 10/17/26", "%H:%M %D"), Some(date(2026, 10, 17, 8, 30, 0, None)));
+		assert_eq!(DateTime::parse("100%", "100%%"), Some(DateTime::default()));
+
+		assert_eq!(DateTime::parse("31/02/2026", "%d/%m/%Y"), None);
+		assert_eq!(DateTime::parse("29/02/2025", "%d/%m/%Y"), None);
+		assert!(DateTime::parse("29/02/2024", "%d/%m/%Y").is_some());
+		assert_eq!(DateTime::parse("17/10/2026 extra", "%d/%m/%Y"), None);
+		assert_eq!(DateTime::parse("17-10-2026", "%d/%m/%Y"), None);
+		assert_eq!(DateTime::parse("13:00 PM", "%I:%M %p"), None);
+		assert_eq!(DateTime::parse("", "%d/%m/%Y"), None);
+		assert_eq!(DateTime::parse("2026-366", "%Y-%j"), None);
+		// timestamps whose year doesn't fit
+		assert_eq!(DateTime::parse("9223372036854775807", "%s"), None);
+		assert_eq!(DateTime::parse("-9223372036854775808", "%s"), None);
+		assert_eq!(DateTime::parse("67767976233532799", "%s"), Some(date(i32::MAX, 12, 31, 23, 59, 59, Some(0))));
+		assert_eq!(DateTime::parse("67767976233532800", "%s"), None);
+	}
+
+	#[test]
+	fn parse_iso_test() {
+		assert_eq!(DateTime::parse("2026-10-17", "iso"), Some(date(2026, 10, 17, 0, 0, 0, None)));
+		assert_eq!(DateTime::parse("2026-10-17T08:00:00Z", "iso"), Some(date(2026, 10, 17, 8, 0, 0, Some(0))));
+		assert_eq!(DateTime::parse("2026-10-17 08:00", "iso"), Some(date(2026, 10, 17, 8, 0, 0, None)));
+		assert_eq!(DateTime::parse("2026-10-17T08:00:00-05:30", "iso"), Some(date(2026, 10, 17, 8, 0, 0, Some(-19800))));
+		assert_eq!(
+			DateTime::parse("2026-10-17T08:00:00.25+02", "iso"),
+			Some(DateTime {
+				nanosecond: 250_000_000,
+				..date(2026, 10, 17, 8, 0, 0, Some(7200))
+			})
+		);
+
+		assert_eq!(DateTime::parse("2026-13-01", "iso"), None);
+		assert_eq!(DateTime::parse("2026-10-17T25:00", "iso"), None);
+		assert_eq!(DateTime::parse("2026-10-17T08:00+2", "iso"), Some(date(2026, 10, 17, 8, 0, 0, Some(7200))));
+		assert_eq!(DateTime::parse("2026-10-17T08:00 Berlin", "iso"), None);
+		assert_eq!(DateTime::parse("17.10.2026", "iso"), None);
+	}
+
+	#[test]
+	fn parse_excel_test() {
+		assert_eq!(DateTime::parse("46312", "excel"), Some(date(2026, 10, 17, 0, 0, 0, None)));
+		assert_eq!(DateTime::parse("46312.3333333333", "excel"), Some(date(2026, 10, 17, 8, 0, 0, None)));
+		assert_eq!(DateTime::parse("61", "excel"), Some(date(1900, 3, 1, 0, 0, 0, None)));
+		assert_eq!(DateTime::parse("-1", "excel"), None);
+		assert_eq!(DateTime::parse("soon", "excel"), None);
+	}
+
+	#[test]
+	fn format_test() {
+		let date = DateTime {
+			nanosecond: 123_456_789,
+			..date(2026, 10, 17, 20, 5, 9, Some(7200))
+		};
+		assert_eq!(date.format("%Y-%m-%dT%H:%M:%S.%f%:z"), "2026-10-17T20:05:09.123456+02:00");
+		assert_eq!(
+			date.format("%a %A %b %B %e %y %I%p %j %D %z"),
+			"Sat Saturday Oct October 17 26 08PM 290 10/17/26 +0200"
+		);
+		assert_eq!(date.format("%F %T 100%% %s"), "2026-10-17 20:05:09 100% 1792260309");
+		assert_eq!(DateTime::default().format("%e.%m. %H:%M %z"), " 1.01. 00:00 ");
+		assert_eq!(date.format("iso"), "2026-10-17T20:05:09+02:00");
+		assert_eq!(DateTime::default().format("iso"), "1970-01-01T00:00:00");
+	}
+
+	#[test]
+	fn offset_test() {
+		assert_eq!(parse_offset("+02:00"), Some(7200));
+		assert_eq!(parse_offset("-0530"), Some(-19800));
+		assert_eq!(parse_offset("Z"), Some(0));
+		assert_eq!(parse_offset("UTC"), Some(0));
+		assert_eq!(parse_offset("+24:00"), None);
+		assert_eq!(parse_offset("02:00"), None);
+		assert_eq!(format_offset(-19800, ":"), "-05:30");
+
+		let utc = DateTime::parse("2026-10-17T23:30:00+00:00", "iso").unwrap();
+		assert_eq!(utc.to_offset(7200), Some(date(2026, 10, 18, 1, 30, 0, Some(7200))));
+		assert_eq!(utc.to_offset(7200).unwrap().timestamp(), utc.timestamp());
+		assert_eq!(date(2026, 10, 17, 8, 0, 0, None).to_offset(-3600), Some(date(2026, 10, 17, 8, 0, 0, Some(-3600))));
+		assert_eq!(date(i32::MAX, 12, 31, 23, 0, 0, Some(0)).to_offset(7200), None);
+	}
+
+	#[test]
+	fn check_format_test() {
+		assert_eq!(check_format("%d/%m/%Y %H:%M:%S%:z"), Ok(()));
+		assert_eq!(check_format("iso"), Ok(()));
+		assert_eq!(check_format("%d/%m/%Q"), Err(String::from("%Q")));
+		assert_eq!(check_format("%Y-%"), Err(String::from("%")));
+	}
+
+	#[test]
+	fn days_test() {
+		assert_eq!(days_from_civil(1970, 1, 1), 0);
+		assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+		assert_eq!(days_from_civil(1899, 12, 30), EXCEL_EPOCH_DAYS);
+		for days in [-800_000, -1, 0, 59, 10_957, 20_743, 2_932_896] {
+			let (year, month, day) = civil_from_days(days);
+			assert_eq!(days_from_civil(year as i32, month, day), days);
+		}
+	}
+}
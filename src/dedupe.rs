@@ -0,0 +1,557 @@
+use std::{
+	borrow::Cow,
+	cmp::Reverse,
+	collections::{hash_map::DefaultHasher, BinaryHeap, HashMap, HashSet},
+	fs::{self, File},
+	hash::{Hash, Hasher},
+	io::{BufReader, BufWriter, Write},
+	path::{Path, PathBuf},
+	sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+	cli::{Error, ErrorStages},
+	config::{Item, Row},
+	csv::{quote_csv_cell, CsvParser, Dialect, Record},
+	process::get_value,
+};
+
+/// The memory the keys of a [Deduplicator] may take before they are spilled to disk, in megabytes
+pub const DEFAULT_MEMORY_BUDGET: usize = 256;
+
+/// The number of files the lines are spread over by their key once the keys don't fit into memory anymore
+const PARTITIONS: usize = 16;
+
+/// How often a file that is still larger than the memory budget is spread over smaller files before it's deduplicated
+/// in memory anyway
+const MAX_SPLITS: usize = 4;
+
+/// A rough guess of what the hash table and the allocations of an entry cost on top of its text
+const ENTRY_OVERHEAD: usize = 64;
+
+/// Which of the output lines with the same key is kept
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Keep {
+	/// Keep the first line of a key and drop every later one
+	#[default]
+	First,
+	/// Keep the last line of a key, it is emitted at the position of that last line
+	Last,
+}
+
+/// Parse the item of `--dedupe-by` and resolve its `<col 'heading'>` items against the heading of the output
+pub fn parse_dedupe_key(key_str: &str, heading: &[String]) -> Result<Item, Error> {
+	let mut item = Item::parse(key_str.to_string())?;
+	item.resolve_headers(heading)?;
+	Ok(item)
+}
+
+/// The key of an output line, items like `<cell1>` refer to the columns of the output
+pub fn dedupe_key(item: &Item, line: &[String]) -> Result<String, Error> {
+	get_value(item, &Row::new(line)).map(Cow::into_owned)
+}
+
+/// Drops output lines whose key was already seen, or will be seen again when keeping the last line
+///
+/// Push every output line together with its key in the order of the output then call [Deduplicator::finish].
+/// The keys are held in memory until they take more than the memory budget after which all lines are spread over
+/// temporary files by their key, each file is deduplicated on its own and the results are merged back into order.
+/// A file that is larger than the memory budget is spread over smaller files again before it's deduplicated.
+///
+/// ```rust
+/// use csv_converter::dedupe::{Deduplicator, Keep};
+///
+/// let mut deduplicator = Deduplicator::new(Keep::First, 1024 * 1024);
+/// let line = vec![String::from("shirt"), String::from("red")];
+/// assert_eq!(deduplicator.push(String::from("shirt"), line.clone()), Ok(Some(line)));
+/// assert_eq!(deduplicator.push(String::from("shirt"), vec![String::from("shirt"), String::from("blue")]), Ok(None));
+/// assert_eq!(deduplicator.finish(|_| Ok(())), Ok(1));
+/// ```
+#[derive(Debug)]
+pub struct Deduplicator {
+	keep: Keep,
+	/// The number of bytes the keys, and the lines when keeping the last one, may take in memory
+	memory_budget: usize,
+	memory: usize,
+	/// The position of the next line in the output
+	position: u64,
+	/// The keys of all lines emitted so far when keeping the first line
+	seen: HashSet<String>,
+	/// The latest line and its position for each key when keeping the last line
+	pending: HashMap<String, (u64, Vec<String>)>,
+	spill: Option<Spill>,
+	dropped: usize,
+}
+
+impl Deduplicator {
+	/// A deduplicator that spills to disk once its keys take more than the memory budget in bytes
+	pub fn new(keep: Keep, memory_budget: usize) -> Self {
+		Self {
+			keep,
+			memory_budget,
+			memory: 0,
+			position: 0,
+			seen: HashSet::new(),
+			pending: HashMap::new(),
+			spill: None,
+			dropped: 0,
+		}
+	}
+
+	/// Add the next output line, returns the line if it can be emitted right away
+	pub fn push(&mut self, key: String, line: Vec<String>) -> Result<Option<Vec<String>>, Error> {
+		let position = self.position;
+		self.position += 1;
+
+		if let Some(spill) = &mut self.spill {
+			spill.write(Spill::LINE, position, &key, &line)?;
+			return Ok(None);
+		}
+
+		let output = match self.keep {
+			Keep::First => {
+				if self.seen.contains(&key) {
+					self.dropped += 1;
+					return Ok(None);
+				}
+				self.memory += key.len() + ENTRY_OVERHEAD;
+				self.seen.insert(key);
+				Some(line)
+			},
+			Keep::Last => {
+				self.memory += key.len() + line_size(&line) + ENTRY_OVERHEAD;
+				let key_size = key.len();
+				if let Some((_, previous)) = self.pending.insert(key, (position, line)) {
+					self.memory -= key_size + line_size(&previous) + ENTRY_OVERHEAD;
+					self.dropped += 1;
+				}
+				None
+			},
+		};
+
+		if self.memory > self.memory_budget {
+			self.spill()?;
+		}
+		Ok(output)
+	}
+
+	/// Emit the lines that are still held back in the order of the output, returns the number of dropped lines
+	pub fn finish(mut self, mut emit: impl FnMut(Vec<String>) -> Result<(), Error>) -> Result<usize, Error> {
+		match self.spill.take() {
+			Some(spill) => {
+				self.dropped += spill.merge(self.keep, self.memory_budget, emit)?;
+			},
+			None => {
+				let mut pending: Vec<(u64, Vec<String>)> = self.pending.into_values().collect();
+				pending.sort_unstable_by_key(|(position, _)| *position);
+				for (_, line) in pending {
+					emit(line)?;
+				}
+			},
+		}
+
+		Ok(self.dropped)
+	}
+
+	/// Move everything held in memory to disk, every line pushed after this goes straight to disk as well
+	fn spill(&mut self) -> Result<(), Error> {
+		let mut spill = Spill::new()?;
+
+		// the files have to be in the order of the output and lines that were already emitted come first
+		for key in self.seen.drain() {
+			spill.write(Spill::SEEN, 0, &key, &[])?;
+		}
+		let mut pending: Vec<(String, (u64, Vec<String>))> = self.pending.drain().collect();
+		pending.sort_unstable_by_key(|(_, (position, _))| *position);
+		for (key, (position, line)) in pending {
+			spill.write(Spill::LINE, position, &key, &line)?;
+		}
+
+		self.memory = 0;
+		self.spill = Some(spill);
+		Ok(())
+	}
+}
+
+/// A rough guess of the memory a line takes
+pub(crate) fn line_size(line: &[String]) -> usize {
+	line.iter().map(|cell| cell.len() + std::mem::size_of::<String>()).sum()
+}
+
+/// The temporary files of a [Deduplicator] whose keys didn't fit into memory
+#[derive(Debug)]
+struct Spill {
+	dir: TempDir,
+	partitions: Vec<Partition>,
+	/// The number of files created so far, each file gets a new name
+	files: usize,
+	buffer: String,
+}
+
+/// One of the files records are spread over by the hash of their key
+#[derive(Debug)]
+struct Partition {
+	path: PathBuf,
+	file: BufWriter<File>,
+	/// The number of bytes written to the file
+	size: u64,
+}
+
+impl Spill {
+	/// A record of a key that was emitted before spilling so later lines of that key are dropped
+	const SEEN: &'static str = "S";
+	/// A record of an output line
+	const LINE: &'static str = "L";
+
+	fn new() -> Result<Self, Error> {
+		let mut spill = Self {
+			dir: TempDir::new("dedupe")?,
+			partitions: Vec::new(),
+			files: 0,
+			buffer: String::new(),
+		};
+		spill.partitions = spill.create_partitions()?;
+		Ok(spill)
+	}
+
+	/// Append a record to the partition of its key
+	fn write(&mut self, kind: &str, position: u64, key: &str, line: &[String]) -> Result<(), Error> {
+		let index = partition_index(key, 0);
+		let position = position.to_string();
+		let cells = [kind, &position, key].into_iter().chain(line.iter().map(String::as_str));
+		self.partitions[index].write(cells, &mut self.buffer)
+	}
+
+	/// Deduplicate each partition on its own then merge the kept lines of all partitions back into the order of the
+	/// output, returns the number of dropped lines
+	fn merge(
+		mut self,
+		keep: Keep,
+		memory_budget: usize,
+		mut emit: impl FnMut(Vec<String>) -> Result<(), Error>,
+	) -> Result<usize, Error> {
+		let mut dropped = 0;
+		let mut kept_files = Vec::with_capacity(PARTITIONS);
+		for partition in std::mem::take(&mut self.partitions) {
+			let (path, size) = partition.finish()?;
+			if let Some(kept_path) = self.dedupe(&path, size, 0, keep, memory_budget, &mut dropped)? {
+				kept_files.push(kept_path);
+			}
+		}
+
+		merge_kept(&kept_files, |_, line| emit(line))?;
+		Ok(dropped)
+	}
+
+	/// Deduplicate a partition into a file of the kept lines in the order of the output, a partition larger than the
+	/// memory budget is split into smaller ones by another hash of the key first
+	fn dedupe(
+		&mut self,
+		path: &Path,
+		size: u64,
+		depth: usize,
+		keep: Keep,
+		memory_budget: usize,
+		dropped: &mut usize,
+	) -> Result<Option<PathBuf>, Error> {
+		if size == 0 {
+			fs::remove_file(path).map_err(|error| io_error(path, error))?;
+			return Ok(None);
+		}
+
+		let kept_path = self.next_path();
+		if size > memory_budget as u64 && depth < MAX_SPLITS {
+			let mut partitions = self.create_partitions()?;
+			read_records(path, |record| {
+				let index = partition_index(record.get(2).unwrap_or(""), depth + 1);
+				partitions[index].write(record.iter(), &mut self.buffer)
+			})?;
+			fs::remove_file(path).map_err(|error| io_error(path, error))?;
+
+			let mut kept_files = Vec::with_capacity(PARTITIONS);
+			for partition in partitions {
+				let (path, partition_size) = partition.finish()?;
+				// a split that doesn't make the partition smaller, like a single key, won't get better by splitting again
+				let depth = if partition_size == size { MAX_SPLITS } else { depth + 1 };
+				if let Some(kept_path) = self.dedupe(&path, partition_size, depth, keep, memory_budget, dropped)? {
+					kept_files.push(kept_path);
+				}
+			}
+
+			let mut kept = Partition::create(kept_path)?;
+			merge_kept(&kept_files, |position, line| {
+				let position = position.to_string();
+				kept.write(std::iter::once(position.as_str()).chain(line.iter().map(String::as_str)), &mut self.buffer)
+			})?;
+			return kept.finish().map(|(kept_path, _)| Some(kept_path));
+		}
+
+		// only the keys are held in memory, the lines are streamed from the partition into the kept file
+		let mut kept = Partition::create(kept_path)?;
+		let mut write_kept =
+			|record: &Record, buffer: &mut String| kept.write(record.get(1).into_iter().chain(record.iter().skip(3)), buffer);
+		match keep {
+			Keep::First => {
+				let mut seen = HashSet::new();
+				read_records(path, |record| {
+					if !seen.insert(record.get(2).unwrap_or("").to_string()) {
+						*dropped += 1;
+					} else if record.get(0) == Some(Spill::LINE) {
+						write_kept(record, &mut self.buffer)?;
+					}
+					Ok(())
+				})?;
+			},
+			Keep::Last => {
+				let mut latest = HashMap::new();
+				read_records(path, |record| {
+					if latest.insert(record.get(2).unwrap_or("").to_string(), parse_position(record.get(1))).is_some() {
+						*dropped += 1;
+					}
+					Ok(())
+				})?;
+				read_records(path, |record| {
+					if latest.get(record.get(2).unwrap_or("")) == Some(&parse_position(record.get(1))) {
+						write_kept(record, &mut self.buffer)?;
+					}
+					Ok(())
+				})?;
+			},
+		}
+		fs::remove_file(path).map_err(|error| io_error(path, error))?;
+		kept.finish().map(|(kept_path, _)| Some(kept_path))
+	}
+
+	fn create_partitions(&mut self) -> Result<Vec<Partition>, Error> {
+		(0..PARTITIONS).map(|_| Partition::create(self.next_path())).collect()
+	}
+
+	fn next_path(&mut self) -> PathBuf {
+		self.files += 1;
+		self.dir.join(format!("{}.csv", self.files))
+	}
+}
+
+impl Partition {
+	fn create(path: PathBuf) -> Result<Self, Error> {
+		let file = BufWriter::new(File::create(&path).map_err(|error| io_error(&path, error))?);
+		Ok(Self { path, file, size: 0 })
+	}
+
+	/// Append a record with the given cells
+	fn write<'a>(&mut self, cells: impl Iterator<Item = &'a str>, buffer: &mut String) -> Result<(), Error> {
+		let dialect = Dialect::default();
+		buffer.clear();
+		for (index, cell) in cells.enumerate() {
+			if index > 0 {
+				buffer.push(dialect.delimiter);
+			}
+			quote_csv_cell(cell, buffer, &dialect);
+		}
+		buffer.push_str(&dialect.line_terminator);
+
+		self.size += buffer.len() as u64;
+		self.file.write_all(buffer.as_bytes()).map_err(|error| io_error(&self.path, error))
+	}
+
+	/// Flush and close the file, returns its path and size
+	fn finish(self) -> Result<(PathBuf, u64), Error> {
+		let Self { path, file, size } = self;
+		file.into_inner().map_err(|error| io_error(&path, error.into_error()))?;
+		Ok((path, size))
+	}
+}
+
+/// The partition of a key, each depth of splitting uses another hash so the keys of a partition are spread again
+fn partition_index(key: &str, depth: usize) -> usize {
+	let mut hasher = DefaultHasher::new();
+	depth.hash(&mut hasher);
+	key.hash(&mut hasher);
+	(hasher.finish() % PARTITIONS as u64) as usize
+}
+
+/// Merge files of kept lines, each in the order of the output, into one order, only one line per file is in memory
+fn merge_kept(paths: &[PathBuf], mut emit: impl FnMut(u64, Vec<String>) -> Result<(), Error>) -> Result<(), Error> {
+	let mut readers = Vec::with_capacity(paths.len());
+	for path in paths {
+		readers.push(CsvParser::new(BufReader::new(File::open(path).map_err(|error| io_error(path, error))?)));
+	}
+	let mut record = Record::default();
+	let mut heads: Vec<Vec<String>> = vec![Vec::new(); readers.len()];
+	let mut queue = BinaryHeap::new();
+	let mut advance = |index: usize, heads: &mut Vec<Vec<String>>, queue: &mut BinaryHeap<Reverse<(u64, usize)>>| {
+		if readers[index].read_record(&mut record)? {
+			queue.push(Reverse((parse_position(record.get(0)), index)));
+			heads[index] = record.iter().skip(1).map(String::from).collect();
+		}
+		Ok::<(), Error>(())
+	};
+	for index in 0..paths.len() {
+		advance(index, &mut heads, &mut queue)?;
+	}
+	while let Some(Reverse((position, index))) = queue.pop() {
+		emit(position, std::mem::take(&mut heads[index]))?;
+		advance(index, &mut heads, &mut queue)?;
+	}
+	drop(readers);
+
+	for path in paths {
+		fs::remove_file(path).map_err(|error| io_error(path, error))?;
+	}
+	Ok(())
+}
+
+/// A directory for temporary files which is removed again when it's dropped
+#[derive(Debug)]
+pub(crate) struct TempDir {
+	path: PathBuf,
+}
+
+impl TempDir {
+	/// Create a new directory in the temp dir of the system whose name includes the given purpose
+	pub(crate) fn new(purpose: &str) -> Result<Self, Error> {
+		static DIRS: AtomicUsize = AtomicUsize::new(0);
+		let path = std::env::temp_dir().join(format!(
+			"csv_converter_{purpose}_{}_{}",
+			std::process::id(),
+			DIRS.fetch_add(1, Ordering::Relaxed)
+		));
+		fs::create_dir_all(&path).map_err(|error| io_error(&path, error))?;
+		Ok(Self { path })
+	}
+
+	pub(crate) fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+		self.path.join(name)
+	}
+}
+
+impl Drop for TempDir {
+	fn drop(&mut self) {
+		let _ = fs::remove_dir_all(&self.path);
+	}
+}
+
+fn read_records(path: &Path, mut f: impl FnMut(&Record) -> Result<(), Error>) -> Result<(), Error> {
+	let file = File::open(path).map_err(|error| io_error(path, error))?;
+	let mut csv_file = CsvParser::new(BufReader::new(file));
+	let mut record = Record::default();
+	while csv_file.read_record(&mut record)? {
+		f(&record)?;
+	}
+	Ok(())
+}
+
+fn parse_position(position: Option<&str>) -> u64 {
+	position.and_then(|position| position.parse().ok()).unwrap_or_default()
+}
+
+pub(crate) fn io_error(path: &Path, error: std::io::Error) -> Error {
+	Error::new(ErrorStages::Io, format!("Could not use the temporary file \"{}\": \"{error}\"", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::csv::rows;
+
+	fn dedupe(keep: Keep, memory_budget: usize, lines: &[&[&str]]) -> (Vec<Vec<String>>, usize) {
+		dedupe_lines(keep, memory_budget, rows(lines))
+	}
+
+	fn dedupe_lines(keep: Keep, memory_budget: usize, lines: Vec<Vec<String>>) -> (Vec<Vec<String>>, usize) {
+		let mut deduplicator = Deduplicator::new(keep, memory_budget);
+		let mut output = Vec::new();
+		for line in lines {
+			output.extend(deduplicator.push(line[0].clone(), line).unwrap());
+		}
+		let dropped = deduplicator
+			.finish(|line| {
+				output.push(line);
+				Ok(())
+			})
+			.unwrap();
+		(output, dropped)
+	}
+
+	#[test]
+	fn dedupe_test() {
+		let lines: &[&[&str]] = &[
+			&["shirt", "red"],
+			&["mug", "white"],
+			&["shirt", "blue"],
+			&["cap", ""],
+			&["mug", "black, \"matte\""],
+			&["shirt", "green\nlight"],
+		];
+
+		let first = rows(&[&["shirt", "red"], &["mug", "white"], &["cap", ""]]);
+		let last = rows(&[&["cap", ""], &["mug", "black, \"matte\""], &["shirt", "green\nlight"]]);
+
+		assert_eq!(dedupe(Keep::First, 1024 * 1024, lines), (first.clone(), 3));
+		assert_eq!(dedupe(Keep::Last, 1024 * 1024, lines), (last.clone(), 3));
+
+		// without any memory every line goes through the temporary files and the output stays the same
+		assert_eq!(dedupe(Keep::First, 0, lines), (first.clone(), 3));
+		assert_eq!(dedupe(Keep::Last, 0, lines), (last.clone(), 3));
+
+		// spilling in the middle moves the keys already seen and the lines held back to disk
+		assert_eq!(dedupe(Keep::First, 150, lines), (first, 3));
+		assert_eq!(dedupe(Keep::Last, 250, lines), (last, 3));
+
+		assert_eq!(dedupe(Keep::Last, 0, &[]), (Vec::new(), 0));
+	}
+
+	/// Lines whose keys repeat every `keys` lines
+	fn many_lines(count: usize, keys: usize) -> Vec<Vec<String>> {
+		(0..count).map(|index| vec![format!("key {}", index % keys), index.to_string()]).collect()
+	}
+
+	#[test]
+	fn dedupe_spill_test() {
+		let lines = many_lines(20_000, 3_000);
+		let (first, last) = (lines[..3_000].to_vec(), lines[17_000..].to_vec());
+
+		// a budget this small splits every partition again, the lines held back before spilling span all of them
+		for memory_budget in [4 * 1024, 64 * 1024, 1024 * 1024] {
+			assert_eq!(dedupe_lines(Keep::First, memory_budget, lines.clone()), (first.clone(), 17_000), "{memory_budget}");
+			assert_eq!(dedupe_lines(Keep::Last, memory_budget, lines.clone()), (last.clone(), 17_000), "{memory_budget}");
+		}
+
+		// the lines of a single key can't be split any further
+		let lines = many_lines(5_000, 1);
+		assert_eq!(dedupe_lines(Keep::First, 0, lines.clone()), (lines[..1].to_vec(), 4_999));
+		assert_eq!(dedupe_lines(Keep::Last, 0, lines.clone()), (lines[4_999..].to_vec(), 4_999));
+	}
+
+	#[test]
+	fn dedupe_cleanup_test() {
+		for fail in [false, true] {
+			let mut deduplicator = Deduplicator::new(Keep::Last, 1024);
+			for index in 0..1_000 {
+				deduplicator.push(format!("key {}", index % 100), vec![index.to_string()]).unwrap();
+			}
+			let dir = deduplicator.spill.as_ref().map(|spill| spill.dir.path.clone()).expect("The lines should be spilled");
+			assert!(dir.exists());
+
+			let result = deduplicator.finish(|_| match fail {
+				true => Err(Error::new(ErrorStages::Io, String::from("The output is gone"))),
+				false => Ok(()),
+			});
+			assert_eq!(result.is_ok(), !fail);
+			// the temporary files are removed once done and when emitting fails
+			assert!(!dir.exists(), "{} wasn't removed", dir.display());
+		}
+	}
+
+	#[test]
+	fn dedupe_key_test() {
+		let heading = vec![String::from("Handle"), String::from("Color")];
+		let item = parse_dedupe_key("<col 'Handle' LOWER_CASE>", &heading).unwrap();
+		assert_eq!(dedupe_key(&item, &[String::from("SHIRT"), String::from("red")]), Ok(String::from("shirt")));
+
+		assert_eq!(
+			parse_dedupe_key("<col 'Size'>", &heading).map_err(|error| error.stage),
+			Err(ErrorStages::ConfigParsing)
+		);
+	}
+}
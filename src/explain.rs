@@ -0,0 +1,441 @@
+//! This module traces how the [OutputConfig] is applied to a single input row to help debugging configs
+//!
+//! The trace shows every line and column of the config with the value of each item, what every filter made of it,
+//! the operands and outcome of every condition and why lines were skipped.
+//! Errors are part of the trace so everything up to the failing item is still shown.
+//! Whether a row is kept and what each line emits comes from the interpreter itself so the trace can't tell a different
+//! story than the conversion.
+use std::{
+	borrow::Cow,
+	fmt::{self, Write},
+};
+
+use crate::{
+	aggregate::Aggregator,
+	cli::Error,
+	config::{Condition, Item, Line, OutputConfig, Row},
+	csv::{export, Dialect},
+	process::{self, Recorder},
+};
+
+/// Trace how the config is applied to a single input row that follows the previous input row, if there is one
+///
+/// ```rust
+/// use csv_converter::{config::OutputConfig, csv::CsvParser, explain::explain};
+/// use std::io::Cursor;
+///
+/// let output_config = OutputConfig::new(CsvParser::new(Cursor::new("H1\n<cell1 UPPER_CASE>\n"))).unwrap();
+/// let trace = explain(&[String::from("shirt")], None, &output_config);
+/// assert!(trace.contains("UPPER_CASE -> \"SHIRT\""));
+/// ```
+pub fn explain(input_line: &[String], previous: Option<&[String]>, output_config: &OutputConfig) -> String {
+	let mut trace = Trace::default();
+
+	trace.line(0, "Input");
+	for (index, cell) in input_line.iter().enumerate() {
+		trace.line(1, &format!("<cell{}> = {cell:?}", index + 1));
+	}
+	if let Some(previous) = previous {
+		trace.line(0, "Previous input");
+		for (index, cell) in previous.iter().enumerate() {
+			trace.line(1, &format!("<prev{}> = {cell:?}", index + 1));
+		}
+	}
+
+	if let Some(filter) = &output_config.filter {
+		trace.line(0, &format!("Config row {}: :FILTER {}", filter.row, filter.condition));
+		let _ = trace.condition(&filter.condition, &Row::after(input_line, previous), 1);
+		match output_config.keep(input_line, previous) {
+			Ok(true) => trace.line(1, "=> the row is kept"),
+			Ok(false) => {
+				trace.line(1, "=> the row is rejected so no line is emitted");
+				return trace.output;
+			},
+			Err(error) => {
+				trace.error(1, &error);
+				return trace.output;
+			},
+		}
+	}
+
+	if let Some(group) = &output_config.group {
+		trace.line(0, &format!("Config row {}: :GROUP_BY", group.row));
+		let mut key = Vec::new();
+		for item in &group.keys {
+			match trace.item(item, &Row::after(input_line, previous), 1) {
+				Ok(value) => key.push(value),
+				Err(error) => {
+					trace.error(1, &error.at(group.row, 1));
+					return trace.output;
+				},
+			}
+		}
+		trace.line(1, &format!("=> the row belongs to the group {key:?} which is emitted once all of its rows are in"));
+		// a group of only this row has the values this row adds to its group
+		trace.group = Some(Aggregator::new(output_config).and_then(|mut aggregator| {
+			aggregator.push(input_line, previous)?;
+			Ok(aggregator.finish())
+		}));
+	}
+
+	for (line_index, line) in output_config.lines.iter().enumerate() {
+		trace.line(0, &format!("Config row {}", line.row));
+		match &line.each {
+			Some(each) => {
+				trace.line(1, &format!(":EACH from config row {}", each.row));
+				let elements = match process::get_elements(each, input_line, previous) {
+					Ok(elements) => elements,
+					Err(error) => {
+						trace.error(2, &error);
+						continue;
+					},
+				};
+				if elements.is_empty() {
+					trace.line(2, "=> no elements so no line is emitted");
+				}
+				for (index, element) in elements.iter().enumerate() {
+					trace.line(1, &format!("<each> = {element:?}, <index> = {}", index + 1));
+					let row = Row {
+						cells: input_line,
+						each: Some((element, index + 1)),
+						previous,
+					};
+					trace.config_line(line, line_index, &row, 2);
+				}
+			},
+			None => trace.config_line(line, line_index, &Row::after(input_line, previous), 1),
+		}
+	}
+
+	trace.output
+}
+
+/// The text of the trace with its indentation
+#[derive(Default)]
+struct Trace {
+	output: String,
+	/// The output of a group of only this row when the lines are aggregated by a `:GROUP_BY` directive
+	group: Option<Result<Vec<Vec<String>>, Error>>,
+	/// How deep the step the interpreter notes next is nested
+	depth: usize,
+}
+
+impl Trace {
+	fn line(&mut self, depth: usize, text: &str) {
+		let _ = writeln!(self.output, "{}{text}", "  ".repeat(depth));
+	}
+
+	fn error(&mut self, depth: usize, error: &Error) {
+		self.line(depth, &format!("=> error: {error}"));
+	}
+
+	/// Trace a single config line for a row, returns nothing since the outcome is part of the trace
+	fn config_line(&mut self, line: &Line, line_index: usize, row: &Row, depth: usize) {
+		let mut skipped_by_guard = false;
+		let mut skipped_by = None;
+		if let Some(guard) = &line.guard {
+			self.line(depth, &format!(":WHERE {} from config row {}", guard.condition, guard.row));
+			skipped_by_guard = self.condition(&guard.condition, row, depth + 1) != Ok(true);
+		}
+		if !skipped_by_guard {
+			for (column_index, item) in line.items.iter().enumerate() {
+				self.line(depth, &format!("Column {}: {item}", column_index + 1));
+				match self.item(item, row, depth + 1) {
+					Ok(value) if value == "SKIP_THIS_LINE" && item.uses_skip_this_line() => {
+						skipped_by = skipped_by.or(Some(column_index + 1));
+					},
+					Ok(_) => {},
+					Err(_) => break,
+				}
+			}
+		}
+
+		let outcome = match &self.group {
+			Some(Ok(lines)) => Ok(lines.get(line_index).cloned()),
+			// the error of a group belongs to the line that caused it
+			Some(Err(error)) if error.row.map_or(line_index == 0, |row| row == line.row) => Err(error.clone()),
+			Some(Err(_)) => return,
+			None => process::run_line(line, row),
+		};
+		match outcome {
+			Ok(Some(new_line)) => {
+				let mut output = String::new();
+				export(&[new_line], &mut output);
+				let output = output.trim_end_matches(&Dialect::default().line_terminator);
+				if self.group.is_some() {
+					self.line(depth, &format!("=> the values of this row: {output}"));
+				} else {
+					self.line(depth, &format!("=> emitted: {output}"));
+				}
+			},
+			Ok(None) if skipped_by_guard => self.line(depth, "=> skipped by :WHERE"),
+			Ok(None) => match skipped_by {
+				Some(column) => self.line(depth, &format!("=> skipped by SKIP_THIS_LINE in column {column}")),
+				None => self.line(depth, "=> skipped by SKIP_THIS_LINE"),
+			},
+			Err(error) => self.error(depth + 1, &error),
+		}
+	}
+
+	/// Trace how the value of an item comes together
+	fn item(&mut self, item: &Item, row: &Row, depth: usize) -> Result<String, Error> {
+		match item {
+			Item::Aggregate(aggregate, None) => {
+				self.line(depth, &format!(":{aggregate} counts this row towards its group"));
+				Ok(String::from("1"))
+			},
+			Item::Aggregate(aggregate, Some(operand)) => {
+				let value = self.item(operand, row, depth + 1)?;
+				self.line(depth, &format!(":{aggregate} combines this value with the other rows of its group"));
+				Ok(value)
+			},
+			_ => {
+				self.depth = depth;
+				process::get_value_with(item, row, self).map(Cow::into_owned)
+			},
+		}
+	}
+
+	/// Trace the operands and outcome of a condition, compound conditions trace each of their parts
+	fn condition(&mut self, condition: &Condition, row: &Row, depth: usize) -> Result<bool, Error> {
+		self.depth = depth;
+		condition.is_true_with(row, self)
+	}
+}
+
+/// The interpreter notes every step at the depth of the part it is in
+impl Recorder for Trace {
+	fn enter(&mut self) {
+		self.depth += 1;
+	}
+
+	fn leave(&mut self) {
+		self.depth = self.depth.saturating_sub(1);
+	}
+
+	fn note(&mut self, step: fmt::Arguments) {
+		let _ = writeln!(self.output, "{}{step}", "  ".repeat(self.depth));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::csv::CsvParser;
+	use std::io::Cursor;
+
+	#[test]
+	fn explain_test() {
+		let output_config = OutputConfig::new(CsvParser::new(Cursor::new(
+			"H1,H2,H3\n\
+			:FILTER <cell1> IS_NOT_EMPTY\n\
+			<cell1 TRIM UPPER_CASE>,:IF <cell2> > 10 OR <cell3> IS_EMPTY ('big') ELSE IF <cell2> IS_NUMERIC ('small') ELSE ('none'),fixed\n\
+			:EACH <cell3> BY '|'\n\
+			:WHERE <each> != 'b'\n\
+			<each>,<index>,:IF <each> == 'c' ('SKIP_THIS_LINE')\n\
+			<cell9>,,\n",
+		)))
+		.unwrap();
+
+		let trace = explain(&[String::from(" shirt "), String::from("5"), String::from("a|b|c")], None, &output_config);
+		assert_eq!(
+			trace,
+			[
+				"Input",
+				"  <cell1> = \" shirt \"",
+				"  <cell2> = \"5\"",
+				"  <cell3> = \"a|b|c\"",
+				"Config row 2: :FILTER <cell1> IS_NOT_EMPTY",
+				"    <cell1> = \" shirt \"",
+				"  <cell1> IS_NOT_EMPTY -> true",
+				"  => the row is kept",
+				"Config row 3",
+				"  Column 1: <cell1 TRIM UPPER_CASE>",
+				"    <cell1> = \" shirt \"",
+				"    TRIM -> \"shirt\"",
+				"    UPPER_CASE -> \"SHIRT\"",
+				"  Column 2: :IF (<cell2> > '10' OR <cell3> IS_EMPTY) ('big') ELSE (:IF <cell2> IS_NUMERIC ('small') ELSE ('none'))",
+				"    IF (<cell2> > '10' OR <cell3> IS_EMPTY)",
+				"          <cell2> = \"5\"",
+				"          value \"10\"",
+				"        <cell2> > '10' -> false",
+				"          <cell3> = \"a|b|c\"",
+				"        <cell3> IS_EMPTY -> false",
+				"      (<cell2> > '10' OR <cell3> IS_EMPTY) -> false",
+				"    ELSE :IF <cell2> IS_NUMERIC ('small') ELSE ('none')",
+				"      IF <cell2> IS_NUMERIC",
+				"          <cell2> = \"5\"",
+				"        <cell2> IS_NUMERIC -> true",
+				"      THEN 'small'",
+				"        value \"small\"",
+				"  Column 3: 'fixed'",
+				"    value \"fixed\"",
+				"  => emitted: SHIRT,small,fixed",
+				"Config row 6",
+				"  :EACH from config row 4",
+				"  <each> = \"a\", <index> = 1",
+				"    :WHERE <each> != 'b' from config row 5",
+				"        <each> = \"a\"",
+				"        value \"b\"",
+				"      <each> != 'b' -> true",
+				"    Column 1: <each>",
+				"      <each> = \"a\"",
+				"    Column 2: <index>",
+				"      <index> = \"1\"",
+				"    Column 3: :IF <each> == 'c' ('SKIP_THIS_LINE')",
+				"      IF <each> == 'c'",
+				"          <each> = \"a\"",
+				"          value \"c\"",
+				"        <each> == 'c' -> false",
+				"      no ELSE so the value is empty",
+				"    => emitted: a,1,",
+				"  <each> = \"b\", <index> = 2",
+				"    :WHERE <each> != 'b' from config row 5",
+				"        <each> = \"b\"",
+				"        value \"b\"",
+				"      <each> != 'b' -> false",
+				"    => skipped by :WHERE",
+				"  <each> = \"c\", <index> = 3",
+				"    :WHERE <each> != 'b' from config row 5",
+				"        <each> = \"c\"",
+				"        value \"b\"",
+				"      <each> != 'b' -> true",
+				"    Column 1: <each>",
+				"      <each> = \"c\"",
+				"    Column 2: <index>",
+				"      <index> = \"3\"",
+				"    Column 3: :IF <each> == 'c' ('SKIP_THIS_LINE')",
+				"      IF <each> == 'c'",
+				"          <each> = \"c\"",
+				"          value \"c\"",
+				"        <each> == 'c' -> true",
+				"      THEN 'SKIP_THIS_LINE'",
+				"        value \"SKIP_THIS_LINE\"",
+				"    => skipped by SKIP_THIS_LINE in column 3",
+				"Config row 7",
+				"  Column 1: <cell9>",
+				"    => error: Cell not found \"<cell9>\" (config row 7, column 1)",
+				"",
+			]
+			.join("\n")
+		);
+
+		let trace = explain(&[String::new(), String::from("5"), String::new()], None, &output_config);
+		assert!(trace.ends_with("  <cell1> IS_NOT_EMPTY -> false\n  => the row is rejected so no line is emitted\n"));
+
+		let output_config =
+			OutputConfig::new(CsvParser::new(Cursor::new("H1\n\":CALC ROUND(<cell1> * 1.1, 2) + <cell2>\"\n"))).unwrap();
+		let trace = explain(&[String::from("19.99"), String::from("1")], None, &output_config);
+		assert!(trace.ends_with(
+			"  Column 1: :CALC ROUND(<cell1> * 1.1, 2) + <cell2>\n    <cell1> = \"19.99\"\n    <cell2> = \"1\"\n    :CALC -> \"22.99\"\n  => emitted: 22.99\n"
+		));
+
+		let output_config =
+			OutputConfig::new(CsvParser::new(Cursor::new("H1,H2,H3\n:GROUP_BY <cell1 TRIM>\n<cell1>,:SUM <cell2>,:COUNT\n")))
+				.unwrap();
+		let trace = explain(&[String::from(" mug "), String::from("5")], None, &output_config);
+		assert_eq!(
+			trace,
+			[
+				"Input",
+				"  <cell1> = \" mug \"",
+				"  <cell2> = \"5\"",
+				"Config row 2: :GROUP_BY",
+				"  <cell1> = \" mug \"",
+				"  TRIM -> \"mug\"",
+				"  => the row belongs to the group [\"mug\"] which is emitted once all of its rows are in",
+				"Config row 3",
+				"  Column 1: <cell1>",
+				"    <cell1> = \" mug \"",
+				"  Column 2: :SUM <cell2>",
+				"      <cell2> = \"5\"",
+				"    :SUM combines this value with the other rows of its group",
+				"  Column 3: :COUNT",
+				"    :COUNT counts this row towards its group",
+				"  => the values of this row:  mug ,5,1",
+				"",
+			]
+			.join("\n")
+		);
+	}
+
+	#[test]
+	fn explain_previous_and_errors_test() {
+		let output_config = OutputConfig::new(CsvParser::new(Cursor::new(
+			"H1,H2\n<cell1>,:IF <cell1> IS_FIRST_IN_GROUP ('NEW') ELSE ('MERGE')\n<prev2>,<cell2 ROUND|1>\n",
+		)))
+		.unwrap();
+		let row = [String::from("shirt"), String::from("1.25")];
+		let previous = [String::from("shirt"), String::from("many")];
+
+		let trace = explain(&row, Some(&previous), &output_config);
+		assert!(trace.contains("Previous input\n  <prev1> = \"shirt\"\n  <prev2> = \"many\"\n"), "{trace}");
+		assert!(trace.contains("  => emitted: shirt,MERGE\n"), "{trace}");
+		assert!(trace.contains("    <prev2> = \"many\"\n"), "{trace}");
+		assert!(trace.ends_with("  => emitted: many,1.3\n"), "{trace}");
+
+		// the first row has no previous row so <prev2> is empty
+		let trace = explain(&row, None, &output_config);
+		assert!(trace.contains("  => emitted: shirt,NEW\n"), "{trace}");
+		assert!(trace.ends_with("  => emitted: ,1.3\n"), "{trace}");
+
+		let trace = explain(&previous, None, &output_config);
+		assert!(
+			trace.ends_with(
+				"    <cell2> = \"many\"\n    => error: The ROUND|1 filter needs a number, was \"many\", use PARSE_NUMBER first for numbers like \"1.234,50 €\" (config row 3, column 2)\n"
+			),
+			"{trace}"
+		);
+
+		let output_config = OutputConfig::new(CsvParser::new(Cursor::new(
+			"H1,H2\n:GROUP_BY <cell1>\n<cell1>,:COUNT\n<cell1>,:SUM <cell2>\n",
+		)))
+		.unwrap();
+		let trace = explain(&previous, None, &output_config);
+		// the row fails as a whole so the line before has no values either
+		assert!(trace.contains("    :COUNT counts this row towards its group\nConfig row 4\n"), "{trace}");
+		assert!(
+			trace
+				.ends_with("    => error: The value of :SUM <cell2> is not a number, was \"many\" (config row 4, column 2)\n"),
+			"{trace}"
+		);
+		// numbers are written the way the group writes them
+		let trace = explain(&[String::from("shirt"), String::from("2.50")], None, &output_config);
+		assert!(trace.ends_with("  => the values of this row: shirt,2.5\n"), "{trace}");
+	}
+
+	#[test]
+	fn explain_matches_conversion_test() {
+		let output_config = OutputConfig::new(CsvParser::new(Cursor::new(
+			"H1,H2,H3\n\
+			:FILTER <cell1> IS_NOT_EMPTY\n\
+			<cell1 TRIM>,\":IF <cell2> > 10 AND NOT <prev2> == <cell2> ('big') ELSE ('small')\",\":CALC <cell2> * 2\"\n\
+			:EACH <cell3> BY '|'\n\
+			:WHERE <each> != 'b'\n\
+			<each UPPER_CASE>,<index>,:IF <each> == 'c' ('SKIP_THIS_LINE') ELSE (<prev1>)\n",
+		)))
+		.unwrap();
+		let rows = [
+			vec![String::from(" shirt "), String::from("12"), String::from("a|b|c")],
+			vec![String::from("mug"), String::from("12"), String::from("d| |a")],
+			vec![String::new(), String::from("3"), String::from("a")],
+			vec![String::from("cap"), String::from("3"), String::new()],
+		];
+
+		let mut emitted_lines = 0;
+		for (index, row) in rows.iter().enumerate() {
+			let previous = index.checked_sub(1).map(|previous| rows[previous].as_slice());
+			let trace = explain(row, previous, &output_config);
+			let emitted: Vec<&str> =
+				trace.lines().filter_map(|line| line.trim_start().strip_prefix("=> emitted: ")).collect();
+
+			let mut expected = String::new();
+			if output_config.keep(row, previous).unwrap() {
+				export(&process::run_after(row, previous, &output_config).unwrap(), &mut expected);
+			}
+			assert_eq!(emitted, expected.lines().collect::<Vec<_>>(), "{trace}");
+			emitted_lines += emitted.len();
+		}
+		assert_eq!(emitted_lines, 6);
+	}
+}
@@ -0,0 +1,184 @@
+//! This module scaffolds a starter config from the headings of an input file
+//!
+//! Without a template the config maps every input column to itself.
+//! With a template, like the heading row of a Shopify product import, every template heading is matched against the
+//! input headings by name and the ones we can't match are left empty to be filled in by hand.
+
+/// A starter config together with everything that could not be matched
+#[derive(Debug, PartialEq)]
+pub struct Scaffold {
+	/// The rows of the config, the heading and a single line
+	pub rows: Vec<Vec<String>>,
+	/// The template headings no input column was matched to, their cells are left empty
+	pub unmatched: Vec<String>,
+	/// The input headings that are not used by the config
+	pub unused: Vec<String>,
+}
+
+/// Build a starter config for an input file from its headings, optionally against the headings of a template
+///
+/// ```rust
+/// use csv_converter::init::scaffold;
+///
+/// let input = [String::from("Product Name"), String::from("SKU"), String::from("Colour")];
+/// let template = [String::from("Title"), String::from("Variant SKU"), String::from("Name")];
+/// let scaffold = scaffold(&input, Some(&template));
+/// assert_eq!(scaffold.rows[1], vec![String::from(""), String::from("<cell2>"), String::from("<cell1>")]);
+/// assert_eq!(scaffold.unmatched, vec![String::from("Title")]);
+/// assert_eq!(scaffold.unused, vec![String::from("Colour")]);
+/// ```
+pub fn scaffold(input_headings: &[String], template: Option<&[String]>) -> Scaffold {
+	let Some(template) = template else {
+		return Scaffold {
+			rows: vec![
+				input_headings.to_vec(),
+				(1..=input_headings.len()).map(|cell| format!("<cell{cell}>")).collect(),
+			],
+			unmatched: Vec::new(),
+			unused: Vec::new(),
+		};
+	};
+
+	let input_words = input_headings.iter().map(|heading| words(heading)).collect::<Vec<_>>();
+	let template_words = template.iter().map(|heading| words(heading)).collect::<Vec<_>>();
+
+	// every pair that looks alike, best matches first and in template order for equally good ones
+	let mut pairs = Vec::new();
+	for (template_index, template_heading) in template_words.iter().enumerate() {
+		for (input_index, input_heading) in input_words.iter().enumerate() {
+			let score = similarity(template_heading, input_heading);
+			if score > 0.0 {
+				pairs.push((score, template_index, input_index));
+			}
+		}
+	}
+	pairs.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+
+	let mut matches = vec![None; template.len()];
+	let mut used = vec![false; input_headings.len()];
+	for (_, template_index, input_index) in pairs {
+		if matches[template_index].is_none() && !used[input_index] {
+			matches[template_index] = Some(input_index);
+			used[input_index] = true;
+		}
+	}
+
+	Scaffold {
+		rows: vec![
+			template.to_vec(),
+			matches
+				.iter()
+				.map(|input_index| input_index.map(|index| format!("<cell{}>", index + 1)).unwrap_or_default())
+				.collect(),
+		],
+		unmatched: template
+			.iter()
+			.zip(&matches)
+			.filter(|(_, input_index)| input_index.is_none())
+			.map(|(heading, _)| heading.clone())
+			.collect(),
+		unused: input_headings.iter().zip(&used).filter(|(_, used)| !**used).map(|(heading, _)| heading.clone()).collect(),
+	}
+}
+
+/// Split a heading into lower case words so "Variant SKU", "variant_sku" and "VariantSku" all look the same
+fn words(heading: &str) -> Vec<String> {
+	let mut words = Vec::new();
+	let mut word = String::new();
+	let mut previous_lower = false;
+
+	for c in heading.trim().chars() {
+		if (!c.is_alphanumeric() || (c.is_uppercase() && previous_lower)) && !word.is_empty() {
+			words.push(std::mem::take(&mut word));
+		}
+		if c.is_alphanumeric() {
+			word.extend(c.to_lowercase());
+		}
+		previous_lower = c.is_lowercase();
+	}
+	if !word.is_empty() {
+		words.push(word);
+	}
+
+	words
+}
+
+/// How alike two headings are from 0 for nothing in common to 1 for the same words
+fn similarity(a: &[String], b: &[String]) -> f64 {
+	if a.is_empty() || b.is_empty() {
+		return 0.0;
+	}
+	if a == b {
+		return 1.0;
+	}
+
+	let shared = a.iter().filter(|word| b.contains(word)).count() as f64;
+	let all = (a.len() + b.len()) as f64 - shared;
+	// all words of one heading within the other, like "SKU" and "Variant SKU", beats only sharing some of them
+	let (shorter, longer) = if a.len() < b.len() { (a, b) } else { (b, a) };
+	if shorter.iter().all(|word| longer.contains(word)) {
+		0.5 + 0.4 * shared / all
+	} else if shared / all >= 0.5 {
+		0.4 * shared / all
+	} else {
+		0.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn strings(items: &[&str]) -> Vec<String> {
+		items.iter().map(|item| item.to_string()).collect()
+	}
+
+	#[test]
+	fn words_test() {
+		assert_eq!(words("Variant SKU"), strings(&["variant", "sku"]));
+		assert_eq!(words(" variant_sku "), strings(&["variant", "sku"]));
+		assert_eq!(words("VariantSku"), strings(&["variant", "sku"]));
+		assert_eq!(words("Image Src (URL)"), strings(&["image", "src", "url"]));
+		assert_eq!(words("Größe"), strings(&["größe"]));
+		assert_eq!(words("--"), Vec::<String>::new());
+	}
+
+	#[test]
+	fn scaffold_test() {
+		assert_eq!(
+			scaffold(&strings(&["SKU", "Name", "Price"]), None),
+			Scaffold {
+				rows: vec![
+					strings(&["SKU", "Name", "Price"]),
+					strings(&["<cell1>", "<cell2>", "<cell3>"])
+				],
+				unmatched: Vec::new(),
+				unused: Vec::new(),
+			}
+		);
+
+		let input = strings(&[
+			"product_title",
+			"SKU",
+			"Price",
+			"Compare Price",
+			"Image URL",
+			"Image Alt",
+			"Colour",
+		]);
+		let template = strings(&[
+			"Handle",
+			"Title",
+			"Variant SKU",
+			"Variant Price",
+			"Variant Compare At Price",
+			"Image Src",
+			"Image Alt Text",
+		]);
+		let scaffold = scaffold(&input, Some(&template));
+		assert_eq!(scaffold.rows[0], template);
+		assert_eq!(scaffold.rows[1], strings(&["", "<cell1>", "<cell2>", "<cell3>", "<cell4>", "", "<cell6>"]));
+		assert_eq!(scaffold.unmatched, strings(&["Handle", "Image Src"]));
+		assert_eq!(scaffold.unused, strings(&["Image URL", "Colour"]));
+	}
+}
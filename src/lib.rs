@@ -21,29 +21,51 @@
 //! let file = BufReader::new(File::open("tests/config.csv").unwrap());
 //! let reader = BufReader::new(file);
 //! let config_file = CsvParser::new(reader);
-//! let output_config = OutputConfig::new(config_file);
+//! let mut output_config = OutputConfig::new(config_file).unwrap();
 //!
 //! let mut is_heading = true;
+//! let mut context = process::Context::new();
 //! let mut output = String::new();
 //! let reader = BufReader::new(File::open("tests/input.csv").unwrap());
 //! let mut csv_file = CsvParser::new(reader);
 //! while let Some(row) = csv_file.next() {
 //!     if is_heading {
 //!         is_heading = false;
-//!         output = format!("{}\n", output_config.heading);
+//!         output_config.resolve_headers(&row).unwrap();
+//!         export(&[output_config.headings()], &mut output);
 //!     } else {
-//!         export(&process::run(&row, &output_config), &mut output);
+//!         export(&process::run(&row, &output_config, &mut context).unwrap(), &mut output);
 //!     };
 //!     // output is a String with the new content in the format and can now be written to the output file
 //! }
 //! ```
+//!
+//! None of the library functions exit the process.
+//! Parsing and processing return a [Error] instead which includes the [ErrorStages] and, where possible, the config
+//! row and column together with the offending cell text.
 
+pub mod aggregate;
 pub mod cli;
 pub mod config;
 pub mod csv;
+pub mod date;
+pub mod dedupe;
+pub mod explain;
+pub mod init;
+pub mod plan;
 pub mod process;
+pub mod regex;
+pub mod sort;
 
+pub use aggregate::*;
 pub use cli::*;
 pub use config::*;
 pub use csv::*;
+pub use date::*;
+pub use dedupe::*;
+pub use explain::*;
+pub use init::*;
+pub use plan::*;
 pub use process::*;
+pub use regex::*;
+pub use sort::*;
@@ -1,24 +1,37 @@
 use std::{
+	collections::BTreeMap,
 	env,
-	fs::File,
-	io::{BufReader, BufWriter, Write},
+	fs::{File, OpenOptions},
+	io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write},
+	mem,
+	path::Path,
+	sync::{mpsc, Arc, Mutex},
+	thread,
 	time::{Duration, Instant},
 };
 
-mod cli;
-mod config;
-mod csv;
-mod process;
-
-use crate::{
-	cli::{exit_with_error, help, CliColor::*, ErrorStages, Settings},
-	config::OutputConfig,
-	csv::CsvParser,
+use csv_converter::{
+	aggregate::Aggregator,
+	cli::{exit_with_error, help, CliColor::*, Command, Error, ErrorStages, Settings},
+	config::{Condition, Item, OutputConfig, Row},
+	csv::{self, CsvParser, Dialect, Record},
+	dedupe::{dedupe_key, parse_dedupe_key, Deduplicator, DEFAULT_MEMORY_BUDGET},
+	explain::explain,
+	init::scaffold,
+	plan::{Plan, Scratch},
+	process,
+	sort::{SortKey, Sorter},
 };
 
+/// The number of input rows each worker thread processes at a time
+const BATCH_SIZE: usize = 1024;
+
 fn main() {
 	let time = Instant::now();
-	let settings = Settings::new(env::args().skip(1).collect());
+	let settings = match Settings::new(env::args().skip(1).collect()) {
+		Ok(settings) => settings,
+		Err(error) => exit_with_error(Some(error.to_string()), Some(error.stage), 1),
+	};
 
 	if settings.version {
 		println!("v{}", env!("CARGO_PKG_VERSION"));
@@ -30,89 +43,697 @@ fn main() {
 		exit_with_error(None, None, 0);
 	}
 
-	let output_config = match File::open(&settings.output_config) {
-		Ok(file) => {
-			let reader = BufReader::new(file);
-			let config_file = CsvParser::new(reader);
-			OutputConfig::new(config_file)
-		},
+	match settings.command {
+		Command::Check => check(&settings),
+		Command::Init => init(&settings),
+		Command::Convert => {},
+	}
+
+	let (mut output_config, diagnostics) = match File::open(&settings.output_config) {
+		Ok(file) => OutputConfig::check_in(CsvParser::new(BufReader::new(file)), config_dir(&settings)),
 		Err(error) => {
 			exit_with_error(
-				Some(format!("Could not open output config \"{}\": \"{Red}{error}{Reset}\"", settings.output)),
+				Some(format!("Could not open output config \"{}\": \"{Red}{error}{Reset}\"", settings.output_config)),
 				Some(ErrorStages::Io),
 				1,
 			);
 		},
 	};
+	for warning in &diagnostics.warnings {
+		eprintln!(" {Yellow}WARNING{Reset} {} {warning}", warning.stage);
+	}
+	if let Some(error) = diagnostics.errors.into_iter().next() {
+		exit_with_error(Some(error.to_string()), Some(error.stage), 1);
+	}
+
+	let mut filter = match settings.filter.as_deref().map(Condition::parse_expression) {
+		Some(Ok(condition)) => Some(condition),
+		Some(Err(error)) => exit_with_error(Some(format!("Invalid --where: {error}")), Some(error.stage), 1),
+		None => None,
+	};
+	let dedupe_by = match settings.dedupe_by.as_deref().map(|key| parse_dedupe_key(key, &output_config.headings())) {
+		Some(Ok(item)) => Some(item),
+		Some(Err(error)) => exit_with_error(Some(format!("Invalid --dedupe-by: {error}")), Some(error.stage), 1),
+		None => None,
+	};
+	let sort_by = match settings.sort_by.as_deref().map(|keys| SortKey::parse_keys(keys, &output_config.headings())) {
+		Some(Ok(keys)) => Some(keys),
+		Some(Err(error)) => exit_with_error(Some(format!("Invalid --sort-by: {error}")), Some(error.stage), 1),
+		None => None,
+	};
 
-	let input_file = match File::open(&settings.input) {
-		Ok(file) => file,
+	let (input, total_size): (Box<dyn Read>, Option<u64>) = if settings.input == "-" {
+		(Box::new(io::stdin().lock()), None)
+	} else {
+		let input_file = match File::open(&settings.input) {
+			Ok(file) => file,
+			Err(error) => {
+				exit_with_error(
+					Some(format!("Could not open input file \"{}\": \"{Red}{error}{Reset}\"", settings.input)),
+					Some(ErrorStages::Io),
+					1,
+				);
+			},
+		};
+		match input_file.metadata() {
+			Ok(metadata) => (Box::new(input_file), Some(metadata.len())),
+			Err(error) => {
+				exit_with_error(
+					Some(format!("Could not get metadata for input file \"{}\": \"{Red}{error}{Reset}\"", settings.input)),
+					Some(ErrorStages::Io),
+					1,
+				);
+			},
+		}
+	};
+	let mut reader = BufReader::with_capacity(64 * 1024, input);
+	let input_dialect = match reader.fill_buf() {
+		Ok(sample) => settings.input_dialect(&String::from_utf8_lossy(sample)),
 		Err(error) => {
 			exit_with_error(
-				Some(format!("Could not open input file \"{}\": \"{Red}{error}{Reset}\"", settings.output)),
+				Some(format!("Could not read input file \"{}\": \"{Red}{error}{Reset}\"", settings.input)),
 				Some(ErrorStages::Io),
 				1,
 			);
 		},
 	};
-	let total_size = match input_file.metadata() {
-		Ok(metadata) => metadata.len(),
-		Err(error) => {
+	let output_dialect = settings.output_dialect();
+
+	if let Some(row_number) = settings.explain {
+		explain_row(row_number, &mut output_config, CsvParser::with_dialect(reader, input_dialect), filter);
+	}
+
+	let output_file: Box<dyn Write + Send> = if settings.output == "-" {
+		Box::new(io::stdout())
+	} else {
+		match File::create(&settings.output) {
+			Ok(file) => Box::new(file),
+			Err(error) => {
+				exit_with_error(
+					Some(format!("Could not create output file \"{}\": \"{Red}{error}{Reset}\"", settings.output)),
+					Some(ErrorStages::Io),
+					1,
+				);
+			},
+		}
+	};
+	let mut writer = BufWriter::with_capacity(256 * 1024, output_file);
+
+	let mut output = String::new();
+	let mut last_report_time = Instant::now();
+	let mut rows = 0;
+	// progress goes to stderr so stdout stays pure CSV and is only shown to humans
+	let show_progress = io::stderr().is_terminal();
+	let threads = settings.threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |threads| threads.get()));
+
+	let mut csv_file = CsvParser::with_dialect(reader, input_dialect);
+
+	if let Some(row) = read_row(&mut csv_file) {
+		resolve_headings(&mut output_config, filter.as_mut(), &row);
+		csv::export_with_dialect(&[output_config.headings()], &mut output, &output_dialect);
+		if let Err(error) = writer.write_all(output.as_bytes()) {
 			exit_with_error(
-				Some(format!("Could not get metadata for input file \"{}\": \"{Red}{error}{Reset}\"", settings.output)),
+				Some(format!("Failed to write to output file: \"{Red}{error}{Reset}\"")),
 				Some(ErrorStages::Io),
 				1,
 			);
-		},
+		}
+	}
+
+	let plan = match Plan::new(&output_config) {
+		Ok(plan) => plan,
+		Err(error) => exit_with_error(Some(error.to_string()), Some(error.stage), 1),
+	};
+	let megabytes = |memory: Option<usize>| memory.unwrap_or(DEFAULT_MEMORY_BUDGET).saturating_mul(1024 * 1024);
+	let output_writer = OutputWriter {
+		writer,
+		dialect: &output_dialect,
+		dedupe: dedupe_by
+			.as_ref()
+			.map(|item| (item, Deduplicator::new(settings.dedupe_keep, megabytes(settings.dedupe_memory)))),
+		sorter: sort_by.map(|keys| Sorter::new(keys, megabytes(settings.sort_memory))),
+		buffer: String::new(),
+	};
+	// duplicates can only be dropped and lines only be sorted by the writer so the workers hand it the lines
+	let keep_lines = output_writer.dedupe.is_some() || output_writer.sorter.is_some();
+
+	if show_progress {
+		eprintln!("Progress: 0%");
+	}
+	let mut report_progress = |rows: usize, bytes_read: u128| {
+		if show_progress && last_report_time.elapsed() >= Duration::from_secs(1) {
+			match total_size {
+				Some(total_size) => {
+					let progress = (bytes_read as f64 / total_size as f64) * 100.0;
+					eprintln!("\x1b[1A\x1b[0GProgress: {:.2}%\x1b[0K", progress);
+				},
+				// the size of stdin is unknown so we can only show how fast we are going
+				None => {
+					let rows_per_second = rows as f64 / time.elapsed().as_secs_f64();
+					eprintln!("\x1b[1A\x1b[0GProgress: {rows} rows ({rows_per_second:.0} rows/s)\x1b[0K");
+				},
+			}
+			last_report_time = Instant::now();
+		}
+	};
+
+	// Groups need every row in turn so they are aggregated on this thread.
+	// Otherwise the reader (this thread) applies the filters, since the previous row of a row is the last one that was
+	// kept, and sends numbered batches of the kept rows, together with the row before each batch, to a pool of workers
+	// and the writer puts their output back into the order of the input
+	let result = if output_config.group.is_some() {
+		Ok(aggregate_rows(&mut csv_file, &output_config, filter.as_ref(), output_writer, &mut rows, report_progress))
+	} else {
+		thread::scope(|scope| {
+			let (batch_sender, batch_receiver) =
+				mpsc::sync_channel::<(usize, Option<Vec<String>>, Vec<Vec<String>>)>(threads * 2);
+			// shared by the workers and dropped with the last one so the reader stops when they all stopped
+			let batch_receiver = Arc::new(Mutex::new(batch_receiver));
+			let (output_sender, output_receiver) = mpsc::sync_channel::<(usize, Result<Batch, Error>)>(threads * 2);
+			// the workers hand back the batches they are done with so the reader can reuse the allocations of their rows
+			let (recycle_sender, recycle_receiver) = mpsc::channel::<Vec<Vec<String>>>();
+			for _ in 0..threads {
+				let batch_receiver = Arc::clone(&batch_receiver);
+				let output_sender = output_sender.clone();
+				let recycle_sender = recycle_sender.clone();
+				let output_config = &output_config;
+				let plan = &plan;
+				let output_dialect = &output_dialect;
+				let dedupe_by = dedupe_by.as_ref();
+				scope.spawn(move || {
+					let mut scratch = Scratch::default();
+					loop {
+						let batch = match batch_receiver.lock() {
+							Ok(receiver) => receiver.recv(),
+							Err(_) => break,
+						};
+						let Ok((index, previous, batch)) = batch else {
+							break;
+						};
+						let output = if keep_lines {
+							lines_batch(&batch, previous.as_deref(), output_config, dedupe_by)
+						} else {
+							process_batch(&batch, previous.as_deref(), plan, output_dialect, &mut scratch).map(Batch::Csv)
+						};
+						let _ = recycle_sender.send(batch);
+						if output_sender.send((index, output)).is_err() {
+							break;
+						}
+					}
+				});
+			}
+			drop(output_sender);
+			drop(recycle_sender);
+			drop(batch_receiver);
+
+			let writer_thread = scope.spawn(move || -> Result<usize, Error> {
+				let mut output_writer = output_writer;
+				let mut pending = BTreeMap::new();
+				let mut next_index = 0;
+
+				for (index, output) in output_receiver {
+					pending.insert(index, output);
+					while let Some(output) = pending.remove(&next_index) {
+						match output? {
+							Batch::Csv(output) => output_writer.write_csv(&output)?,
+							Batch::Lines(lines) => output_writer.write_keyed(lines)?,
+						}
+						next_index += 1;
+					}
+				}
+
+				output_writer.finish()
+			});
+
+			let mut record = Record::default();
+			let mut batch = Vec::with_capacity(BATCH_SIZE);
+			let mut batch_len = 0;
+			let mut batch_index = 0;
+			let mut previous: Option<Vec<String>> = None;
+			let mut rejected_rows = 0;
+			let read_error = loop {
+				match csv_file.read_record(&mut record) {
+					Ok(true) => {},
+					Ok(false) => break None,
+					Err(error) => break Some(error),
+				}
+				rows += 1;
+				match batch.get_mut(batch_len) {
+					Some(row) => record.copy_to(row),
+					None => batch.push(record.to_vec()),
+				}
+				let previous_kept = match batch_len {
+					0 => previous.as_deref(),
+					_ => Some(batch[batch_len - 1].as_slice()),
+				};
+				// a rejected row is overwritten by the next one
+				match keep_row(&batch[batch_len], previous_kept, &output_config, filter.as_ref()) {
+					Ok(true) => batch_len += 1,
+					Ok(false) => rejected_rows += 1,
+					Err(error) => break Some(error),
+				}
+				if batch_len == BATCH_SIZE {
+					let next_batch = recycle_receiver.try_recv().unwrap_or_else(|_| Vec::with_capacity(BATCH_SIZE));
+					let full_batch = mem::replace(&mut batch, next_batch);
+					batch_len = 0;
+					let batch_previous = mem::replace(&mut previous, full_batch.last().cloned());
+					// the workers only hang up when the writer stopped because of an error
+					if batch_sender.send((batch_index, batch_previous, full_batch)).is_err() {
+						break None;
+					}
+					batch_index += 1;
+				}
+
+				report_progress(rows, csv_file.bytes_read);
+			};
+			if batch_len > 0 {
+				batch.truncate(batch_len);
+				let _ = batch_sender.send((batch_index, previous, batch));
+			}
+			drop(batch_sender);
+
+			let result = writer_thread.join();
+			match read_error {
+				Some(error) => Ok(Err(error)),
+				None => result.map(|result| result.map(|dropped_rows| (rejected_rows, dropped_rows))),
+			}
+		})
 	};
-	let reader = BufReader::with_capacity(64 * 1024, input_file);
 
-	let output_file = match File::create(&settings.output) {
-		Ok(file) => file,
+	if show_progress {
+		eprint!("\x1b[1A\x1b[0G");
+	}
+
+	match result {
+		Ok(Ok((rejected_rows, dropped_rows))) => {
+			let output_name = if settings.output == "-" {
+				"stdout"
+			} else {
+				&settings.output
+			};
+			let dropped = match dedupe_by {
+				Some(_) => format!("\nRows dropped as duplicates: {dropped_rows}"),
+				None => String::new(),
+			};
+			eprintln!(
+				"File successfully written to \"{GreenBright}{output_name}{Reset}\"\nRows processed: {rows}\nRows rejected by filters: {rejected_rows}{dropped}\nTime: {:#?}",
+				time.elapsed()
+			)
+		},
+		Ok(Err(error)) => exit_with_error(Some(error.to_string()), Some(error.stage), 1),
+		Err(_) => exit_with_error(Some(String::from("The writer thread panicked")), Some(ErrorStages::Process), 1),
+	}
+}
+
+/// Report every problem of the config and, if given, of the input headings then exit
+fn check(settings: &Settings) -> ! {
+	let (mut output_config, mut diagnostics) = match File::open(&settings.output_config) {
+		Ok(file) => OutputConfig::check_in(CsvParser::new(BufReader::new(file)), config_dir(settings)),
 		Err(error) => {
 			exit_with_error(
-				Some(format!("Could not create output file \"{}\": \"{Red}{error}{Reset}\"", settings.output)),
+				Some(format!("Could not open output config \"{}\": \"{Red}{error}{Reset}\"", settings.output_config)),
 				Some(ErrorStages::Io),
 				1,
 			);
 		},
 	};
-	let mut writer = BufWriter::with_capacity(256 * 1024, output_file);
 
-	let mut is_heading = true;
-	let mut output = String::new();
-	let mut last_report_time = Instant::now();
+	let headings = match settings.input.is_empty() {
+		true => None,
+		false => read_headings(&settings.input, |sample| settings.input_dialect(sample)),
+	};
+	let filter = settings
+		.filter
+		.as_deref()
+		.map(|filter| {
+			let mut condition = Condition::parse_expression(filter)?;
+			if let Some(headings) = &headings {
+				condition.resolve_headers(headings)?;
+			}
+			Ok::<_, Error>(condition)
+		})
+		.transpose();
+	if let Err(error) = filter {
+		diagnostics.errors.push(Error::new(error.stage, format!("Invalid --where: {error}")));
+	}
+	if let Some(Err(error)) = settings.dedupe_by.as_deref().map(|key| parse_dedupe_key(key, &output_config.headings())) {
+		diagnostics.errors.push(Error::new(error.stage, format!("Invalid --dedupe-by: {error}")));
+	}
+	if let Some(Err(error)) = settings.sort_by.as_deref().map(|keys| SortKey::parse_keys(keys, &output_config.headings()))
+	{
+		diagnostics.errors.push(Error::new(error.stage, format!("Invalid --sort-by: {error}")));
+	}
+
+	if let Some(headings) = &headings {
+		diagnostics.errors.extend(output_config.check_headers(headings));
+	}
 
-	let mut csv_file = CsvParser::new(reader);
+	for error in &diagnostics.errors {
+		eprintln!(" {Red}ERROR{Reset} {} {error}", error.stage);
+	}
+	for warning in &diagnostics.warnings {
+		eprintln!(" {Yellow}WARNING{Reset} {} {warning}", warning.stage);
+	}
 
-	println!("Progress: 0%");
-	while let Some(row) = csv_file.next() {
-		if is_heading {
-			is_heading = false;
-			output = format!("{}\n", output_config.heading);
-		} else {
-			csv::export(&process::run(&row, &output_config), &mut output);
+	if diagnostics.is_empty() {
+		eprintln!("Config \"{GreenBright}{}{Reset}\" is valid", settings.output_config);
+		exit_with_error(None, None, 0);
+	} else {
+		eprintln!(
+			"Config \"{Red}{}{Reset}\" has {} error(s) and {} warning(s)",
+			settings.output_config,
+			diagnostics.errors.len(),
+			diagnostics.warnings.len()
+		);
+		exit_with_error(None, None, 1);
+	}
+}
+
+/// The directory of the config file which relative `LOOKUP` paths are resolved against
+fn config_dir(settings: &Settings) -> &Path {
+	Path::new(&settings.output_config).parent().unwrap_or(Path::new(""))
+}
+
+/// Write a starter config for the input file then exit
+fn init(settings: &Settings) -> ! {
+	let Some(input_headings) = read_headings(&settings.input, |sample| settings.input_dialect(sample)) else {
+		exit_with_error(Some(format!("The input file \"{}\" is empty", settings.input)), Some(ErrorStages::Io), 1);
+	};
+	let template = settings.template.as_ref().map(|template| match read_headings(template, Dialect::detect) {
+		Some(headings) => headings,
+		None => exit_with_error(Some(format!("The template \"{template}\" is empty")), Some(ErrorStages::Io), 1),
+	});
+
+	let scaffold = scaffold(&input_headings, template.as_deref());
+	let mut output = String::new();
+	csv::export(&scaffold.rows, &mut output);
+
+	let result = if settings.output == "-" {
+		io::stdout().write_all(output.as_bytes())
+	} else {
+		// an existing config is most likely hand written so it's only replaced when asked to
+		OpenOptions::new()
+			.write(true)
+			.create_new(!settings.force)
+			.create(true)
+			.truncate(true)
+			.open(&settings.output)
+			.and_then(|mut file| file.write_all(output.as_bytes()))
+	};
+	if let Err(error) = result {
+		let message = match error.kind() {
+			io::ErrorKind::AlreadyExists => {
+				format!("The config \"{}\" already exists, pass --force to overwrite it", settings.output)
+			},
+			_ => format!("Could not write config \"{}\": \"{Red}{error}{Reset}\"", settings.output),
 		};
+		exit_with_error(Some(message), Some(ErrorStages::Io), 1);
+	}
 
-		if let Err(error) = writer.write_all(output.as_bytes()) {
+	let output_name = if settings.output == "-" {
+		"stdout"
+	} else {
+		&settings.output
+	};
+	eprintln!("Config successfully written to \"{GreenBright}{output_name}{Reset}\"");
+	if !scaffold.unmatched.is_empty() {
+		eprintln!("No input column found for: {Yellow}{}{Reset}", scaffold.unmatched.join(", "));
+	}
+	if !scaffold.unused.is_empty() {
+		eprintln!("Input columns not used: {Yellow}{}{Reset}", scaffold.unused.join(", "));
+	}
+	exit_with_error(None, None, 0);
+}
+
+/// Read the heading row of a CSV file, use "-" for stdin, returns None if the file is empty
+fn read_headings(path: &str, dialect: impl Fn(&str) -> Dialect) -> Option<Vec<String>> {
+	let input: Box<dyn Read> = if path == "-" {
+		Box::new(io::stdin().lock())
+	} else {
+		match File::open(path) {
+			Ok(file) => Box::new(file),
+			Err(error) => {
+				exit_with_error(
+					Some(format!("Could not open file \"{path}\": \"{Red}{error}{Reset}\"")),
+					Some(ErrorStages::Io),
+					1,
+				);
+			},
+		}
+	};
+
+	let mut reader = BufReader::new(input);
+	let dialect = match reader.fill_buf() {
+		Ok(sample) => dialect(&String::from_utf8_lossy(sample)),
+		Err(error) => {
 			exit_with_error(
-				Some(format!("Failed to write to output file: \"{Red}{error}{Reset}\"")),
+				Some(format!("Could not read file \"{path}\": \"{Red}{error}{Reset}\"")),
 				Some(ErrorStages::Io),
 				1,
 			);
+		},
+	};
+	read_row(&mut CsvParser::with_dialect(reader, dialect))
+}
+
+/// Read the next row of a CSV file, exits if the file can't be read
+fn read_row<R: BufRead>(csv_file: &mut CsvParser<R>) -> Option<Vec<String>> {
+	let mut record = Record::default();
+	match csv_file.read_record(&mut record) {
+		Ok(true) => Some(record.to_vec()),
+		Ok(false) => None,
+		Err(error) => exit_with_error(Some(error.to_string()), Some(error.stage), 1),
+	}
+}
+
+/// Resolve the `<col 'heading'>` items of the config and of `--where` against the input headings, exits on an unknown
+/// column
+fn resolve_headings(output_config: &mut OutputConfig, filter: Option<&mut Condition>, headings: &[String]) {
+	if let Err(error) = output_config.resolve_headers(headings) {
+		exit_with_error(Some(error.to_string()), Some(error.stage), 1);
+	}
+	if let Some(Err(error)) = filter.map(|filter| filter.resolve_headers(headings)) {
+		exit_with_error(Some(format!("Invalid --where: {error}")), Some(error.stage), 1);
+	}
+}
+
+/// Print how the config is applied to a single input row then exit
+fn explain_row<R: BufRead>(
+	row_number: usize,
+	output_config: &mut OutputConfig,
+	mut csv_file: CsvParser<R>,
+	mut filter: Option<Condition>,
+) -> ! {
+	if let Some(row) = read_row(&mut csv_file) {
+		resolve_headings(output_config, filter.as_mut(), &row);
+	}
+	let filter = filter.as_ref();
+
+	// the heading is row 1 and the previous row is the last row before this one that passed the filters
+	let mut previous = None;
+	for _ in 3..=row_number {
+		let Some(row) = read_row(&mut csv_file) else { break };
+		match keep_row(&row, previous.as_deref(), output_config, filter) {
+			Ok(true) => previous = Some(row),
+			Ok(false) => {},
+			Err(error) => exit_with_error(Some(error.to_string()), Some(error.stage), 1),
 		}
+	}
+	let Some(row) = read_row(&mut csv_file) else {
+		exit_with_error(Some(format!("The input has no row {row_number}")), Some(ErrorStages::Cli), 1);
+	};
 
-		if last_report_time.elapsed() >= Duration::from_secs(1) {
-			let progress = (csv_file.bytes_read as f64 / total_size as f64) * 100.0;
-			println!("\x1b[1A\x1b[0GProgress: {:.2}%\x1b[0K", progress);
-			last_report_time = Instant::now();
+	println!("Row {row_number}");
+	if let Some(condition) = filter {
+		match condition.is_true(&Row::after(&row, previous.as_deref())) {
+			Ok(true) => println!("--where {condition} -> true"),
+			Ok(false) => println!("--where {condition} -> false\n=> the row is rejected so no line is emitted"),
+			Err(error) => println!("--where {condition}\n=> error: {error}"),
 		}
 	}
-	print!("\x1b[1A\x1b[0G");
+	print!("{}", explain(&row, previous.as_deref(), output_config));
+	exit_with_error(None, None, 0);
+}
 
-	if let Err(error) = writer.flush() {
-		exit_with_error(Some(format!("Failed to flush output file: \"{Red}{error}{Reset}\"")), Some(ErrorStages::Io), 1);
-	} else {
-		println!("File successfully written to \"{GreenBright}{}{Reset}\"\nTime: {:#?}", settings.output, time.elapsed())
+/// Aggregate the input rows that pass the filters into the groups of the `:GROUP_BY` directive and write the output of
+/// each group as soon as it's complete, returns the number of rows rejected by filters and dropped as duplicates
+fn aggregate_rows<R: BufRead>(
+	csv_file: &mut CsvParser<R>,
+	output_config: &OutputConfig,
+	filter: Option<&Condition>,
+	mut output_writer: OutputWriter<impl Write>,
+	rows: &mut usize,
+	mut report_progress: impl FnMut(usize, u128),
+) -> Result<(usize, usize), Error> {
+	let mut aggregator = Aggregator::new(output_config)?;
+	let mut record = Record::default();
+	let mut row = Vec::new();
+	let mut previous: Option<Vec<String>> = None;
+	let mut rejected_rows = 0;
+	while csv_file.read_record(&mut record)? {
+		*rows += 1;
+		record.copy_to(&mut row);
+		if keep_row(&row, previous.as_deref(), output_config, filter)? {
+			output_writer.write_lines(aggregator.push(&row, previous.as_deref())?)?;
+			// the previous row is the last kept one and the next row reuses the allocations of the one before
+			match &mut previous {
+				Some(previous) => mem::swap(previous, &mut row),
+				None => previous = Some(mem::take(&mut row)),
+			}
+		} else {
+			rejected_rows += 1;
+		}
+		report_progress(*rows, csv_file.bytes_read);
 	}
+	output_writer.write_lines(aggregator.finish())?;
+
+	Ok((rejected_rows, output_writer.finish()?))
+}
+
+/// The output of a batch of input rows
+enum Batch {
+	/// The output lines as CSV
+	Csv(String),
+	/// The output lines with their `--dedupe-by` key, which is empty without it, for the writer to dedupe and sort them
+	Lines(Vec<(String, Vec<String>)>),
+}
+
+/// Writes the output lines in order, drops duplicates when `--dedupe-by` is given and sorts the lines that are left
+/// when `--sort-by` is given
+struct OutputWriter<'a, W: Write> {
+	writer: W,
+	dialect: &'a Dialect,
+	dedupe: Option<(&'a Item, Deduplicator)>,
+	sorter: Option<Sorter>,
+	buffer: String,
+}
+
+impl<W: Write> OutputWriter<'_, W> {
+	fn write_csv(&mut self, output: &str) -> Result<(), Error> {
+		self.writer.write_all(output.as_bytes()).map_err(write_error)
+	}
+
+	fn write_lines(&mut self, lines: Vec<Vec<String>>) -> Result<(), Error> {
+		let item = self.dedupe.as_ref().map(|(item, _)| *item);
+		let lines = lines.into_iter().map(|line| Ok((line_key(item, &line)?, line))).collect::<Result<Vec<_>, Error>>()?;
+		self.write_keyed(lines)
+	}
+
+	fn write_keyed(&mut self, lines: Vec<(String, Vec<String>)>) -> Result<(), Error> {
+		let mut kept = Vec::with_capacity(lines.len());
+		for (key, line) in lines {
+			match &mut self.dedupe {
+				Some((_, deduplicator)) => kept.extend(deduplicator.push(key, line)?),
+				None => kept.push(line),
+			}
+		}
+		if let Some(sorter) = &mut self.sorter {
+			return kept.into_iter().try_for_each(|line| sorter.push(line));
+		}
+		csv::export_with_dialect(&kept, &mut self.buffer, self.dialect);
+		self.writer.write_all(self.buffer.as_bytes()).map_err(write_error)
+	}
+
+	/// Write the lines held back by the deduplicator and the sorter and flush, returns the number of lines dropped as
+	/// duplicates
+	fn finish(self) -> Result<usize, Error> {
+		let Self {
+			mut writer,
+			dialect,
+			dedupe,
+			mut sorter,
+			mut buffer,
+		} = self;
+		let mut write_line = |line: Vec<String>| {
+			csv::export_with_dialect(&[line], &mut buffer, dialect);
+			writer.write_all(buffer.as_bytes()).map_err(write_error)
+		};
+
+		let dropped_rows = match dedupe {
+			Some((_, deduplicator)) => deduplicator.finish(|line| match &mut sorter {
+				Some(sorter) => sorter.push(line),
+				None => write_line(line),
+			})?,
+			None => 0,
+		};
+		if let Some(sorter) = sorter {
+			sorter.finish(write_line)?;
+		}
+		writer
+			.flush()
+			.map_err(|error| Error::new(ErrorStages::Io, format!("Failed to flush output file: \"{Red}{error}{Reset}\"")))?;
+		Ok(dropped_rows)
+	}
+}
+
+fn write_error(error: io::Error) -> Error {
+	Error::new(ErrorStages::Io, format!("Failed to write to output file: \"{Red}{error}{Reset}\""))
+}
+
+/// The `--dedupe-by` key of an output line, empty without it
+fn line_key(item: Option<&Item>, line: &[String]) -> Result<String, Error> {
+	match item {
+		Some(item) => dedupe_key(item, line)
+			.map_err(|error| Error::new(error.stage, format!("Could not get the --dedupe-by key {item}: {error}"))),
+		None => Ok(String::new()),
+	}
+}
+
+/// Whether an input row passes the `:FILTER` directive of the config and the `--where` condition
+fn keep_row(
+	row: &[String],
+	previous: Option<&[String]>,
+	output_config: &OutputConfig,
+	filter: Option<&Condition>,
+) -> Result<bool, Error> {
+	Ok(
+		output_config.keep(row, previous)?
+			&& match filter {
+				Some(condition) => condition.is_true(&Row::after(row, previous))?,
+				None => true,
+			},
+	)
+}
+
+/// Convert a batch of kept input rows with the interpreter and get the `--dedupe-by` key of every output line
+fn lines_batch(
+	batch: &[Vec<String>],
+	previous: Option<&[String]>,
+	output_config: &OutputConfig,
+	item: Option<&Item>,
+) -> Result<Batch, Error> {
+	let mut lines = Vec::with_capacity(batch.len());
+
+	for (index, row) in batch.iter().enumerate() {
+		let previous = match index {
+			0 => previous,
+			_ => Some(batch[index - 1].as_slice()),
+		};
+		for line in process::run_after(row, previous, output_config)? {
+			lines.push((line_key(item, &line)?, line));
+		}
+	}
+
+	Ok(Batch::Lines(lines))
+}
+
+/// Convert a batch of kept input rows that follows the previous kept row, returns the CSV output
+fn process_batch(
+	batch: &[Vec<String>],
+	previous: Option<&[String]>,
+	plan: &Plan,
+	output_dialect: &Dialect,
+	scratch: &mut Scratch,
+) -> Result<String, Error> {
+	let mut output = String::with_capacity(batch.len() * 128);
+
+	for (index, row) in batch.iter().enumerate() {
+		let previous = match index {
+			0 => previous,
+			_ => Some(batch[index - 1].as_slice()),
+		};
+		plan.write(row, previous, &mut output, output_dialect, scratch)?;
+	}
+
+	Ok(output)
 }
@@ -0,0 +1,460 @@
+//! This module compiles an [OutputConfig] into a [Plan] which is faster to apply to many rows than [process::run]
+//!
+//! The plan borrows everything it can from the config, fuses neighbouring filters, writes CSV straight into the
+//! output and reuses its buffers between rows.
+//! Conditions and directives are still evaluated by the interpreter so both always produce the same output.
+use std::{borrow::Cow, fmt::Write, mem};
+
+use crate::{
+	cli::{Error, ErrorStages},
+	config::{Filter, Item, Line, OutputConfig, Row},
+	csv::{quote_csv_cell, Dialect},
+	process,
+};
+
+/// How to get the value of a single output cell
+#[derive(Debug, PartialEq)]
+enum Step<'a> {
+	/// A hardcoded value
+	Value(&'a str),
+	/// A cell copied as is
+	Cell(usize),
+	/// A cell with its fused filters
+	FilteredCell(usize, Vec<Filter>),
+	/// The element of an `:EACH` directive with its fused filters
+	Each(Vec<Filter>),
+	/// The index of an `:EACH` directive with its fused filters
+	Index(Vec<Filter>),
+	/// Anything else, like conditions, is handed to the interpreter
+	Interpret(&'a Item),
+}
+
+/// A compiled config line
+#[derive(Debug, PartialEq)]
+struct PlanLine<'a> {
+	line: &'a Line,
+	steps: Vec<Step<'a>>,
+	/// Whether any condition of this line can evaluate to the deprecated `SKIP_THIS_LINE`
+	may_skip: bool,
+}
+
+/// The buffers a [Plan] reuses between rows, keep one per thread
+#[derive(Debug, Default)]
+pub struct Scratch {
+	value: String,
+	temp: String,
+}
+
+/// An [OutputConfig] compiled for fast evaluation
+///
+/// ```rust
+/// use csv_converter::{config::OutputConfig, csv::{CsvParser, Dialect}, plan::{Plan, Scratch}};
+/// use std::io::Cursor;
+///
+/// let output_config = OutputConfig::new(CsvParser::new(Cursor::new("H1,H2\n<cell2 UPPER_CASE>,<cell1>\n"))).unwrap();
+/// let plan = Plan::new(&output_config).unwrap();
+/// let mut scratch = Scratch::default();
+/// let mut output = String::new();
+/// plan.write(&[String::from("a"), String::from("b")], None, &mut output, &Dialect::default(), &mut scratch).unwrap();
+/// assert_eq!(output, "B,a\n");
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct Plan<'a> {
+	lines: Vec<PlanLine<'a>>,
+}
+
+impl<'a> Plan<'a> {
+	/// Compile the config, all `<col 'heading'>` items must have been resolved with [OutputConfig::resolve_headers]
+	pub fn new(output_config: &'a OutputConfig) -> Result<Self, Error> {
+		let mut lines = Vec::with_capacity(output_config.lines.len());
+
+		for line in &output_config.lines {
+			let mut steps = Vec::with_capacity(line.items.len());
+			for (column_index, item) in line.items.iter().enumerate() {
+				let step = match item {
+					Item::Value(value) => Step::Value(value),
+					Item::Cell(i, None) => Step::Cell(*i),
+					Item::Cell(i, Some(filters)) => Step::FilteredCell(*i, fuse(filters)),
+					// without a directive these fail in the interpreter with the right error
+					Item::Each(filters) if line.each.is_some() => Step::Each(fuse(filters.as_deref().unwrap_or_default())),
+					Item::Index(filters) if line.each.is_some() => Step::Index(fuse(filters.as_deref().unwrap_or_default())),
+					Item::Column(name, _) => {
+						return Err(
+							Error::new(
+								ErrorStages::Process,
+								format!("Column \"{name}\" has not been resolved against the input headings"),
+							)
+							.at(line.row, column_index + 1),
+						);
+					},
+					_ => Step::Interpret(item),
+				};
+				steps.push(step);
+			}
+
+			lines.push(PlanLine {
+				line,
+				steps,
+				may_skip: line.items.iter().any(|item| item.uses_skip_this_line()),
+			});
+		}
+
+		Ok(Self { lines })
+	}
+
+	/// Apply the plan to an input row that follows the previous input row, if there is one, and append the resulting
+	/// lines as CSV of the given [Dialect] to the output
+	///
+	/// The output and errors are the same as [process::run] followed by [crate::csv::export_with_dialect]
+	pub fn write(
+		&self,
+		input_line: &[String],
+		previous: Option<&[String]>,
+		output: &mut String,
+		dialect: &Dialect,
+		scratch: &mut Scratch,
+	) -> Result<(), Error> {
+		for plan_line in &self.lines {
+			match &plan_line.line.each {
+				Some(each) => {
+					for (index, element) in process::get_elements(each, input_line, previous)?.iter().enumerate() {
+						let row = Row {
+							cells: input_line,
+							each: Some((element, index + 1)),
+							previous,
+						};
+						write_line(plan_line, &row, output, dialect, scratch)?;
+					}
+				},
+				None => write_line(plan_line, &Row::after(input_line, previous), output, dialect, scratch)?,
+			}
+		}
+
+		Ok(())
+	}
+}
+
+fn write_line(
+	plan_line: &PlanLine,
+	row: &Row,
+	output: &mut String,
+	dialect: &Dialect,
+	scratch: &mut Scratch,
+) -> Result<(), Error> {
+	let line = plan_line.line;
+	if let Some(guard) = &line.guard {
+		if !guard.condition.is_true(row).map_err(|error| error.at(guard.row, 1))? {
+			return Ok(());
+		}
+	}
+
+	let start = output.len();
+	let mut skip_line = false;
+
+	for (column_index, step) in plan_line.steps.iter().enumerate() {
+		if column_index > 0 {
+			output.push(dialect.delimiter);
+		}
+
+		match step {
+			Step::Value(value) => quote_csv_cell(value, output, dialect),
+			Step::Cell(i) => match row.cells.get(*i) {
+				Some(cell) => quote_csv_cell(cell, output, dialect),
+				None => return Err(cell_not_found(*i).at(line.row, column_index + 1)),
+			},
+			Step::FilteredCell(i, filters) => match row.cells.get(*i) {
+				Some(cell) => {
+					scratch.value.clear();
+					scratch.value.push_str(cell);
+					apply_filters(filters, scratch).map_err(|error| error.at(line.row, column_index + 1))?;
+					quote_csv_cell(&scratch.value, output, dialect);
+				},
+				None => return Err(cell_not_found(*i).at(line.row, column_index + 1)),
+			},
+			Step::Each(filters) => {
+				scratch.value.clear();
+				if let Some((element, _)) = row.each {
+					scratch.value.push_str(element);
+				}
+				apply_filters(filters, scratch).map_err(|error| error.at(line.row, column_index + 1))?;
+				quote_csv_cell(&scratch.value, output, dialect);
+			},
+			Step::Index(filters) => {
+				scratch.value.clear();
+				if let Some((_, index)) = row.each {
+					let _ = write!(scratch.value, "{index}");
+				}
+				apply_filters(filters, scratch).map_err(|error| error.at(line.row, column_index + 1))?;
+				quote_csv_cell(&scratch.value, output, dialect);
+			},
+			Step::Interpret(item) => {
+				let value = process::get_value(item, row).map_err(|error| error.at(line.row, column_index + 1))?;
+				if plan_line.may_skip && value == "SKIP_THIS_LINE" && item.uses_skip_this_line() {
+					skip_line = true;
+				}
+				quote_csv_cell(&value, output, dialect);
+			},
+		}
+	}
+
+	if skip_line {
+		output.truncate(start);
+	} else {
+		output.push_str(&dialect.line_terminator);
+	}
+	Ok(())
+}
+
+fn cell_not_found(i: usize) -> Error {
+	Error::new(ErrorStages::Process, format!("Cell not found \"<cell{}>\"", i + 1))
+}
+
+/// Merge neighbouring filters that can be applied as one
+fn fuse(filters: &[Filter]) -> Vec<Filter> {
+	let mut fused: Vec<Filter> = Vec::with_capacity(filters.len());
+
+	for filter in filters {
+		match (fused.last_mut(), filter) {
+			(Some(Filter::Append(suffix)), Filter::Append(next)) => suffix.push_str(next),
+			(Some(Filter::Prepend(prefix)), Filter::Prepend(next)) => prefix.insert_str(0, next),
+			(Some(Filter::Trim), Filter::Trim | Filter::TrimStart | Filter::TrimEnd)
+			| (Some(Filter::UpperCase), Filter::UpperCase)
+			| (Some(Filter::LowerCase), Filter::LowerCase)
+			| (_, Filter::Unknown(_)) => {},
+			(Some(last @ (Filter::TrimStart | Filter::TrimEnd)), Filter::Trim)
+			| (Some(last @ Filter::TrimStart), Filter::TrimEnd)
+			| (Some(last @ Filter::TrimEnd), Filter::TrimStart) => *last = Filter::Trim,
+			_ => fused.push(filter.clone()),
+		}
+	}
+
+	fused
+}
+
+/// Apply filters to the value in the scratch buffer, in place where we can
+fn apply_filters(filters: &[Filter], scratch: &mut Scratch) -> Result<(), Error> {
+	let Scratch { value, temp } = scratch;
+
+	for filter in filters {
+		match filter {
+			Filter::UpperCase if value.is_ascii() => value.make_ascii_uppercase(),
+			Filter::LowerCase if value.is_ascii() => value.make_ascii_lowercase(),
+			Filter::Length => {
+				let length = value.len();
+				value.clear();
+				let _ = write!(value, "{length}");
+			},
+			Filter::Trim => {
+				value.truncate(value.trim_end().len());
+				value.drain(..value.len() - value.trim_start().len());
+			},
+			Filter::TrimStart => {
+				value.drain(..value.len() - value.trim_start().len());
+			},
+			Filter::TrimEnd => value.truncate(value.trim_end().len()),
+			Filter::Append(suffix) => value.push_str(suffix),
+			Filter::Prepend(prefix) => value.insert_str(0, prefix),
+			Filter::Replace(search, replacement) if !search.is_empty() => {
+				if value.contains(search.as_str()) {
+					temp.clear();
+					let mut last = 0;
+					for (start, part) in value.match_indices(search.as_str()) {
+						temp.push_str(&value[last..start]);
+						temp.push_str(replacement);
+						last = start + part.len();
+					}
+					temp.push_str(&value[last..]);
+					mem::swap(value, temp);
+				}
+			},
+			_ => match filter.run(Cow::Borrowed(value.as_str()))? {
+				Cow::Owned(result) => *value = result,
+				Cow::Borrowed(result) => {
+					if result.len() != value.len() || result.as_ptr() != value.as_ptr() {
+						temp.clear();
+						temp.push_str(result);
+						mem::swap(value, temp);
+					}
+				},
+			},
+		}
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		csv::{export_with_dialect, CsvParser},
+		process::Context,
+	};
+	use std::io::Cursor;
+
+	fn interpret(input_line: &[String], output_config: &OutputConfig, dialect: &Dialect) -> Result<String, Error> {
+		let mut output = String::new();
+		export_with_dialect(&process::run(input_line, output_config, &mut Context::new())?, &mut output, dialect);
+		Ok(output)
+	}
+
+	fn compiled(input_line: &[String], output_config: &OutputConfig, dialect: &Dialect) -> Result<String, Error> {
+		let mut output = String::new();
+		Plan::new(output_config)?.write(input_line, None, &mut output, dialect, &mut Scratch::default())?;
+		Ok(output)
+	}
+
+	#[test]
+	fn fuse_test() {
+		assert_eq!(
+			fuse(&[
+				Filter::Append(String::from("a")),
+				Filter::Append(String::from("b")),
+				Filter::Prepend(String::from("c")),
+				Filter::Prepend(String::from("d")),
+				Filter::TrimStart,
+				Filter::TrimEnd,
+				Filter::Trim,
+				Filter::UpperCase,
+				Filter::UpperCase,
+				Filter::LowerCase,
+			]),
+			vec![
+				Filter::Append(String::from("ab")),
+				Filter::Prepend(String::from("dc")),
+				Filter::Trim,
+				Filter::UpperCase,
+				Filter::LowerCase,
+			]
+		);
+		assert_eq!(
+			fuse(&[
+				Filter::Split(String::from("-"), 1),
+				Filter::Unknown(String::from("UPPERCASE")),
+				Filter::Split(String::from("-"), 1)
+			]),
+			vec![Filter::Split(String::from("-"), 1), Filter::Split(String::from("-"), 1)]
+		);
+	}
+
+	#[test]
+	fn apply_filters_test() {
+		let filters = [
+			Filter::UpperCase,
+			Filter::LowerCase,
+			Filter::Length,
+			Filter::Trim,
+			Filter::TrimStart,
+			Filter::TrimEnd,
+			Filter::Replace(String::from("l"), String::from("L")),
+			Filter::Replace(String::from("o W"), String::from("")),
+			Filter::Append(String::from("!")),
+			Filter::Prepend(String::from("¡")),
+			Filter::Split(String::from(" "), 2),
+			Filter::SubString(3, Some(4)),
+			Filter::SubString(99, None),
+			Filter::Round(1),
+			Filter::PadNumber(4),
+		];
+		for input in ["  Hello World  ", "", "Größe Straße 😬", "lll", " 3.14 "] {
+			for filter in &filters {
+				let mut scratch = Scratch {
+					value: String::from(input),
+					temp: String::from("leftover"),
+				};
+				assert_eq!(
+					apply_filters(&[filter.clone()], &mut scratch).map(|_| Cow::Borrowed(scratch.value.as_str())),
+					filter.run(Cow::Borrowed(input)),
+					"{filter:?} on \"{input}\""
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn write_test() {
+		let mut output_config = OutputConfig::new(CsvParser::new(Cursor::new(
+			"H1,H2,H3,H4\n\
+			<cell1 TRIM UPPER_CASE APPEND|'-' APPEND|'x'>,\"a,b\",<col 'Name' REPLACE|' '|'-'>,:IF <cell3> > 10 ('big') ELSE IF <cell3> IS_NUMERIC (<cell3 PREPEND|'#'>) ELSE ('SKIP_THIS_LINE')\n\
+			:EACH <cell4> BY '|'\n\
+			:WHERE NOT <each> ENDS_WITH|'.gif'\n\
+			<cell1>,<each UPPER_CASE>,<index PREPEND|'0'>,<cell2>\n\
+			<cell2>,<cell5>,,\n",
+		)))
+		.unwrap();
+		output_config
+			.resolve_headers(&[
+				String::from("SKU"),
+				String::from("Name"),
+				String::from("Price"),
+				String::from("Images"),
+				String::from("Extra"),
+			])
+			.unwrap();
+
+		let rows = [
+			vec![
+				String::from(" sku1 "),
+				String::from("Blue Shirt"),
+				String::from("5"),
+				String::from("a.jpg|b.gif|c.png"),
+				String::from("with \"quotes\""),
+			],
+			vec![
+				String::from("sku2"),
+				String::from("Größe"),
+				String::from("42"),
+				String::from(""),
+				String::from("line\nbreak"),
+			],
+			vec![
+				String::from("sku3"),
+				String::from("Skipped"),
+				String::from("free"),
+				String::from("x.jpg"),
+				String::from("SKIP_THIS_LINE"),
+			],
+		];
+
+		for dialect in [
+			Dialect::default(),
+			Dialect {
+				delimiter: ';',
+				escape: Some('\\'),
+				line_terminator: String::from("\r\n"),
+				..Dialect::default()
+			},
+		] {
+			for row in &rows {
+				assert_eq!(compiled(row, &output_config, &dialect), interpret(row, &output_config, &dialect));
+			}
+		}
+
+		assert_eq!(
+			compiled(&rows[0], &output_config, &Dialect::default()),
+			Ok(String::from(
+				"SKU1-x,\"a,b\",Blue-Shirt,#5\n sku1 ,A.JPG,01,Blue Shirt\n sku1 ,C.PNG,03,Blue Shirt\nBlue Shirt,\"with \"\"quotes\"\"\",,\n"
+			))
+		);
+
+		// errors point at the same place
+		let short_row = [String::from("sku4"), String::from("Short")];
+		assert_eq!(
+			compiled(&short_row, &output_config, &Dialect::default()),
+			interpret(&short_row, &output_config, &Dialect::default())
+		);
+		assert!(compiled(&short_row, &output_config, &Dialect::default()).is_err());
+
+		let output_config = OutputConfig::new(CsvParser::new(Cursor::new("H1\n<each>\n"))).unwrap();
+		assert_eq!(
+			compiled(&short_row, &output_config, &Dialect::default()),
+			interpret(&short_row, &output_config, &Dialect::default())
+		);
+
+		let output_config = OutputConfig::new(CsvParser::new(Cursor::new("H1\n<col 'SKU'>\n"))).unwrap();
+		assert_eq!(
+			Plan::new(&output_config).map_err(|error| (error.stage, error.row, error.column)),
+			Err((ErrorStages::Process, Some(2), Some(1)))
+		);
+	}
+}
@@ -1,52 +1,280 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt};
 
 use crate::{
-	cli::{exit_with_error, ErrorStages},
-	config::{Item, OutputConfig},
+	cli::{Error, ErrorStages},
+	config::{Each, EachSource, Filter, Item, Line, OutputConfig, Row},
 };
 
+/// What [run] remembers from one input row to the next
+///
+/// Keep one for the whole input file so `<prev1>` items and conditions like `IS_FIRST_IN_GROUP` see the row before
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Context {
+	previous: Option<Vec<String>>,
+}
+
+impl Context {
+	/// A context for the first input row
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// A context that continues after the given input row
+	pub fn after(previous: Vec<String>) -> Self {
+		Self {
+			previous: Some(previous),
+		}
+	}
+
+	/// The input row before the current one, None for the first input row
+	pub fn previous(&self) -> Option<&[String]> {
+		self.previous.as_deref()
+	}
+}
+
 /// This function takes the [OutputConfig] and applies it to the input file line by line
-pub fn run(input_line: &[String], output_config: &OutputConfig) -> Vec<Vec<String>> {
+///
+/// Lines with an `:EACH` directive are emitted once for every element of their list and lines with a `:WHERE`
+/// directive only for the rows or elements that meet its condition.
+/// The [Context] remembers the input row for the next call, even when the row fails.
+/// Errors carry the config row and column of the item that failed
+pub fn run(
+	input_line: &[String],
+	output_config: &OutputConfig,
+	context: &mut Context,
+) -> Result<Vec<Vec<String>>, Error> {
+	let new_lines = run_after(input_line, context.previous(), output_config);
+	context.previous = Some(input_line.to_vec());
+	new_lines
+}
+
+/// Like [run] for an input row that follows the given previous row, for callers that keep track of it themselves
+pub fn run_after(
+	input_line: &[String],
+	previous: Option<&[String]>,
+	output_config: &OutputConfig,
+) -> Result<Vec<Vec<String>>, Error> {
 	let mut new_lines = Vec::new();
+
+	for line in &output_config.lines {
+		match &line.each {
+			Some(each) => {
+				for (index, element) in get_elements(each, input_line, previous)?.iter().enumerate() {
+					let row = Row {
+						cells: input_line,
+						each: Some((element, index + 1)),
+						previous,
+					};
+					if let Some(new_line) = run_line(line, &row)? {
+						new_lines.push(new_line);
+					}
+				}
+			},
+			None => {
+				if let Some(new_line) = run_line(line, &Row::after(input_line, previous))? {
+					new_lines.push(new_line);
+				}
+			},
+		}
+	}
+
+	Ok(new_lines)
+}
+
+/// Apply the items of a single config line to a row, returns None if the line should be skipped
+pub(crate) fn run_line(config_line: &Line, row: &Row) -> Result<Option<Vec<String>>, Error> {
+	if let Some(guard) = &config_line.guard {
+		if !guard.condition.is_true(row).map_err(|error| error.at(guard.row, 1))? {
+			return Ok(None);
+		}
+	}
+
+	let mut line: Vec<String> = Vec::with_capacity(config_line.items.len());
 	let mut skip_line = false;
 
-	for items in &output_config.lines {
-		let mut line: Vec<String> = Vec::with_capacity(items.len());
-		for item in items {
-			match item {
-				Item::Cell(i, filters) => match input_line.get(*i) {
-					Some(v) => {
-						let mut value: Cow<str> = Cow::Borrowed(v.as_str());
-						if let Some(filters) = filters {
-							for filter in filters {
-								value = filter.run(value);
-							}
-						}
-						line.push(value.to_string())
-					},
+	for (column_index, item) in config_line.items.iter().enumerate() {
+		let value = get_value(item, row).map_err(|error| error.at(config_line.row, column_index + 1))?;
+		// Deprecated: use a :WHERE directive instead.
+		// Only conditions that can return the literal SKIP_THIS_LINE skip so a cell with that text doesn't drop the line
+		if value == "SKIP_THIS_LINE" && item.uses_skip_this_line() {
+			skip_line = true;
+		}
+		line.push(value.into_owned());
+	}
+
+	if skip_line {
+		Ok(None)
+	} else {
+		Ok(Some(line))
+	}
+}
+
+/// Follows how the interpreter comes to a value, the trace of `--explain` is one
+pub(crate) trait Recorder {
+	/// The steps noted next are part of the step noted last
+	fn enter(&mut self) {}
+
+	/// The steps noted next are no longer part of the step noted last
+	fn leave(&mut self) {}
+
+	/// A step of the evaluation like the value of a cell or what a filter made of it
+	fn note(&mut self, _step: fmt::Arguments) {}
+}
+
+/// Nothing is recorded while converting
+impl Recorder for () {}
+
+/// Evaluate a single item for a row
+pub(crate) fn get_value<'a>(item: &'a Item, row: &Row<'a>) -> Result<Cow<'a, str>, Error> {
+	get_value_with(item, row, &mut ())
+}
+
+/// Evaluate a single item for a row and tell the recorder every step on the way
+pub(crate) fn get_value_with<'a, R: Recorder>(
+	item: &'a Item,
+	row: &Row<'a>,
+	recorder: &mut R,
+) -> Result<Cow<'a, str>, Error> {
+	let (mut value, filters): (Cow<str>, _) = match item {
+		Item::Cell(i, filters) => match row.cells.get(*i) {
+			Some(v) => {
+				recorder.note(format_args!("<cell{}> = {v:?}", i + 1));
+				(Cow::Borrowed(v.as_str()), filters)
+			},
+			None => {
+				return Err(Error::new(ErrorStages::Process, format!("Cell not found \"<cell{}>\"", i + 1)));
+			},
+		},
+		Item::Previous(i, filters) => match row.previous.map(|previous| previous.get(*i)) {
+			Some(Some(v)) => {
+				recorder.note(format_args!("<prev{}> = {v:?}", i + 1));
+				(Cow::Borrowed(v.as_str()), filters)
+			},
+			Some(None) => {
+				return Err(Error::new(ErrorStages::Process, format!("Cell not found \"<prev{}>\"", i + 1)));
+			},
+			None => {
+				recorder.note(format_args!("<prev{}> = \"\"", i + 1));
+				(Cow::Borrowed(""), filters)
+			},
+		},
+		Item::Each(filters) => match row.each {
+			Some((element, _)) => {
+				recorder.note(format_args!("<each> = {element:?}"));
+				(Cow::Borrowed(element), filters)
+			},
+			None => {
+				return Err(Error::new(
+					ErrorStages::Process,
+					String::from("<each> can only be used in a line below an :EACH directive"),
+				));
+			},
+		},
+		Item::Index(filters) => match row.each {
+			Some((_, index)) => {
+				recorder.note(format_args!("<index> = \"{index}\""));
+				(Cow::Owned(index.to_string()), filters)
+			},
+			None => {
+				return Err(Error::new(
+					ErrorStages::Process,
+					String::from("<index> can only be used in a line below an :EACH directive"),
+				));
+			},
+		},
+		Item::If(condition, then_item, else_item) => {
+			recorder.note(format_args!("IF {condition}"));
+			recorder.enter();
+			let outcome = condition.is_true_with(row, recorder)?;
+			recorder.leave();
+			let item = match (outcome, else_item) {
+				(true, _) => then_item,
+				(false, Some(else_item)) => else_item,
+				(false, None) => {
+					recorder.note(format_args!("no ELSE so the value is empty"));
+					return Ok(Cow::Borrowed(""));
+				},
+			};
+			recorder.note(format_args!("{} {item}", if outcome { "THEN" } else { "ELSE" }));
+			recorder.enter();
+			let value = get_value_with(item, row, recorder)?;
+			recorder.leave();
+			return Ok(value);
+		},
+		Item::Calc(expression) => {
+			let value = expression.run_with(row, recorder)?;
+			recorder.note(format_args!(":CALC -> {value:?}"));
+			return Ok(Cow::Owned(value));
+		},
+		Item::Aggregate(_, _) => {
+			return Err(Error::new(
+				ErrorStages::Process,
+				format!("{item} can only be used as a whole cell of a config with a :GROUP_BY directive"),
+			));
+		},
+		Item::Column(name, _) => {
+			return Err(Error::new(
+				ErrorStages::Process,
+				format!("Column \"{name}\" has not been resolved against the input headings"),
+			));
+		},
+		Item::Value(v) => {
+			recorder.note(format_args!("value {v:?}"));
+			return Ok(Cow::Borrowed(v));
+		},
+	};
+
+	for filter in filters.iter().flatten() {
+		value = filter.run(value)?;
+		match filter {
+			Filter::Unknown(_) => recorder.note(format_args!("{filter} is not a filter and was ignored")),
+			_ => recorder.note(format_args!("{filter} -> {value:?}")),
+		}
+	}
+	Ok(value)
+}
+
+/// Collect the non-empty elements of an `:EACH` directive for a row
+pub(crate) fn get_elements(
+	each: &Each,
+	input_line: &[String],
+	previous: Option<&[String]>,
+) -> Result<Vec<String>, Error> {
+	let row = Row::after(input_line, previous);
+	let mut elements = Vec::new();
+
+	match &each.source {
+		EachSource::Split(item, separator) => {
+			let value = get_value(item, &row).map_err(|error| error.at(each.row, 1))?;
+			for element in value.split(separator.as_str()) {
+				if !element.trim().is_empty() {
+					elements.push(element.to_string());
+				}
+			}
+		},
+		EachSource::Range(start, end, filters) => {
+			for i in *start..=*end {
+				let mut element: Cow<str> = match input_line.get(i) {
+					Some(cell) => Cow::Borrowed(cell),
 					None => {
-						exit_with_error(Some(format!("Cell not found \"<cell{i}>\"")), Some(ErrorStages::Process), 1);
+						return Err(
+							Error::new(ErrorStages::Process, format!("Cell not found \"<cell{}>\"", i + 1)).at(each.row, 1),
+						);
 					},
-				},
-				Item::If(condition, then_item, else_item) => {
-					let condition_result =
-						condition.run(then_item, &else_item.as_ref().map(|b| (**b).clone()), input_line).to_string();
-					if &condition_result == "SKIP_THIS_LINE" {
-						skip_line = true;
+				};
+				if let Some(filters) = filters {
+					for filter in filters {
+						element = filter.run(element).map_err(|error| error.at(each.row, 1))?;
 					}
-					line.push(condition_result)
-				},
-				Item::Value(v) => line.push(v.clone()),
+				}
+				if !element.trim().is_empty() {
+					elements.push(element.into_owned());
+				}
 			}
-		}
-		if skip_line {
-			skip_line = false;
-		} else {
-			new_lines.push(line);
-		}
+		},
 	}
 
-	new_lines
+	Ok(elements)
 }
 
 #[cfg(test)]
@@ -59,10 +287,11 @@ mod tests {
 	fn run_cell_test() {
 		assert_eq!(
 			run(
-				&vec![String::from("A"), String::from("B"), String::from("C")],
-				&OutputConfig::new(CsvParser::new(Cursor::new("A,B,C\n<cell1>,<cell3>,<cell2>\n"))),
+				&[String::from("A"), String::from("B"), String::from("C")],
+				&OutputConfig::new(CsvParser::new(Cursor::new("A,B,C\n<cell1>,<cell3>,<cell2>\n"))).unwrap(),
+				&mut Context::new(),
 			),
-			vec![vec![String::from("A"), String::from("C"), String::from("B")]]
+			Ok(vec![vec![String::from("A"), String::from("C"), String::from("B")]])
 		);
 	}
 
@@ -70,17 +299,22 @@ mod tests {
 	fn run_value_test() {
 		assert_eq!(
 			run(
-				&vec![String::from("A"), String::from("B"), String::from("C")],
+				&[String::from("A"), String::from("B"), String::from("C")],
 				&OutputConfig {
 					heading: String::new(),
-					lines: vec![vec![
-						Item::Value(String::from("NEW")),
-						Item::Cell(2, None),
-						Item::Cell(1, None)
-					]],
+					lines: vec![Line::new(
+						2,
+						vec![
+							Item::Value(String::from("NEW")),
+							Item::Cell(2, None),
+							Item::Cell(1, None)
+						]
+					)],
+					..Default::default()
 				},
+				&mut Context::new(),
 			),
-			vec![vec![String::from("NEW"), String::from("C"), String::from("B")]]
+			Ok(vec![vec![String::from("NEW"), String::from("C"), String::from("B")]])
 		);
 	}
 
@@ -88,19 +322,21 @@ mod tests {
 	fn run_multiple_lines_test() {
 		assert_eq!(
 			run(
-				&vec![String::from("A"), String::from("B"), String::from("C")],
+				&[String::from("A"), String::from("B"), String::from("C")],
 				&OutputConfig {
 					heading: String::new(),
 					lines: vec![
-						vec![Item::Cell(0, None), Item::Cell(2, None), Item::Cell(1, None)],
-						vec![Item::Cell(0, None), Item::Cell(1, None), Item::Cell(2, None)],
+						Line::new(2, vec![Item::Cell(0, None), Item::Cell(2, None), Item::Cell(1, None)]),
+						Line::new(3, vec![Item::Cell(0, None), Item::Cell(1, None), Item::Cell(2, None)]),
 					],
+					..Default::default()
 				},
+				&mut Context::new(),
 			),
-			vec![
+			Ok(vec![
 				vec![String::from("A"), String::from("C"), String::from("B")],
 				vec![String::from("A"), String::from("B"), String::from("C")],
-			]
+			])
 		);
 	}
 
@@ -108,29 +344,49 @@ mod tests {
 	fn run_everything_test() {
 		assert_eq!(
 			run(
-				&vec![String::from("A"), String::from("B"), String::from("C")],
+				&[String::from("A"), String::from("B"), String::from("C")],
 				&OutputConfig {
 					heading: String::new(),
 					lines: vec![
-						vec![Item::Cell(0, None), Item::Cell(2, None), Item::Cell(1, None)],
-						vec![
-							Item::Cell(2, None),
-							Item::Value(String::from("MERGE")),
-							Item::Cell(2, None)
-						],
-						vec![
-							Item::Cell(1, None),
-							Item::Cell(0, None),
-							Item::Value(String::from("NEW"))
-						],
+						Line::new(2, vec![Item::Cell(0, None), Item::Cell(2, None), Item::Cell(1, None)]),
+						Line::new(
+							3,
+							vec![
+								Item::Cell(2, None),
+								Item::Value(String::from("MERGE")),
+								Item::Cell(2, None)
+							]
+						),
+						Line::new(
+							4,
+							vec![
+								Item::Cell(1, None),
+								Item::Cell(0, None),
+								Item::Value(String::from("NEW"))
+							]
+						),
 					],
+					..Default::default()
 				},
+				&mut Context::new(),
 			),
-			vec![
+			Ok(vec![
 				vec![String::from("A"), String::from("C"), String::from("B")],
 				vec![String::from("C"), String::from("MERGE"), String::from("C")],
 				vec![String::from("B"), String::from("A"), String::from("NEW")],
-			]
+			])
+		);
+	}
+
+	#[test]
+	fn run_error_test() {
+		assert_eq!(
+			run(
+				&[String::from("A"), String::from("B")],
+				&OutputConfig::new(CsvParser::new(Cursor::new("A,B\n<cell1>,<cell2>\n<cell2>,<cell3>\n"))).unwrap(),
+				&mut Context::new(),
+			),
+			Err(Error::new(ErrorStages::Process, String::from("Cell not found \"<cell3>\"")).at(3, 2))
 		);
 	}
 
@@ -138,18 +394,213 @@ mod tests {
 	fn skip_line_test() {
 		assert_eq!(
 			run(
-				&vec![
+				&[
 					String::from("A"),
 					String::from("B"),
 					String::from("C"),
 					String::from("D")
 				],
-				&OutputConfig::new(CsvParser::new(Cursor::new("Column A,Column B,Column C\n<cell1>,MERGE,<cell2>\n<cell1>,NEW,:IF <cell3> == 'D' ('SKIP_THIS_LINE') ELSE (<cell3>)\n<cell1>,NEW,:IF <cell4> == 'D' ('SKIP_THIS_LINE') ELSE (<cell4>)\n"))),
+				&OutputConfig::new(CsvParser::new(Cursor::new("Column A,Column B,Column C\n<cell1>,MERGE,<cell2>\n<cell1>,NEW,:IF <cell3> == 'D' ('SKIP_THIS_LINE') ELSE (<cell3>)\n<cell1>,NEW,:IF <cell4> == 'D' ('SKIP_THIS_LINE') ELSE (<cell4>)\n"))).unwrap(),
+				&mut Context::new(),
 			),
-			vec![
+			Ok(vec![
 				vec![String::from("A"), String::from("MERGE"), String::from("B")],
 				vec![String::from("A"), String::from("NEW"), String::from("C")],
-			]
+			])
+		);
+	}
+
+	#[test]
+	fn run_each_test() {
+		let output_config = OutputConfig::new(CsvParser::new(Cursor::new(
+			"H1,H2,H3\n<cell1>,NEW,\n:EACH <cell2> BY '|'\n<cell1>,<each TRIM>,<index>\n:EACH <cell3..cell5>\n<cell1>,<each>,:IF <index> > 1 ('MERGE') ELSE ('NEW')\n",
+		)))
+		.unwrap();
+
+		assert_eq!(
+			run(
+				&[
+					String::from("A"),
+					String::from("a.jpg| b.jpg||c.jpg|"),
+					String::from("red"),
+					String::from(""),
+					String::from("blue")
+				],
+				&output_config,
+				&mut Context::new(),
+			),
+			Ok(vec![
+				vec![String::from("A"), String::from("NEW"), String::from("")],
+				vec![String::from("A"), String::from("a.jpg"), String::from("1")],
+				vec![String::from("A"), String::from("b.jpg"), String::from("2")],
+				vec![String::from("A"), String::from("c.jpg"), String::from("3")],
+				vec![String::from("A"), String::from("red"), String::from("NEW")],
+				vec![String::from("A"), String::from("blue"), String::from("MERGE")],
+			])
+		);
+
+		assert_eq!(
+			run(
+				&[
+					String::from("B"),
+					String::from(""),
+					String::from(""),
+					String::from(""),
+					String::from("")
+				],
+				&output_config,
+				&mut Context::new(),
+			),
+			Ok(vec![vec![String::from("B"), String::from("NEW"), String::from("")]])
+		);
+
+		assert_eq!(
+			run(&[String::from("C"), String::from("x"), String::from("y")], &output_config, &mut Context::new()),
+			Err(Error::new(ErrorStages::Process, String::from("Cell not found \"<cell4>\"")).at(5, 1))
+		);
+
+		assert_eq!(
+			run(
+				&[String::from("A")],
+				&OutputConfig::new(CsvParser::new(Cursor::new("H1\n<each>\n"))).unwrap(),
+				&mut Context::new()
+			)
+			.map_err(|error| (error.stage, error.row, error.column)),
+			Err((ErrorStages::Process, Some(2), Some(1)))
+		);
+	}
+
+	#[test]
+	fn run_calc_test() {
+		let output_config = OutputConfig::new(CsvParser::new(Cursor::new(
+			"SKU,Price,Compare At Price,Weight\n<cell1>,:CALC <cell2>,\":CALC ROUND(<cell2> * 1.1, 2)\",\":IF <cell3> IS_EMPTY ('') ELSE (:CALC <cell3> / 1000)\"\n",
+		)))
+		.unwrap();
+
+		assert_eq!(
+			run(&[String::from("A"), String::from("19.99"), String::from("250")], &output_config, &mut Context::new()),
+			Ok(vec![vec![
+				String::from("A"),
+				String::from("19.99"),
+				String::from("21.99"),
+				String::from("0.25")
+			]])
+		);
+		assert_eq!(
+			run(&[String::from("B"), String::from("n/a"), String::from("")], &output_config, &mut Context::new()),
+			Err(
+				Error::new(
+					ErrorStages::ConfigCalcEvaluating,
+					String::from("The value of <cell2> is not a number, was \"n/a\"")
+				)
+				.at(2, 2)
+			)
+		);
+	}
+
+	#[test]
+	fn run_context_test() {
+		let output_config = OutputConfig::new(CsvParser::new(Cursor::new(
+			"Handle,Command,Option1 Value,Previous
+<cell1 HANDLEIZE>,:IF <cell1> IS_FIRST_IN_GROUP ('NEW') ELSE ('MERGE'),<cell2>,<prev2>
+",
+		)))
+		.unwrap();
+		let mut context = Context::new();
+		let mut run =
+			|cells: [&str; 2]| run(&cells.map(String::from), &output_config, &mut context).map(|mut lines| lines.remove(0));
+
+		assert_eq!(
+			run(["Shirt", "S"]),
+			Ok(vec![
+				String::from("shirt"),
+				String::from("NEW"),
+				String::from("S"),
+				String::new()
+			])
+		);
+		assert_eq!(
+			run(["Shirt", "M"]),
+			Ok(vec![
+				String::from("shirt"),
+				String::from("MERGE"),
+				String::from("M"),
+				String::from("S")
+			])
+		);
+		assert_eq!(
+			run(["Pants", "M"]),
+			Ok(vec![
+				String::from("pants"),
+				String::from("NEW"),
+				String::from("M"),
+				String::from("M")
+			])
+		);
+		assert_eq!(context.previous(), Some([String::from("Pants"), String::from("M")].as_slice()));
+		assert_eq!(Context::after(vec![String::from("Pants")]).previous(), Some([String::from("Pants")].as_slice()));
+
+		// the elements of an :EACH directive see the previous row too
+		let output_config =
+			OutputConfig::new(CsvParser::new(Cursor::new("Handle,Size\n:EACH <prev2> BY '|'\n<cell1>,<each>\n"))).unwrap();
+		let previous = [String::from("Shirt"), String::from("S|M")];
+		assert_eq!(
+			run_after(&[String::from("Shirt"), String::from("L")], Some(&previous), &output_config),
+			Ok(vec![
+				vec![String::from("Shirt"), String::from("S")],
+				vec![String::from("Shirt"), String::from("M")],
+			])
+		);
+	}
+
+	#[test]
+	fn run_where_test() {
+		let output_config = OutputConfig::new(CsvParser::new(Cursor::new(
+			"H1,H2\n:WHERE <cell2> > 0\n<cell1>,NEW\n:EACH <cell3> BY '|'\n:WHERE NOT <each> ENDS_WITH|'.gif'\n<cell1>,<each>\n<cell1>,:IF <cell2> == '0' ('zero') ELSE (<cell4>)\n",
+		)))
+		.unwrap();
+
+		assert_eq!(
+			run(
+				&[
+					String::from("A"),
+					String::from("5"),
+					String::from("a.jpg|b.gif|c.png"),
+					String::from("SKIP_THIS_LINE")
+				],
+				&output_config,
+				&mut Context::new(),
+			),
+			Ok(vec![
+				vec![String::from("A"), String::from("NEW")],
+				vec![String::from("A"), String::from("a.jpg")],
+				vec![String::from("A"), String::from("c.png")],
+				vec![String::from("A"), String::from("SKIP_THIS_LINE")],
+			])
+		);
+
+		assert_eq!(
+			run(
+				&[String::from("B"), String::from("0"), String::from(""), String::from("")],
+				&output_config,
+				&mut Context::new()
+			),
+			Ok(vec![vec![String::from("B"), String::from("zero")]])
+		);
+
+		assert_eq!(
+			run(
+				&[
+					String::from("C"),
+					String::from("many"),
+					String::from(""),
+					String::from("")
+				],
+				&output_config,
+				&mut Context::new(),
+			)
+			.map_err(|error| (error.stage, error.row, error.column)),
+			Err((ErrorStages::ConfigConditionEvaluating, Some(2), Some(1)))
 		);
 	}
 }
@@ -0,0 +1,999 @@
+//! A small regular expression engine for the `REGEX_REPLACE` and `REGEX_EXTRACT` filters and the `MATCHES` condition
+//!
+//! Patterns are compiled into a program for a backtracking machine that remembers which states already failed, so
+//! matching takes at most as many steps as the length of the text times the length of the program. When the table of
+//! those states would grow too large the threads of the machine run side by side instead, which takes as many steps but
+//! only needs memory for the program.
+//!
+//! Supported are literals, `.`, classes like `[a-z_]` or `[^0-9]`, `\d`, `\w`, `\s` and their negations `\D`, `\W`,
+//! `\S`, the anchors `^`, `$`, `\b` and `\B`, groups `(...)` and `(?:...)`, alternation `|`, the quantifiers `*`, `+`,
+//! `?`, `{n}`, `{n,}` and `{n,m}` with their lazy versions like `*?` and a leading `(?i)` to ignore case.
+
+use std::{cell::RefCell, mem};
+
+/// The most a single quantifier can repeat
+const MAX_REPEAT: u32 = 1000;
+
+/// The most instructions a compiled pattern can have
+const MAX_PROGRAM: usize = 20_000;
+
+/// The most states a backtracking search remembers, the bits take 512KB
+const MAX_VISITED: usize = 1 << 22;
+
+/// A compiled regular expression
+///
+/// ```rust
+/// use csv_converter::regex::Regex;
+///
+/// let regex = Regex::new(r"\((S|M|X*L)\)").unwrap();
+/// assert_eq!(regex.extract("T-Shirt (XL) Blue", 1), Some("XL"));
+/// assert_eq!(regex.replace_all("T-Shirt (XL) Blue", "[$1]"), "T-Shirt [XL] Blue");
+/// assert!(Regex::new("(?i)^sku-\\d{4}$").unwrap().is_match("SKU-0042"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Regex {
+	pattern: String,
+	program: Vec<Inst>,
+	groups: usize,
+	case_insensitive: bool,
+}
+
+/// Two regular expressions are equal when they are written the same
+impl PartialEq for Regex {
+	fn eq(&self, other: &Self) -> bool {
+		self.pattern == other.pattern
+	}
+}
+
+impl std::fmt::Display for Regex {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.pattern)
+	}
+}
+
+impl Regex {
+	/// Compile a pattern, the error describes what is wrong with it
+	pub fn new(pattern: &str) -> Result<Self, String> {
+		let (case_insensitive, body) = match pattern.strip_prefix("(?i)") {
+			Some(body) => (true, body),
+			None => (false, pattern),
+		};
+
+		let mut parser = Parser {
+			chars: body.chars().collect(),
+			position: 0,
+			groups: 0,
+		};
+		let node = parser.alternation().map_err(|message| invalid(pattern, &message))?;
+		if parser.position < parser.chars.len() {
+			return Err(invalid(pattern, "unmatched \")\""));
+		}
+
+		let mut program = Vec::new();
+		compile(&node, &mut program).map_err(|message| invalid(pattern, &message))?;
+		program.push(Inst::Match);
+
+		Ok(Self {
+			pattern: pattern.to_string(),
+			program,
+			groups: parser.groups,
+			case_insensitive,
+		})
+	}
+
+	/// The pattern as it was written
+	pub fn as_str(&self) -> &str {
+		&self.pattern
+	}
+
+	/// The number of capturing groups, not counting the whole match
+	pub fn groups(&self) -> usize {
+		self.groups
+	}
+
+	/// Whether the pattern matches anywhere within the text
+	pub fn is_match(&self, text: &str) -> bool {
+		self.captures(text).is_some()
+	}
+
+	/// The byte ranges of the whole first match and of each of its groups, groups that didn't take part are `None`
+	pub fn captures(&self, text: &str) -> Option<Vec<Option<(usize, usize)>>> {
+		let input = Input::new(text);
+		let slots = MEMORY.with_borrow_mut(|memory| self.find_at(&input, 0, memory))?;
+		Some(input.ranges(&slots))
+	}
+
+	/// The text of a group of the first match, group 0 being the whole match
+	pub fn extract<'a>(&self, text: &'a str, group: usize) -> Option<&'a str> {
+		let (start, end) = (*self.captures(text)?.get(group)?)?;
+		Some(&text[start..end])
+	}
+
+	/// Replace every match, `$1` or `${1}` in the replacement is the text of a group, `$0` the whole match and `$$`
+	/// a dollar sign
+	pub fn replace_all(&self, text: &str, replacement: &str) -> String {
+		let input = Input::new(text);
+		let mut output = String::with_capacity(text.len());
+		let mut copied = 0;
+		let mut position = 0;
+
+		while position <= input.chars.len() {
+			let Some(slots) = MEMORY.with_borrow_mut(|memory| self.find_at(&input, position, memory)) else {
+				break;
+			};
+			let ranges = input.ranges(&slots);
+			let Some((start, end)) = ranges[0] else {
+				break;
+			};
+			output.push_str(&text[copied..start]);
+			expand(replacement, &ranges, text, &mut output);
+			copied = end;
+
+			let match_end = slots[1].unwrap_or(position);
+			// an empty match moves on by a character so it isn't found again
+			position = if match_end == slots[0].unwrap_or(position) {
+				match_end + 1
+			} else {
+				match_end
+			};
+		}
+
+		output.push_str(&text[copied..]);
+		output
+	}
+
+	/// Search from a character position onwards, returns the character positions of all groups
+	///
+	/// Backtracks while the table of visited states fits into [MAX_VISITED], beyond that the threads run side by side.
+	fn find_at(&self, input: &Input, from: usize, memory: &mut Memory) -> Option<Vec<Option<usize>>> {
+		match self.program.len().checked_mul(input.chars.len() + 1) {
+			Some(states) if states <= MAX_VISITED => self.backtrack(input, from, memory),
+			_ => self.pike(input, from, memory),
+		}
+	}
+
+	/// Search by backtracking, every state of the machine is tried at most once
+	fn backtrack(&self, input: &Input, from: usize, memory: &mut Memory) -> Option<Vec<Option<usize>>> {
+		let length = input.chars.len() + 1;
+		let Memory { visited, stack, .. } = memory;
+		// whether a state failed doesn't depend on where the search started so they are only tried once
+		visited.reset(self.program.len() * length);
+		stack.clear();
+		let mut slots = vec![None; (self.groups + 1) * 2];
+
+		for start in from..length {
+			slots.iter_mut().for_each(|slot| *slot = None);
+			slots[0] = Some(start);
+			stack.push(Job::Explore(0, start));
+
+			while let Some(job) = stack.pop() {
+				let (mut pc, mut position) = match job {
+					Job::Restore(slot, value) => {
+						slots[slot] = value;
+						continue;
+					},
+					Job::Explore(pc, position) => (pc, position),
+				};
+
+				loop {
+					if !visited.insert(pc * length + position) {
+						break;
+					}
+
+					match &self.program[pc] {
+						Inst::Char(_) | Inst::Any | Inst::Class(_) => {
+							if !self.consumes(pc, input, position) {
+								break;
+							}
+							pc += 1;
+							position += 1;
+						},
+						Inst::Start if position == 0 => pc += 1,
+						Inst::End if position == input.chars.len() => pc += 1,
+						Inst::WordBoundary(expected) if input.is_word_boundary(position) == *expected => pc += 1,
+						Inst::Start | Inst::End | Inst::WordBoundary(_) => break,
+						Inst::Split(preferred, other) => {
+							stack.push(Job::Explore(*other, position));
+							pc = *preferred;
+						},
+						Inst::Jump(target) => pc = *target,
+						Inst::Save(slot) => {
+							stack.push(Job::Restore(*slot, slots[*slot]));
+							slots[*slot] = Some(position);
+							pc += 1;
+						},
+						Inst::Match => {
+							slots[1] = Some(position);
+							return Some(slots);
+						},
+					}
+				}
+			}
+		}
+
+		None
+	}
+
+	/// Search with all threads of the machine in lockstep, a thread that reaches an instruction a thread of higher
+	/// priority already reached at the same position is dropped so the memory only depends on the program
+	fn pike(&self, input: &Input, from: usize, memory: &mut Memory) -> Option<Vec<Option<usize>>> {
+		let width = (self.groups + 1) * 2;
+		let Memory {
+			stack, current, next, ..
+		} = memory;
+		current.reset(self.program.len(), width);
+		next.reset(self.program.len(), width);
+		let mut slots = vec![None; width];
+		let mut found = None;
+
+		for position in from..=input.chars.len() {
+			// a thread starting here has the lowest priority and none starts once a match was found
+			if found.is_none() {
+				slots.iter_mut().for_each(|slot| *slot = None);
+				slots[0] = Some(position);
+				self.follow(current, stack, 0, position, input, &mut slots);
+			} else if current.pcs.is_empty() {
+				break;
+			}
+
+			for index in 0..current.pcs.len() {
+				let pc = current.pcs[index];
+				slots.copy_from_slice(current.slots(pc));
+				if let Inst::Match = self.program[pc] {
+					slots[1] = Some(position);
+					found = Some(slots.clone());
+					// the threads after this one have a lower priority
+					break;
+				}
+				if self.consumes(pc, input, position) {
+					self.follow(next, stack, pc + 1, position + 1, input, &mut slots);
+				}
+			}
+
+			mem::swap(current, next);
+			next.clear();
+		}
+
+		found
+	}
+
+	/// Add a thread and every thread it reaches without consuming a character
+	fn follow(
+		&self,
+		threads: &mut Threads,
+		stack: &mut Vec<Job>,
+		pc: usize,
+		position: usize,
+		input: &Input,
+		slots: &mut [Option<usize>],
+	) {
+		stack.push(Job::Explore(pc, position));
+
+		while let Some(job) = stack.pop() {
+			let mut pc = match job {
+				Job::Restore(slot, value) => {
+					slots[slot] = value;
+					continue;
+				},
+				Job::Explore(pc, _) => pc,
+			};
+
+			while threads.insert(pc) {
+				match &self.program[pc] {
+					Inst::Start if position == 0 => pc += 1,
+					Inst::End if position == input.chars.len() => pc += 1,
+					Inst::WordBoundary(expected) if input.is_word_boundary(position) == *expected => pc += 1,
+					Inst::Start | Inst::End | Inst::WordBoundary(_) => break,
+					Inst::Split(preferred, other) => {
+						stack.push(Job::Explore(*other, position));
+						pc = *preferred;
+					},
+					Inst::Jump(target) => pc = *target,
+					Inst::Save(slot) => {
+						stack.push(Job::Restore(*slot, slots[*slot]));
+						slots[*slot] = Some(position);
+						pc += 1;
+					},
+					Inst::Char(_) | Inst::Any | Inst::Class(_) | Inst::Match => {
+						threads.keep(pc, slots);
+						break;
+					},
+				}
+			}
+		}
+	}
+
+	/// Whether the instruction consumes the character at the position
+	fn consumes(&self, pc: usize, input: &Input, position: usize) -> bool {
+		let Some(&(_, next)) = input.chars.get(position) else {
+			return false;
+		};
+		match &self.program[pc] {
+			Inst::Char(c) => self.same(next, *c),
+			Inst::Any => next != '\n',
+			Inst::Class(class) => class.matches(next, self.case_insensitive),
+			_ => false,
+		}
+	}
+
+	fn same(&self, a: char, b: char) -> bool {
+		a == b || (self.case_insensitive && a.to_lowercase().eq(b.to_lowercase()))
+	}
+}
+
+fn invalid(pattern: &str, message: &str) -> String {
+	format!("Invalid regular expression \"{pattern}\": {message}")
+}
+
+/// Write the replacement for a match with its groups filled in
+fn expand(replacement: &str, ranges: &[Option<(usize, usize)>], text: &str, output: &mut String) {
+	let mut chars = replacement.char_indices().peekable();
+
+	while let Some((i, c)) = chars.next() {
+		if c != '$' {
+			output.push(c);
+			continue;
+		}
+		let rest = &replacement[i + 1..];
+		let (digits, skip) = if let Some(braced) = rest.strip_prefix('{') {
+			match braced.find('}') {
+				Some(end) if end > 0 && braced[..end].bytes().all(|b| b.is_ascii_digit()) => (&braced[..end], end + 2),
+				_ => ("", 0),
+			}
+		} else {
+			let end = rest.bytes().take_while(u8::is_ascii_digit).count();
+			(&rest[..end], end)
+		};
+
+		if rest.starts_with('$') {
+			output.push('$');
+			chars.next();
+		} else if digits.is_empty() {
+			output.push('$');
+		} else {
+			if let Some(Some((start, end))) = digits.parse::<usize>().ok().and_then(|group| ranges.get(group)) {
+				output.push_str(&text[*start..*end]);
+			}
+			for _ in 0..skip {
+				chars.next();
+			}
+		}
+	}
+}
+
+/// The text to match split into characters with their byte offsets
+struct Input<'a> {
+	text: &'a str,
+	chars: Vec<(usize, char)>,
+}
+
+impl<'a> Input<'a> {
+	fn new(text: &'a str) -> Self {
+		Self {
+			text,
+			chars: text.char_indices().collect(),
+		}
+	}
+
+	fn byte(&self, position: usize) -> usize {
+		self.chars.get(position).map_or(self.text.len(), |(byte, _)| *byte)
+	}
+
+	fn ranges(&self, slots: &[Option<usize>]) -> Vec<Option<(usize, usize)>> {
+		slots
+			.chunks(2)
+			.map(|pair| match pair {
+				[Some(start), Some(end)] => Some((self.byte(*start), self.byte(*end))),
+				_ => None,
+			})
+			.collect()
+	}
+
+	fn is_word_boundary(&self, position: usize) -> bool {
+		let before = position > 0 && is_word(self.chars[position - 1].1);
+		let after = self.chars.get(position).is_some_and(|(_, c)| is_word(*c));
+		before != after
+	}
+}
+
+fn is_word(c: char) -> bool {
+	c.is_alphanumeric() || c == '_'
+}
+
+enum Job {
+	Explore(usize, usize),
+	Restore(usize, Option<usize>),
+}
+
+/// The buffers of the searches, every thread keeps its own so a cell doesn't allocate them again
+#[derive(Default)]
+struct Memory {
+	visited: Visited,
+	stack: Vec<Job>,
+	current: Threads,
+	next: Threads,
+}
+
+thread_local! {
+	static MEMORY: RefCell<Memory> = RefCell::default();
+}
+
+/// The states of the machine a search already tried, one bit each
+///
+/// The words that have bits set are remembered so forgetting the states only touches those.
+#[derive(Default)]
+struct Visited {
+	bits: Vec<u64>,
+	set: Vec<usize>,
+}
+
+impl Visited {
+	/// Forget all visited states and make room for the states of a search
+	fn reset(&mut self, states: usize) {
+		for word in self.set.drain(..) {
+			self.bits[word] = 0;
+		}
+		let words = states.div_ceil(64);
+		if self.bits.len() < words {
+			self.bits.resize(words, 0);
+		}
+	}
+
+	/// Mark a state as visited, returns false if it already was
+	fn insert(&mut self, state: usize) -> bool {
+		let (word, bit) = (state / 64, 1 << (state % 64));
+		let bits = &mut self.bits[word];
+		if *bits & bit != 0 {
+			return false;
+		}
+		if *bits == 0 {
+			self.set.push(word);
+		}
+		*bits |= bit;
+		true
+	}
+}
+
+/// The threads of the machine at one position in order of priority
+#[derive(Default)]
+struct Threads {
+	/// Every instruction reached at this position, `sparse` holds where each one is in `dense`
+	dense: Vec<usize>,
+	sparse: Vec<usize>,
+	/// The instructions that wait for a character or match, the groups of their threads are in `slots`
+	pcs: Vec<usize>,
+	slots: Vec<Option<usize>>,
+	width: usize,
+}
+
+impl Threads {
+	fn reset(&mut self, instructions: usize, width: usize) {
+		self.clear();
+		self.sparse.resize(instructions, 0);
+		self.slots.resize(instructions * width, None);
+		self.width = width;
+	}
+
+	fn clear(&mut self) {
+		self.dense.clear();
+		self.pcs.clear();
+	}
+
+	/// Mark an instruction as reached, returns false if it already was
+	fn insert(&mut self, pc: usize) -> bool {
+		let index = self.sparse[pc];
+		if index < self.dense.len() && self.dense[index] == pc {
+			return false;
+		}
+		self.sparse[pc] = self.dense.len();
+		self.dense.push(pc);
+		true
+	}
+
+	fn keep(&mut self, pc: usize, slots: &[Option<usize>]) {
+		self.pcs.push(pc);
+		self.slots[pc * self.width..(pc + 1) * self.width].copy_from_slice(slots);
+	}
+
+	fn slots(&self, pc: usize) -> &[Option<usize>] {
+		&self.slots[pc * self.width..(pc + 1) * self.width]
+	}
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+	Char(char),
+	Any,
+	Class(Class),
+	Start,
+	End,
+	WordBoundary(bool),
+	/// Continue at the first instruction and backtrack to the second one
+	Split(usize, usize),
+	Jump(usize),
+	Save(usize),
+	Match,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+	Char(char),
+	Any,
+	Class(Class),
+	Start,
+	End,
+	WordBoundary(bool),
+	Group(Box<Node>, Option<usize>),
+	Concat(Vec<Node>),
+	Alternate(Vec<Node>),
+	Repeat {
+		node: Box<Node>,
+		min: u32,
+		max: Option<u32>,
+		greedy: bool,
+	},
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+	negated: bool,
+	items: Vec<ClassItem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+	Range(char, char),
+	/// `\d` or with true `\D`
+	Digit(bool),
+	/// `\w` or with true `\W`
+	Word(bool),
+	/// `\s` or with true `\S`
+	Space(bool),
+}
+
+impl Class {
+	fn matches(&self, c: char, case_insensitive: bool) -> bool {
+		let contains = |c: char| {
+			self.items.iter().any(|item| match item {
+				ClassItem::Range(start, end) => (*start..=*end).contains(&c),
+				ClassItem::Digit(negated) => c.is_ascii_digit() != *negated,
+				ClassItem::Word(negated) => is_word(c) != *negated,
+				ClassItem::Space(negated) => c.is_whitespace() != *negated,
+			})
+		};
+		let found = contains(c) || (case_insensitive && (c.to_lowercase().any(contains) || c.to_uppercase().any(contains)));
+		found != self.negated
+	}
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) -> Result<(), String> {
+	if program.len() > MAX_PROGRAM {
+		return Err(String::from("the pattern is too large"));
+	}
+
+	match node {
+		Node::Char(c) => program.push(Inst::Char(*c)),
+		Node::Any => program.push(Inst::Any),
+		Node::Class(class) => program.push(Inst::Class(class.clone())),
+		Node::Start => program.push(Inst::Start),
+		Node::End => program.push(Inst::End),
+		Node::WordBoundary(expected) => program.push(Inst::WordBoundary(*expected)),
+		Node::Group(node, None) => compile(node, program)?,
+		Node::Group(node, Some(index)) => {
+			program.push(Inst::Save(index * 2));
+			compile(node, program)?;
+			program.push(Inst::Save(index * 2 + 1));
+		},
+		Node::Concat(nodes) => {
+			for node in nodes {
+				compile(node, program)?;
+			}
+		},
+		Node::Alternate(branches) => {
+			let mut jumps = Vec::new();
+			for (index, branch) in branches.iter().enumerate() {
+				if index + 1 == branches.len() {
+					compile(branch, program)?;
+				} else {
+					let split = program.len();
+					program.push(Inst::Split(split + 1, 0));
+					compile(branch, program)?;
+					jumps.push(program.len());
+					program.push(Inst::Jump(0));
+					program[split] = Inst::Split(split + 1, program.len());
+				}
+			}
+			for jump in jumps {
+				program[jump] = Inst::Jump(program.len());
+			}
+		},
+		Node::Repeat { node, min, max, greedy } => {
+			for _ in 0..*min {
+				compile(node, program)?;
+			}
+			let split = |body: usize, exit: usize| {
+				if *greedy {
+					Inst::Split(body, exit)
+				} else {
+					Inst::Split(exit, body)
+				}
+			};
+			match max {
+				None => {
+					let start = program.len();
+					program.push(Inst::Jump(0));
+					compile(node, program)?;
+					program.push(Inst::Jump(start));
+					program[start] = split(start + 1, program.len());
+				},
+				Some(max) => {
+					let mut splits = Vec::new();
+					for _ in *min..*max {
+						splits.push(program.len());
+						program.push(Inst::Jump(0));
+						compile(node, program)?;
+					}
+					for start in splits {
+						program[start] = split(start + 1, program.len());
+					}
+				},
+			}
+		},
+	}
+
+	Ok(())
+}
+
+/// A recursive descent parser for patterns
+///
+/// ```text
+/// alternation := concat { '|' concat }
+/// concat      := { quantified }
+/// quantified  := atom [ ( '*' | '+' | '?' | '{' n [ ',' [ m ] ] '}' ) [ '?' ] ]
+/// atom        := char | '.' | '^' | '$' | escape | class | '(' [ '?:' ] alternation ')'
+/// ```
+struct Parser {
+	chars: Vec<char>,
+	position: usize,
+	groups: usize,
+}
+
+impl Parser {
+	fn peek(&self) -> Option<char> {
+		self.chars.get(self.position).copied()
+	}
+
+	fn next(&mut self) -> Option<char> {
+		let c = self.peek()?;
+		self.position += 1;
+		Some(c)
+	}
+
+	fn eat(&mut self, c: char) -> bool {
+		if self.peek() == Some(c) {
+			self.position += 1;
+			true
+		} else {
+			false
+		}
+	}
+
+	fn alternation(&mut self) -> Result<Node, String> {
+		let mut branches = vec![self.concat()?];
+		while self.eat('|') {
+			branches.push(self.concat()?);
+		}
+		Ok(match branches.len() {
+			1 => branches.remove(0),
+			_ => Node::Alternate(branches),
+		})
+	}
+
+	fn concat(&mut self) -> Result<Node, String> {
+		let mut nodes = Vec::new();
+		while let Some(c) = self.peek() {
+			if c == '|' || c == ')' {
+				break;
+			}
+			let atom = self.atom()?;
+			nodes.push(self.quantified(atom)?);
+		}
+		Ok(Node::Concat(nodes))
+	}
+
+	fn quantified(&mut self, node: Node) -> Result<Node, String> {
+		let (min, max) = match self.peek() {
+			Some('*') => (0, None),
+			Some('+') => (1, None),
+			Some('?') => (0, Some(1)),
+			Some('{') => match self.braces() {
+				Some(range) => range,
+				None => return Ok(node),
+			},
+			_ => return Ok(node),
+		};
+		// past the quantifier or the closing brace
+		self.position += 1;
+
+		if matches!(node, Node::Start | Node::End | Node::WordBoundary(_)) {
+			return Err(format!("nothing to repeat before position {}", self.position));
+		}
+		if max.is_some_and(|max| max < min) {
+			return Err(format!("the quantifier {{{min},{}}} has its maximum below its minimum", max.unwrap_or_default()));
+		}
+		if min > MAX_REPEAT || max.is_some_and(|max| max > MAX_REPEAT) {
+			return Err(format!("quantifiers can't repeat more than {MAX_REPEAT} times"));
+		}
+		let greedy = !self.eat('?');
+		if matches!(self.peek(), Some('*' | '+' | '?')) {
+			return Err(format!("nothing to repeat at position {}", self.position + 1));
+		}
+
+		Ok(Node::Repeat {
+			node: Box::new(node),
+			min,
+			max,
+			greedy,
+		})
+	}
+
+	/// A `{n}`, `{n,}` or `{n,m}` quantifier up to but not including its closing brace, anything else is taken as
+	/// a literal `{` like most engines do
+	fn braces(&mut self) -> Option<(u32, Option<u32>)> {
+		let start = self.position;
+		let number = |parser: &mut Self| {
+			let digits = parser.chars[parser.position..].iter().take_while(|c| c.is_ascii_digit()).count();
+			let number = parser.chars[parser.position..parser.position + digits].iter().collect::<String>().parse().ok();
+			parser.position += digits;
+			number
+		};
+
+		self.position += 1;
+		let range = number(self).and_then(|min| {
+			if self.eat(',') {
+				match self.peek() {
+					Some('}') => Some((min, None)),
+					_ => number(self).map(|max| (min, Some(max))),
+				}
+			} else {
+				Some((min, Some(min)))
+			}
+		});
+
+		match range {
+			Some(range) if self.peek() == Some('}') => Some(range),
+			_ => {
+				self.position = start;
+				None
+			},
+		}
+	}
+
+	fn atom(&mut self) -> Result<Node, String> {
+		let position = self.position + 1;
+		match self.next() {
+			Some('(') => {
+				let index = if self.peek() == Some('?') {
+					if !(self.eat('?') && self.eat(':')) {
+						return Err(format!("unsupported group at position {position}, only (...) and (?:...) are supported"));
+					}
+					None
+				} else {
+					self.groups += 1;
+					Some(self.groups)
+				};
+				let node = self.alternation()?;
+				if !self.eat(')') {
+					return Err(format!("the group at position {position} is missing its closing \")\""));
+				}
+				Ok(Node::Group(Box::new(node), index))
+			},
+			Some('[') => self.class(),
+			Some('.') => Ok(Node::Any),
+			Some('^') => Ok(Node::Start),
+			Some('$') => Ok(Node::End),
+			Some('\\') => match self.escape()? {
+				Escape::Char(c) => Ok(Node::Char(c)),
+				Escape::Class(item) => Ok(Node::Class(Class {
+					negated: false,
+					items: vec![item],
+				})),
+				Escape::WordBoundary(expected) => Ok(Node::WordBoundary(expected)),
+			},
+			Some('*' | '+' | '?') => Err(format!("nothing to repeat at position {position}")),
+			Some(c) => Ok(Node::Char(c)),
+			None => Err(String::from("the pattern ended unexpectedly")),
+		}
+	}
+
+	fn escape(&mut self) -> Result<Escape, String> {
+		match self.next() {
+			Some('d') => Ok(Escape::Class(ClassItem::Digit(false))),
+			Some('D') => Ok(Escape::Class(ClassItem::Digit(true))),
+			Some('w') => Ok(Escape::Class(ClassItem::Word(false))),
+			Some('W') => Ok(Escape::Class(ClassItem::Word(true))),
+			Some('s') => Ok(Escape::Class(ClassItem::Space(false))),
+			Some('S') => Ok(Escape::Class(ClassItem::Space(true))),
+			Some('b') => Ok(Escape::WordBoundary(true)),
+			Some('B') => Ok(Escape::WordBoundary(false)),
+			Some('n') => Ok(Escape::Char('\n')),
+			Some('r') => Ok(Escape::Char('\r')),
+			Some('t') => Ok(Escape::Char('\t')),
+			Some(c) if !c.is_alphanumeric() => Ok(Escape::Char(c)),
+			Some(c) => Err(format!("unknown escape \"\\{c}\" at position {}", self.position - 1)),
+			None => Err(String::from("the pattern ends with a \"\\\"")),
+		}
+	}
+
+	fn class(&mut self) -> Result<Node, String> {
+		let position = self.position;
+		let negated = self.eat('^');
+		let mut items = Vec::new();
+		let mut first = true;
+
+		loop {
+			let start = match self.next() {
+				None => return Err(format!("the class at position {position} is missing its closing \"]\"")),
+				Some(']') if !first => break,
+				Some('\\') => match self.escape()? {
+					Escape::Char(c) => c,
+					Escape::Class(item) => {
+						items.push(item);
+						first = false;
+						continue;
+					},
+					Escape::WordBoundary(_) => return Err(String::from("\\b and \\B can't be used within a class")),
+				},
+				Some(c) => c,
+			};
+			first = false;
+
+			if self.peek() == Some('-') && !matches!(self.chars.get(self.position + 1), None | Some(']')) {
+				self.position += 1;
+				let end = match self.next() {
+					Some('\\') => match self.escape()? {
+						Escape::Char(c) => c,
+						_ => return Err(format!("the range at position {position} has to end with a character")),
+					},
+					Some(c) => c,
+					None => unreachable!("checked above"),
+				};
+				if end < start {
+					return Err(format!("the range {start}-{end} is out of order"));
+				}
+				items.push(ClassItem::Range(start, end));
+			} else {
+				items.push(ClassItem::Range(start, start));
+			}
+		}
+
+		Ok(Node::Class(Class { negated, items }))
+	}
+}
+
+enum Escape {
+	Char(char),
+	Class(ClassItem),
+	WordBoundary(bool),
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn find(pattern: &str, text: &str) -> Option<String> {
+		let regex = Regex::new(pattern).unwrap();
+		// running the threads side by side must find the same match as backtracking
+		let input = Input::new(text);
+		let pike = regex.pike(&input, 0, &mut Memory::default()).map(|slots| input.ranges(&slots));
+		assert_eq!(regex.captures(text), pike, "{pattern}");
+		regex.extract(text, 0).map(String::from)
+	}
+
+	#[test]
+	fn match_test() {
+		assert_eq!(find("abc", "xxabcxx"), Some(String::from("abc")));
+		assert_eq!(find("a.c", "a\nc abc"), Some(String::from("abc")));
+		assert_eq!(find("^abc", "xabc"), None);
+		assert_eq!(find("abc$", "abcx abc"), Some(String::from("abc")));
+		assert_eq!(find("colou?r", "color"), Some(String::from("color")));
+		assert_eq!(find("a+", "baaab"), Some(String::from("aaa")));
+		assert_eq!(find("a+?", "baaab"), Some(String::from("a")));
+		assert_eq!(find("<.*>", "<a><b>"), Some(String::from("<a><b>")));
+		assert_eq!(find("<.*?>", "<a><b>"), Some(String::from("<a>")));
+		assert_eq!(find("\\d{3}", "ab12345"), Some(String::from("123")));
+		assert_eq!(find("\\d{2,}", "1 22 333"), Some(String::from("22")));
+		assert_eq!(find("\\d{2,3}", "12345"), Some(String::from("123")));
+		assert_eq!(find("x{2}", "x{2}"), None);
+		assert_eq!(find("x{a}", "x{a}"), Some(String::from("x{a}")));
+		assert_eq!(find("[a-c]+", "xxcabd"), Some(String::from("cab")));
+		assert_eq!(find("[^a-c ]+", "abc def"), Some(String::from("def")));
+		assert_eq!(find("[]a-]+", "x]-a]"), Some(String::from("]-a]")));
+		assert_eq!(find("[\\d.,]+", "EUR 1.234,50"), Some(String::from("1.234,50")));
+		assert_eq!(find("\\w+", "  größe_1 "), Some(String::from("größe_1")));
+		assert_eq!(find("\\s+\\S", "a  \tb"), Some(String::from("  \tb")));
+		assert_eq!(find("\\bcat\\b", "concat cat"), Some(String::from("cat")));
+		assert_eq!(find("\\Bcat", "cat concat"), Some(String::from("cat")));
+		assert_eq!(find("S|M|XL", "size XL"), Some(String::from("XL")));
+		assert_eq!(find("(?:ab)+", "ababa"), Some(String::from("abab")));
+		assert_eq!(find("(?i)t-shirt", "T-SHIRT"), Some(String::from("T-SHIRT")));
+		assert_eq!(find("(?i)[a-z]+", "ÄbC"), Some(String::from("bC")));
+		assert_eq!(find("\\(\\$\\)", "($)"), Some(String::from("($)")));
+		assert_eq!(find("", "abc"), Some(String::from("")));
+		assert_eq!(find("x*", "abc"), Some(String::from("")));
+		assert_eq!(find("😬+", "a😬😬b"), Some(String::from("😬😬")));
+
+		// would take forever with plain backtracking
+		let text = "a".repeat(5000);
+		assert!(!Regex::new("(a*)*b").unwrap().is_match(&text));
+		assert!(!Regex::new("(a|aa)+$").unwrap().is_match(&format!("{text}!")));
+
+		// every match searches the same input again so the searches must not start from scratch
+		let text = "a1 ".repeat(20_000);
+		let started = std::time::Instant::now();
+		assert_eq!(Regex::new("\\d").unwrap().replace_all(&text, "#"), "a# ".repeat(20_000));
+		assert!(started.elapsed() < std::time::Duration::from_secs(2), "took {:?}", started.elapsed());
+	}
+
+	#[test]
+	fn captures_test() {
+		let regex = Regex::new("(\\d+)\\s*(cm|mm)?").unwrap();
+		assert_eq!(regex.groups(), 2);
+		assert_eq!(regex.captures("size 42 cm"), Some(vec![Some((5, 10)), Some((5, 7)), Some((8, 10))]));
+		assert_eq!(regex.captures("size 42"), Some(vec![Some((5, 7)), Some((5, 7)), None]));
+		assert_eq!(regex.captures("no size"), None);
+		assert_eq!(regex.extract("T-Shirt 42mm", 2), Some("mm"));
+		assert_eq!(regex.extract("T-Shirt 42", 2), None);
+		assert_eq!(regex.extract("T-Shirt 42", 3), None);
+
+		assert_eq!(Regex::new("(a)|(b)").unwrap().captures("b"), Some(vec![Some((0, 1)), None, Some((0, 1))]));
+		assert_eq!(Regex::new("(a)+").unwrap().extract("aaa", 1), Some("a"));
+		assert_eq!(Regex::new("(\\w)(\\w)?").unwrap().extract("ü", 1), Some("ü"));
+	}
+
+	#[test]
+	fn large_input_test() {
+		// remembering the visited states would take gigabytes so the threads run side by side
+		let regex = Regex::new("\\w{1,1000}").unwrap();
+		let text = "ab ".repeat(70_000);
+		assert!(regex.program.len() * text.len() > MAX_VISITED);
+		assert_eq!(regex.replace_all(&text, "y"), "y ".repeat(70_000));
+		assert_eq!(regex.replace_all(&"a".repeat(5000), "y"), "yyyyy");
+		assert_eq!(Regex::new("(a|ab)(c|bcd)").unwrap().captures(&"x".repeat(5000)), None);
+		assert_eq!(Regex::new("(\\d+)-(\\d+)").unwrap().extract(&format!("{}12-345", " ".repeat(100_000)), 2), Some("345"));
+		MEMORY.with_borrow(|memory| assert!(memory.visited.bits.len() <= MAX_VISITED / 64));
+	}
+
+	#[test]
+	fn replace_all_test() {
+		let regex = Regex::new("(\\d+),(\\d+)").unwrap();
+		assert_eq!(regex.replace_all("1,5 and 20,25", "$1.$2"), "1.5 and 20.25");
+		assert_eq!(regex.replace_all("1,5", "${1}0 $$ $0 $3 $x"), "10 $ 1,5  $x");
+		assert_eq!(Regex::new("\\s+").unwrap().replace_all("  a  b ", " "), " a b ");
+		assert_eq!(Regex::new("x*").unwrap().replace_all("abc", "-"), "-a-b-c-");
+		assert_eq!(Regex::new("b*").unwrap().replace_all("abbc", "-"), "-a--c-");
+		assert_eq!(Regex::new("ö").unwrap().replace_all("schön grün", "oe"), "schoen grün");
+		assert_eq!(Regex::new("z").unwrap().replace_all("abc", "-"), "abc");
+	}
+
+	#[test]
+	fn error_test() {
+		for (pattern, message) in [
+			("(ab", "the group at position 1 is missing its closing \")\""),
+			("ab)", "unmatched \")\""),
+			("[ab", "the class at position 1 is missing its closing \"]\""),
+			("*a", "nothing to repeat at position 1"),
+			("a**", "nothing to repeat at position 3"),
+			("^*", "nothing to repeat before position 2"),
+			("a{3,1}", "the quantifier {3,1} has its maximum below its minimum"),
+			("a{1001}", "quantifiers can't repeat more than 1000 times"),
+			("(a{1000}){1000}", "the pattern is too large"),
+			("[z-a]", "the range z-a is out of order"),
+			("\\q", "unknown escape \"\\q\" at position 1"),
+			("ab\\", "the pattern ends with a \"\\\""),
+			("(?=a)", "unsupported group at position 1, only (...) and (?:...) are supported"),
+		] {
+			assert_eq!(Regex::new(pattern), Err(format!("Invalid regular expression \"{pattern}\": {message}")), "{pattern}");
+		}
+	}
+}
@@ -0,0 +1,432 @@
+use std::{
+	cmp::Ordering,
+	fs::{self, File},
+	io::{BufReader, BufWriter, Write},
+	mem,
+	path::PathBuf,
+};
+
+use crate::{
+	cli::{Error, ErrorStages},
+	config::Item,
+	csv::{export_with_dialect, CsvParser, Dialect, Record},
+	dedupe::{io_error, line_size, TempDir},
+};
+
+/// The number of sorted runs merged at once, more runs are merged in several passes to keep the number of open files
+/// low
+const MERGE_WIDTH: usize = 64;
+
+/// A column of the output to sort by
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+	/// The index of the column starting at 0
+	pub column: usize,
+	/// Compare the values as numbers instead of text, numbers come before values that aren't numbers
+	pub numeric: bool,
+	/// Sort from the largest to the smallest value
+	pub descending: bool,
+}
+
+impl SortKey {
+	/// The parser for the columns of `--sort-by`, they are resolved against the heading of the output
+	///
+	/// ```rust
+	/// use csv_converter::sort::SortKey;
+	///
+	/// let heading = vec![String::from("Handle"), String::from("Position")];
+	/// assert_eq!(
+	///     SortKey::parse_keys("<col 'Handle'> <cell2> NUMERIC DESC", &heading),
+	///     Ok(vec![
+	///         SortKey { column: 0, numeric: false, descending: false },
+	///         SortKey { column: 1, numeric: true, descending: true },
+	///     ])
+	/// );
+	/// ```
+	pub fn parse_keys(sort_str: &str, heading: &[String]) -> Result<Vec<Self>, Error> {
+		let error = |message: String| {
+			Error::new(
+				ErrorStages::Cli,
+				format!(
+					"{message}, was \"{sort_str}\"\n\
+					Usage: <cell[x]> [NUMERIC optional] [ASC|DESC optional] [more columns optional]\n\
+					Examples:\n\
+					<cell1>\n\
+					<col 'Handle'> <col 'Image Position'> NUMERIC DESC"
+				),
+			)
+		};
+
+		let mut keys: Vec<Self> = Vec::new();
+		let mut rest = sort_str.trim_start();
+		while !rest.is_empty() {
+			if rest.starts_with('<') {
+				let mut in_quotes = false;
+				let end = rest.char_indices().find(|(_, c)| {
+					if *c == '\'' {
+						in_quotes = !in_quotes;
+					}
+					!in_quotes && *c == '>'
+				});
+				let Some((end, _)) = end else {
+					return Err(error(format!("Column \"{rest}\" is missing its closing \">\"")));
+				};
+
+				let mut item = Item::parse(rest[..=end].to_string())?;
+				item.resolve_headers(heading)?;
+				match item {
+					Item::Cell(column, None) if column < heading.len() => keys.push(Self {
+						column,
+						numeric: false,
+						descending: false,
+					}),
+					Item::Cell(_, None) => {
+						return Err(error(format!("Column {item} is not part of the output heading \"{}\"", heading.join(","))))
+					},
+					_ => return Err(error(format!("Only whole columns like <cell1> can be sorted by, found {item}"))),
+				}
+				rest = rest[end + 1..].trim_start();
+				continue;
+			}
+
+			let word = rest.split_whitespace().next().unwrap_or_default();
+			let Some(key) = keys.last_mut() else {
+				return Err(error(format!("Expected a column but found \"{word}\"")));
+			};
+			match word {
+				"NUMERIC" => key.numeric = true,
+				"ASC" => key.descending = false,
+				"DESC" => key.descending = true,
+				_ => return Err(error(format!("Expected a column, NUMERIC, ASC or DESC but found \"{word}\""))),
+			}
+			rest = rest[word.len()..].trim_start();
+		}
+
+		if keys.is_empty() {
+			return Err(error(String::from("At least one column is needed to sort by")));
+		}
+		Ok(keys)
+	}
+}
+
+/// Compare two lines by the given keys, the first key that tells them apart decides
+pub fn compare_lines(a: &[String], b: &[String], keys: &[SortKey]) -> Ordering {
+	for key in keys {
+		let a = a.get(key.column).map_or("", String::as_str);
+		let b = b.get(key.column).map_or("", String::as_str);
+		let ordering = if key.numeric { compare_numbers(a, b) } else { a.cmp(b) };
+		let ordering = if key.descending { ordering.reverse() } else { ordering };
+		if ordering != Ordering::Equal {
+			return ordering;
+		}
+	}
+	Ordering::Equal
+}
+
+fn compare_numbers(a: &str, b: &str) -> Ordering {
+	let parse = |value: &str| value.trim().parse::<f64>().ok().filter(|number| number.is_finite());
+	match (parse(a), parse(b)) {
+		(Some(a), Some(b)) => a.total_cmp(&b),
+		(Some(_), None) => Ordering::Less,
+		(None, Some(_)) => Ordering::Greater,
+		(None, None) => a.cmp(b),
+	}
+}
+
+/// Sorts lines by [SortKey]s with a stable external merge sort
+///
+/// Push every line with [Sorter::push] then call [Sorter::finish] to get them back in order.
+/// The lines are held in memory until they take more than the memory budget, then they are sorted and written to a
+/// temporary file as a run. Lines that compare equal keep the order they were pushed in.
+///
+/// ```rust
+/// use csv_converter::sort::{SortKey, Sorter};
+///
+/// let mut sorter = Sorter::new(vec![SortKey { column: 0, numeric: true, descending: false }], 1024 * 1024);
+/// for value in ["10", "9", "100"] {
+///     sorter.push(vec![String::from(value)]).unwrap();
+/// }
+/// let mut output = Vec::new();
+/// sorter.finish(|line| Ok(output.push(line[0].clone()))).unwrap();
+/// assert_eq!(output, vec!["9", "10", "100"]);
+/// ```
+#[derive(Debug)]
+pub struct Sorter {
+	keys: Vec<SortKey>,
+	/// The number of bytes the lines may take in memory
+	memory_budget: usize,
+	memory: usize,
+	lines: Vec<Vec<String>>,
+	/// The sorted runs on disk in the order their lines were pushed
+	runs: Vec<PathBuf>,
+	dir: Option<TempDir>,
+	/// The number of temporary files created so far which is used to name the next one
+	files: usize,
+}
+
+impl Sorter {
+	/// A sorter that writes runs to disk once its lines take more than the memory budget in bytes
+	pub fn new(keys: Vec<SortKey>, memory_budget: usize) -> Self {
+		Self {
+			keys,
+			memory_budget,
+			memory: 0,
+			lines: Vec::new(),
+			runs: Vec::new(),
+			dir: None,
+			files: 0,
+		}
+	}
+
+	/// Add the next line
+	pub fn push(&mut self, line: Vec<String>) -> Result<(), Error> {
+		self.memory += line_size(&line);
+		self.lines.push(line);
+		if self.memory > self.memory_budget {
+			self.write_run()?;
+		}
+		Ok(())
+	}
+
+	/// Emit all lines in order
+	pub fn finish(mut self, mut emit: impl FnMut(Vec<String>) -> Result<(), Error>) -> Result<(), Error> {
+		if self.runs.is_empty() {
+			let keys = &self.keys;
+			self.lines.sort_by(|a, b| compare_lines(a, b, keys));
+			for line in self.lines {
+				emit(line)?;
+			}
+			return Ok(());
+		}
+
+		if !self.lines.is_empty() {
+			self.write_run()?;
+		}
+		// neighbouring runs are merged so the runs stay in the order their lines were pushed which keeps the sort stable
+		while self.runs.len() > MERGE_WIDTH {
+			for runs in mem::take(&mut self.runs).chunks(MERGE_WIDTH) {
+				let mut run = RunWriter::create(self.next_path()?)?;
+				merge_runs(runs, &self.keys, |line| run.write(line))?;
+				self.runs.push(run.finish()?);
+			}
+		}
+		merge_runs(&self.runs, &self.keys, emit)
+	}
+
+	/// Sort the lines held in memory and write them to a new run
+	fn write_run(&mut self) -> Result<(), Error> {
+		let keys = &self.keys;
+		self.lines.sort_by(|a, b| compare_lines(a, b, keys));
+
+		let mut run = RunWriter::create(self.next_path()?)?;
+		for line in self.lines.drain(..) {
+			run.write(line)?;
+		}
+		self.runs.push(run.finish()?);
+		self.memory = 0;
+		Ok(())
+	}
+
+	fn next_path(&mut self) -> Result<PathBuf, Error> {
+		let dir = match &self.dir {
+			Some(dir) => dir,
+			None => self.dir.insert(TempDir::new("sort")?),
+		};
+		self.files += 1;
+		Ok(dir.join(format!("{}.csv", self.files)))
+	}
+}
+
+/// A temporary file with a sorted run of lines
+struct RunWriter {
+	path: PathBuf,
+	file: BufWriter<File>,
+	buffer: String,
+}
+
+impl RunWriter {
+	fn create(path: PathBuf) -> Result<Self, Error> {
+		let file = BufWriter::new(File::create(&path).map_err(|error| io_error(&path, error))?);
+		Ok(Self {
+			path,
+			file,
+			buffer: String::new(),
+		})
+	}
+
+	fn write(&mut self, line: Vec<String>) -> Result<(), Error> {
+		export_with_dialect(&[line], &mut self.buffer, &Dialect::default());
+		self.file.write_all(self.buffer.as_bytes()).map_err(|error| io_error(&self.path, error))
+	}
+
+	fn finish(mut self) -> Result<PathBuf, Error> {
+		self.file.flush().map_err(|error| io_error(&self.path, error))?;
+		Ok(self.path)
+	}
+}
+
+/// Merge sorted runs into one sorted stream of lines and remove their files, on equal lines the earlier run goes first
+fn merge_runs(
+	runs: &[PathBuf],
+	keys: &[SortKey],
+	mut emit: impl FnMut(Vec<String>) -> Result<(), Error>,
+) -> Result<(), Error> {
+	let mut readers = Vec::with_capacity(runs.len());
+	for path in runs {
+		readers.push(CsvParser::new(BufReader::new(File::open(path).map_err(|error| io_error(path, error))?)));
+	}
+	let mut record = Record::default();
+	let mut heads = Vec::with_capacity(runs.len());
+	for reader in readers.iter_mut() {
+		heads.push(reader.read_record(&mut record)?.then(|| record.to_vec()));
+	}
+
+	loop {
+		let mut next: Option<usize> = None;
+		for (index, head) in heads.iter().enumerate() {
+			let Some(head) = head else {
+				continue;
+			};
+			// only a strictly smaller line replaces the current one so equal lines come out in the order of the runs
+			if next.and_then(|next| heads[next].as_ref()).is_none_or(|best| compare_lines(head, best, keys).is_lt()) {
+				next = Some(index);
+			}
+		}
+		let Some(index) = next else {
+			break;
+		};
+
+		let line = heads[index].take().unwrap_or_default();
+		heads[index] = readers[index].read_record(&mut record)?.then(|| record.to_vec());
+		emit(line)?;
+	}
+
+	for path in runs {
+		fs::remove_file(path).map_err(|error| io_error(path, error))?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::csv::rows;
+
+	fn sort(keys: &[SortKey], memory_budget: usize, lines: &[&[&str]]) -> Vec<Vec<String>> {
+		let mut sorter = Sorter::new(keys.to_vec(), memory_budget);
+		for line in lines {
+			sorter.push(line.iter().map(|cell| cell.to_string()).collect()).unwrap();
+		}
+		let mut output = Vec::new();
+		sorter
+			.finish(|line| {
+				output.push(line);
+				Ok(())
+			})
+			.unwrap();
+		output
+	}
+
+	fn key(column: usize, numeric: bool, descending: bool) -> SortKey {
+		SortKey {
+			column,
+			numeric,
+			descending,
+		}
+	}
+
+	#[test]
+	fn sort_test() {
+		let lines: &[&[&str]] = &[
+			&["shirt", "2", "a"],
+			&["mug", "10", "b"],
+			&["shirt", "1", "c"],
+			&["", "x", "d"],
+			&["mug", "9", "e\n\"quoted\""],
+			&["shirt", "2", "f"],
+		];
+
+		// equal lines keep their order whether they were sorted in memory, in a few runs or with one run per line
+		for memory_budget in [1024 * 1024, 300, 0] {
+			assert_eq!(
+				sort(&[key(0, false, false)], memory_budget, lines),
+				rows(&[
+					&["", "x", "d"],
+					&["mug", "10", "b"],
+					&["mug", "9", "e\n\"quoted\""],
+					&["shirt", "2", "a"],
+					&["shirt", "1", "c"],
+					&["shirt", "2", "f"],
+				]),
+				"{memory_budget}"
+			);
+
+			assert_eq!(
+				sort(&[key(0, false, true), key(1, true, false)], memory_budget, lines),
+				rows(&[
+					&["shirt", "1", "c"],
+					&["shirt", "2", "a"],
+					&["shirt", "2", "f"],
+					&["mug", "9", "e\n\"quoted\""],
+					&["mug", "10", "b"],
+					&["", "x", "d"],
+				]),
+				"{memory_budget}"
+			);
+		}
+
+		// numbers come before values that aren't numbers
+		assert_eq!(
+			sort(&[key(0, true, false)], 0, &[&["b"], &["-1.5"], &[""], &["2"], &["a"]]),
+			rows(&[&["-1.5"], &["2"], &[""], &["a"], &["b"]])
+		);
+
+		assert_eq!(sort(&[key(0, false, false)], 0, &[]), Vec::<Vec<String>>::new());
+	}
+
+	#[test]
+	fn sort_many_runs_test() {
+		// more runs than are merged at once need several merge passes
+		let lines = (0..MERGE_WIDTH * 3).map(|index| vec![(index % 7).to_string(), index.to_string()]).collect::<Vec<_>>();
+		let mut expected = lines.clone();
+		expected.sort_by_key(|line| line[0].clone());
+
+		let mut sorter = Sorter::new(vec![key(0, true, false)], 0);
+		for line in lines {
+			sorter.push(line).unwrap();
+		}
+		let mut output = Vec::new();
+		sorter
+			.finish(|line| {
+				output.push(line);
+				Ok(())
+			})
+			.unwrap();
+		assert_eq!(output, expected);
+	}
+
+	#[test]
+	fn parse_keys_test() {
+		let heading = vec![
+			String::from("Handle"),
+			String::from("Image Position"),
+			String::from("Note"),
+		];
+		assert_eq!(
+			SortKey::parse_keys("<col 'Handle'> DESC <col 'Image Position'> NUMERIC <cell3> DESC ASC", &heading),
+			Ok(vec![key(0, false, true), key(1, true, false), key(2, false, false)])
+		);
+
+		for sort_str in [
+			"",
+			"DESC <cell1>",
+			"<cell1> UP",
+			"<cell1",
+			"<cell4>",
+			"<cell1 TRIM>",
+			"<col 'Size'>",
+		] {
+			assert!(SortKey::parse_keys(sort_str, &heading).is_err(), "{sort_str}");
+		}
+	}
+}
@@ -1,6 +1,7 @@
 use std::fs;
+use std::io::Write;
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, Stdio};
 
 #[test]
 fn test_csv_converter_end_to_end() {
@@ -32,8 +33,490 @@ fn test_csv_converter_end_to_end() {
 	assert!(Path::new(output_file).exists(), "Output file was not created");
 
 	let actual_output = fs::read_to_string(output_file).expect("Failed to read the output file");
-	let expected_output =
-		fs::read_to_string(expected_output_file).expect("Failed to read the expected output file").replace("\r\n", "\n");
+	// the input uses CRLF line endings and the CR inside the quoted multi-line cell must survive the conversion
+	let expected_output = fs::read_to_string(expected_output_file).expect("Failed to read the expected output file");
 
 	assert_eq!(actual_output, expected_output, "The output does not match the expected output");
 }
+
+#[test]
+fn test_csv_converter_stdin_to_stdout() {
+	let input = fs::read("tests/input.csv").expect("Failed to read the input file");
+	let expected_output =
+		fs::read_to_string("tests/expected_output.csv").expect("Failed to read the expected output file");
+
+	let mut child = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+		.arg("-i")
+		.arg("-")
+		.arg("-o")
+		.arg("-")
+		.arg("-c")
+		.arg("tests/config.csv")
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()
+		.expect("Failed to execute csv_converter");
+	child.stdin.take().expect("Failed to open stdin").write_all(&input).expect("Failed to write to stdin");
+	let output = child.wait_with_output().expect("Failed to wait for csv_converter");
+
+	assert!(
+		output.status.success(),
+		"csv_converter did not run successfully: {}",
+		String::from_utf8_lossy(&output.stderr)
+	);
+	// stdout must only contain the CSV, all status messages go to stderr
+	assert_eq!(String::from_utf8_lossy(&output.stdout), expected_output, "The output does not match the expected output");
+}
+
+#[test]
+fn test_csv_converter_threads_keep_order() {
+	let dir = std::env::temp_dir().join("csv_converter_threads_test");
+	fs::create_dir_all(&dir).expect("Failed to create the temp dir");
+	let input_file = dir.join("input.csv");
+	let config_file = dir.join("config.csv");
+
+	let mut input = String::from("ID,Name\n");
+	let mut expected_output = String::from("Name,ID\n");
+	for i in 0..10_000 {
+		input.push_str(&format!("{i},name {i}\n"));
+		if i % 3 != 0 {
+			expected_output.push_str(&format!("NAME {i},{i}\n"));
+		}
+	}
+	fs::write(&input_file, input).expect("Failed to write the input file");
+	fs::write(&config_file, "Name,ID\n:FILTER NOT <cell1> % 3 = 0\n<cell2 UPPER_CASE>,<cell1>\n")
+		.expect("Failed to write the config file");
+
+	for threads in ["1", "4"] {
+		let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+			.arg("-i")
+			.arg(&input_file)
+			.arg("-o")
+			.arg("-")
+			.arg("-c")
+			.arg(&config_file)
+			.arg("--threads")
+			.arg(threads)
+			.output()
+			.expect("Failed to execute csv_converter");
+
+		assert!(
+			output.status.success(),
+			"csv_converter did not run successfully: {}",
+			String::from_utf8_lossy(&output.stderr)
+		);
+		assert_eq!(
+			String::from_utf8_lossy(&output.stdout),
+			expected_output,
+			"The output with {threads} threads is out of order"
+		);
+	}
+
+	// an error in any batch must stop all threads instead of hanging
+	fs::write(&config_file, "Name,ID\n<cell2>,:IF <cell1> > 9000 (<cell3>)\n").expect("Failed to write the config file");
+	let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+		.arg("-i")
+		.arg(&input_file)
+		.arg("-o")
+		.arg("-")
+		.arg("-c")
+		.arg(&config_file)
+		.arg("--threads")
+		.arg("4")
+		.output()
+		.expect("Failed to execute csv_converter");
+	assert!(!output.status.success(), "csv_converter should fail on a missing cell");
+}
+
+#[test]
+fn test_csv_converter_previous_row_passed_filters() {
+	let dir = std::env::temp_dir().join("csv_converter_previous_test");
+	fs::create_dir_all(&dir).expect("Failed to create the temp dir");
+	let input_file = dir.join("input.csv");
+	let config_file = dir.join("config.csv");
+
+	// the first row of every handle is out of stock so the second one must start the group, handles are zero padded so
+	// sorting by them keeps the input order
+	let mut input = String::from("Handle,Stock\n");
+	let mut expected_output = String::from("Handle,Type\n");
+	for i in 0..3_000 {
+		let handle = i / 3;
+		input.push_str(&format!("handle-{handle:04},{}\n", i % 3));
+		match i % 3 {
+			1 => expected_output.push_str(&format!("handle-{handle:04},NEW\n")),
+			2 => expected_output.push_str(&format!("handle-{handle:04},MERGE\n")),
+			_ => {},
+		}
+	}
+	fs::write(&input_file, input).expect("Failed to write the input file");
+
+	let filter_config =
+		"Handle,Type\n:FILTER <cell2> > 0\n<cell1>,:IF <cell1> IS_FIRST_IN_GROUP ('NEW') ELSE ('MERGE')\n";
+	let where_config = "Handle,Type\n<cell1>,:IF <cell1> IS_FIRST_IN_GROUP ('NEW') ELSE ('MERGE')\n";
+	for (config, args) in [
+		(filter_config, &["--threads", "1"][..]),
+		(filter_config, &["--threads", "4"][..]),
+		(filter_config, &["--sort-by", "<col 'Handle'>"][..]),
+		(where_config, &["--where", "<cell2> > 0"][..]),
+	] {
+		fs::write(&config_file, config).expect("Failed to write the config file");
+		let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+			.arg("-i")
+			.arg(&input_file)
+			.arg("-o")
+			.arg("-")
+			.arg("-c")
+			.arg(&config_file)
+			.args(args)
+			.output()
+			.expect("Failed to execute csv_converter");
+
+		assert!(
+			output.status.success(),
+			"csv_converter did not run successfully: {}",
+			String::from_utf8_lossy(&output.stderr)
+		);
+		assert_eq!(String::from_utf8_lossy(&output.stdout), expected_output, "Wrong groups with {args:?}");
+	}
+}
+
+#[test]
+fn test_csv_converter_where_by_column() {
+	let dir = std::env::temp_dir().join("csv_converter_where_test");
+	fs::create_dir_all(&dir).expect("Failed to create the temp dir");
+	let input_file = dir.join("input.csv");
+	let config_file = dir.join("config.csv");
+	fs::write(&input_file, "Handle,Stock\nmug,0\ncup,2\nbowl,5\n").expect("Failed to write the input file");
+	fs::write(&config_file, "Handle\n<cell1>\n").expect("Failed to write the config file");
+
+	let run = |args: &[&str]| {
+		Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+			.args(args)
+			.arg("-i")
+			.arg(&input_file)
+			.arg("-c")
+			.arg(&config_file)
+			.output()
+			.expect("Failed to execute csv_converter")
+	};
+
+	let output = run(&["-o", "-", "--where", "<col 'Stock'> > 0"]);
+	assert!(output.status.success(), "The conversion failed: {}", String::from_utf8_lossy(&output.stderr));
+	assert_eq!(String::from_utf8_lossy(&output.stdout), "Handle\ncup\nbowl\n");
+
+	let output = run(&["--explain", "2", "--where", "<col 'Stock'> > 0"]);
+	assert!(output.status.success(), "The explain failed: {}", String::from_utf8_lossy(&output.stderr));
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	assert!(stdout.contains("-> false\n=> the row is rejected so no line is emitted"), "{stdout}");
+
+	let output = run(&["check", "--where", "<col 'Stock'> > 0"]);
+	assert!(output.status.success(), "The check failed: {}", String::from_utf8_lossy(&output.stderr));
+
+	// an unknown column is reported before any row is converted
+	for args in [
+		&["-o", "-", "--where", "<col 'Price'> > 0"][..],
+		&["check", "--where", "<col 'Price'> > 0"][..],
+	] {
+		let output = run(args);
+		assert_eq!(output.status.code(), Some(1), "{args:?}");
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		assert!(stderr.contains("Invalid --where") && stderr.contains("Column \"Price\" not found"), "{stderr}");
+	}
+}
+
+#[test]
+fn test_csv_converter_group_by() {
+	let dir = std::env::temp_dir().join("csv_converter_group_by_test");
+	fs::create_dir_all(&dir).expect("Failed to create the temp dir");
+	let input_file = dir.join("input.csv");
+	let config_file = dir.join("config.csv");
+	fs::write(
+		&input_file,
+		"Handle,Stock,Price,Tag\nshirt,3,19.99,red\nshirt,2,17.5,blue\nmug,,7.5,\nhat,0,5,\nmug,10,8,white\n",
+	)
+	.expect("Failed to write the input file");
+
+	for (directive, expected_output) in [
+		(":GROUP_BY <cell1>", "Handle,Stock,Price,Tags\nshirt,3,19.99,red\nmug,10,7.5,white\nhat,0,5,\n"),
+		// sorted input emits a group as soon as its key changes so the mug rows are two groups
+		(":GROUP_BY <cell1> SORTED", "Handle,Stock,Price,Tags\nshirt,3,19.99,red\nmug,0,7.5,\nhat,0,5,\nmug,10,8,white\n"),
+	] {
+		fs::write(
+			&config_file,
+			format!(
+				"Handle,Stock,Price,Tags\n{directive}\n:FILTER <cell4> != 'blue'\n<cell1>,:SUM <cell2>,:MIN <cell3>,\":JOIN|', ' <cell4>\"\n"
+			),
+		)
+		.expect("Failed to write the config file");
+
+		let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+			.arg("-i")
+			.arg(&input_file)
+			.arg("-o")
+			.arg("-")
+			.arg("-c")
+			.arg(&config_file)
+			.output()
+			.expect("Failed to execute csv_converter");
+
+		assert!(
+			output.status.success(),
+			"csv_converter did not run successfully: {}",
+			String::from_utf8_lossy(&output.stderr)
+		);
+		assert_eq!(String::from_utf8_lossy(&output.stdout), expected_output, "{directive}");
+		assert!(String::from_utf8_lossy(&output.stderr).contains("Rows rejected by filters: 1"));
+	}
+}
+
+#[test]
+fn test_csv_converter_dedupe() {
+	let dir = std::env::temp_dir().join("csv_converter_dedupe_test");
+	fs::create_dir_all(&dir).expect("Failed to create the temp dir");
+	let input_file = dir.join("input.csv");
+	let config_file = dir.join("config.csv");
+	// enough rows for several batches of the worker threads
+	let mut input = String::from("Handle,Value\n");
+	for i in 0..3000 {
+		input.push_str(&format!("item{},{i}\n", i % 100));
+	}
+	fs::write(&input_file, input).expect("Failed to write the input file");
+	fs::write(&config_file, "Handle,Value\n<cell1 UPPER_CASE>,<cell2>\n").expect("Failed to write the config file");
+
+	for (keep, memory, first_value) in [
+		("first", "256", 0),
+		("first", "0", 0),
+		("last", "256", 2900),
+		("last", "0", 2900),
+	] {
+		let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+			.arg("-i")
+			.arg(&input_file)
+			.arg("-o")
+			.arg("-")
+			.arg("-c")
+			.arg(&config_file)
+			.arg("--dedupe-by")
+			.arg("<col 'Handle'>")
+			.arg("--dedupe-keep")
+			.arg(keep)
+			.arg("--dedupe-memory")
+			.arg(memory)
+			.output()
+			.expect("Failed to execute csv_converter");
+
+		assert!(
+			output.status.success(),
+			"csv_converter did not run successfully: {}",
+			String::from_utf8_lossy(&output.stderr)
+		);
+		let mut expected_output = String::from("Handle,Value\n");
+		for i in 0..100 {
+			expected_output.push_str(&format!("ITEM{i},{}\n", first_value + i));
+		}
+		assert_eq!(String::from_utf8_lossy(&output.stdout), expected_output, "{keep} {memory}");
+		assert!(String::from_utf8_lossy(&output.stderr).contains("Rows dropped as duplicates: 2900"));
+	}
+}
+
+#[test]
+fn test_csv_converter_sort() {
+	let dir = std::env::temp_dir().join("csv_converter_sort_test");
+	fs::create_dir_all(&dir).expect("Failed to create the temp dir");
+	let input_file = dir.join("input.csv");
+	let config_file = dir.join("config.csv");
+	// the input is in crawl order with the images of each product spread over several batches of the worker threads
+	let mut input = String::from("Handle,Image\n");
+	for i in 0..3000 {
+		input.push_str(&format!("product-{},{}\n", i % 100, i / 100 + 1));
+	}
+	fs::write(&input_file, input).expect("Failed to write the input file");
+	fs::write(&config_file, "Handle,Image Position\n<cell1>,<cell2>\n").expect("Failed to write the config file");
+
+	let mut expected_output = String::from("Handle,Image Position\n");
+	let mut handles = (0..100).map(|i| format!("product-{i}")).collect::<Vec<_>>();
+	handles.sort();
+	for handle in &handles {
+		for position in (1..=30).rev() {
+			expected_output.push_str(&format!("{handle},{position}\n"));
+		}
+	}
+
+	for memory in ["256", "0"] {
+		let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+			.arg("-i")
+			.arg(&input_file)
+			.arg("-o")
+			.arg("-")
+			.arg("-c")
+			.arg(&config_file)
+			.arg("--sort-by")
+			.arg("<col 'Handle'> <col 'Image Position'> NUMERIC DESC")
+			.arg("--sort-memory")
+			.arg(memory)
+			.output()
+			.expect("Failed to execute csv_converter");
+
+		assert!(
+			output.status.success(),
+			"csv_converter did not run successfully: {}",
+			String::from_utf8_lossy(&output.stderr)
+		);
+		assert_eq!(String::from_utf8_lossy(&output.stdout), expected_output, "{memory}");
+	}
+}
+
+#[test]
+fn test_csv_converter_check() {
+	let dir = std::env::temp_dir().join("csv_converter_check_test");
+	fs::create_dir_all(&dir).expect("Failed to create the temp dir");
+	let config_file = dir.join("config.csv");
+
+	fs::write(&config_file, "Name,Note\n<cell1 TRIM>,<col 'Note'>\n").expect("Failed to write the config file");
+	let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+		.arg("check")
+		.arg("-c")
+		.arg(&config_file)
+		.arg("-i")
+		.arg("tests/input.csv")
+		.output()
+		.expect("Failed to execute csv_converter");
+	assert!(output.status.success(), "The check failed: {}", String::from_utf8_lossy(&output.stderr));
+	assert!(output.stdout.is_empty());
+
+	fs::write(&config_file, "Name,Note\n<cellx>,<cell1 UPPERCASE>\n<cell9>,<col 'Missing'>\n")
+		.expect("Failed to write the config file");
+	let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+		.arg("check")
+		.arg("-c")
+		.arg(&config_file)
+		.arg("-i")
+		.arg("tests/input.csv")
+		.output()
+		.expect("Failed to execute csv_converter");
+	assert_eq!(output.status.code(), Some(1));
+	let stderr = String::from_utf8_lossy(&output.stderr);
+	assert!(stderr.contains("config row 2, column 1: \"<cellx>\""), "{stderr}");
+	assert!(stderr.contains("Filter not recognized \"UPPERCASE\""), "{stderr}");
+	assert!(stderr.contains("config row 3, column 1: \"<cell9>\""), "{stderr}");
+	assert!(stderr.contains("Column \"Missing\" not found"), "{stderr}");
+	assert!(stderr.contains("has 3 error(s) and 1 warning(s)"), "{stderr}");
+
+	// a conversion reports the warnings of the config on stderr and keeps going
+	fs::write(&config_file, "Name\n<cell1 UPPERCASE>\n").expect("Failed to write the config file");
+	let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+		.arg("-c")
+		.arg(&config_file)
+		.arg("-i")
+		.arg("tests/input.csv")
+		.arg("-o")
+		.arg("-")
+		.output()
+		.expect("Failed to execute csv_converter");
+	assert!(output.status.success(), "The conversion failed: {}", String::from_utf8_lossy(&output.stderr));
+	assert!(String::from_utf8_lossy(&output.stderr).contains("Filter not recognized \"UPPERCASE\""));
+	assert!(String::from_utf8_lossy(&output.stdout).starts_with("Name\n"));
+}
+
+#[test]
+fn test_csv_converter_explain() {
+	let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+		.arg("-i")
+		.arg("tests/input.csv")
+		.arg("-c")
+		.arg("tests/config.csv")
+		.arg("--explain")
+		.arg("3")
+		.output()
+		.expect("Failed to execute csv_converter");
+	assert!(output.status.success(), "The explain failed: {}", String::from_utf8_lossy(&output.stderr));
+
+	let stdout = String::from_utf8_lossy(&output.stdout);
+	assert!(stdout.starts_with("Row 3\nInput\n  <cell1> = \"Bob Builder\"\n"), "{stdout}");
+	assert!(stdout.contains("    SPLIT|' '|0 -> \"BOB\"\n"), "{stdout}");
+	assert!(stdout.contains("      <cell4> IS_EMPTY -> false\n    ELSE <cell2>\n"), "{stdout}");
+	assert!(stdout.contains("  => emitted: BOB,NEW,"), "{stdout}");
+
+	let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+		.arg("-i")
+		.arg("tests/input.csv")
+		.arg("-c")
+		.arg("tests/config.csv")
+		.arg("--explain")
+		.arg("1000")
+		.output()
+		.expect("Failed to execute csv_converter");
+	assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_csv_converter_init() {
+	let dir = std::env::temp_dir().join("csv_converter_init_test");
+	fs::create_dir_all(&dir).expect("Failed to create the temp dir");
+	let template_file = dir.join("template.csv");
+	let config_file = dir.join("config.csv");
+	fs::write(&template_file, "Handle,Title,Body (HTML),Image Alt Text\nexample,Example,,\n")
+		.expect("Failed to write the template file");
+	let _ = fs::remove_file(&config_file);
+
+	let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+		.arg("init")
+		.arg("-i")
+		.arg("tests/input.csv")
+		.arg("-o")
+		.arg(&config_file)
+		.arg("--template")
+		.arg(&template_file)
+		.output()
+		.expect("Failed to execute csv_converter");
+	assert!(output.status.success(), "The init failed: {}", String::from_utf8_lossy(&output.stderr));
+	assert_eq!(
+		fs::read_to_string(&config_file).expect("Failed to read the config file"),
+		"Handle,Title,Body (HTML),Image Alt Text\n,,<cell4>,\n"
+	);
+	let stderr = String::from_utf8_lossy(&output.stderr);
+	assert!(stderr.contains("Handle, Title, Image Alt Text"), "{stderr}");
+	assert!(stderr.contains("Name, Address, Note"), "{stderr}");
+
+	// an existing config is only replaced with --force
+	let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+		.arg("init")
+		.arg("-i")
+		.arg("tests/input.csv")
+		.arg("-o")
+		.arg(&config_file)
+		.output()
+		.expect("Failed to execute csv_converter");
+	assert!(!output.status.success(), "The init should not overwrite the config");
+	assert!(String::from_utf8_lossy(&output.stderr).contains("already exists, pass --force to overwrite it"));
+	assert_eq!(
+		fs::read_to_string(&config_file).expect("Failed to read the config file"),
+		"Handle,Title,Body (HTML),Image Alt Text\n,,<cell4>,\n"
+	);
+
+	// a config without a template converts the input as it is
+	let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+		.arg("init")
+		.arg("-i")
+		.arg("tests/input.csv")
+		.arg("-o")
+		.arg(&config_file)
+		.arg("--force")
+		.output()
+		.expect("Failed to execute csv_converter");
+	assert!(output.status.success(), "The init failed: {}", String::from_utf8_lossy(&output.stderr));
+
+	let output = Command::new(env!("CARGO_BIN_EXE_csv_converter"))
+		.arg("-i")
+		.arg("tests/input.csv")
+		.arg("-o")
+		.arg("-")
+		.arg("-c")
+		.arg(&config_file)
+		.output()
+		.expect("Failed to execute csv_converter");
+	assert!(output.status.success(), "The conversion failed: {}", String::from_utf8_lossy(&output.stderr));
+	let input = fs::read_to_string("tests/input.csv").expect("Failed to read the input file");
+	assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), input.lines().count());
+}